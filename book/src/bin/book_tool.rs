@@ -0,0 +1,122 @@
+use book::{
+    load_from_ron, merge_books,
+    prune::{prune, PruneOptions},
+    save_book_to_ron,
+    stats::book_stats,
+    validate::{canonicalize, validate},
+    Book,
+};
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(about = "Inspect, validate and maintain opening books")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check every move is legal and report structural problems
+    Validate {
+        book: String,
+        /// Write a fixed copy of the book, rekeyed with duplicates merged and illegal moves removed
+        #[arg(long)]
+        fix: Option<String>,
+    },
+    /// Print coverage statistics
+    Stats {
+        book: String,
+        /// Number of popular lines to show
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        /// Max length of the popular lines, in plies
+        #[arg(long, default_value_t = 12)]
+        max_line_ply: usize,
+    },
+    /// Merge several books into one, summing weights
+    Merge {
+        #[arg(required = true)]
+        books: Vec<String>,
+        #[arg(short, long)]
+        out: String,
+    },
+    /// Remove rare, illegal, deep or unreachable entries
+    Prune {
+        book: String,
+        #[arg(short, long)]
+        out: String,
+        /// Remove moves played fewer times than this
+        #[arg(long, default_value_t = 1)]
+        min_weight: u32,
+        /// Remove positions whose moves add up to less than this
+        #[arg(long, default_value_t = 1)]
+        min_position_weight: u32,
+        /// Remove positions deeper than this many plies
+        #[arg(long)]
+        max_ply: Option<usize>,
+        #[arg(long)]
+        keep_illegal: bool,
+        #[arg(long)]
+        keep_unreachable: bool,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Validate { book, fix } => {
+            let book = load_from_ron(&book);
+            let report = validate(&book);
+            print!("{report}");
+
+            if let Some(out) = fix {
+                let fixed = canonicalize(book);
+                println!("Saving {} positions to {}", fixed.len(), out);
+                save_book_to_ron(&fixed, &out).expect("Failed to Save ron");
+            } else if !report.is_clean() {
+                std::process::exit(1);
+            }
+        }
+        Command::Stats { book, top, max_line_ply } => {
+            let book = load_from_ron(&book);
+            print!("{}", book_stats(&book, top, max_line_ply));
+        }
+        Command::Merge { books, out } => {
+            let mut merged = Book::new();
+            for path in &books {
+                let book = load_from_ron(path);
+                println!("Loaded {} positions from {}", book.len(), path);
+                merge_books(&mut merged, canonicalize(book));
+            }
+            println!("Saving {} positions to {}", merged.len(), out);
+            save_book_to_ron(&merged, &out).expect("Failed to Save ron");
+        }
+        Command::Prune {
+            book,
+            out,
+            min_weight,
+            min_position_weight,
+            max_ply,
+            keep_illegal,
+            keep_unreachable,
+        } => {
+            let mut book = load_from_ron(&book);
+            let options = PruneOptions {
+                min_weight,
+                min_position_weight,
+                max_ply,
+                drop_illegal: !keep_illegal,
+                drop_unreachable: !keep_unreachable,
+            };
+            let summary = prune(&mut book, &options);
+            println!(
+                "Removed {} moves and {} positions",
+                summary.moves_removed, summary.positions_removed
+            );
+            println!("Saving {} positions to {}", book.len(), out);
+            save_book_to_ron(&book, &out).expect("Failed to Save ron");
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use pleco::Board;
+
+use crate::{position_key, san::apply_san_line};
+
+/// Bundled table of "eco \t name \t SAN line" rows
+static ECO_TSV: &str = include_str!("../data/eco.tsv");
+
+/// Opening names keyed by the book key of the position each line ends in
+static OPENINGS: LazyLock<HashMap<String, Opening>> = LazyLock::new(|| {
+    let mut openings = HashMap::new();
+    for (eco, name, line) in eco_rows() {
+        let mut board = Board::start_pos();
+        if !apply_san_line(&mut board, line) {
+            debug_assert!(false, "Bad ECO line: {} {} {}", eco, name, line);
+            continue;
+        }
+        // First row wins when two lines transpose into the same position
+        openings.entry(position_key(&board)).or_insert(Opening { eco, name });
+    }
+    openings
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Opening {
+    pub eco: &'static str,
+    pub name: &'static str,
+}
+
+fn eco_rows() -> impl Iterator<Item = (&'static str, &'static str, &'static str)> {
+    ECO_TSV.lines().skip(1).filter_map(|row| {
+        let mut cols = row.split('\t');
+        Some((cols.next()?, cols.next()?, cols.next()?))
+    })
+}
+
+/// Opening for exactly this position, regardless of how it was reached
+pub fn lookup(board: &Board) -> Option<Opening> {
+    OPENINGS.get(&position_key(board)).copied()
+}
+
+/// ECO code and name of the opening being played. Walks back through the
+/// board's move history to the most recent named position, and since lookups
+/// are by position, transpositions get the name of the line they transpose into.
+pub fn classify(board: &Board) -> Option<(&'static str, &'static str)> {
+    let mut board = board.parallel_clone();
+    loop {
+        if let Some(opening) = lookup(&board) {
+            return Some((opening.eco, opening.name));
+        }
+        if board.depth() == 0 || board.last_move().is_none() {
+            return None;
+        }
+        board.undo_move();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_lines_parse() {
+        for (eco, name, line) in eco_rows() {
+            let mut board = Board::start_pos();
+            assert!(apply_san_line(&mut board, line), "{} {}: {}", eco, name, line);
+        }
+        assert!(OPENINGS.len() > 200);
+    }
+
+    #[test]
+    fn test_classify() {
+        let mut board = Board::start_pos();
+        assert_eq!(classify(&board), None);
+
+        // Najdorf, followed by a move that is not in the table
+        apply_san_line(&mut board, "e4 c5 Nf3 d6 d4 cxd4 Nxd4 Nf6 Nc3 a6 f4");
+        assert_eq!(classify(&board), Some(("B90", "Sicilian Defense: Najdorf Variation")));
+
+        // Reached through the Two Knights move order
+        let mut board = Board::start_pos();
+        apply_san_line(&mut board, "e4 e5 Bc4 Nf6 Nf3 Nc6");
+        assert_eq!(classify(&board), Some(("C55", "Italian Game: Two Knights Defense")));
+
+        // A board without history is classified by its position alone
+        let board = Board::from_fen(&board.fen()).unwrap();
+        assert_eq!(classify(&board).map(|(eco, _)| eco), Some("C55"));
+    }
+}
@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{lookup, Book};
+
+/// Win/draw/loss counts of the games a book move was played in
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResultStats {
+    pub white_wins: u32,
+    pub draws: u32,
+    pub black_wins: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExplorerMove {
+    /// Move in UCI notation
+    pub mv: String,
+    pub weight: u32,
+    /// Fraction of the position's total weight, in [0, 1]
+    pub share: f64,
+    /// None when the book was built without results
+    pub stats: Option<ResultStats>,
+}
+
+/// Every book move for `fen`, most played first. Empty if the position is not in the book.
+pub fn explore(book: &Book, fen: &str) -> Vec<ExplorerMove> {
+    let Some(moves) = lookup(book, fen) else {
+        return Vec::new();
+    };
+
+    let total: u32 = moves.iter().map(|bm| bm.weight).sum();
+    let mut explored: Vec<ExplorerMove> = moves
+        .iter()
+        .map(|bm| ExplorerMove {
+            mv: bm.mv.clone(),
+            weight: bm.weight,
+            share: if total == 0 { 0.0 } else { bm.weight as f64 / total as f64 },
+            stats: (bm.games() > 0).then_some(ResultStats {
+                white_wins: bm.white_wins,
+                draws: bm.draws,
+                black_wins: bm.black_wins,
+            }),
+        })
+        .collect();
+    explored.sort_by(|a, b| b.weight.cmp(&a.weight).then_with(|| a.mv.cmp(&b.mv)));
+
+    explored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{add_book_entry, add_book_move_weighted, child_key, root_key, BookMove};
+
+    #[test]
+    fn test_explore() {
+        let mut book = Book::new();
+        let root = root_key();
+        add_book_move_weighted(&mut book, &root, "d2d4", 1);
+        add_book_entry(&mut book, &root, BookMove { white_wins: 2, draws: 1, ..BookMove::new("e2e4", 3) });
+
+        let moves = explore(&book, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0].mv, "e2e4");
+        assert_eq!(moves[0].share, 0.75);
+        assert_eq!(moves[0].stats, Some(ResultStats { white_wins: 2, draws: 1, black_wins: 0 }));
+        assert_eq!(moves[1].stats, None);
+
+        // Client FENs carry the e.p. square even when no capture is possible
+        add_book_move_weighted(&mut book, &child_key(&root, "e2e4").unwrap(), "c7c5", 1);
+        let after_e4 = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        assert_eq!(explore(&book, after_e4)[0].mv, "c7c5");
+    }
+
+    #[test]
+    fn test_shipped_book() {
+        let book = crate::load_from_ron(concat!(env!("CARGO_MANIFEST_DIR"), "/../book.ron"));
+
+        // Clients may send the e.p. square even when no capture is possible
+        for after_e4 in [
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+        ] {
+            assert!(crate::get_book_move(&book, after_e4).is_some());
+            assert!(!explore(&book, after_e4).is_empty());
+        }
+
+        // Books from before `position_key` keep the e.p. square of every double push
+        let mut legacy = Book::new();
+        let after_d4 = "rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq d3 0 1";
+        add_book_move_weighted(&mut legacy, &crate::normalize_fen(after_d4, None), "g8f6", 1);
+        assert_eq!(crate::get_book_move(&legacy, after_d4).as_deref(), Some("g8f6"));
+    }
+}
@@ -1,4 +1,13 @@
-use std::collections::HashMap;
+pub mod eco;
+pub mod explorer;
+pub mod pgn;
+pub mod prune;
+pub mod san;
+pub mod stats;
+pub mod validate;
+
+use std::collections::{HashMap, VecDeque};
+use pleco::{core::sq::NO_SQ, Board, PieceType};
 use rand::Rng;
 use ron::ser::{to_string_pretty, PrettyConfig};
 use serde::{Serialize, Deserialize};
@@ -8,26 +17,73 @@ use std::io::Write;
 /// A Book Move Entry
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BookMove {
-    mv: String,
-    weight: u32,
+    /// Move in UCI notation
+    pub mv: String,
+    /// Relative selection weight, the number of times the move was seen
+    pub weight: u32,
+    /// Results of the games the move was played in, older books don't have them
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub white_wins: u32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub draws: u32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub black_wins: u32,
+}
+
+fn is_zero(count: &u32) -> bool {
+    *count == 0
+}
+
+impl BookMove {
+    pub fn new(mv: &str, weight: u32) -> Self {
+        Self {
+            mv: mv.to_string(),
+            weight,
+            white_wins: 0,
+            draws: 0,
+            black_wins: 0,
+        }
+    }
+
+    /// Number of games with a known result
+    pub fn games(&self) -> u32 {
+        self.white_wins + self.draws + self.black_wins
+    }
 }
 
 pub type Book = HashMap<String, Vec<BookMove>>;
 
 pub fn add_book_move(book: &mut Book, fen: &str, mv: &str) {
+    add_book_move_weighted(book, fen, mv, 1);
+}
+
+pub fn add_book_move_weighted(book: &mut Book, fen: &str, mv: &str, weight: u32) {
+    add_book_entry(book, fen, BookMove::new(mv, weight));
+}
+
+/// Add `book_move` to the entry for `fen`, summing weight and results if the move is already there
+pub fn add_book_entry(book: &mut Book, fen: &str, book_move: BookMove) {
     // Get or create vector for this FEN
     let entry = book.entry(fen.to_string()).or_default();
 
     // Look for existing move
-    if let Some(book_move) = entry.iter_mut().find(|bm| bm.mv == mv) {
-        // Increment weight
-        book_move.weight += 1;
+    if let Some(existing) = entry.iter_mut().find(|bm| bm.mv == book_move.mv) {
+        existing.weight += book_move.weight;
+        existing.white_wins += book_move.white_wins;
+        existing.draws += book_move.draws;
+        existing.black_wins += book_move.black_wins;
     } else {
         // Insert new
-        entry.push(BookMove {
-            mv: mv.to_string(),
-            weight: 1,
-        });
+        entry.push(book_move);
+    }
+}
+
+/// Merge `other` into `book`, summing the weights of moves present in both
+pub fn merge_books(book: &mut Book, other: Book) {
+    for (fen, moves) in other {
+        for bm in moves {
+            add_book_entry(book, &fen, bm);
+        }
     }
 }
 fn choose_weighted_move(moves: &[BookMove]) -> &BookMove {
@@ -52,9 +108,15 @@ fn choose_weighted_move(moves: &[BookMove]) -> &BookMove {
     panic!("Failed to choose weighted move");
 }
 
+/// Book moves for `fen`. Books built before `position_key` keep the en passant square
+/// of every double push, so a miss falls back to the FEN's own en passant field.
+pub fn lookup<'a>(book: &'a Book, fen: &str) -> Option<&'a Vec<BookMove>> {
+    let keyed = Board::from_fen(fen).ok().and_then(|board| book.get(&position_key(&board)));
+    keyed.or_else(|| book.get(&normalize_fen(fen, None)))
+}
+
 pub fn get_book_move(book: &Book, fen: &str) -> Option<String> {
-    let fen = normalize_fen(fen, None);
-    if let Some(moves) = book.get(&fen) {
+    if let Some(moves) = lookup(book, fen) {
         // Select a move randomly, but influenced by weight
         let mv = choose_weighted_move(moves);
         return Some(mv.mv.clone());
@@ -84,6 +146,12 @@ pub fn save_book_to_ron(book: &Book, path: &str) -> Result<(), Box<dyn std::erro
     Ok(())
 }
 
+pub fn save_book_to_json(book: &Book, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), book)?;
+
+    Ok(())
+}
 
 pub fn print_book(book: &Book) {
     for (fen, moves) in book {
@@ -93,6 +161,62 @@ pub fn print_book(book: &Book) {
         }
     }
 }
+/// Book key for a position. The en passant square is only kept when
+/// a capture onto it is possible, so keys don't depend on how the FEN was produced.
+pub fn position_key(board: &Board) -> String {
+    let ep = board.ep_square();
+    let us = board.turn();
+    let ep_square = if ep != NO_SQ
+        && (board.attacks_from(PieceType::P, ep, !us) & board.piece_bb(us, PieceType::P)).is_not_empty()
+    {
+        ep.to_string()
+    } else {
+        "-".to_string()
+    };
+    normalize_fen(&board.fen(), Some(ep_square))
+}
+
+/// Book key of the standard start position
+pub fn root_key() -> String {
+    position_key(&Board::start_pos())
+}
+
+/// Key of the position reached by playing `mv` from the position `key`, if the move is legal
+pub fn child_key(key: &str, mv: &str) -> Option<String> {
+    let mut board = Board::from_fen(key).ok()?;
+    if !board.apply_uci_move(mv) {
+        return None;
+    }
+    Some(position_key(&board))
+}
+
+/// Shortest distance in plies from the start position to every position
+/// reachable by following book moves
+pub fn reachable_plies(book: &Book) -> HashMap<String, usize> {
+    let mut plies: HashMap<String, usize> = HashMap::new();
+    let mut queue: VecDeque<(String, usize)> = VecDeque::new();
+
+    let root = root_key();
+    if book.contains_key(&root) {
+        plies.insert(root.clone(), 0);
+        queue.push_back((root, 0));
+    }
+
+    while let Some((key, ply)) = queue.pop_front() {
+        for bm in &book[&key] {
+            let Some(child) = child_key(&key, &bm.mv) else {
+                continue;
+            };
+            if book.contains_key(&child) && !plies.contains_key(&child) {
+                plies.insert(child.clone(), ply + 1);
+                queue.push_back((child, ply + 1));
+            }
+        }
+    }
+
+    plies
+}
+
 /// Normalize a FEN so only meaningful book fields remain:
 /// 1. board
 /// 2. side to move
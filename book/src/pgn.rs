@@ -0,0 +1,127 @@
+use pleco::{BitMove, Board};
+
+use crate::{eco::classify, san::move_to_san};
+
+/// A finished game, written out as PGN. `ECO` and `Opening` tags are added from the moves.
+pub struct PgnGame {
+    tags: Vec<(String, String)>,
+    start_fen: Option<String>,
+    moves: Vec<BitMove>,
+    result: String,
+}
+
+impl PgnGame {
+    /// `start_fen` is None for games from the standard start position
+    pub fn new(start_fen: Option<&str>) -> Self {
+        Self {
+            tags: Vec::new(),
+            start_fen: start_fen.map(str::to_string),
+            moves: Vec::new(),
+            result: "*".to_string(),
+        }
+    }
+
+    pub fn tag(&mut self, name: &str, value: &str) {
+        self.tags.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn push(&mut self, mv: BitMove) {
+        self.moves.push(mv);
+    }
+
+    /// "1-0", "0-1", "1/2-1/2" or "*"
+    pub fn set_result(&mut self, result: &str) {
+        self.result = result.to_string();
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let mut board = match &self.start_fen {
+            Some(fen) => Board::from_fen(fen).expect("Invalid PGN start FEN"),
+            None => Board::start_pos(),
+        };
+        let start_number = board.moves_played() / 2 + 1;
+        let black_first = board.turn() == pleco::Player::Black;
+
+        let mut movetext: Vec<String> = Vec::with_capacity(self.moves.len() * 2);
+        for (i, &mv) in self.moves.iter().enumerate() {
+            let number = start_number as usize + (i + black_first as usize) / 2;
+            if board.turn() == pleco::Player::White {
+                movetext.push(format!("{}.", number));
+            } else if i == 0 {
+                movetext.push(format!("{}...", number));
+            }
+            movetext.push(move_to_san(&board, mv));
+            board.apply_move(mv);
+        }
+        movetext.push(self.result.clone());
+
+        let mut pgn = String::new();
+        for (name, value) in &self.tags {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+        }
+        pgn.push_str(&format!("[Result \"{}\"]\n", self.result));
+        if let Some(fen) = &self.start_fen {
+            pgn.push_str("[SetUp \"1\"]\n");
+            pgn.push_str(&format!("[FEN \"{}\"]\n", fen));
+        }
+        if let Some((eco, name)) = classify(&board) {
+            pgn.push_str(&format!("[ECO \"{}\"]\n", eco));
+            pgn.push_str(&format!("[Opening \"{}\"]\n", name));
+        }
+        pgn.push('\n');
+
+        // Wrap movetext at 80 columns
+        let mut line_len = 0;
+        for token in movetext {
+            if line_len > 0 && line_len + token.len() + 1 > 80 {
+                pgn.push('\n');
+                line_len = 0;
+            } else if line_len > 0 {
+                pgn.push(' ');
+                line_len += 1;
+            }
+            line_len += token.len();
+            pgn.push_str(&token);
+        }
+        pgn.push_str("\n\n");
+        pgn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pgn_export() {
+        let mut board = Board::start_pos();
+        let mut game = PgnGame::new(None);
+        game.tag("Event", "Test");
+        for uci in ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"] {
+            assert!(board.apply_uci_move(uci));
+            game.push(board.last_move().unwrap());
+        }
+        game.set_result("1/2-1/2");
+
+        let pgn = game.to_pgn();
+        assert!(pgn.starts_with("[Event \"Test\"]\n[Result \"1/2-1/2\"]\n"));
+        assert!(pgn.contains("[ECO \"C60\"]\n[Opening \"Ruy Lopez\"]\n"));
+        assert!(pgn.contains("1. e4 e5 2. Nf3 Nc6 3. Bb5 1/2-1/2"));
+    }
+
+    #[test]
+    fn test_pgn_export_from_fen() {
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+        let mut board = Board::from_fen(fen).unwrap();
+        let mut game = PgnGame::new(Some(fen));
+        for uci in ["c7c5", "g1f3"] {
+            assert!(board.apply_uci_move(uci));
+            game.push(board.last_move().unwrap());
+        }
+
+        let pgn = game.to_pgn();
+        assert!(pgn.contains("[FEN \"rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1\"]"));
+        assert!(pgn.contains("[ECO \"B20\"]\n[Opening \"Sicilian Defense\"]"));
+        assert!(pgn.contains("1... c5 2. Nf3 *"));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{child_key, reachable_plies, Book};
+
+pub struct PruneOptions {
+    /// Moves played fewer times than this are removed
+    pub min_weight: u32,
+    /// Positions whose moves add up to less than this are removed
+    pub min_position_weight: u32,
+    /// Positions further than this from the start position are removed
+    pub max_ply: Option<usize>,
+    pub drop_illegal: bool,
+    pub drop_unreachable: bool,
+}
+
+impl Default for PruneOptions {
+    fn default() -> Self {
+        Self {
+            min_weight: 1,
+            min_position_weight: 1,
+            max_ply: None,
+            drop_illegal: true,
+            drop_unreachable: true,
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct PruneSummary {
+    pub moves_removed: usize,
+    pub positions_removed: usize,
+}
+
+pub fn prune(book: &mut Book, options: &PruneOptions) -> PruneSummary {
+    let mut summary = PruneSummary::default();
+    let positions_before = book.len();
+
+    for (key, moves) in book.iter_mut() {
+        let before = moves.len();
+        moves.retain(|bm| {
+            bm.weight >= options.min_weight
+                && (!options.drop_illegal || child_key(key, &bm.mv).is_some())
+        });
+        summary.moves_removed += before - moves.len();
+    }
+
+    book.retain(|_, moves| {
+        let keep = !moves.is_empty()
+            && moves.iter().map(|bm| bm.weight).sum::<u32>() >= options.min_position_weight;
+        if !keep {
+            summary.moves_removed += moves.len();
+        }
+        keep
+    });
+
+    if options.drop_unreachable || options.max_ply.is_some() {
+        // Removing moves above can cut off whole subtrees, so reachability is computed last
+        let plies = reachable_plies(book);
+        book.retain(|key, moves| {
+            let keep = match plies.get(key) {
+                Some(&ply) => options.max_ply.is_none_or(|max| ply <= max),
+                None => !options.drop_unreachable,
+            };
+            if !keep {
+                summary.moves_removed += moves.len();
+            }
+            keep
+        });
+    }
+
+    summary.positions_removed = positions_before - book.len();
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{add_book_move_weighted, root_key};
+
+    #[test]
+    fn test_prune() {
+        let mut book = Book::new();
+        let root = root_key();
+        let e4 = child_key(&root, "e2e4").unwrap();
+        let a4 = child_key(&root, "a2a4").unwrap();
+        add_book_move_weighted(&mut book, &root, "e2e4", 10);
+        add_book_move_weighted(&mut book, &root, "a2a4", 1);
+        add_book_move_weighted(&mut book, &root, "e2e5", 3);
+        add_book_move_weighted(&mut book, &e4, "c7c5", 6);
+        add_book_move_weighted(&mut book, &a4, "e7e5", 5);
+
+        let options = PruneOptions { min_weight: 2, ..Default::default() };
+        let summary = prune(&mut book, &options);
+
+        // a2a4 is too rare, e2e5 is illegal, and the a4 position is now unreachable
+        assert_eq!(summary.moves_removed, 3);
+        assert_eq!(summary.positions_removed, 1);
+        assert_eq!(book.len(), 2);
+        assert_eq!(book[&root].len(), 1);
+
+        let options = PruneOptions { max_ply: Some(0), ..Default::default() };
+        prune(&mut book, &options);
+        assert_eq!(book.len(), 1);
+    }
+}
@@ -0,0 +1,141 @@
+use pleco::{BitMove, Board, PieceType};
+
+/// Standard algebraic notation of a legal move, including check and mate suffixes
+pub fn move_to_san(board: &Board, mv: BitMove) -> String {
+    let mut san = san_without_suffix(board, mv);
+
+    let mut after = board.shallow_clone();
+    after.apply_move(mv);
+    if after.checkmate() {
+        san.push('#');
+    } else if after.in_check() {
+        san.push('+');
+    }
+    san
+}
+
+/// Find the legal move `san` refers to. Check, mate and annotation suffixes are
+/// ignored, as is the `=` of promotions, so "e8Q" and "e8=Q+" both parse.
+pub fn san_to_move(board: &Board, san: &str) -> Option<BitMove> {
+    let wanted = strip_san(san);
+    board
+        .generate_moves()
+        .iter()
+        .copied()
+        .find(|&mv| strip_san(&san_without_suffix(board, mv)) == wanted)
+}
+
+/// Play a line of SAN moves from `board`, returning false at the first move that doesn't parse
+pub fn apply_san_line(board: &mut Board, line: &str) -> bool {
+    for token in line.split_whitespace() {
+        // Move numbers, "1." or "1...", are not moves
+        if token.ends_with('.') || token.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            continue;
+        }
+        match san_to_move(board, token) {
+            Some(mv) => board.apply_move(mv),
+            None => return false,
+        }
+    }
+    true
+}
+
+fn strip_san(san: &str) -> String {
+    san.trim_end_matches(['+', '#', '!', '?'])
+        .replace('=', "")
+        .replace('0', "O")
+}
+
+fn san_without_suffix(board: &Board, mv: BitMove) -> String {
+    if mv.is_king_castle() {
+        return "O-O".to_string();
+    }
+    if mv.is_queen_castle() {
+        return "O-O-O".to_string();
+    }
+
+    let src = mv.get_src();
+    let dest = mv.get_dest();
+    let piece = board.piece_at_sq(src).type_of();
+    let src_str = src.to_string();
+    let (src_file, src_rank) = (&src_str[0..1], &src_str[1..2]);
+
+    let mut san = String::new();
+    if piece == PieceType::P {
+        if mv.is_capture() {
+            san.push_str(src_file);
+            san.push('x');
+        }
+        san.push_str(&dest.to_string());
+        if mv.is_promo() {
+            san.push('=');
+            san.push(mv.promo_piece().char_upper());
+        }
+        return san;
+    }
+
+    san.push(piece.char_upper());
+
+    // Other pieces of the same type that can reach the same square
+    let rivals: Vec<String> = board
+        .generate_moves()
+        .iter()
+        .filter(|m| {
+            m.get_dest() == dest
+                && m.get_src() != src
+                && board.piece_at_sq(m.get_src()).type_of() == piece
+        })
+        .map(|m| m.get_src().to_string())
+        .collect();
+    if !rivals.is_empty() {
+        if rivals.iter().all(|r| &r[0..1] != src_file) {
+            san.push_str(src_file);
+        } else if rivals.iter().all(|r| &r[1..2] != src_rank) {
+            san.push_str(src_rank);
+        } else {
+            san.push_str(&src_str);
+        }
+    }
+
+    if mv.is_capture() {
+        san.push('x');
+    }
+    san.push_str(&dest.to_string());
+    san
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_san_round_trip() {
+        let mut board = Board::start_pos();
+        assert!(apply_san_line(&mut board, "1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 4. Ba4 Nf6 5. O-O Be7"));
+
+        let mv = san_to_move(&board, "Re1").unwrap();
+        assert_eq!(move_to_san(&board, mv), "Re1");
+        assert_eq!(mv.to_string(), "f1e1");
+        assert!(san_to_move(&board, "Qh5").is_none());
+    }
+
+    #[test]
+    fn test_san_disambiguation_and_mate() {
+        // Both knights can reach d2
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/RN2KN1R w - - 0 1").unwrap();
+        let nd2: Vec<String> = board
+            .generate_moves()
+            .iter()
+            .filter(|m| m.get_dest().to_string() == "d2")
+            .map(|&m| move_to_san(&board, m))
+            .filter(|san| san.starts_with('N'))
+            .collect();
+        assert_eq!(nd2.len(), 2);
+        assert!(nd2.contains(&"Nbd2".to_string()) && nd2.contains(&"Nfd2".to_string()));
+
+        let mut board = Board::start_pos();
+        assert!(apply_san_line(&mut board, "f3 e5 g4"));
+        let mate = san_to_move(&board, "Qh4").unwrap();
+        assert_eq!(move_to_san(&board, mate), "Qh4#");
+    }
+}
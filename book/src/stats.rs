@@ -0,0 +1,200 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::fmt;
+
+use crate::{child_key, reachable_plies, root_key, Book};
+
+#[derive(Default, Clone, Copy)]
+pub struct PlyStats {
+    pub positions: usize,
+    pub moves: usize,
+}
+
+impl PlyStats {
+    /// Average number of book moves per position
+    pub fn branching_factor(&self) -> f64 {
+        if self.positions == 0 {
+            0.0
+        } else {
+            self.moves as f64 / self.positions as f64
+        }
+    }
+}
+
+pub struct BookLine {
+    pub moves: Vec<String>,
+    /// Min weight along the line, an upper bound on the games that followed it
+    pub weight: u32,
+}
+
+#[derive(Default)]
+pub struct BookStats {
+    pub positions: usize,
+    pub moves: usize,
+    pub total_weight: u64,
+    /// Indexed by distance in plies from the start position
+    pub plies: Vec<PlyStats>,
+    pub unreachable: usize,
+    pub top_lines: Vec<BookLine>,
+}
+
+/// Coverage statistics of a book, plus the `top_n` most played lines up to `max_line_ply`
+pub fn book_stats(book: &Book, top_n: usize, max_line_ply: usize) -> BookStats {
+    let mut stats = BookStats {
+        positions: book.len(),
+        ..Default::default()
+    };
+
+    for moves in book.values() {
+        stats.moves += moves.len();
+        stats.total_weight += moves.iter().map(|bm| bm.weight as u64).sum::<u64>();
+    }
+
+    let plies = reachable_plies(book);
+    stats.unreachable = book.len() - plies.len();
+    for (key, &ply) in &plies {
+        if stats.plies.len() <= ply {
+            stats.plies.resize(ply + 1, PlyStats::default());
+        }
+        stats.plies[ply].positions += 1;
+        stats.plies[ply].moves += book[key].len();
+    }
+
+    let mut finder = LineFinder {
+        book,
+        top_n,
+        max_ply: max_line_ply,
+        best: BinaryHeap::new(),
+        line: Vec::new(),
+        on_path: HashSet::new(),
+    };
+    let root = root_key();
+    if top_n > 0 && book.contains_key(&root) {
+        finder.search(&root, u32::MAX);
+    }
+    let mut top_lines: Vec<BookLine> = finder
+        .best
+        .into_iter()
+        .map(|Reverse((weight, moves))| BookLine { moves, weight })
+        .collect();
+    top_lines.sort_by(|a, b| b.weight.cmp(&a.weight).then_with(|| a.moves.cmp(&b.moves)));
+    stats.top_lines = top_lines;
+
+    stats
+}
+
+/// Depth first walk over the book keeping the `top_n` heaviest complete lines
+struct LineFinder<'a> {
+    book: &'a Book,
+    top_n: usize,
+    max_ply: usize,
+    best: BinaryHeap<Reverse<(u32, Vec<String>)>>,
+    line: Vec<String>,
+    on_path: HashSet<String>,
+}
+
+impl LineFinder<'_> {
+    fn lightest_kept(&self) -> u32 {
+        if self.best.len() < self.top_n {
+            0
+        } else {
+            self.best.peek().map_or(0, |Reverse((w, _))| *w)
+        }
+    }
+
+    fn record(&mut self, weight: u32) {
+        if self.line.is_empty() || weight <= self.lightest_kept() {
+            return;
+        }
+        self.best.push(Reverse((weight, self.line.clone())));
+        if self.best.len() > self.top_n {
+            self.best.pop();
+        }
+    }
+
+    fn search(&mut self, key: &str, weight: u32) {
+        let moves = match self.book.get(key) {
+            Some(moves) if self.line.len() < self.max_ply => moves,
+            _ => return self.record(weight),
+        };
+        self.on_path.insert(key.to_string());
+
+        let mut extended = false;
+        for bm in moves {
+            let w = weight.min(bm.weight);
+            // No line through this move can beat what we already have
+            if w <= self.lightest_kept() {
+                continue;
+            }
+            let Some(child) = child_key(key, &bm.mv) else {
+                continue;
+            };
+            if self.on_path.contains(&child) {
+                continue;
+            }
+            extended = true;
+            self.line.push(bm.mv.clone());
+            self.search(&child, w);
+            self.line.pop();
+        }
+        if !extended {
+            self.record(weight);
+        }
+
+        self.on_path.remove(key);
+    }
+}
+
+impl fmt::Display for BookStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Positions:    {}", self.positions)?;
+        writeln!(f, "Moves:        {}", self.moves)?;
+        writeln!(f, "Total weight: {}", self.total_weight)?;
+        writeln!(f, "Unreachable:  {}", self.unreachable)?;
+        writeln!(f)?;
+        writeln!(f, "{:>4} | {:>9} | {:>7} | {:>9}", "Ply", "Positions", "Moves", "Branching")?;
+        for (ply, p) in self.plies.iter().enumerate() {
+            writeln!(f, "{:>4} | {:>9} | {:>7} | {:>9.2}", ply, p.positions, p.moves, p.branching_factor())?;
+        }
+        if !self.top_lines.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Most popular lines:")?;
+            for line in &self.top_lines {
+                writeln!(f, "{:>8}  {}", line.weight, line.moves.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::add_book_move_weighted;
+
+    #[test]
+    fn test_book_stats() {
+        let mut book = Book::new();
+        let root = root_key();
+        let e4 = child_key(&root, "e2e4").unwrap();
+        let d4 = child_key(&root, "d2d4").unwrap();
+        add_book_move_weighted(&mut book, &root, "e2e4", 10);
+        add_book_move_weighted(&mut book, &root, "d2d4", 4);
+        add_book_move_weighted(&mut book, &e4, "c7c5", 6);
+        add_book_move_weighted(&mut book, &e4, "e7e5", 3);
+        add_book_move_weighted(&mut book, &d4, "d7d5", 4);
+
+        let stats = book_stats(&book, 2, 10);
+        assert_eq!(stats.positions, 3);
+        assert_eq!(stats.moves, 5);
+        assert_eq!(stats.total_weight, 27);
+        assert_eq!(stats.plies.len(), 2);
+        assert_eq!(stats.plies[1].positions, 2);
+        assert_eq!(stats.plies[1].branching_factor(), 1.5);
+
+        assert_eq!(stats.top_lines.len(), 2);
+        assert_eq!(stats.top_lines[0].moves, vec!["e2e4", "c7c5"]);
+        assert_eq!(stats.top_lines[0].weight, 6);
+        assert_eq!(stats.top_lines[1].moves, vec!["d2d4", "d7d5"]);
+    }
+}
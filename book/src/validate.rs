@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use pleco::Board;
+
+use crate::{add_book_entry, child_key, position_key, reachable_plies, root_key, Book};
+
+#[derive(Default)]
+pub struct ValidationReport {
+    pub positions: usize,
+    pub moves: usize,
+    /// Keys that don't parse as a position
+    pub invalid_keys: Vec<String>,
+    /// (key, move) pairs where the move is not legal in the key position
+    pub illegal_moves: Vec<(String, String)>,
+    /// (key, move) pairs listed more than once in the same entry
+    pub repeated_moves: Vec<(String, String)>,
+    pub zero_weight_moves: Vec<(String, String)>,
+    pub empty_entries: Vec<String>,
+    /// Positions no book move leads to
+    pub orphaned: Vec<String>,
+    /// Positions with a parent in the book, but no path from the start position
+    pub unreachable: Vec<String>,
+    /// Groups of keys that describe the same position once normalized
+    pub duplicates: Vec<Vec<String>>,
+    /// Keys that differ from their normalized form
+    pub non_canonical: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.invalid_keys.is_empty()
+            && self.illegal_moves.is_empty()
+            && self.repeated_moves.is_empty()
+            && self.zero_weight_moves.is_empty()
+            && self.empty_entries.is_empty()
+            && self.orphaned.is_empty()
+            && self.unreachable.is_empty()
+            && self.duplicates.is_empty()
+            && self.non_canonical.is_empty()
+    }
+}
+
+/// Check every entry of the book, see `ValidationReport` for what is reported
+pub fn validate(book: &Book) -> ValidationReport {
+    let mut report = ValidationReport {
+        positions: book.len(),
+        ..Default::default()
+    };
+
+    let mut children: HashSet<String> = HashSet::new();
+    let mut canonical: HashMap<String, Vec<String>> = HashMap::new();
+
+    for (key, moves) in book {
+        report.moves += moves.len();
+        if moves.is_empty() {
+            report.empty_entries.push(key.clone());
+        }
+
+        let board = match Board::from_fen(key) {
+            Ok(board) => board,
+            Err(_) => {
+                report.invalid_keys.push(key.clone());
+                continue;
+            }
+        };
+
+        let normalized = position_key(&board);
+        if &normalized != key {
+            report.non_canonical.push(key.clone());
+        }
+        canonical.entry(normalized).or_default().push(key.clone());
+
+        let mut seen: HashSet<&str> = HashSet::new();
+        for bm in moves {
+            if !seen.insert(&bm.mv) {
+                report.repeated_moves.push((key.clone(), bm.mv.clone()));
+            }
+            if bm.weight == 0 {
+                report.zero_weight_moves.push((key.clone(), bm.mv.clone()));
+            }
+            match child_key(key, &bm.mv) {
+                Some(child) => {
+                    children.insert(child);
+                }
+                None => report.illegal_moves.push((key.clone(), bm.mv.clone())),
+            }
+        }
+    }
+
+    let root = root_key();
+    let reachable = reachable_plies(book);
+    // Non canonical keys are never looked up, they are reported on their own
+    let reported: HashSet<&str> =
+        report.invalid_keys.iter().chain(&report.non_canonical).map(String::as_str).collect();
+    for key in book.keys() {
+        if reachable.contains_key(key) || reported.contains(key.as_str()) {
+            continue;
+        }
+        if *key != root && !children.contains(key) {
+            report.orphaned.push(key.clone());
+        } else {
+            report.unreachable.push(key.clone());
+        }
+    }
+
+    report.duplicates = canonical
+        .into_values()
+        .filter(|keys| keys.len() > 1)
+        .map(|mut keys| {
+            keys.sort();
+            keys
+        })
+        .collect();
+
+    report.invalid_keys.sort();
+    report.illegal_moves.sort();
+    report.repeated_moves.sort();
+    report.zero_weight_moves.sort();
+    report.empty_entries.sort();
+    report.orphaned.sort();
+    report.unreachable.sort();
+    report.duplicates.sort();
+    report.non_canonical.sort();
+
+    report
+}
+
+/// Rekey every entry with `position_key`, merging entries that describe the
+/// same position and repeated moves. Invalid keys and illegal moves are dropped.
+pub fn canonicalize(book: Book) -> Book {
+    let mut out = Book::new();
+    for (key, moves) in book {
+        let Ok(board) = Board::from_fen(&key) else {
+            continue;
+        };
+        let normalized = position_key(&board);
+        for bm in moves {
+            if child_key(&normalized, &bm.mv).is_some() {
+                add_book_entry(&mut out, &normalized, bm);
+            }
+        }
+    }
+    out
+}
+
+fn write_section<T: fmt::Debug>(f: &mut fmt::Formatter<'_>, title: &str, items: &[T]) -> fmt::Result {
+    if items.is_empty() {
+        return Ok(());
+    }
+    writeln!(f, "{} ({}):", title, items.len())?;
+    for item in items {
+        writeln!(f, "  {:?}", item)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Checked {} moves in {} positions", self.moves, self.positions)?;
+        write_section(f, "Invalid keys", &self.invalid_keys)?;
+        write_section(f, "Illegal moves", &self.illegal_moves)?;
+        write_section(f, "Repeated moves", &self.repeated_moves)?;
+        write_section(f, "Zero weight moves", &self.zero_weight_moves)?;
+        write_section(f, "Empty entries", &self.empty_entries)?;
+        write_section(f, "Orphaned positions", &self.orphaned)?;
+        write_section(f, "Unreachable positions", &self.unreachable)?;
+        write_section(f, "Duplicate positions", &self.duplicates)?;
+        write_section(f, "Non canonical keys", &self.non_canonical)?;
+        if self.is_clean() {
+            writeln!(f, "No problems found")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::add_book_move;
+
+    #[test]
+    fn test_validate_finds_problems() {
+        let root = root_key();
+        let after_e4 = child_key(&root, "e2e4").unwrap();
+        // The builder used to always write the e.p. square after a double push
+        let after_e4_ep = after_e4.replace(" -", " e3");
+        let orphan = "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq -";
+
+        let mut book = Book::new();
+        add_book_move(&mut book, &root, "e2e4");
+        add_book_move(&mut book, &root, "e2e5");
+        add_book_move(&mut book, &after_e4, "c7c5");
+        add_book_move(&mut book, &after_e4_ep, "e7e5");
+        add_book_move(&mut book, orphan, "d7d5");
+
+        let report = validate(&book);
+        assert!(!report.is_clean());
+        assert_eq!(report.illegal_moves, vec![(root.clone(), "e2e5".to_string())]);
+        assert_eq!(report.orphaned, vec![orphan.to_string()]);
+        assert_eq!(report.non_canonical, vec![after_e4_ep.clone()]);
+        assert_eq!(report.duplicates.len(), 1);
+
+        let fixed = canonicalize(book);
+        assert_eq!(fixed[&after_e4].len(), 2);
+        assert_eq!(fixed[&root].len(), 1);
+        let report = validate(&fixed);
+        assert!(report.illegal_moves.is_empty() && report.duplicates.is_empty());
+    }
+}
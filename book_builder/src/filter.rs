@@ -0,0 +1,213 @@
+use clap::ValueEnum;
+use std::str::FromStr;
+
+use crate::pgn::PgnEntry;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GameResult {
+    White,
+    Draw,
+    Black,
+}
+
+impl GameResult {
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "1-0" => Some(Self::White),
+            "1/2-1/2" => Some(Self::Draw),
+            "0-1" => Some(Self::Black),
+            _ => None,
+        }
+    }
+}
+
+/// Speed category of a game, using the lichess estimate of base + 40 * increment seconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimeClass {
+    UltraBullet,
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
+impl TimeClass {
+    /// Classify a PGN `TimeControl` tag such as "180+2". Returns None for unknown ("?") controls.
+    pub fn from_time_control(tc: &str) -> Option<Self> {
+        if tc == "-" {
+            return Some(Self::Correspondence);
+        }
+        // Multi-stage controls ("40/7200:3600") are classified by their first stage
+        let first = tc.split(':').next()?;
+        let first = first.split('/').next_back()?;
+        let (base, inc) = match first.split_once('+') {
+            Some((base, inc)) => (base.parse::<u32>().ok()?, inc.parse::<u32>().ok()?),
+            None => (first.parse::<u32>().ok()?, 0),
+        };
+
+        Some(match base + 40 * inc {
+            0..=29 => Self::UltraBullet,
+            30..=179 => Self::Bullet,
+            180..=479 => Self::Blitz,
+            480..=1499 => Self::Rapid,
+            _ => Self::Classical,
+        })
+    }
+}
+
+/// A possibly partial PGN date, "2025.05.??" has no day
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PgnDate {
+    year: u16,
+    month: Option<u8>,
+    day: Option<u8>,
+}
+
+impl PgnDate {
+    /// Earliest (year, month, day) this date could refer to
+    fn earliest(&self) -> (u16, u8, u8) {
+        (self.year, self.month.unwrap_or(1), self.day.unwrap_or(1))
+    }
+
+    /// Latest (year, month, day) this date could refer to
+    fn latest(&self) -> (u16, u8, u8) {
+        (self.year, self.month.unwrap_or(12), self.day.unwrap_or(31))
+    }
+}
+
+impl FromStr for PgnDate {
+    type Err = String;
+
+    /// Accepts "YYYY.MM.DD" or "YYYY-MM-DD", trailing parts may be missing or "??"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(['.', '-']);
+        let year = parts
+            .next()
+            .and_then(|y| y.parse::<u16>().ok())
+            .ok_or_else(|| format!("invalid date '{}', expected YYYY.MM.DD", s))?;
+        let month = parts.next().and_then(|m| m.parse::<u8>().ok());
+        let day = month.and(parts.next().and_then(|d| d.parse::<u8>().ok()));
+
+        Ok(Self { year, month, day })
+    }
+}
+
+#[derive(Default)]
+pub struct GameFilter {
+    pub min_white_elo: u32,
+    pub min_black_elo: u32,
+    /// Accepted time classes, any if empty
+    pub time_classes: Vec<TimeClass>,
+    /// Accepted results, any if empty
+    pub results: Vec<GameResult>,
+    pub date_from: Option<PgnDate>,
+    pub date_to: Option<PgnDate>,
+    /// Games with fewer half moves than this are skipped, however many go into the book
+    pub min_ply: usize,
+}
+
+impl GameFilter {
+    pub fn accepts(&self, entry: &PgnEntry) -> bool {
+        if entry.white_elo.unwrap_or(0) < self.min_white_elo
+            || entry.black_elo.unwrap_or(0) < self.min_black_elo
+        {
+            return false;
+        }
+        if entry.plies < self.min_ply {
+            return false;
+        }
+
+        if !self.time_classes.is_empty() {
+            let class = entry.time_control.as_deref().and_then(TimeClass::from_time_control);
+            if !class.is_some_and(|c| self.time_classes.contains(&c)) {
+                return false;
+            }
+        }
+
+        if !self.results.is_empty() && !entry.result.is_some_and(|r| self.results.contains(&r)) {
+            return false;
+        }
+
+        if self.date_from.is_some() || self.date_to.is_some() {
+            let Some(date) = entry.date.as_deref().and_then(|d| d.parse::<PgnDate>().ok()) else {
+                return false;
+            };
+            // Partial dates pass as long as some day they could refer to is in range
+            if self.date_from.is_some_and(|from| date.latest() < from.earliest()) {
+                return false;
+            }
+            if self.date_to.is_some_and(|to| date.earliest() > to.latest()) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time_control: &str, result: &str, date: &str) -> PgnEntry {
+        PgnEntry {
+            white_elo: Some(2700),
+            black_elo: Some(2500),
+            time_control: Some(time_control.to_string()),
+            result: GameResult::from_tag(result),
+            date: Some(date.to_string()),
+            move_sequence: vec!["e4".to_string(); 10],
+            plies: 40,
+        }
+    }
+
+    #[test]
+    fn test_time_class() {
+        assert_eq!(TimeClass::from_time_control("15"), Some(TimeClass::UltraBullet));
+        assert_eq!(TimeClass::from_time_control("60+1"), Some(TimeClass::Bullet));
+        assert_eq!(TimeClass::from_time_control("180+2"), Some(TimeClass::Blitz));
+        assert_eq!(TimeClass::from_time_control("600"), Some(TimeClass::Rapid));
+        assert_eq!(TimeClass::from_time_control("40/7200:3600"), Some(TimeClass::Classical));
+        assert_eq!(TimeClass::from_time_control("-"), Some(TimeClass::Correspondence));
+        assert_eq!(TimeClass::from_time_control("?"), None);
+    }
+
+    #[test]
+    fn test_filter() {
+        let game = entry("180+2", "1-0", "2024.03.??");
+
+        assert!(GameFilter::default().accepts(&game));
+        assert!(GameFilter { min_white_elo: 2600, ..Default::default() }.accepts(&game));
+        assert!(!GameFilter { min_black_elo: 2600, ..Default::default() }.accepts(&game));
+        // The whole game counts, not just the moves kept for the book
+        assert!(GameFilter { min_ply: 40, ..Default::default() }.accepts(&game));
+        assert!(!GameFilter { min_ply: 41, ..Default::default() }.accepts(&game));
+
+        let blitz = GameFilter { time_classes: vec![TimeClass::Blitz], ..Default::default() };
+        assert!(blitz.accepts(&game));
+        let rapid = GameFilter { time_classes: vec![TimeClass::Rapid], ..Default::default() };
+        assert!(!rapid.accepts(&game));
+
+        let draws = GameFilter { results: vec![GameResult::Draw], ..Default::default() };
+        assert!(!draws.accepts(&game));
+    }
+
+    #[test]
+    fn test_date_range() {
+        let game = entry("600", "1/2-1/2", "2024.03.??");
+        let range = |from: &str, to: &str| GameFilter {
+            date_from: Some(from.parse().unwrap()),
+            date_to: Some(to.parse().unwrap()),
+            ..Default::default()
+        };
+
+        assert!(range("2024.03.15", "2024.12.31").accepts(&game));
+        assert!(range("2023", "2024-03-01").accepts(&game));
+        assert!(!range("2024.04.01", "2025.01.01").accepts(&game));
+        assert!(!range("2020.01.01", "2024.02.29").accepts(&game));
+
+        let undated = entry("600", "1/2-1/2", "????.??.??");
+        assert!(!range("2020", "2030").accepts(&undated));
+    }
+}
@@ -1,28 +1,204 @@
-mod lumbras;
+mod filter;
+mod pgn;
+mod prune;
 mod utils;
 
-use book::{save_book_to_ron};
-use lumbras::load_lumbras_book;
+use book::{load_from_ron, save_book_to_json, save_book_to_ron};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use filter::{GameFilter, GameResult, PgnDate, TimeClass};
+use pgn::{build_book, BuildConfig};
+use prune::{prune_book, PruneConfig, PruneMode};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(about = "Build and maintain the opening book")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build a book by streaming games from PGN files
+    Build(BuildArgs),
+    /// Verify every book move with the engine and prune the ones that lose too much
+    Prune {
+        /// Book to verify
+        book: String,
+        /// Where to write the pruned book
+        #[arg(short, long, default_value = "pruned_book.ron")]
+        out: String,
+        /// Where to write the report of pruned lines
+        #[arg(short, long, default_value = "prune_report.txt")]
+        report: String,
+        /// Search depth per candidate move
+        #[arg(short, long, default_value_t = 10)]
+        depth: u8,
+        /// Optional search time per candidate move, in ms
+        #[arg(short, long)]
+        time_ms: Option<u128>,
+        /// Max centipawn loss against the best book move
+        #[arg(long, default_value_t = 80)]
+        threshold: i32,
+        #[arg(short, long, value_enum, default_value_t = ModeArg::Drop)]
+        mode: ModeArg,
+    },
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    /// PGN files or glob patterns
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// Where to write the book
+    #[arg(short, long, default_value = "book.ron")]
+    out: String,
+    /// Output format, inferred from the output extension if not given
+    #[arg(short, long, value_enum)]
+    format: Option<BookFormat>,
+    /// Min Elo for both sides
+    #[arg(long, default_value_t = 0)]
+    min_elo: u32,
+    /// Min Elo for white, overrides --min-elo
+    #[arg(long)]
+    min_white_elo: Option<u32>,
+    /// Min Elo for black, overrides --min-elo
+    #[arg(long)]
+    min_black_elo: Option<u32>,
+    /// Accepted time classes, may be repeated. Any if not given
+    #[arg(long, value_enum)]
+    time_class: Vec<TimeClass>,
+    /// Accepted results, may be repeated. Any if not given
+    #[arg(long, value_enum)]
+    result: Vec<GameResult>,
+    /// Earliest game date, YYYY.MM.DD
+    #[arg(long)]
+    date_from: Option<PgnDate>,
+    /// Latest game date, YYYY.MM.DD
+    #[arg(long)]
+    date_to: Option<PgnDate>,
+    /// Skip games shorter than this many half moves, counting past --max-ply
+    #[arg(long, default_value_t = 5)]
+    min_ply: usize,
+    /// Only the first N half moves of each game go into the book
+    #[arg(long, default_value_t = 10)]
+    max_ply: usize,
+    /// Drop positions seen fewer times than this
+    #[arg(long, default_value_t = 1)]
+    min_frequency: u32,
+    /// Worker threads, defaults to the number of cores
+    #[arg(short, long)]
+    threads: Option<usize>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum BookFormat {
+    Ron,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ModeArg {
+    Drop,
+    DownWeight,
+}
 
 fn main() {
-    const FNAME: &str = "My_Book";
-    // const FNAME: &str = "Benn_Test";
-    let file_path = format!("/home/bmellin/chess_db/openings/{}.pgn", FNAME);
-
-    let book = load_lumbras_book(
-        &file_path,
-        2600,
-        5,
-        Some(10)
-    );
-    let out_name = format!("lumbras_{}.ron", FNAME.to_lowercase());
-    println!("Saving to {}", out_name);
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Build(args) => build(args),
+        Command::Prune { book, out, report, depth, time_ms, threshold, mode } => {
+            let config = PruneConfig {
+                depth,
+                time_ms,
+                threshold,
+                mode: match mode {
+                    ModeArg::Drop => PruneMode::Drop,
+                    ModeArg::DownWeight => PruneMode::DownWeight,
+                },
+            };
+            prune(&book, &out, &report, &config);
+        }
+    }
+}
 
-    if let Some(book) = book {
-        println!("Loaded book with {} positions.", book.len());
-        save_book_to_ron(&book, &out_name).expect("Failed to Save ron");
+fn build(args: BuildArgs) {
+    let inputs = expand_inputs(&args.inputs);
+    if inputs.is_empty() {
+        println!("No PGN files matched {:?}", args.inputs);
+        return;
+    }
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Failed to configure thread pool");
+    }
+
+    let config = BuildConfig {
+        filter: GameFilter {
+            min_white_elo: args.min_white_elo.unwrap_or(args.min_elo),
+            min_black_elo: args.min_black_elo.unwrap_or(args.min_elo),
+            time_classes: args.time_class,
+            results: args.result,
+            date_from: args.date_from,
+            date_to: args.date_to,
+            min_ply: args.min_ply,
+        },
+        max_ply: Some(args.max_ply),
+        min_frequency: args.min_frequency,
+    };
+
+    let book = build_book(&inputs, &config).expect("Failed to read PGN input");
+    println!("Loaded book with {} positions.", book.len());
+
+    let format = args.format.unwrap_or(if args.out.ends_with(".json") {
+        BookFormat::Json
     } else {
-        println!("Failed to load book.");
+        BookFormat::Ron
+    });
+    println!("Saving to {}", args.out);
+    match format {
+        BookFormat::Ron => save_book_to_ron(&book, &args.out).expect("Failed to Save ron"),
+        BookFormat::Json => save_book_to_json(&book, &args.out).expect("Failed to Save json"),
+    }
+}
+
+/// Expand glob patterns, plain paths are passed through as is
+fn expand_inputs(inputs: &[String]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        match glob::glob(input) {
+            Ok(matches) => {
+                let before = paths.len();
+                paths.extend(matches.filter_map(Result::ok));
+                if paths.len() == before {
+                    paths.push(PathBuf::from(input));
+                }
+            }
+            Err(_) => paths.push(PathBuf::from(input)),
+        }
     }
+    paths
+}
+
+fn prune(book_path: &str, out_path: &str, report_path: &str, config: &PruneConfig) {
+    if let Err(e) = nnue::nnue::init_big_nnue() {
+        eprintln!("Failed to load NNUE: {}", e);
+        std::process::exit(1);
+    }
+    let mut book = load_from_ron(book_path);
+    println!("Loaded book with {} positions.", book.len());
+
+    let report = prune_book(&mut book, config);
+    println!(
+        "Scored {} moves in {} positions, pruned {}.",
+        report.moves_scored, report.positions, report.pruned.len()
+    );
 
+    report.write_to_file(report_path).expect("Failed to write prune report");
+    println!("Saving to {}", out_path);
+    save_book_to_ron(&book, out_path).expect("Failed to Save ron");
 }
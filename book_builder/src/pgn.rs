@@ -0,0 +1,455 @@
+use book::{add_book_entry, merge_books, Book, BookMove};
+use crate::{filter::{GameFilter, GameResult}, utils};
+use rayon::prelude::*;
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    ops::ControlFlow,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use pgn_reader::{RawTag, Reader, SanPlus, Skip, Visitor};
+
+/// How often to report progress while streaming games
+const PROGRESS_INTERVAL: usize = 10_000;
+
+pub struct PgnEntry {
+    pub white_elo: Option<u32>,
+    pub black_elo: Option<u32>,
+    pub time_control: Option<String>,
+    pub result: Option<GameResult>,
+    pub date: Option<String>,
+    /// The first `max_ply` half moves of the game
+    pub move_sequence: Vec<String>,
+    /// Half moves in the whole game, `move_sequence` may be cut short
+    pub plies: usize,
+}
+
+impl PgnEntry {
+    pub fn min_rating(&self) -> u32 {
+        self.white_elo.unwrap_or(0).min(self.black_elo.unwrap_or(0))
+    }
+}
+
+pub struct PgnVisitor {
+    white_elo: Option<u32>,
+    black_elo: Option<u32>,
+    time_control: Option<String>,
+    result: Option<GameResult>,
+    date: Option<String>,
+    plies: usize,
+    max_depth: Option<usize>,
+}
+
+impl PgnVisitor {
+    pub fn new(max_depth: Option<usize>) -> Self {
+        Self {
+            white_elo: None,
+            black_elo: None,
+            time_control: None,
+            result: None,
+            date: None,
+            plies: 0,
+            max_depth,
+        }
+    }
+}
+
+impl Visitor for PgnVisitor {
+    // Returned from begin_tags()
+    type Tags = ();
+
+    // The per-game mutable movetext state
+    type Movetext = Vec<String>;
+
+    // The output for a completed game
+    type Output = PgnEntry;
+
+    // --- TAGS ------------------------------------------------------
+
+    fn begin_tags(&mut self) -> ControlFlow<Self::Output, Self::Tags> {
+        // Reset for each game
+        self.white_elo = None;
+        self.black_elo = None;
+        self.time_control = None;
+        self.result = None;
+        self.date = None;
+        ControlFlow::Continue(())
+    }
+
+    fn tag(&mut self, _tags: &mut Self::Tags, name: &[u8], value: RawTag<'_>) -> ControlFlow<Self::Output> {
+        let name = std::str::from_utf8(name).unwrap_or("");
+        let value = value.decode_utf8().unwrap_or_default();
+        match name {
+            "WhiteElo" => {
+                if let Ok(v) = value.parse::<u32>() {
+                    self.white_elo = Some(v);
+                }
+            }
+            "BlackElo" => {
+                if let Ok(v) = value.parse::<u32>() {
+                    self.black_elo = Some(v);
+                }
+            }
+            "TimeControl" => self.time_control = Some(value.to_string()),
+            "Result" => self.result = GameResult::from_tag(&value),
+            // Prefer Date, fall back to UTCDate when it is the only one present
+            "Date" => self.date = Some(value.to_string()),
+            "UTCDate" if self.date.is_none() => self.date = Some(value.to_string()),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    // --- MOVETEXT --------------------------------------------------
+
+    fn begin_movetext(
+        &mut self,
+        _tags: Self::Tags,
+    ) -> ControlFlow<Self::Output, Self::Movetext> {
+        // Start a new vector for SAN moves
+        self.plies = 0;
+        ControlFlow::Continue(Vec::new())
+    }
+
+    fn san(
+        &mut self,
+        moves: &mut Self::Movetext,
+        san_plus: SanPlus,
+    ) -> ControlFlow<Self::Output> {
+        self.plies += 1;
+        if let (Some(max_depth), moves_len) = (self.max_depth, moves.len()) {
+            if moves_len >= max_depth {
+                return ControlFlow::Continue(());
+            }
+        }
+        moves.push(san_plus.to_string());
+        ControlFlow::Continue(())
+    }
+
+    fn begin_variation(
+        &mut self,
+        _movetext: &mut Self::Movetext,
+    ) -> ControlFlow<Self::Output, Skip> {
+        // Skip ALL side variations, stay in mainline only
+        ControlFlow::Continue(Skip(true))
+    }
+
+    // --- OUTPUT ----------------------------------------------------
+
+    fn end_game(&mut self, moves: Self::Movetext) -> Self::Output {
+        PgnEntry {
+            white_elo: self.white_elo,
+            black_elo: self.black_elo,
+            time_control: self.time_control.take(),
+            result: self.result,
+            date: self.date.take(),
+            move_sequence: moves,
+            plies: self.plies,
+        }
+    }
+}
+
+impl std::fmt::Display for PgnEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PgnEntry(min_rating: {}, moves: {:?})", self.min_rating(), self.move_sequence)
+    }
+}
+
+/// Lazily reads games one at a time from a PGN stream. A read error is the last item.
+pub struct PgnGames<R: Read> {
+    reader: Reader<R>,
+    visitor: PgnVisitor,
+    failed: bool,
+}
+
+impl<R: Read> PgnGames<R> {
+    pub fn new(source: R, max_depth: Option<usize>) -> Self {
+        Self {
+            reader: Reader::new(source),
+            visitor: PgnVisitor::new(max_depth),
+            failed: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for PgnGames<R> {
+    type Item = io::Result<PgnEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let game = self.reader.read_game(&mut self.visitor).transpose();
+        self.failed = matches!(game, Some(Err(_)));
+        game
+    }
+}
+
+pub struct BuildConfig {
+    pub filter: GameFilter,
+    /// Only the first `max_ply` half moves of each game go into the book
+    pub max_ply: Option<usize>,
+    /// Positions seen fewer times than this are left out of the book
+    pub min_frequency: u32,
+}
+
+/// Stream every game of every file through `config.filter` and build a book
+/// out of the survivors. Games are replayed in parallel. Fails on the first read error.
+pub fn build_book<P: AsRef<Path>>(paths: &[P], config: &BuildConfig) -> io::Result<Book> {
+    let readers = paths
+        .iter()
+        .map(|path| {
+            println!("Reading games from {}", path.as_ref().display());
+            let games = PgnGames::new(BufReader::new(File::open(path)?), config.max_ply);
+            let path = path.as_ref().display().to_string();
+            Ok(games.map(move |game| game.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let games_read = AtomicUsize::new(0);
+    let games_used = AtomicUsize::new(0);
+
+    let mut book = readers
+        .into_iter()
+        .flatten()
+        .inspect(|_| {
+            let read = games_read.fetch_add(1, Ordering::Relaxed) + 1;
+            if read.is_multiple_of(PROGRESS_INTERVAL) {
+                println!("Read {} games, {} used", read, games_used.load(Ordering::Relaxed));
+            }
+        })
+        .filter(|game| game.as_ref().map_or(true, |entry| config.filter.accepts(entry)))
+        .par_bridge()
+        .try_fold(Book::new, |mut book, game| -> io::Result<Book> {
+            if add_game(&mut book, &game?) {
+                games_used.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(book)
+        })
+        .try_reduce(Book::new, |mut a, b| {
+            merge_books(&mut a, b);
+            Ok(a)
+        })?;
+
+    println!(
+        "Read {} games, {} used",
+        games_read.load(Ordering::Relaxed),
+        games_used.load(Ordering::Relaxed)
+    );
+
+    if config.min_frequency > 1 {
+        book.retain(|_, moves| moves.iter().map(|bm| bm.weight).sum::<u32>() >= config.min_frequency);
+    }
+
+    Ok(book)
+}
+
+/// Replay a game from the start position and add each of its moves to `book`.
+/// The game is only added if every move converts and applies cleanly.
+fn add_game(book: &mut Book, entry: &PgnEntry) -> bool {
+    let mut board = pleco::Board::start_pos();
+    let mut book_moves: Vec<(String, String)> = Vec::with_capacity(entry.move_sequence.len());
+
+    for mv in &entry.move_sequence {
+        let uci_move = match utils::san_to_uci(mv, &board.fen()) {
+            Some(uci) => uci,
+            None => return false,
+        };
+        let key = book::position_key(&board);
+
+        if !board.apply_uci_move(&uci_move) {
+            return false;
+        }
+        book_moves.push((key, uci_move));
+    }
+
+    for (fen, mv) in &book_moves {
+        let mut book_move = BookMove::new(mv, 1);
+        match entry.result {
+            Some(GameResult::White) => book_move.white_wins = 1,
+            Some(GameResult::Draw) => book_move.draws = 1,
+            Some(GameResult::Black) => book_move.black_wins = 1,
+            None => {}
+        }
+        add_book_entry(book, fen, book_move);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_san_clock() {
+        let pgn = r#"[Event "CCT Chess.com Classic 2025 | Play-in - Match Play"]
+[Site "?"]
+[Date "2025.05.19"]
+[Round "2"]
+[White "Dubov, Daniil"]
+[Black "Erigaisi Arjun"]
+[Result "0-1"]
+[WhiteElo "2688"]
+[BlackElo "2708"]
+[ECO "E05l"]
+[ImportDate "2025-06-03"]
+[Source "LichessBroadcast"]
+[WhiteTitle "GM"]
+[BlackTitle "GM"]
+[TimeControl "600"]
+[WhiteFideId "24126055"]
+[BlackFideId "35009192"]
+
+1. d4 {[%clk 0:09:59]} 1. ... d5 {[%clk 0:09:58]} 2. c4 {[%clk 0:09:59]} 
+2. ... e6 {[%clk 0:09:57]} 3. Nf3 {[%clk 0:09:58]} 3. ... Nf6 {[%clk
+0:09:56]} 4. g3 {[%clk 0:09:58]} 4. ... Be7 {[%clk 0:09:53]} 5. Bg2 {[%clk
+0:09:56]} 5. ... O-O {[%clk 0:09:53]} 6. O-O {[%clk 0:09:56]} 6. ... dxc4 
+{[%clk 0:09:52]} 7. Qc2 {[%clk 0:09:55]} 7. ... a6 {[%clk 0:09:50]} 8. a4 
+{[%clk 0:09:54]} 8. ... Bd7 {[%clk 0:09:47]} 9. Rd1 {[%clk
+0:09:52]} 9. ... Bc6 {[%clk 0:09:44]} 10. Bg5 {[%clk 0:09:51]} 10. ... 
+Nbd7 {[%clk 0:09:30]} 11. Qxc4 {[%clk 0:09:47]} 11. ... h6 {[%clk
+0:09:25]} 12. Bxf6 {[%clk 0:09:46]} 12. ... Nxf6 {[%clk 0:09:24]} 13. Nc3 
+{[%clk 0:09:44]} 13. ... a5 {[%clk 0:09:22]} 14. Ne5 {[%clk 0:09:43]} 
+14. ... Bxg2 {[%clk 0:09:21]} 15. Kxg2 {[%clk 0:09:43]} 15. ... c6 {[%clk 
+0:09:21]} 16. e3 {[%clk 0:09:42]} 16. ... Qb6 {[%clk 0:09:18]} 17. Qe2 {
+[%clk 0:09:41]} 17. ... Qa6 {[%clk 0:09:13]} 18. Qf3 {[%clk
+0:09:39]} 18. ... Rad8 {[%clk 0:09:02]} 19. h4 {[%clk 0:09:35]} 19. ... 
+Nd5 {[%clk 0:08:52]} 20. Ne4 {[%clk 0:09:23]} 20. ... Qb6 {[%clk
+0:08:39]} 21. Rd2 {[%clk 0:09:06]} 21. ... Qb3 {[%clk 0:08:26]} 22. Nd3 {
+[%clk 0:08:51]} 22. ... Qb6 {[%clk 0:08:16]} 23. Rc1 {[%clk 0:08:39]} 
+23. ... Qb3 {[%clk 0:08:07]} 24. g4 {[%clk 0:08:31]} 24. ... Nf6 {[%clk
+0:05:46]} 25. Nec5 {[%clk 0:07:34]} 25. ... Bxc5 {[%clk 0:05:41]} 26. Nxc5
+{[%clk 0:07:34]} 26. ... Qb4 {[%clk 0:05:40]} 27. Rd3 {[%clk
+0:07:21]} 27. ... Qxb2 {[%clk 0:05:32]} 28. Qd1 {[%clk 0:07:17]} 28. ... 
+Qb6 {[%clk 0:05:14]} 29. g5 {[%clk 0:06:51]} 29. ... Nd5 {[%clk 0:05:06]} 
+30. gxh6 {[%clk 0:06:42]} 30. ... gxh6 {[%clk 0:04:49]} 31. Rb1 {[%clk
+0:06:08]} 31. ... Nb4 {[%clk 0:04:48]} 32. Qh5 {[%clk 0:06:02]} 32. ... 
+Kh7 {[%clk 0:04:23]} 33. e4 {[%clk 0:05:25]} 33. ... Nxd3 {[%clk
+0:04:13]} 0-1
+"#;
+        let mut reader = Reader::new(io::Cursor::new(&pgn));
+        let mut visitor = PgnVisitor::new(None);
+
+        let moves = reader.read_game(&mut visitor).unwrap();
+        assert!(moves.is_some());
+        let entry = moves.unwrap();
+        println!("Entry: {}", entry);
+        assert_eq!(entry.min_rating(), 2688);
+        assert_eq!(entry.result, Some(GameResult::Black));
+        assert_eq!(entry.time_control.as_deref(), Some("600"));
+        assert_eq!(entry.date.as_deref(), Some("2025.05.19"));
+        assert_eq!(entry.move_sequence.len(), 66);
+        assert_eq!(entry.move_sequence[0], "d4");
+        assert_eq!(entry.move_sequence[65], "Nxd3");
+
+    }
+
+    #[test]
+    fn test_san_base() {
+        let pgn = r#"
+[Event "Titled Tue 27. May Late"]
+[Site "chess.com INT"]
+[Date "2025.05.27"]
+[Round "6"]
+[White "Ermolaev, Evgeny"]
+[Black "Chelly, Yahya"]
+[Result "1-0"]
+[WhiteElo "2282"]
+[BlackElo "2039"]
+[ECO "E04b"]
+[EventDate "2025.05.27"]
+[ImportDate "2025-06-03"]
+[Source "TWIC"]
+[WhiteTitle "FM"]
+[BlackTitle "CM"]
+[WhiteFideId "24103195"]
+[BlackFideId "5516200"]
+
+1. d4 d5 2. c4 e6 3. Nf3 Nf6 4. g3 dxc4 5. Bg2 Bb4+ 6. Bd2 a5 7. Qc2 Bxd2+
+8. Qxd2 O-O 9. Ne5 c6 10. Nxc4 b5 11. Ne5 Qb6 12. O-O Rd8 13. e3 Nbd7 14. 
+Nxc6 Bb7 15. Nxd8 Bxg2 16. Kxg2 Rxd8 17. Nc3 b4 18. Ne2 Ne4 19. Qc2 Ndf6 
+20. Rac1 Qb7 21. Qc6 Qe7 22. Qc7 Rd7 23. Qxa5 h5 24. Nf4 g5 25. Nxh5 Nxh5 
+26. Qa8+ Kg7 27. Qxe4 Nf6 28. Qe5 Rd5 29. Qc7 Qf8 30. Qc8 Qd6 31. Rc6 Qe7 
+32. Rfc1 Ne4 33. f3 Nd2 34. R6c2 Nxf3 35. Kxf3 Qf6+ 36. Kg2 g4 37. Rf2 Qh6
+38. Rcf1 f5 39. e4 Rxd4 40. exf5 exf5 41. Qxf5 Qh3+ 42. Kg1 Rd6 43. Qf8+ 
+Kg6 44. Qxd6+ Kg5 45. Qe7+ 1-0"#;
+        let mut reader = Reader::new(io::Cursor::new(&pgn));
+        let mut visitor = PgnVisitor::new(None);
+
+        let moves = reader.read_game(&mut visitor).unwrap();
+        assert!(moves.is_some());
+        let entry = moves.unwrap();
+        assert_eq!(entry.min_rating(), 2039);
+        assert_eq!(entry.move_sequence.len(), 89);
+        assert_eq!(entry.move_sequence[0], "d4");
+        assert_eq!(entry.move_sequence[88], "Qe7+");
+    }
+
+    #[test]
+    fn test_stream_games_to_book() {
+        let pgn = r#"[Event "A"]
+[WhiteElo "2700"]
+[BlackElo "2650"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bb5 1-0
+
+[Event "B"]
+[WhiteElo "2600"]
+[BlackElo "2610"]
+[Result "1/2-1/2"]
+
+1. e4 c5 2. Nf3 d6 1/2-1/2
+
+[Event "C"]
+[WhiteElo "2100"]
+[BlackElo "2200"]
+[Result "0-1"]
+
+1. d4 d5 0-1
+"#;
+        let filter = GameFilter { min_white_elo: 2500, ..Default::default() };
+        let games: Vec<PgnEntry> = PgnGames::new(io::Cursor::new(pgn), Some(3))
+            .map(Result::unwrap)
+            .filter(|entry| filter.accepts(entry))
+            .collect();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].move_sequence, vec!["e4", "e5", "Nf3"]);
+        assert_eq!(games[0].plies, 5);
+        assert_eq!(games[1].result, Some(GameResult::Draw));
+
+        let mut book = Book::new();
+        for game in &games {
+            assert!(add_game(&mut book, game));
+        }
+        let start = book::root_key();
+        let moves = &book[&start];
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].mv, "e2e4");
+        assert_eq!(moves[0].weight, 2);
+        assert_eq!((moves[0].white_wins, moves[0].draws, moves[0].black_wins), (1, 1, 0));
+        assert_eq!(book.len(), 4);
+    }
+
+    /// Fails every read, like a file that can't be read past some point
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk on fire"))
+        }
+    }
+
+    #[test]
+    fn test_read_error_fails_the_build() {
+        let pgn = "[Result \"1-0\"]\n\n1. e4 e5 1-0\n\n";
+        // The stream ends with the error instead of retrying the broken reader forever
+        let games: Vec<io::Result<PgnEntry>> = PgnGames::new(io::Cursor::new(pgn).chain(Broken), None).collect();
+        assert!(games.last().unwrap().is_err());
+
+        // A directory opens but can't be read
+        let config = BuildConfig { filter: GameFilter::default(), max_ply: None, min_frequency: 1 };
+        assert!(build_book(&[std::env::temp_dir()], &config).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use book::{Book, BookMove};
+use engine::{debug::{NoTrace, Tracing}, search::MySearcher};
+use nnue::nnue::NnueEvaluator;
+use pleco::{core::score::MATE, Board};
+use std::{fs::File, io::{self, BufWriter, Write}};
+
+/// Weight divisor applied to moves that fail verification in `PruneMode::DownWeight`
+const DOWN_WEIGHT_DIVISOR: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PruneMode {
+    /// Remove the move from the book entirely
+    Drop,
+    /// Keep the move but make it much less likely to be played
+    DownWeight,
+}
+
+pub struct PruneConfig {
+    /// Max search depth per candidate move
+    pub depth: u8,
+    /// Optional time limit per candidate move, in ms
+    pub time_ms: Option<u128>,
+    /// Max centipawn loss against the best book move before a move is pruned
+    pub threshold: i32,
+    pub mode: PruneMode,
+}
+
+/// A book move that was removed or down-weighted by the pruning pass
+pub struct PrunedLine {
+    pub fen: String,
+    pub mv: String,
+    /// Engine score of the move from the mover's perspective, None if the move is illegal
+    pub score: Option<i32>,
+    pub best_move: String,
+    pub best_score: i32,
+    pub old_weight: u32,
+    /// Zero if the move was dropped
+    pub new_weight: u32,
+}
+
+impl std::fmt::Display for PrunedLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = if self.new_weight == 0 {
+            "dropped".to_string()
+        } else {
+            format!("weight {} -> {}", self.old_weight, self.new_weight)
+        };
+        match self.score {
+            Some(score) => write!(
+                f,
+                "{} | {} {} | best {} {} | loss {} | {}",
+                self.fen, self.mv, score, self.best_move, self.best_score, self.best_score - score, action
+            ),
+            None => write!(f, "{} | {} illegal | {}", self.fen, self.mv, action),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PruneReport {
+    pub positions: usize,
+    pub moves_scored: usize,
+    /// Book keys that could not be parsed back into a board, left untouched
+    pub bad_positions: Vec<String>,
+    pub pruned: Vec<PrunedLine>,
+}
+
+impl PruneReport {
+    pub fn write_to_file(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "# positions: {}, moves scored: {}, pruned: {}",
+            self.positions, self.moves_scored, self.pruned.len())?;
+        for line in &self.pruned {
+            writeln!(out, "{line}")?;
+        }
+        for fen in &self.bad_positions {
+            writeln!(out, "{fen} | unparseable position")?;
+        }
+        out.flush()
+    }
+}
+
+/// Score every candidate move of every book position with the engine and
+/// drop or down-weight those that lose more than `config.threshold` against the best one.
+pub fn prune_book(book: &mut Book, config: &PruneConfig) -> PruneReport {
+    let mut nnue_eval = NnueEvaluator::new();
+    let mut report = PruneReport::default();
+
+    let total = book.len();
+    for (ctr, (fen, moves)) in book.iter_mut().enumerate() {
+        if ctr % 100 == 0 {
+            println!("Verifying position {} / {}", ctr, total);
+        }
+        let board = match Board::from_fen(fen) {
+            Ok(board) => board,
+            Err(_) => {
+                report.bad_positions.push(fen.clone());
+                continue;
+            }
+        };
+        report.positions += 1;
+
+        let scores: Vec<Option<i32>> = moves
+            .iter()
+            .map(|bm| score_book_move(&mut nnue_eval, &board, &bm.mv, config))
+            .collect();
+        report.moves_scored += scores.len();
+
+        report.pruned.extend(apply_scores(fen, moves, &scores, config));
+    }
+    book.retain(|_, moves| !moves.is_empty());
+
+    report
+}
+
+/// Search the position after `mv`, returning the score from the perspective of the side playing it
+fn score_book_move(
+    nnue_eval: &mut NnueEvaluator,
+    board: &Board,
+    mv: &str,
+    config: &PruneConfig,
+) -> Option<i32> {
+    let mut board = board.shallow_clone();
+    if !board.apply_uci_move(mv) {
+        return None;
+    }
+
+    if board.generate_moves().is_empty() {
+        return Some(if board.in_check() { MATE } else { 0 });
+    }
+
+    let mut searcher = MySearcher::new(nnue_eval, NoTrace::new(), config.time_ms);
+    let res = searcher.perform_search(&mut board, config.depth);
+    Some(-(res.score as i32))
+}
+
+/// Prune `moves` in place given their engine scores, returning what was changed.
+/// The best scoring move is always kept and illegal moves are always dropped.
+fn apply_scores(
+    fen: &str,
+    moves: &mut Vec<BookMove>,
+    scores: &[Option<i32>],
+    config: &PruneConfig,
+) -> Vec<PrunedLine> {
+    let (best_move, best_score) = match scores
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| s.map(|s| (i, s)))
+        .max_by_key(|&(_, s)| s)
+    {
+        Some((i, s)) => (moves[i].mv.clone(), s),
+        None => (String::new(), 0),
+    };
+
+    let mut pruned = Vec::new();
+    let mut keep = vec![true; moves.len()];
+
+    for (i, bm) in moves.iter_mut().enumerate() {
+        let new_weight = match scores[i] {
+            None => 0,
+            Some(score) if best_score - score > config.threshold => match config.mode {
+                PruneMode::Drop => 0,
+                PruneMode::DownWeight => (bm.weight / DOWN_WEIGHT_DIVISOR).max(1),
+            },
+            Some(_) => continue,
+        };
+
+        pruned.push(PrunedLine {
+            fen: fen.to_string(),
+            mv: bm.mv.clone(),
+            score: scores[i],
+            best_move: best_move.clone(),
+            best_score,
+            old_weight: bm.weight,
+            new_weight,
+        });
+
+        keep[i] = new_weight > 0;
+        bm.weight = new_weight;
+    }
+
+    let mut keep_iter = keep.into_iter();
+    moves.retain(|_| keep_iter.next().unwrap());
+
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book_moves(moves: &[(&str, u32)]) -> Vec<BookMove> {
+        moves
+            .iter()
+            .map(|&(mv, weight)| BookMove::new(mv, weight))
+            .collect()
+    }
+
+    fn config(mode: PruneMode) -> PruneConfig {
+        PruneConfig { depth: 1, time_ms: None, threshold: 50, mode }
+    }
+
+    #[test]
+    fn test_drop_losing_moves() {
+        let mut moves = book_moves(&[("e2e4", 20), ("g2g4", 3), ("d2d4", 15)]);
+        let scores = [Some(30), Some(-120), Some(10)];
+
+        let pruned = apply_scores("fen", &mut moves, &scores, &config(PruneMode::Drop));
+
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].mv, "g2g4");
+        assert_eq!(pruned[0].best_move, "e2e4");
+        assert_eq!(pruned[0].new_weight, 0);
+        let kept: Vec<&str> = moves.iter().map(|bm| bm.mv.as_str()).collect();
+        assert_eq!(kept, vec!["e2e4", "d2d4"]);
+    }
+
+    #[test]
+    fn test_down_weight_losing_moves() {
+        let mut moves = book_moves(&[("e2e4", 20), ("g2g4", 35), ("a2a3", 4)]);
+        let scores = [Some(30), Some(-120), None];
+
+        let pruned = apply_scores("fen", &mut moves, &scores, &config(PruneMode::DownWeight));
+
+        assert_eq!(pruned.len(), 2);
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0].weight, 20);
+        assert_eq!(moves[1].mv, "g2g4");
+        assert_eq!(moves[1].weight, 3);
+        // Illegal moves are dropped regardless of mode
+        assert!(pruned.iter().any(|l| l.mv == "a2a3" && l.new_weight == 0));
+    }
+}
@@ -1,20 +1,4 @@
-use std::{fs::File, io::{BufRead, BufReader}, str::FromStr};
-
-pub fn file_to_lines(file_path: &str) -> Vec<String> {
-    println!("Reading lines from file: {}", file_path);
-    let file = File::open(file_path);
-    if let Err(e) = file {
-        panic!("Cannot open file {}: {:?}", file_path, e);
-    }
-    let file = file.expect("Failed to convert file to lines");
-    let reader = BufReader::new(file);
-
-    reader.lines()
-        .map(|line| line.expect("Failed to read line").trim().to_string())
-        .filter(|line| !line.is_empty())
-        .collect()
-}
-
+use std::str::FromStr;
 
 pub fn san_to_uci(san: &str, fen: &str) -> Option<String> {
     // println!("Converting SAN '{}' on FEN '{}'", san, fen);
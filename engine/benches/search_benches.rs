@@ -3,7 +3,9 @@ use std::{sync::LazyLock, time::Duration};
 use criterion::{BatchSize, Bencher, Criterion, black_box, criterion_group};
 use engine::{
     debug::{NoTrace, Tracing},
+    search,
     search_wip::MySearcher,
+    tables::eval_cache::DEFAULT_EVAL_CACHE_ENTRIES,
 };
 use pleco::Board;
 
@@ -62,9 +64,61 @@ fn bench_engine_search(c: &mut Criterion) {
     }
 }
 
+/// The current search with and without its evaluation cache
+fn bench_eval_cache(c: &mut Criterion) {
+    for (name, entries) in [("Eval Cache", DEFAULT_EVAL_CACHE_ENTRIES), ("No Eval Cache", 0)] {
+        c.bench_function(&format!("Search Kiwipete Depth 7, {}", name), |b| {
+            b.iter_batched(
+                || Board::from_fen(KIWIPETE).unwrap(),
+                |mut board| {
+                    let mut guard = NNUE_EVAL.lock().unwrap();
+                    let mut searcher = search::MySearcher::new(&mut guard, NoTrace::new(), None)
+                        .with_eval_cache_entries(entries);
+                    black_box(searcher.find_best_move(&mut board, 7))
+                },
+                BatchSize::PerIteration,
+            );
+        });
+    }
+}
+
+fn bench_history_tables(c: &mut Criterion) {
+    for (name, enabled) in [("History Tables", true), ("No History Tables", false)] {
+        c.bench_function(&format!("Search Kiwipete Depth 7, {}", name), |b| {
+            b.iter_batched(
+                || Board::from_fen(KIWIPETE).unwrap(),
+                |mut board| {
+                    let mut guard = NNUE_EVAL.lock().unwrap();
+                    let mut searcher = search::MySearcher::new(&mut guard, NoTrace::new(), None)
+                        .with_history_tables(enabled);
+                    black_box(searcher.find_best_move(&mut board, 7))
+                },
+                BatchSize::PerIteration,
+            );
+        });
+    }
+}
+
+fn bench_see_pruning(c: &mut Criterion) {
+    for (name, enabled) in [("SEE Pruning", true), ("No SEE Pruning", false)] {
+        c.bench_function(&format!("Search Kiwipete Depth 7, {}", name), |b| {
+            b.iter_batched(
+                || Board::from_fen(KIWIPETE).unwrap(),
+                |mut board| {
+                    let mut guard = NNUE_EVAL.lock().unwrap();
+                    let mut searcher = search::MySearcher::new(&mut guard, NoTrace::new(), None)
+                        .with_see_pruning(enabled);
+                    black_box(searcher.find_best_move(&mut board, 7))
+                },
+                BatchSize::PerIteration,
+            );
+        });
+    }
+}
+
 criterion_group!(name = search_benches;
     config = Criterion::default()
        .sample_size(10)
        .warm_up_time(Duration::from_millis(150));
-   targets = bench_engine_search
+   targets = bench_engine_search, bench_eval_cache, bench_history_tables, bench_see_pruning
 );
@@ -2,6 +2,7 @@ pub mod search;
 pub mod search_wip;
 pub mod consts;
 pub mod debug;
+pub mod move_picker;
 
 pub mod tables;
 pub mod evaluation;
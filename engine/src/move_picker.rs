@@ -0,0 +1,406 @@
+//! Staged move generation for the search. Moves come out best first without generating or
+//! sorting more than needed: the TT move before anything is generated, then good captures,
+//! killers and the counter-move, quiets by history and the captures that lose material last.
+//! Moves are generated pseudo-legal and only checked for legality when they are picked.
+
+use pleco::{
+    core::{
+        mono_traits::{BlackType, PlayerTrait, WhiteType},
+        move_list::MAX_MOVES,
+        GenTypes,
+    },
+    BitMove, Board, MoveList, PieceType, Player,
+};
+
+use crate::{
+    consts::{MyVal, MVV_LVA},
+    tables::{
+        butterfly::ButterflyHistory, capture_piece_history::CapturePieceToHistory,
+        continuation::PieceToHistory,
+    },
+};
+
+/// The statistics moves are ordered by, borrowed from the searcher
+pub struct Histories<'a> {
+    pub butterfly: &'a ButterflyHistory,
+    pub capture: Option<&'a CapturePieceToHistory>,
+    /// By the piece and destination of the moves one and two plies ago, `None` at the root,
+    /// after a null move or with the tables turned off
+    pub continuation: [Option<&'a PieceToHistory>; 2],
+}
+
+impl Histories<'_> {
+    /// History of a quiet move, the sum of the butterfly and the continuation histories
+    #[inline(always)]
+    pub fn quiet(&self, board: &Board, mv: BitMove) -> i32 {
+        let piece = board.moved_piece(mv);
+        let to = mv.get_dest();
+        let mut score = self.butterfly[(board.turn(), mv)] as i32;
+        for history in self.continuation.iter().flatten() {
+            score += history[(piece, to)] as i32;
+        }
+        score
+    }
+
+    #[inline(always)]
+    pub fn capture(&self, board: &Board, mv: BitMove) -> i32 {
+        match self.capture {
+            Some(history) if board.is_capture(mv) => {
+                history[(board.moved_piece(mv), mv.get_dest(), board.captured_piece(mv))] as i32
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    TtMove,
+    InitCaptures,
+    GoodCaptures,
+    Refutations,
+    InitQuiets,
+    Quiets,
+    InitQuietChecks,
+    QuietChecks,
+    BadCaptures,
+    InitEvasions,
+    Evasions,
+    Done,
+}
+
+pub struct MovePicker {
+    stage: Stage,
+    tt_move: BitMove,
+    // Killers and the counter-move, in that order
+    refutations: [BitMove; 3],
+    // Only captures and promotions, the tail of the quiescence search
+    quiescence: bool,
+    // With quiescence, the quiet moves giving check too
+    quiet_checks: bool,
+
+    moves: MoveList,
+    scores: [i32; MAX_MOVES],
+    cur: usize,
+    // Captures that lose material by SEE, tried after the quiets
+    bad_captures: MoveList,
+    bad_cur: usize,
+}
+
+impl MovePicker {
+    /// Every legal move of the position
+    pub fn new(board: &Board, tt_move: BitMove, killers: [BitMove; 2], counter_move: BitMove) -> Self {
+        Self::with_stages(board, tt_move, [killers[0], killers[1], counter_move], false, false)
+    }
+
+    /// The legal captures and promotions, then the quiet checks if `quiet_checks`, or every
+    /// legal move when in check
+    pub fn quiescence(board: &Board, tt_move: BitMove, quiet_checks: bool) -> Self {
+        Self::with_stages(board, tt_move, [BitMove::null(); 3], true, quiet_checks)
+    }
+
+    fn with_stages(
+        board: &Board,
+        tt_move: BitMove,
+        refutations: [BitMove; 3],
+        quiescence: bool,
+        quiet_checks: bool,
+    ) -> Self {
+        let tt_usable = !tt_move.is_null()
+            && board.pseudo_legal_move(tt_move)
+            && (board.in_check()
+                || !quiescence
+                || board.is_capture_or_promotion(tt_move)
+                || quiet_checks && board.gives_check(tt_move))
+            && board.legal_move(tt_move);
+
+        Self {
+            stage: if tt_usable { Stage::TtMove } else { first_generation(board) },
+            tt_move: if tt_usable { tt_move } else { BitMove::null() },
+            refutations,
+            quiescence,
+            quiet_checks,
+            moves: MoveList::default(),
+            scores: [0; MAX_MOVES],
+            cur: 0,
+            bad_captures: MoveList::default(),
+            bad_cur: 0,
+        }
+    }
+
+    /// The next legal move, `None` once all have been returned. The board must be in the
+    /// position the picker was created for.
+    pub fn next(&mut self, board: &Board, histories: &Histories) -> Option<BitMove> {
+        loop {
+            match self.stage {
+                Stage::TtMove => {
+                    self.stage = first_generation(board);
+                    return Some(self.tt_move);
+                }
+                Stage::InitCaptures => {
+                    self.generate(board, GenTypes::Captures, |board, mv| {
+                        capture_score(board, mv) + histories.capture(board, mv)
+                    });
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => match self.select_best() {
+                    Some(mv) if mv == self.tt_move || !board.legal_move(mv) => {}
+                    Some(mv) if !board.see_ge(mv, 0) => self.bad_captures.push(mv),
+                    Some(mv) => return Some(mv),
+                    None => {
+                        self.stage = match (self.quiescence, self.quiet_checks) {
+                            (false, _) => Stage::Refutations,
+                            (true, true) => Stage::InitQuietChecks,
+                            (true, false) => Stage::BadCaptures,
+                        };
+                        self.cur = 0;
+                    }
+                },
+                Stage::Refutations => {
+                    let Some(&mv) = self.refutations.get(self.cur) else {
+                        self.stage = Stage::InitQuiets;
+                        continue;
+                    };
+                    self.cur += 1;
+                    let repeated = self.refutations[..self.cur - 1].contains(&mv);
+                    if !mv.is_null()
+                        && mv != self.tt_move
+                        && !repeated
+                        && !mv.is_capture()
+                        && !mv.is_promo()
+                        && board.pseudo_legal_move(mv)
+                        && board.legal_move(mv)
+                    {
+                        return Some(mv);
+                    }
+                }
+                Stage::InitQuiets => {
+                    self.generate(board, GenTypes::Quiets, |board, mv| histories.quiet(board, mv));
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => match self.select_best() {
+                    Some(mv)
+                        if mv == self.tt_move || self.refutations.contains(&mv) || !board.legal_move(mv) => {}
+                    Some(mv) => return Some(mv),
+                    None => self.stage = Stage::BadCaptures,
+                },
+                Stage::InitQuietChecks => {
+                    self.generate(board, GenTypes::QuietChecks, |board, mv| histories.quiet(board, mv));
+                    self.stage = Stage::QuietChecks;
+                }
+                // pleco's generation has false positives, and promotions were already picked
+                Stage::QuietChecks => match self.select_best() {
+                    Some(mv)
+                        if mv == self.tt_move
+                            || mv.is_promo()
+                            || !board.gives_check(mv)
+                            || !board.legal_move(mv) => {}
+                    Some(mv) => return Some(mv),
+                    None => self.stage = Stage::BadCaptures,
+                },
+                Stage::BadCaptures => {
+                    // Already in order, they were set aside while picking the good ones
+                    let Some(&mv) = self.bad_captures.get(self.bad_cur) else {
+                        self.stage = Stage::Done;
+                        continue;
+                    };
+                    self.bad_cur += 1;
+                    return Some(mv);
+                }
+                Stage::InitEvasions => {
+                    self.generate(board, GenTypes::Evasions, |board, mv| {
+                        if board.is_capture_or_promotion(mv) {
+                            1_000_000 + capture_score(board, mv) + histories.capture(board, mv)
+                        } else {
+                            histories.quiet(board, mv)
+                        }
+                    });
+                    self.stage = Stage::Evasions;
+                }
+                Stage::Evasions => match self.select_best() {
+                    Some(mv) if mv == self.tt_move || !board.legal_move(mv) => {}
+                    Some(mv) => return Some(mv),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    fn generate(&mut self, board: &Board, gen_type: GenTypes, score: impl Fn(&Board, BitMove) -> i32) {
+        self.moves = board.generate_pseudolegal_moves_of_type(gen_type);
+        for (i, &mv) in self.moves.iter().enumerate() {
+            self.scores[i] = score(board, mv);
+        }
+        self.cur = 0;
+    }
+
+    /// Selection sort one step at a time, most nodes only look at a few moves
+    fn select_best(&mut self) -> Option<BitMove> {
+        let best = (self.cur..self.moves.len()).max_by_key(|&i| self.scores[i])?;
+        self.moves.swap(self.cur, best);
+        self.scores.swap(self.cur, best);
+        self.cur += 1;
+        Some(self.moves[self.cur - 1])
+    }
+}
+
+fn first_generation(board: &Board) -> Stage {
+    if board.in_check() {
+        Stage::InitEvasions
+    } else {
+        Stage::InitCaptures
+    }
+}
+
+/// Whether the side to move has a legal move, only checking moves up to the first legal one
+pub fn has_legal_move(board: &Board) -> bool {
+    board.generate_pseudolegal_moves().iter().any(|&mv| board.legal_move(mv))
+}
+
+/// Captures by MVV-LVA ahead of quiet promotions, higher is better
+fn capture_score(board: &Board, mv: BitMove) -> i32 {
+    if board.is_capture(mv) {
+        let mut score = 500_000 - get_capture_score(board, &mv) as i32 * 100;
+        if mv.is_promo() {
+            // Capture-promotion is even more forcing.
+            score += 50_000;
+        }
+        score
+    } else {
+        match mv.promo_piece() {
+            PieceType::Q => 400_000,
+            PieceType::R => 350_000,
+            PieceType::B => 325_000,
+            PieceType::N => 320_000,
+            _ => 300_000,
+        }
+    }
+}
+
+pub fn get_capture_score(board: &Board, mv: &BitMove) -> MyVal {
+    let attacker = board.piece_at_sq(mv.get_src());
+    let dest = if mv.is_en_passant() {
+        if board.turn() == Player::White {
+            WhiteType::down(mv.get_dest())
+        } else {
+            BlackType::down(mv.get_dest())
+        }
+    } else {
+        mv.get_dest()
+    };
+    let captured = board.piece_at_sq(dest);
+    if (!attacker.type_of().is_real()) || (!captured.type_of().is_real()) {
+        println!(
+            "Error in get_capture_score: attacker = {:?}, captured = {:?}",
+            attacker, captured
+        );
+        println!("Attack as usize = {}", attacker.type_of() as usize - 1);
+        println!("Captured as usize = {}", captured.type_of() as usize - 1);
+        panic!("THE ERROR");
+    }
+
+    MVV_LVA[attacker.type_of() as usize - 1][captured.type_of() as usize - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use pleco::{Piece, SQ};
+
+    use super::*;
+    use crate::tables::StatBoard;
+
+    const FENS: [&str; 6] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        // In check, pinned pieces and promotions
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "1k1rr3/pp3p1Q/5q2/P7/4n1B1/1P1p3P/3P1PP1/1R3K1R w - - 2 25",
+        // En passant out of check
+        "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+    ];
+
+    fn picked(board: &Board, mut picker: MovePicker, histories: &Histories) -> Vec<BitMove> {
+        let mut moves = Vec::new();
+        while let Some(mv) = picker.next(board, histories) {
+            moves.push(mv);
+        }
+        moves
+    }
+
+    fn sorted(mut moves: Vec<BitMove>) -> Vec<BitMove> {
+        moves.sort_by_key(|mv| mv.get_raw());
+        moves
+    }
+
+    #[test]
+    fn test_picks_each_legal_move_once() {
+        let mut butterfly = ButterflyHistory::new();
+        butterfly[(Player::White, BitMove::make_pawn_push(SQ::E2, SQ::E4))] = 500;
+        let history = Histories { butterfly: &butterfly, capture: None, continuation: [None; 2] };
+        for fen in FENS {
+            let board = Board::from_fen(fen).unwrap();
+            let legal = board.generate_moves().vec();
+            let tt_move = *legal.last().unwrap();
+            // Refutations that are legal, one repeated, and one that is not
+            let quiet = legal.iter().copied().find(|mv| !board.is_capture_or_promotion(*mv)).unwrap();
+            let killers = [quiet, BitMove::make_quiet(SQ::A1, SQ::H8)];
+
+            let moves = picked(&board, MovePicker::new(&board, tt_move, killers, quiet), &history);
+            assert_eq!(moves[0], tt_move, "{}", fen);
+            assert_eq!(sorted(moves), sorted(legal.clone()), "{}", fen);
+
+            let moves = picked(&board, MovePicker::new(&board, BitMove::null(), [BitMove::null(); 2], BitMove::null()), &history);
+            assert_eq!(sorted(moves), sorted(legal.clone()), "{}", fen);
+
+            let expected = if board.in_check() {
+                board.generate_moves()
+            } else {
+                board.generate_moves_of_type(GenTypes::Captures)
+            };
+            let moves = picked(&board, MovePicker::quiescence(&board, quiet, false), &history);
+            assert_eq!(sorted(moves), sorted(expected.vec()), "{}", fen);
+
+            let expected: Vec<BitMove> = if board.in_check() {
+                board.generate_moves().vec()
+            } else {
+                legal.iter().copied().filter(|&mv| board.is_capture_or_promotion(mv) || board.gives_check(mv)).collect()
+            };
+            let moves = picked(&board, MovePicker::quiescence(&board, quiet, true), &history);
+            assert_eq!(sorted(moves), sorted(expected), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_pick_order() {
+        // Kiwipete: the good captures, then the killer, the quiets by history, the bad captures
+        let board = Board::from_fen(FENS[1]).unwrap();
+        let by_name = |name: &str| board.generate_moves().iter().copied().find(|mv| mv.stringify() == name).unwrap();
+        let killer = by_name("a2a3");
+        let favourite = by_name("g2g3");
+        let second = by_name("e1d1");
+        let mut butterfly = ButterflyHistory::new();
+        butterfly[(Player::White, second)] = 500;
+        butterfly[(Player::White, favourite)] = -300;
+        // As a reply to the last move the pawn push is better
+        let mut continuation = PieceToHistory::new();
+        continuation[(Piece::WhitePawn, SQ::G3)] = 1000;
+        let history = Histories { butterfly: &butterfly, capture: None, continuation: [Some(&continuation), None] };
+
+        let moves = picked(&board, MovePicker::new(&board, BitMove::null(), [killer, BitMove::null()], BitMove::null()), &history);
+        let captures = moves.iter().take_while(|mv| board.is_capture(**mv)).count();
+        assert!(captures > 0);
+        assert!(moves[..captures].iter().all(|&mv| board.see_ge(mv, 0)));
+        assert_eq!(moves[captures], killer);
+        assert_eq!(moves[captures + 1], favourite);
+        assert_eq!(moves[captures + 2], second);
+        let bad = moves.iter().rev().take_while(|mv| board.is_capture(**mv)).count();
+        assert!(bad > 0);
+        assert!(moves[moves.len() - bad..].iter().all(|&mv| !board.see_ge(mv, 0)));
+        // MVV-LVA among the good captures
+        assert!(moves[..captures].windows(2).all(|w| capture_score(&board, w[0]) >= capture_score(&board, w[1])));
+        assert!(!has_legal_move(&Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap()));
+        assert!(has_legal_move(&board));
+    }
+}
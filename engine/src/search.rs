@@ -2,21 +2,25 @@ use std::time::{Duration, Instant};
 
 use nnue::nnue::NnueEvaluator;
 use pleco::{
-    core::{
-        mono_traits::{BlackType, PlayerTrait, WhiteType},
-        score::{DRAW, INFINITE, MATE, NEG_INFINITE},
-        GenTypes,
-    },
+    core::score::{DRAW, INFINITE, MATE, NEG_INFINITE},
     tools::{
         tt::{Entry, NodeBound, TranspositionTable},
         PreFetchable,
     },
-    BitMove, Board, PieceType, Player, ScoringMove,
+    BitMove, Board, MoveList, Piece, PieceType, Player, Rank, ScoringMove, SQ,
 };
 
 use crate::{
-    consts::MVV_LVA,
     debug::NoTrace,
+    move_picker::{has_legal_move, Histories, MovePicker},
+    tables::{
+        butterfly::ButterflyHistory,
+        capture_piece_history::CapturePieceToHistory,
+        continuation::ContinuationHistory,
+        counter_move::CounterMoveHistory,
+        eval_cache::{EvalCache, EvalCacheStats, DEFAULT_EVAL_CACHE_ENTRIES},
+        NumStatCube, StatBoard,
+    },
 };
 
 use super::{
@@ -39,13 +43,19 @@ const NULL_BIT_MOVE: BitMove = BitMove::null();
 
 // const TT_ENTRIES: usize = 170_000; // Enough to use 16 MB of memory
 const TT_ENTRIES: usize = 500_000;
-pub const MAX_PLY: usize = 31;
-const NUM_SQUARES: usize = 64;
+pub const MAX_PLY: usize = 128;
+// Deepest iteration, so that ply and depth stay in range of the tables and of i8
+const MAX_DEPTH: u8 = (MAX_PLY - 1) as u8;
 
 // Mild LMR parameters.
 const LMR_MIN_DEPTH: i8 = 3;
 // const LMR_MIN_MOVE_INDEX: i32 = 5;
 const LMR_MIN_MOVE_INDEX: i32 = 6;
+// Quiet history worth one ply less or more of reduction
+const LMR_HISTORY_DIVISOR: i32 = 8192;
+
+// Largest history bonus, the depth at which it is reached is about 17
+const MAX_HISTORY_BONUS: i32 = 324;
 
 // Null-move parameters.
 const NULL_MOVE_MIN_DEPTH: i8 = 3;
@@ -55,7 +65,25 @@ const NULL_MOVE_REDUCTION_BASE: i8 = 2;
 const FUTILITY_MAX_DEPTH: i8 = 2; // only at depth 1..2
 const FUTILITY_BASE_MARGIN: MyVal = 100; // ~1 pawn per depth unit
 
-// Searcher with TT, history, killers.
+// Singular extension parameters: the TT move is extended when every other move fails low
+// against its score less a margin, in a search of half the depth.
+const SINGULAR_MIN_DEPTH: i8 = 6;
+const SINGULAR_TT_DEPTH_SLACK: i8 = 3;
+const SINGULAR_MARGIN: MyVal = 2; // per depth unit
+
+// Quiescence depths the TT stores: the first ply, which searches quiet checks too, and the
+// rest, which only search captures. Positions in check search every evasion and count as the
+// first ply.
+const DEPTH_QS_CHECKS: i8 = 0;
+const DEPTH_QS_NO_CHECKS: i8 = -1;
+
+// SEE pruning: at low depth, skip moves losing more than a depth-scaled amount of material.
+// Thresholds are in pleco's SEE units, where a pawn is worth 171.
+const SEE_PRUNING_MAX_DEPTH: i8 = 6;
+const SEE_CAPTURE_MARGIN: i32 = 180; // ~1 pawn per depth unit
+const SEE_QUIET_MARGIN: i32 = 30; // per depth unit squared
+
+// Searcher with TT, history, killers, counter-moves.
 pub struct MySearcher<'a, T: Tracing<SearchDebugger>> {
     nnue_eval: &'a mut NnueEvaluator,
     start_time: Instant,
@@ -63,6 +91,9 @@ pub struct MySearcher<'a, T: Tracing<SearchDebugger>> {
 
     tracer: T,
     nodes_explored: i64,
+    // Nodes of the whole search, including quiescence, and where to stop
+    nodes: u64,
+    node_limit: Option<u64>,
 
     // PV moves (for root / debug; not a full PV table)
     pv_moves: [ScoringMove; MAX_PLY],
@@ -70,10 +101,30 @@ pub struct MySearcher<'a, T: Tracing<SearchDebugger>> {
     // Killer moves: two per ply
     killer_moves: [[BitMove; 2]; MAX_PLY],
     // History heuristic: [side][from][to]
-    history: [[[i32; NUM_SQUARES]; NUM_SQUARES]; 2],
+    history: ButterflyHistory,
+    // Counter-move heuristic: [piece][to] of the previous move
+    counter_moves: CounterMoveHistory,
+    // Quiet history as a reply to the moves one and two plies earlier, by their [piece][to]
+    continuation: Box<ContinuationHistory>,
+    // Capture history: [piece][to][captured piece type]
+    capture_history: CapturePieceToHistory,
+    // Whether ordering and LMR use the continuation and capture histories
+    use_history_tables: bool,
+    // Piece and destination of the move made at each ply, `None` for null moves
+    played: [Option<(Piece, SQ)>; MAX_PLY],
+
+    extensions: Extensions,
+    // Whether quiescence searches quiet checks on its first ply
+    quiet_checks: bool,
+    // Whether losing moves are pruned by SEE at low depth and in quiescence
+    see_pruning: bool,
+    // Depth of the current iteration, extensions stop at twice as many plies
+    root_depth: i8,
 
     // Transposition table
     tt: TranspositionTable,
+    // Scaled NNUE evaluations by position
+    eval_cache: EvalCache,
 
     // Last root best move (for aspiration + PV ordering)
     last_root_move: BitMove,
@@ -81,6 +132,42 @@ pub struct MySearcher<'a, T: Tracing<SearchDebugger>> {
 
 pub const NULL_SCORE: ScoringMove = ScoringMove::null();
 
+/// Which search extensions are on, all of them by default. A move is extended by one ply at
+/// most, whichever of them apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Extensions {
+    /// Moves giving check that don't lose material
+    pub check: bool,
+    /// The TT move, when a reduced search shows every other move is clearly worse
+    pub singular: bool,
+    /// Captures on the square of the last capture that don't lose material, in the PV
+    pub recapture: bool,
+    /// Pushes of a passed pawn to the sixth rank or further
+    pub passed_pawn_push: bool,
+}
+
+impl Extensions {
+    pub const ALL: Extensions = Extensions {
+        check: true,
+        singular: true,
+        recapture: true,
+        passed_pawn_push: true,
+    };
+
+    pub const NONE: Extensions = Extensions {
+        check: false,
+        singular: false,
+        recapture: false,
+        passed_pawn_push: false,
+    };
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Extensions::ALL
+    }
+}
+
 pub fn search_to_depth_and_time(
     nnue_eval: &mut NnueEvaluator,
     board: &mut Board,
@@ -112,12 +199,25 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
 
             tracer,
             nodes_explored: 0,
+            nodes: 0,
+            node_limit: None,
             pv_moves: [NULL_SCORE; MAX_PLY],
 
             killer_moves: [[NULL_BIT_MOVE; 2]; MAX_PLY],
-            history: [[[0; NUM_SQUARES]; NUM_SQUARES]; 2],
+            history: ButterflyHistory::new(),
+            counter_moves: CounterMoveHistory::new(),
+            continuation: ContinuationHistory::new_boxed(),
+            capture_history: CapturePieceToHistory::new(),
+            use_history_tables: true,
+            played: [None; MAX_PLY],
+
+            extensions: Extensions::ALL,
+            quiet_checks: false,
+            see_pruning: true,
+            root_depth: 0,
 
             tt: TranspositionTable::new_num_entries(TT_ENTRIES),
+            eval_cache: EvalCache::new(DEFAULT_EVAL_CACHE_ENTRIES),
             last_root_move: NULL_BIT_MOVE,
         }
     }
@@ -130,20 +230,91 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
 
             tracer,
             nodes_explored: 0,
+            nodes: 0,
+            node_limit: None,
             pv_moves: [NULL_SCORE; MAX_PLY],
 
             killer_moves: [[NULL_BIT_MOVE; 2]; MAX_PLY],
-            history: [[[0; NUM_SQUARES]; NUM_SQUARES]; 2],
+            history: ButterflyHistory::new(),
+            counter_moves: CounterMoveHistory::new(),
+            continuation: ContinuationHistory::new_boxed(),
+            capture_history: CapturePieceToHistory::new(),
+            use_history_tables: true,
+            played: [None; MAX_PLY],
+
+            extensions: Extensions::ALL,
+            quiet_checks: false,
+            see_pruning: true,
+            root_depth: 0,
 
             tt: TranspositionTable::new_num_entries(TT_ENTRIES),
+            eval_cache: EvalCache::new(DEFAULT_EVAL_CACHE_ENTRIES),
             last_root_move: NULL_BIT_MOVE,
         }
     }
 
+    /// Stop after about `nodes` nodes instead of at a time limit. The first iteration always
+    /// completes, so there is a move to play.
+    pub fn with_node_limit(mut self, nodes: u64) -> Self {
+        self.node_limit = Some(nodes);
+        self
+    }
+
+    /// Nodes searched by the last search
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /// Cache NNUE evaluations in a table of `entries` entries, rounded down to a power of 2,
+    /// instead of the default one. 0 turns the cache off.
+    pub fn with_eval_cache_entries(mut self, entries: usize) -> Self {
+        self.eval_cache = EvalCache::new(entries);
+        self
+    }
+
+    /// Order and reduce moves with the continuation and capture histories too, on by default.
+    /// Without them there are still the butterfly history, killers and counter-moves.
+    pub fn with_history_tables(mut self, enabled: bool) -> Self {
+        self.use_history_tables = enabled;
+        self
+    }
+
+    /// Turn search extensions on or off, all of them are on by default
+    pub fn with_extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Search the quiet moves giving check on the first ply of quiescence too, off by default.
+    /// They see some mates a ply earlier at the price of a bigger tree.
+    pub fn with_quiet_checks(mut self, enabled: bool) -> Self {
+        self.quiet_checks = enabled;
+        self
+    }
+
+    /// Skip the moves losing material by SEE at low depth and in quiescence, on by default
+    pub fn with_see_pruning(mut self, enabled: bool) -> Self {
+        self.see_pruning = enabled;
+        self
+    }
+
+    /// How often the last search found its evaluations in the cache
+    pub fn eval_cache_stats(&self) -> EvalCacheStats {
+        self.eval_cache.stats()
+    }
+
     // #[inline(always)]
     pub fn eval(&mut self, board: &Board) -> MyVal {
-        let eval = self.nnue_eval.evaluate(board);
-        let scaled = eval.scaled_total();
+        // The network only sees the pieces, the rule 50 scaling below is applied on every call
+        let key = board.zobrist();
+        let scaled = match self.eval_cache.probe(key) {
+            Some(scaled) => scaled,
+            None => {
+                let scaled = self.nnue_eval.evaluate(board).scaled_total();
+                self.eval_cache.store(key, scaled);
+                scaled
+            }
+        };
         let pawn_count = board.count_piece(Player::Black, PieceType::P)
             + board.count_piece(Player::White, PieceType::P);
         let pawn_score = 535 * pawn_count as i32;
@@ -166,6 +337,11 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
 
     #[inline(always)]
     pub fn time_up(&self) -> bool {
+        if let Some(limit) = self.node_limit {
+            if self.nodes >= limit && !self.last_root_move.is_null() {
+                return true;
+            }
+        }
         if let Some(limit) = self.time_limit_ms {
             self.start_time.elapsed().as_millis() > limit
         } else {
@@ -188,10 +364,16 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
     pub fn perform_search(&mut self, board: &mut Board, max_ply: u8) -> ScoringMove {
         self.start_time = Instant::now();
         self.nodes_explored = 0;
+        self.nodes = 0;
         self.pv_moves = [NULL_SCORE; MAX_PLY];
         self.killer_moves = [[NULL_BIT_MOVE; 2]; MAX_PLY];
-        self.history = [[[0; NUM_SQUARES]; NUM_SQUARES]; 2];
+        self.history.clear();
+        self.counter_moves.clear();
+        self.continuation.clear();
+        self.capture_history.clear();
+        self.played = [None; MAX_PLY];
         self.tt.new_search();
+        self.eval_cache.reset_stats();
         self.last_root_move = NULL_BIT_MOVE;
 
         self.nnue_eval.reset(board);
@@ -204,7 +386,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         let mut reached_depth: u8 = 1;
         let mut aspiration_cntr: u8 = 0;
 
-        'iterative: for depth in 1..=max_ply {
+        'iterative: for depth in 1..=max_ply.min(MAX_DEPTH) {
             if self.time_up() {
                 break 'iterative;
             }
@@ -222,6 +404,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
 
             let mut fail_count = 0;
             let root_pv_move = self.last_root_move;
+            self.root_depth = depth as i8;
 
             'aspiration: loop {
                 let res = self.alpha_beta(
@@ -232,6 +415,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                     0,
                     false, // allow_null = false at root
                     root_pv_move,
+                    NULL_BIT_MOVE,
                 );
 
                 if self.time_up() {
@@ -250,7 +434,9 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                 }
 
                 if best_move.score >= MATE_V - max_ply as MyVal {
-                    println!("Mate found at depth = {depth}");
+                    if self.tracer.trace().is_some() {
+                        println!("Mate found at depth = {depth}");
+                    }
                     break 'iterative;
                 }
 
@@ -315,8 +501,10 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
             println!("{dbg}");
             let trace = self.nnue_eval.trace_eval(board);
             trace.print(board);
+            self.nnue_eval.piece_values(board).print(board);
             println!("AB Eval = {}", best_move.score);
             println!("Window Attempts = {}", aspiration_cntr);
+            println!("Eval Cache = {}", self.eval_cache.stats());
             println!("Reached Depth = {reached_depth}");
             if best_move.score >= MATE_V - max_ply as MyVal {
                 println!("Mate Found At Depth = {reached_depth}");
@@ -335,6 +523,10 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
     /// - mild LMR
     /// - null-move pruning
     /// - mild forward futility pruning
+    /// - check, singular, recapture and passed pawn push extensions
+    ///
+    /// `excluded` is left out of the search, to verify whether the TT move is singular.
+    #[allow(clippy::too_many_arguments)]
     fn alpha_beta(
         &mut self,
         board: &mut Board,
@@ -344,6 +536,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         ply: u8,
         allow_null: bool,
         root_pv_move: BitMove,
+        excluded: BitMove,
     ) -> ScoringMove {
         if self.time_up() {
             // Fail-soft: current alpha as best-known.
@@ -351,6 +544,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         }
 
         self.nodes_explored += 1;
+        self.nodes += 1;
 
         if (ply as usize) >= MAX_PLY - 1 {
             depth = 0;
@@ -382,30 +576,26 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
 
         let mut static_eval = self.eval(board);
 
-        // Generate moves early so we can detect checkmate/stalemate; we may still cut off via null move.
-        let mut moves = board.generate_moves();
-
-        if moves.is_empty() {
-            if in_check {
-                return ScoringMove::blank(mated_in(ply));
-            } else {
-                return ScoringMove::blank(DRAW_V);
-            }
-        }
-
         let alpha_orig = alpha;
-        let zobrist = board.zobrist();
+        // A search without a move is a different search, and gets its own entry
+        let zobrist = if excluded.is_null() {
+            board.zobrist()
+        } else {
+            board.zobrist() ^ (excluded.get_raw() as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        };
 
         // ---- TT PROBE BLOCK ----
         let mut tt_move = NULL_BIT_MOVE;
+        let mut tt_hit = None;
         {
             let (hit, entry): (bool, &mut Entry) = self.tt.probe(zobrist);
 
             if hit {
                 tt_move = entry.best_move;
-                let tt_val = entry.score;
+                let tt_val = value_from_tt(entry.score, ply);
                 let tt_depth = entry.depth as i8;
                 let tt_bound = entry.node_type();
+                tt_hit = Some((tt_val, tt_depth, tt_bound));
 
                 if let Some(cut_score) =
                     tt_maybe_cutoff(tt_val, tt_depth, depth, alpha, beta, tt_bound)
@@ -413,7 +603,9 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                     return ScoringMove::new_score(entry.best_move, cut_score);
                 }
 
-                if tt_can_improve_static(tt_val, static_eval, tt_bound) {
+                // Quiescence entries only searched captures, they don't refine the eval the
+                // pruning below relies on
+                if tt_depth > 0 && tt_can_improve_static(tt_val, static_eval, tt_bound) {
                     static_eval = tt_val;
                 }
             }
@@ -427,9 +619,15 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         // - low depth (<= FUTILITY_MAX_DEPTH)
         // - eval is clearly below alpha even after adding a small margin
         // - not a known near-mate score
-        if !in_check && depth <= FUTILITY_MAX_DEPTH && static_eval.abs() < MATE_V - 256 {
+        // - there is a move, qsearch would miss a stalemate
+        // - no move is excluded, qsearch would search it
+        if !in_check
+            && depth <= FUTILITY_MAX_DEPTH
+            && static_eval.abs() < MATE_V - 256
+            && excluded.is_null()
+        {
             let margin = futility_margin(depth);
-            if static_eval + margin <= alpha {
+            if static_eval + margin <= alpha && has_legal_move(board) {
                 // Go to qsearch instead of full tree; preserves tactics.
                 let q = self.quiescence_search(board, alpha, beta, ply, 0);
                 return ScoringMove::blank(q);
@@ -445,16 +643,20 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         // - depth reasonably high
         // - static_eval already good enough to fail high
         // - side to move has some non-pawn material
+        // - side to move is not stalemated
+        // - no move is excluded
         if allow_null
+            && excluded.is_null()
             && depth >= NULL_MOVE_MIN_DEPTH
             && !in_check
             && static_eval >= beta
             && static_eval.abs() < MATE_V - 256
         {
             let npm = board.non_pawn_material(board.turn());
-            if npm > 0 {
+            if npm > 0 && has_legal_move(board) {
                 //TODO: Try 3
                 let r = NULL_MOVE_REDUCTION_BASE + depth / 4; // small reduction scaling with depth
+                self.played[ply as usize] = None;
                 unsafe {
                     board.apply_null_move();
                 }
@@ -466,6 +668,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                     ply + 1,
                     false, // do not allow nested null
                     NULL_BIT_MOVE,
+                    NULL_BIT_MOVE,
                 );
                 unsafe {
                     board.undo_null_move();
@@ -479,74 +682,145 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         }
         // ------------------------------------------------
 
-        let side_idx = match board.turn() {
-            Player::White => 0,
-            Player::Black => 1,
-        };
-
         let killers = if (ply as usize) < MAX_PLY {
             self.killer_moves[ply as usize]
         } else {
             [NULL_BIT_MOVE; 2]
         };
 
-        let root_hint = if ply == 0 {
-            root_pv_move
-        } else {
-            NULL_BIT_MOVE
-        };
+        // At the root the best move of the last iteration goes first
+        if ply == 0 && !root_pv_move.is_null() {
+            tt_move = root_pv_move;
+        }
 
-        moves.sort_unstable_by_key(|mv| {
-            score_move(
-                board,
-                *mv,
-                ply,
-                side_idx,
-                root_hint,
-                tt_move,
-                &killers,
-                &self.history,
-            )
-        });
+        // -------- Singular extension --------
+        //
+        // Only when:
+        // - not at the root, and not already verifying a move
+        // - depth reasonably high
+        // - the TT move is legal and its score is a lower bound from almost as deep a search
+        // - not a known near-mate score
+        // Searching the other moves against a bound below the TT score, the TT move is
+        // singular when they all fail low. When they fail high over beta, several moves beat
+        // beta and the node is cut.
+        let mut singular = false;
+        if let Some((tt_val, tt_depth, tt_bound)) = tt_hit {
+            if self.extensions.singular
+                && ply > 0
+                && excluded.is_null()
+                && depth >= SINGULAR_MIN_DEPTH
+                && tt_depth >= depth - SINGULAR_TT_DEPTH_SLACK
+                && !matches!(tt_bound, NodeBound::UpperBound | NodeBound::NoBound)
+                && tt_val.abs() < MATE_V - 256
+                && !tt_move.is_null()
+                && board.pseudo_legal_move(tt_move)
+                && board.legal_move(tt_move)
+            {
+                let singular_beta = tt_val - SINGULAR_MARGIN * depth as MyVal;
+                let score = self
+                    .alpha_beta(
+                        board,
+                        singular_beta - 1,
+                        singular_beta,
+                        (depth - 1) / 2,
+                        ply,
+                        false,
+                        NULL_BIT_MOVE,
+                        tt_move,
+                    )
+                    .score;
+                if score < singular_beta {
+                    singular = true;
+                } else if singular_beta >= beta {
+                    return ScoringMove::blank(singular_beta);
+                }
+            }
+        }
+        // ------------------------------------------------
+
+        let previous = self.earlier_move(ply, 1);
+        let counter_move = previous.map_or(NULL_BIT_MOVE, |prev| self.counter_moves[prev]);
+        let mut picker = MovePicker::new(board, tt_move, killers, counter_move);
 
         let mut best_move = NULL_BIT_MOVE;
         let mut best_score = NEG_INF_V;
         let mut legal_moves = 0;
         let mut first_move = true;
+        // Searched without a cutoff, their histories are lowered when a later move cuts off
+        let mut quiets_tried = MoveList::default();
+        let mut captures_tried = MoveList::default();
 
-        for (idx, mv) in moves.into_iter().enumerate() {
+        while let Some(mv) = picker.next(board, &self.histories(ply)) {
             if self.time_up() {
                 break;
             }
+            if mv == excluded {
+                continue;
+            }
 
-            let move_index = idx as i32 + 1;
+            let move_index = legal_moves + 1;
 
             let is_capture_or_promo = board.is_capture_or_promotion(mv);
             let gives_check = board.gives_check(mv);
 
-            self.tt.prefetch(board.key_after(mv));
+            // -------- SEE pruning --------
+            //
+            // Only when:
+            // - turned on, see `with_see_pruning`
+            // - not at the root and not in check
+            // - a move was already searched, so the node can't look like a stalemate
+            // - low depth (<= SEE_PRUNING_MAX_DEPTH)
+            // - the move doesn't give check
+            // - the move loses more material than the threshold of its kind
+            if self.see_pruning
+                && ply > 0
+                && !in_check
+                && !first_move
+                && !gives_check
+                && depth <= SEE_PRUNING_MAX_DEPTH
+                && best_score > -(MATE_V - 256)
+                && !board.see_ge(mv, see_pruning_threshold(depth, is_capture_or_promo))
+            {
+                continue;
+            }
+            // ------------------------------------------------
 
-            self.nnue_eval.do_move(&board, mv);
-            board.apply_move(mv);
+            let extension = if (ply as i32) < 2 * self.root_depth as i32 {
+                let pv_node = beta as i32 - alpha_orig as i32 > 1;
+                self.extension(board, mv, gives_check, singular && mv == tt_move, pv_node)
+            } else {
+                0
+            };
+            let full_depth = depth - 1 + extension;
 
-            // Mild LMR on quiet, non-check, non-first moves.
-            let mut new_depth = depth - 1;
+            // Mild LMR on quiet, non-check, non-extended, non-first moves.
+            let mut new_depth = full_depth;
             let mut reduced = false;
             if !in_check
                 && !is_capture_or_promo
                 && !gives_check
+                && extension == 0
                 && !first_move
                 && depth >= LMR_MIN_DEPTH
                 && move_index >= LMR_MIN_MOVE_INDEX
             {
-                // reduce by 1 ply
-                new_depth -= 1;
-                // if new_depth > 3 && move_index >= 6 {
-                //     new_depth -= 1;
-                // }
-                reduced = true;
+                // reduce by 1 ply, 0 for moves with a good history and 2 for bad ones
+                let reduction = if self.use_history_tables {
+                    let history = self.histories(ply).quiet(board, mv);
+                    (1 - history / LMR_HISTORY_DIVISOR).clamp(0, 2) as i8
+                } else {
+                    1
+                };
+                new_depth -= reduction;
+                reduced = reduction > 0;
             }
 
+            self.tt.prefetch(board.key_after(mv));
+            self.played[ply as usize] = Some((board.moved_piece(mv), mv.get_dest()));
+
+            self.nnue_eval.do_move(&board, mv);
+            board.apply_move(mv);
+
             let mut score: MyVal;
 
             if first_move {
@@ -556,10 +830,11 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                         board,
                         -beta,
                         -alpha,
-                        depth - 1,
+                        full_depth,
                         ply + 1,
                         true, // allow null below PV
                         NULL_BIT_MOVE,
+                        NULL_BIT_MOVE,
                     )
                     .score;
                 first_move = false;
@@ -574,6 +849,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                         ply + 1,
                         true, // allow null below
                         NULL_BIT_MOVE,
+                        NULL_BIT_MOVE,
                     )
                     .score;
 
@@ -584,10 +860,11 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                             board,
                             -alpha - 1,
                             -alpha,
-                            depth - 1,
+                            full_depth,
                             ply + 1,
                             true,
                             NULL_BIT_MOVE,
+                            NULL_BIT_MOVE,
                         )
                         .score;
                 }
@@ -599,10 +876,11 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                             board,
                             -beta,
                             -alpha,
-                            depth - 1,
+                            full_depth,
                             ply + 1,
                             true,
                             NULL_BIT_MOVE,
+                            NULL_BIT_MOVE,
                         )
                         .score;
                 }
@@ -621,11 +899,14 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                 alpha = score;
 
                 if alpha >= beta {
-                    // Beta cutoff: update killers/history for quiet moves
+                    // Beta cutoff: update killers/counter-moves for quiet moves, histories for all
                     if !is_capture_or_promo && !gives_check {
                         self.store_killer(ply, mv);
-                        self.update_history(side_idx, mv, depth);
+                        if let Some(prev) = previous {
+                            self.counter_moves[prev] = mv;
+                        }
                     }
+                    self.update_histories(board, ply, mv, depth, &quiets_tried, &captures_tried);
 
                     if !self.time_up() {
                         let age = self.tt.time_age();
@@ -633,7 +914,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                         entry.place(
                             zobrist,
                             best_move,
-                            best_score,
+                            value_to_tt(best_score, ply),
                             static_eval,
                             depth as i16,
                             NodeBound::LowerBound,
@@ -644,9 +925,19 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                     return ScoringMove::new_score(best_move, best_score);
                 }
             }
+
+            if is_capture_or_promo {
+                captures_tried.push(mv);
+            } else {
+                quiets_tried.push(mv);
+            }
         }
 
         if legal_moves == 0 {
+            // Only the excluded move was legal, it is the one to play
+            if !excluded.is_null() {
+                return ScoringMove::blank(alpha);
+            }
             if in_check {
                 return ScoringMove::blank(mated_in(ply));
             } else {
@@ -671,7 +962,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
             entry.place(
                 zobrist,
                 best_move,
-                best_score,
+                value_to_tt(best_score, ply),
                 static_eval,
                 depth as i16,
                 tt_flag,
@@ -682,7 +973,8 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         ScoringMove::new_score(best_move, best_score)
     }
 
-    /// Quiescence search: stand-pat, captures, evasions if in check.
+    /// Quiescence search: stand-pat, captures and optionally quiet checks on the first ply,
+    /// evasions if in check. Results are kept in the TT at depth 0 or -1, see `DEPTH_QS_CHECKS`.
     fn quiescence_search(
         &mut self,
         board: &mut Board,
@@ -694,6 +986,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         if self.time_up() {
             return alpha;
         }
+        self.nodes += 1;
 
         if board.fifty_move_rule() || board.threefold_repetition() {
             return DRAW_V;
@@ -702,20 +995,66 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         let in_check = board.in_check();
         let static_eval = self.eval(board);
 
-        // Depth cap: allow fall-through when in check to search evasions;
-        // only stand-pat when not in check.
-        if depth <= -5 && !in_check {
+        // Ply cap: mate scores past it would collide with VALUE_MATE_IN_MAX_PLY
+        if (ply as usize) >= MAX_PLY - 1 {
             return static_eval;
         }
 
+        let alpha_orig = alpha;
+        let zobrist = board.zobrist();
+        let tt_depth = if in_check || depth >= DEPTH_QS_CHECKS {
+            DEPTH_QS_CHECKS
+        } else {
+            DEPTH_QS_NO_CHECKS
+        };
+
+        // ---- TT PROBE BLOCK ----
+        let mut tt_move = NULL_BIT_MOVE;
+        let mut stand_pat = static_eval;
+        let tt_hit;
+        {
+            let (hit, entry): (bool, &mut Entry) = self.tt.probe(zobrist);
+            tt_hit = hit;
+
+            if hit {
+                tt_move = entry.best_move;
+                let tt_val = value_from_tt(entry.score, ply);
+                let tt_bound = entry.node_type();
+
+                if let Some(cut_score) =
+                    tt_maybe_cutoff(tt_val, entry.depth, tt_depth, alpha, beta, tt_bound)
+                {
+                    return cut_score;
+                }
+
+                if tt_can_improve_static(tt_val, stand_pat, tt_bound) {
+                    stand_pat = tt_val;
+                }
+            }
+        }
+        // ---- end TT block ----
+
         if !in_check {
             // Stand-pat
-            if static_eval >= beta {
-                return static_eval;
+            if stand_pat >= beta {
+                if !tt_hit {
+                    let age = self.tt.time_age();
+                    let (_, entry): (bool, &mut Entry) = self.tt.probe(zobrist);
+                    entry.place(
+                        zobrist,
+                        NULL_BIT_MOVE,
+                        value_to_tt(stand_pat, ply),
+                        static_eval,
+                        tt_depth as i16,
+                        NodeBound::LowerBound,
+                        age,
+                    );
+                }
+                return stand_pat;
             }
 
-            if static_eval > alpha {
-                alpha = static_eval;
+            if stand_pat > alpha {
+                alpha = stand_pat;
             }
 
             // Simple delta pruning: if even max capture gain can't raise static_eval to alpha, prune.
@@ -726,48 +1065,32 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
                 }
             }
 
-            if (static_eval as i32) + max_gain < alpha as i32 {
-                return static_eval;
+            if (stand_pat as i32) + max_gain < alpha as i32 {
+                return stand_pat;
             }
         }
 
-        // Generate moves: evasions if in check, otherwise captures.
-        let mut moves = if in_check {
-            board.generate_moves_of_type(GenTypes::Evasions)
-        } else {
-            board.generate_moves_of_type(GenTypes::Captures)
-        };
-
-        if moves.is_empty() {
-            if in_check {
-                return mated_in(ply);
-            } else {
-                return static_eval;
-            }
-        }
-
-        // Order captures by MVV-LVA (using your table).
-        if !in_check {
-            moves.sort_unstable_by_key(|mv| {
-                if mv.is_capture() {
-                    get_capture_score(board, mv)
-                } else if mv.is_promo() {
-                    (mv.promo_piece() as MyVal).saturating_mul(-3)
-                } else {
-                    0
-                }
-            });
-        }
-
-        let mut best = if in_check { NEG_INF_V } else { static_eval };
+        // Evasions if in check, otherwise captures by MVV-LVA and on the first ply the quiet
+        // checks
+        let quiet_checks = self.quiet_checks && tt_depth == DEPTH_QS_CHECKS;
+        let mut picker = MovePicker::quiescence(board, tt_move, quiet_checks);
+        let mut best = if in_check { NEG_INF_V } else { stand_pat };
+        let mut best_move = NULL_BIT_MOVE;
         let next_depth = depth - 1;
+        let mut legal_moves = 0;
 
-        for mv in moves {
+        while let Some(mv) = picker.next(board, &self.histories(ply)) {
             if self.time_up() {
                 return best.max(alpha);
             }
+            // Neither a losing capture nor a check hanging the piece can raise the stand-pat
+            if self.see_pruning && !in_check && !board.see_ge(mv, 0) {
+                continue;
+            }
+            legal_moves += 1;
 
             self.tt.prefetch(board.key_after(mv));
+            self.played[ply as usize] = Some((board.moved_piece(mv), mv.get_dest()));
 
             self.nnue_eval.do_move(&board, mv);
             board.apply_move(mv);
@@ -775,21 +1098,99 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
             board.undo_move();
             self.nnue_eval.undo_move();
 
-            if score >= beta {
-                return score;
-            }
-
             if score > best {
                 best = score;
                 if best > alpha {
+                    best_move = mv;
                     alpha = best;
                 }
             }
+
+            if score >= beta {
+                break;
+            }
+        }
+
+        if in_check && legal_moves == 0 {
+            return mated_in(ply);
+        }
+
+        if !self.time_up() {
+            let tt_flag = if best >= beta {
+                NodeBound::LowerBound
+            } else if best > alpha_orig {
+                NodeBound::Exact
+            } else {
+                NodeBound::UpperBound
+            };
+            let age = self.tt.time_age();
+            let (hit, entry): (bool, &mut Entry) = self.tt.probe(zobrist);
+            // An entry of the main search for the position is worth more than this one
+            if !hit || entry.depth <= tt_depth {
+                entry.place(
+                    zobrist,
+                    best_move,
+                    value_to_tt(best, ply),
+                    static_eval,
+                    tt_depth as i16,
+                    tt_flag,
+                    age,
+                );
+            }
         }
 
         best
     }
 
+    /// Plies to extend `mv` by, 0 or 1
+    fn extension(
+        &self,
+        board: &Board,
+        mv: BitMove,
+        gives_check: bool,
+        singular: bool,
+        pv_node: bool,
+    ) -> i8 {
+        let ext = self.extensions;
+        let check = ext.check && gives_check && board.see_ge(mv, 0);
+        // Exchanges are frequent, outside the PV they'd cost more than they find
+        let recapture = ext.recapture
+            && pv_node
+            && board.is_capture(mv)
+            && board.see_ge(mv, 0)
+            && board.piece_captured_last_turn() != PieceType::None
+            && board
+                .last_move()
+                .is_some_and(|last| last.get_dest() == mv.get_dest());
+        let passed_pawn_push = ext.passed_pawn_push
+            && !board.is_capture(mv)
+            && board.moved_piece(mv).type_of() == PieceType::P
+            && board.turn().relative_rank_of_sq(mv.get_dest()) >= Rank::R6
+            && board.pawn_passed(board.turn(), mv.get_dest());
+        (singular || check || recapture || passed_pawn_push) as i8
+    }
+
+    /// The move made `back` plies before the node at `ply`, `None` before the root or for a
+    /// null move
+    #[inline(always)]
+    fn earlier_move(&self, ply: u8, back: usize) -> Option<(Piece, SQ)> {
+        self.played[(ply as usize).checked_sub(back)?]
+    }
+
+    /// Ordering statistics for the node at `ply`
+    fn histories(&self, ply: u8) -> Histories<'_> {
+        let continuation = |back| {
+            self.earlier_move(ply, back)
+                .filter(|_| self.use_history_tables)
+                .map(|prev| &self.continuation[prev])
+        };
+        Histories {
+            butterfly: &self.history,
+            capture: self.use_history_tables.then_some(&self.capture_history),
+            continuation: [continuation(1), continuation(2)],
+        }
+    }
+
     // #[inline(always)]
     fn store_killer(&mut self, ply: u8, mv: BitMove) {
         let p = ply as usize;
@@ -803,17 +1204,54 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         }
     }
 
-    // #[inline(always)]
-    fn update_history(&mut self, side_idx: usize, mv: BitMove, depth: i8) {
-        let from = mv.get_src_u8() as usize;
-        let to = mv.get_dest_u8() as usize;
-        if from >= NUM_SQUARES || to >= NUM_SQUARES {
+    /// Rewards the move that caused a beta cutoff and lowers the moves tried before it: the
+    /// quiets when it is quiet, and the captures in any case
+    fn update_histories(
+        &mut self,
+        board: &Board,
+        ply: u8,
+        best: BitMove,
+        depth: i8,
+        quiets_tried: &MoveList,
+        captures_tried: &MoveList,
+    ) {
+        let bonus = stat_bonus(depth);
+        if board.is_capture_or_promotion(best) {
+            self.update_capture_history(board, best, bonus);
+        } else {
+            self.update_quiet_history(board, ply, best, bonus);
+            for &mv in quiets_tried.iter() {
+                self.update_quiet_history(board, ply, mv, -bonus);
+            }
+        }
+        for &mv in captures_tried.iter() {
+            self.update_capture_history(board, mv, -bonus);
+        }
+    }
+
+    fn update_quiet_history(&mut self, board: &Board, ply: u8, mv: BitMove, bonus: i32) {
+        self.history.update((board.turn(), mv), bonus);
+        if !self.use_history_tables {
             return;
         }
+        let reply = (board.moved_piece(mv), mv.get_dest());
+        for back in 1..=2 {
+            if let Some(prev) = self.earlier_move(ply, back) {
+                self.continuation[prev].update(reply, bonus);
+            }
+        }
+    }
 
-        let bonus = (depth as i32).max(1).pow(2);
-        let entry = &mut self.history[side_idx][from][to];
-        *entry = (*entry + bonus).clamp(-10_000, 10_000);
+    fn update_capture_history(&mut self, board: &Board, mv: BitMove, bonus: i32) {
+        // Quiet promotions have nothing to index by
+        if self.use_history_tables && board.is_capture(mv) {
+            let idx = (
+                board.moved_piece(mv),
+                mv.get_dest(),
+                board.captured_piece(mv),
+            );
+            self.capture_history.update(idx, bonus);
+        }
     }
 }
 
@@ -827,12 +1265,55 @@ fn mated_in(ply: u8) -> MyVal {
     -MATE_V + ply as MyVal
 }
 
+/// Mate scores count plies from the root, the TT keeps them from the node so they hold at
+/// any ply the position is reached at
+#[inline(always)]
+fn value_to_tt(value: MyVal, ply: u8) -> MyVal {
+    if value >= VALUE_MATE_IN_MAX_PLY {
+        value + ply as MyVal
+    } else if value <= -VALUE_MATE_IN_MAX_PLY {
+        value - ply as MyVal
+    } else {
+        value
+    }
+}
+
+/// Inverse of `value_to_tt`
+#[inline(always)]
+fn value_from_tt(value: MyVal, ply: u8) -> MyVal {
+    if value >= VALUE_MATE_IN_MAX_PLY {
+        value - ply as MyVal
+    } else if value <= -VALUE_MATE_IN_MAX_PLY {
+        value + ply as MyVal
+    } else {
+        value
+    }
+}
+
+/// History bonus for a cutoff at `depth`, and malus for the moves that didn't cut off
+#[inline(always)]
+fn stat_bonus(depth: i8) -> i32 {
+    let depth = depth as i32;
+    (depth * depth + 2 * depth - 2).clamp(1, MAX_HISTORY_BONUS)
+}
+
 #[inline(always)]
 fn futility_margin(depth: i8) -> MyVal {
     // Very conservative: ~1 pawn per depth unit, plus a small base
     FUTILITY_BASE_MARGIN * depth.max(1) as MyVal
 }
 
+/// Least SEE a move must keep to be searched at `depth`, quiets get less room at low depth
+#[inline(always)]
+fn see_pruning_threshold(depth: i8, is_capture_or_promo: bool) -> i32 {
+    let depth = depth as i32;
+    if is_capture_or_promo {
+        -SEE_CAPTURE_MARGIN * depth
+    } else {
+        -SEE_QUIET_MARGIN * depth * depth
+    }
+}
+
 /// TT: can we reuse this as an immediate cutoff or exact value?
 #[inline(always)]
 fn tt_maybe_cutoff(
@@ -878,96 +1359,67 @@ fn tt_can_improve_static(tt_value: MyVal, current_val: MyVal, bound: NodeBound)
     }
 }
 
-/// Move ordering score: lower is better (we sort by key ascending).
-// #[inline(always)]
-fn score_move(
-    board: &Board,
-    mv: BitMove,
-    ply: u8,
-    side_idx: usize,
-    root_pv_move: BitMove,
-    tt_move: BitMove,
-    killers: &[BitMove; 2],
-    history: &[[[i32; NUM_SQUARES]; NUM_SQUARES]; 2],
-) -> i32 {
-    // Strong negative = higher priority (we sort ascending).
-    let mut score: i32 = 0;
-
-    // Root PV move
-    if ply == 0 && !root_pv_move.is_null() && mv == root_pv_move {
-        return -1_000_000_000;
-    }
-
-    // TT move
-    if !tt_move.is_null() && mv == tt_move {
-        return -900_000_000;
-    }
-
-    let from = mv.get_src_u8() as usize;
-    let to = mv.get_dest_u8() as usize;
-
-    let is_capture = board.is_capture(mv);
-    let is_promo = mv.is_promo();
-
-    if is_capture {
-        // Captures (including capture-promotions)
-        let cap_score = get_capture_score(board, &mv) as i32; // 0..negative
-        score -= 500_000 + cap_score * 100;
-
-        if is_promo {
-            // Capture-promotion is even more forcing.
-            score -= 50_000;
-        }
-    } else if is_promo {
-        // Quiet promotion (no capture)
-        let promo_piece = mv.promo_piece();
-        let promo_bonus = match promo_piece {
-            PieceType::Q => 400_000,
-            PieceType::R => 350_000,
-            PieceType::B => 325_000,
-            PieceType::N => 320_000,
-            _ => 300_000,
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nnue::architecture::SF_BIG;
+    use nnue::nnue::{Nnue, QuantizedAffine, QuantizedLayerStack, QuantizedNetwork};
+
+    use super::*;
+
+    /// A network that scores everything 0, searches only depend on it for the move order
+    fn zero_network() -> Arc<Nnue> {
+        let arch = &SF_BIG;
+        let dims = arch.transformed_dims;
+        let inputs = arch.feature_set.input_dims();
+        let (fc0_out, fc1_out) = (arch.affine_outputs(0), arch.affine_outputs(1));
+        let affine = |outputs: usize, inputs: usize| QuantizedAffine {
+            biases: vec![0; outputs],
+            weights: vec![0; outputs * inputs],
         };
-        score -= promo_bonus;
-    } else {
-        // Quiet non-promo moves: killers + history
+        let net = QuantizedNetwork {
+            arch,
+            desc: "zero".to_string(),
+            ft_biases: vec![0; dims],
+            ft_weights: vec![0; dims * inputs],
+            psqt_weights: vec![0; arch.psqt_buckets * inputs],
+            layer_stacks: (0..arch.layer_stacks)
+                .map(|_| QuantizedLayerStack {
+                    fc0: affine(fc0_out, dims),
+                    fc1: affine(fc1_out, (fc0_out - 1) * 2),
+                    fc2: affine(1, fc1_out),
+                })
+                .collect(),
+        };
+        Arc::new(Nnue::from_quantized(&net))
+    }
 
-        if mv == killers[0] {
-            score -= 400_000;
-        } else if mv == killers[1] {
-            score -= 399_000;
-        }
+    #[test]
+    fn test_mate_through_transposition() {
+        let mut nnue_eval = NnueEvaluator::with_network(zero_network());
+        let mut searcher = MySearcher::new(&mut nnue_eval, NoTrace::new(), None);
 
-        if from < NUM_SQUARES && to < NUM_SQUARES {
-            let hist = history[side_idx][from][to];
-            score -= hist;
-        }
+        // After 1.Kb6 black has to play Kb8 and is mated by Rh8, the root entry says so
+        let mut after_kb6 = Board::from_fen("k7/7R/1K6/8/8/8/8/8 b - - 1 1").unwrap();
+        assert_eq!(searcher.perform_search(&mut after_kb6, 4).score, mated_in(2));
+
+        // Reached again a ply below the root, where it is a mate in 3 plies from the root
+        let mut board = Board::from_fen("k7/7R/2K5/8/8/8/8/8 w - - 0 1").unwrap();
+        assert_eq!(searcher.perform_search(&mut board, 4).score, mate_in(3));
     }
 
-    score
+    /// Pruning the losing moves makes the Kiwipete tree smaller
+    #[test]
+    fn test_see_pruning() {
+        let mut nnue_eval = NnueEvaluator::with_network(zero_network());
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let nodes = [true, false].map(|enabled| {
+            let mut searcher = MySearcher::new(&mut nnue_eval, NoTrace::new(), None).with_see_pruning(enabled);
+            let mut board = Board::from_fen(kiwipete).unwrap();
+            searcher.perform_search(&mut board, 5);
+            searcher.nodes()
+        });
+        assert!(nodes[0] < nodes[1], "nodes with and without SEE pruning: {:?}", nodes);
+    }
 }
-
-fn get_capture_score(board: &Board, mv: &BitMove) -> MyVal {
-    let attacker = board.piece_at_sq(mv.get_src());
-    let dest = if mv.is_en_passant() {
-        if board.turn() == Player::White {
-            WhiteType::down(mv.get_dest())
-        } else {
-            BlackType::down(mv.get_dest())
-        }
-    } else {
-        mv.get_dest()
-    };
-    let captured = board.piece_at_sq(dest);
-    if (!attacker.type_of().is_real()) || (!captured.type_of().is_real()) {
-        println!(
-            "Error in get_capture_score: attacker = {:?}, captured = {:?}",
-            attacker, captured
-        );
-        println!("Attack as usize = {}", attacker.type_of() as usize - 1);
-        println!("Captured as usize = {}", captured.type_of() as usize - 1);
-        panic!("THE ERROR");
-    }
-
-    MVV_LVA[attacker.type_of() as usize - 1][captured.type_of() as usize - 1]
-}
\ No newline at end of file
@@ -63,6 +63,8 @@ pub struct MySearcher<'a, T: Tracing<SearchDebugger>> {
 
     tracer: T,
     nodes_explored: i64,
+    // Total nodes of the search, nodes_explored is per iteration
+    nodes: u64,
 
     // PV moves (for root / debug; not a full PV table)
     pv_moves: [ScoringMove; MAX_PLY],
@@ -112,6 +114,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
 
             tracer,
             nodes_explored: 0,
+            nodes: 0,
             pv_moves: [NULL_SCORE; MAX_PLY],
 
             killer_moves: [[NULL_BIT_MOVE; 2]; MAX_PLY],
@@ -130,6 +133,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
 
             tracer,
             nodes_explored: 0,
+            nodes: 0,
             pv_moves: [NULL_SCORE; MAX_PLY],
 
             killer_moves: [[NULL_BIT_MOVE; 2]; MAX_PLY],
@@ -184,10 +188,16 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         }
     }
 
+    /// Nodes searched by the last search
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
     /// Top-level search: iterative deepening + aspiration windows.
     pub fn perform_search(&mut self, board: &mut Board, max_ply: u8) -> ScoringMove {
         self.start_time = Instant::now();
         self.nodes_explored = 0;
+        self.nodes = 0;
         self.pv_moves = [NULL_SCORE; MAX_PLY];
         self.killer_moves = [[NULL_BIT_MOVE; 2]; MAX_PLY];
         self.history = [[[0; NUM_SQUARES]; NUM_SQUARES]; 2];
@@ -348,6 +358,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         }
 
         self.nodes_explored += 1;
+        self.nodes += 1;
 
         if (ply as usize) >= MAX_PLY - 1 {
             depth = 0;
@@ -691,6 +702,7 @@ impl<'a, T: Tracing<SearchDebugger>> MySearcher<'a, T> {
         if self.time_up() {
             return alpha;
         }
+        self.nodes += 1;
 
         if board.fifty_move_rule() || board.threefold_repetition() {
             return DRAW_V;
@@ -0,0 +1,49 @@
+//! History of quiet moves by side and from/to squares.
+
+use std::ops::{Index, IndexMut};
+
+use pleco::core::masks::{PLAYER_CNT, SQ_CNT};
+use pleco::{BitMove, Player};
+
+use super::{NumStatCube, StatBoard};
+
+/// Indexed by the side to move and the from and to squares of a move
+pub struct ButterflyHistory {
+    a: [[i16; SQ_CNT * SQ_CNT]; PLAYER_CNT],
+}
+
+// The lower 12 bits of a move are its from and to squares
+const FROM_TO_MASK: u16 = 0x0FFF;
+
+impl Index<(Player, BitMove)> for ButterflyHistory {
+    type Output = i16;
+
+    #[inline(always)]
+    fn index(&self, (player, mv): (Player, BitMove)) -> &Self::Output {
+        unsafe {
+            self.a
+                .get_unchecked(player as usize)
+                .get_unchecked((mv.get_raw() & FROM_TO_MASK) as usize)
+        }
+    }
+}
+
+impl IndexMut<(Player, BitMove)> for ButterflyHistory {
+    #[inline(always)]
+    fn index_mut(&mut self, (player, mv): (Player, BitMove)) -> &mut Self::Output {
+        unsafe {
+            self.a
+                .get_unchecked_mut(player as usize)
+                .get_unchecked_mut((mv.get_raw() & FROM_TO_MASK) as usize)
+        }
+    }
+}
+
+impl StatBoard<i16, (Player, BitMove)> for ButterflyHistory {
+    const FILL: i16 = 0;
+}
+
+impl NumStatCube<(Player, BitMove)> for ButterflyHistory {
+    const D: i32 = 324;
+    const W: i32 = 32;
+}
@@ -0,0 +1,47 @@
+//! History of captures by the moving piece, its destination and the captured piece type.
+
+use std::ops::{Index, IndexMut};
+
+use pleco::core::masks::{PIECE_CNT, PIECE_TYPE_CNT, SQ_CNT};
+use pleco::{Piece, PieceType, SQ};
+
+use super::{NumStatCube, StatBoard};
+
+pub struct CapturePieceToHistory {
+    a: [[[i16; PIECE_TYPE_CNT]; SQ_CNT]; PIECE_CNT],
+}
+
+impl Index<(Piece, SQ, PieceType)> for CapturePieceToHistory {
+    type Output = i16;
+
+    #[inline(always)]
+    fn index(&self, (piece, sq, captured): (Piece, SQ, PieceType)) -> &Self::Output {
+        unsafe {
+            self.a
+                .get_unchecked(piece as usize)
+                .get_unchecked(sq.0 as usize)
+                .get_unchecked(captured as usize)
+        }
+    }
+}
+
+impl IndexMut<(Piece, SQ, PieceType)> for CapturePieceToHistory {
+    #[inline(always)]
+    fn index_mut(&mut self, (piece, sq, captured): (Piece, SQ, PieceType)) -> &mut Self::Output {
+        unsafe {
+            self.a
+                .get_unchecked_mut(piece as usize)
+                .get_unchecked_mut(sq.0 as usize)
+                .get_unchecked_mut(captured as usize)
+        }
+    }
+}
+
+impl StatBoard<i16, (Piece, SQ, PieceType)> for CapturePieceToHistory {
+    const FILL: i16 = 0;
+}
+
+impl NumStatCube<(Piece, SQ, PieceType)> for CapturePieceToHistory {
+    const D: i32 = 324;
+    const W: i32 = 2;
+}
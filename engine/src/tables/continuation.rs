@@ -0,0 +1,99 @@
+//! Continuation histories: how good a quiet move was as a reply to the move one or two plies
+//! before it, both indexed by piece and destination.
+
+use std::ops::{Index, IndexMut};
+
+use pleco::core::masks::{PIECE_CNT, SQ_CNT};
+use pleco::{Piece, SQ};
+
+use super::{NumStatCube, StatBoard};
+
+/// History of a move by its piece and destination, for one earlier move
+#[derive(Clone, Copy)]
+pub struct PieceToHistory {
+    a: [[i16; SQ_CNT]; PIECE_CNT],
+}
+
+impl Index<(Piece, SQ)> for PieceToHistory {
+    type Output = i16;
+
+    #[inline(always)]
+    fn index(&self, (piece, sq): (Piece, SQ)) -> &Self::Output {
+        unsafe { self.a.get_unchecked(piece as usize).get_unchecked(sq.0 as usize) }
+    }
+}
+
+impl IndexMut<(Piece, SQ)> for PieceToHistory {
+    #[inline(always)]
+    fn index_mut(&mut self, (piece, sq): (Piece, SQ)) -> &mut Self::Output {
+        unsafe { self.a.get_unchecked_mut(piece as usize).get_unchecked_mut(sq.0 as usize) }
+    }
+}
+
+impl StatBoard<i16, (Piece, SQ)> for PieceToHistory {
+    const FILL: i16 = 0;
+}
+
+impl NumStatCube<(Piece, SQ)> for PieceToHistory {
+    const D: i32 = 936;
+    const W: i32 = 32;
+}
+
+/// A `PieceToHistory` for every piece and destination of the earlier move
+pub struct ContinuationHistory {
+    a: [[PieceToHistory; SQ_CNT]; PIECE_CNT],
+}
+
+impl ContinuationHistory {
+    /// Zeroed on the heap, at 2 MB the table is too big for the stack
+    pub fn new_boxed() -> Box<Self> {
+        unsafe { Box::new_zeroed().assume_init() }
+    }
+}
+
+impl Index<(Piece, SQ)> for ContinuationHistory {
+    type Output = PieceToHistory;
+
+    #[inline(always)]
+    fn index(&self, (piece, sq): (Piece, SQ)) -> &Self::Output {
+        unsafe { self.a.get_unchecked(piece as usize).get_unchecked(sq.0 as usize) }
+    }
+}
+
+impl IndexMut<(Piece, SQ)> for ContinuationHistory {
+    #[inline(always)]
+    fn index_mut(&mut self, (piece, sq): (Piece, SQ)) -> &mut Self::Output {
+        unsafe { self.a.get_unchecked_mut(piece as usize).get_unchecked_mut(sq.0 as usize) }
+    }
+}
+
+impl StatBoard<PieceToHistory, (Piece, SQ)> for ContinuationHistory {
+    const FILL: PieceToHistory = PieceToHistory { a: [[0; SQ_CNT]; PIECE_CNT] };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continuation_history() {
+        let mut history = ContinuationHistory::new_boxed();
+        let prev = (Piece::WhiteKnight, SQ::F3);
+        let reply = (Piece::BlackPawn, SQ::D5);
+        assert_eq!(history[prev][reply], 0);
+
+        // Bonuses saturate at D * W
+        for _ in 0..1000 {
+            history[prev].update(reply, 900);
+        }
+        let max = PieceToHistory::D * PieceToHistory::W;
+        assert!((history[prev][reply] as i32) <= max);
+        assert!((history[prev][reply] as i32) > max * 9 / 10);
+        history[prev].update(reply, -936);
+        assert!((history[prev][reply] as i32) < max * 9 / 10);
+        assert_eq!(history[(Piece::WhiteKnight, SQ::G3)][reply], 0);
+
+        history.clear();
+        assert_eq!(history[prev][reply], 0);
+    }
+}
@@ -0,0 +1,32 @@
+//! Quiet moves that refuted a move, by the piece and destination of that move.
+
+use std::ops::{Index, IndexMut};
+
+use pleco::core::masks::{PIECE_CNT, SQ_CNT};
+use pleco::{BitMove, Piece, SQ};
+
+use super::StatBoard;
+
+pub struct CounterMoveHistory {
+    a: [[BitMove; SQ_CNT]; PIECE_CNT],
+}
+
+impl Index<(Piece, SQ)> for CounterMoveHistory {
+    type Output = BitMove;
+
+    #[inline(always)]
+    fn index(&self, (piece, sq): (Piece, SQ)) -> &Self::Output {
+        unsafe { self.a.get_unchecked(piece as usize).get_unchecked(sq.0 as usize) }
+    }
+}
+
+impl IndexMut<(Piece, SQ)> for CounterMoveHistory {
+    #[inline(always)]
+    fn index_mut(&mut self, (piece, sq): (Piece, SQ)) -> &mut Self::Output {
+        unsafe { self.a.get_unchecked_mut(piece as usize).get_unchecked_mut(sq.0 as usize) }
+    }
+}
+
+impl StatBoard<BitMove, (Piece, SQ)> for CounterMoveHistory {
+    const FILL: BitMove = BitMove::null();
+}
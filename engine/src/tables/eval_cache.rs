@@ -0,0 +1,146 @@
+//! Table to map from position -> NNUE evaluation, so that transpositions and repeated static
+//! evaluations of a node don't pay for the network again.
+
+use std::fmt::{self, Debug, Display};
+
+/// 4 MB worth of entries
+pub const DEFAULT_EVAL_CACHE_ENTRIES: usize = 1 << 18;
+
+#[derive(Clone, Copy, Default)]
+struct EvalEntry {
+    key: u64,
+    value: i32,
+}
+
+/// Probes and hits since the cache was created or its statistics were last reset
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EvalCacheStats {
+    pub probes: u64,
+    pub hits: u64,
+}
+
+impl EvalCacheStats {
+    /// Share of the probes that were hits, 0 without probes
+    pub fn hit_rate(&self) -> f64 {
+        if self.probes == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.probes as f64
+        }
+    }
+}
+
+impl Display for EvalCacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{} hits ({:.1}%)", self.hits, self.probes, self.hit_rate() * 100.0)
+    }
+}
+
+/// Scaled NNUE evaluations by Zobrist key. Like the other tables it is indexed by the lower bits
+/// of the key and always replaces, the full key is kept to tell positions apart.
+pub struct EvalCache {
+    entries: Box<[EvalEntry]>,
+    stats: EvalCacheStats,
+}
+
+impl Debug for EvalCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Eval Cache of {} entries, {}", self.entries.len(), self.stats)
+    }
+}
+
+impl EvalCache {
+    /// Creates an `EvalCache` of `entries` entries, rounded down to a power of 2. With 0
+    /// entries nothing is cached and every probe misses without being counted.
+    pub fn new(entries: usize) -> Self {
+        let entries = if entries == 0 { 0 } else { 1 << entries.ilog2() };
+        EvalCache {
+            entries: vec![EvalEntry::default(); entries].into_boxed_slice(),
+            stats: EvalCacheStats::default(),
+        }
+    }
+
+    /// Creates the largest `EvalCache` that fits in `mb` megabytes
+    pub fn with_size_mb(mb: usize) -> Self {
+        Self::new(mb * 1024 * 1024 / std::mem::size_of::<EvalEntry>())
+    }
+
+    pub fn entries(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Forgets every evaluation and resets the statistics, e.g. after switching networks
+    pub fn clear(&mut self) {
+        self.entries.fill(EvalEntry::default());
+        self.stats = EvalCacheStats::default();
+    }
+
+    #[inline(always)]
+    fn index(&self, key: u64) -> usize {
+        (key & (self.entries.len() as u64 - 1)) as usize
+    }
+
+    /// The evaluation stored for `key`, if it hasn't been replaced
+    #[inline(always)]
+    pub fn probe(&mut self, key: u64) -> Option<i32> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.stats.probes += 1;
+        let entry = self.entries[self.index(key)];
+        if entry.key == key {
+            self.stats.hits += 1;
+            Some(entry.value)
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub fn store(&mut self, key: u64, value: i32) {
+        if !self.entries.is_empty() {
+            let index = self.index(key);
+            self.entries[index] = EvalEntry { key, value };
+        }
+    }
+
+    pub fn stats(&self) -> EvalCacheStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = EvalCacheStats::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_cache() {
+        let mut cache = EvalCache::new(1000);
+        assert_eq!(cache.entries(), 512);
+        assert_eq!(EvalCache::with_size_mb(4).entries(), DEFAULT_EVAL_CACHE_ENTRIES);
+
+        assert_eq!(cache.probe(7), None);
+        cache.store(7, -35);
+        assert_eq!(cache.probe(7), Some(-35));
+        // Same slot, other position
+        assert_eq!(cache.probe(7 + 512), None);
+        cache.store(7 + 512, 12);
+        assert_eq!(cache.probe(7), None);
+        assert_eq!(cache.probe(7 + 512), Some(12));
+        assert_eq!(cache.stats(), EvalCacheStats { probes: 5, hits: 2 });
+        assert_eq!(cache.stats().to_string(), "2/5 hits (40.0%)");
+
+        cache.clear();
+        assert_eq!(cache.probe(7 + 512), None);
+        assert_eq!(cache.stats(), EvalCacheStats { probes: 1, hits: 0 });
+
+        let mut disabled = EvalCache::new(0);
+        disabled.store(7, 1);
+        assert_eq!(disabled.probe(7), None);
+        assert_eq!(disabled.stats(), EvalCacheStats::default());
+    }
+}
@@ -1,3 +1,8 @@
+pub mod butterfly;
+pub mod capture_piece_history;
+pub mod continuation;
+pub mod counter_move;
+pub mod eval_cache;
 pub mod material;
 pub mod pawn_table;
 
@@ -56,7 +61,9 @@ pub trait NumStatCube<IDX>: StatBoard<i16, IDX> {
     fn update(&mut self, idx: IDX, bonus: i32) {
         assert!(bonus.abs() <= Self::D);
         let entry = self.index_mut(idx);
-        *entry += (bonus * Self::W - (*entry) as i32 * bonus.abs() / Self::D) as i16;
+        // The step can be up to twice the range, only the result is sure to fit an i16
+        let value = (*entry) as i32;
+        *entry = (value + bonus * Self::W - value * bonus.abs() / Self::D) as i16;
         assert!(((*entry) as i32).abs() <= Self::D * Self::W);
     }
 }
@@ -1,15 +1,13 @@
 use core::panic;
-use std::array::from_fn;
 
 use aligned_vec::AVec;
 use pleco::{BitBoard, Board, Piece, Player};
 
+use crate::architecture::FeatureSet;
 use crate::{
     constants::{
-        COLOR_OPS, COLORS, MAX_PLY, PAWN_THROUGH_KING, PIECE_TYPE_NB, PSQT_BUCKETS, PsqtWeightType, SQUARES, TRANSFORMED_FEATURE_DIM_BIG, USE_AVX2, VectorAlignment, WeightType
-    }, feature_sets::{
-        self, IndexList, MAX_ACTIVE_DIMENSIONS, append_changed_indices, requires_refresh,
-    }, feature_transformer::FeatureTransformer, nnue, nnue_misc::DirtyPiece, vectors::{NUM_PSQT_REGS, NUM_REGS_BIG, PSQT_TILE_HEIGHT, PsqtVecT, TILE_HEIGHT_BIG, VecT, 
+        COLOR_OPS, COLORS, MAX_PLY, PAWN_THROUGH_KING, PIECE_TYPE_NB, PSQT_BUCKETS, PsqtWeightType, SQUARES, TRANSFORMED_FEATURE_DIM_BIG, TRANSFORMED_FEATURE_DIM_SF12, TRANSFORMED_FEATURE_DIM_SMALL, VectorAlignment, WeightType
+    }, feature_sets::{IndexList, MAX_ACTIVE_DIMENSIONS}, feature_transformer::FeatureTransformer, nnue, nnue_misc::DirtyPiece, vectors::{NUM_PSQT_REGS, NUM_REGS_BIG, NUM_REGS_SMALL, PSQT_TILE_HEIGHT, PsqtVecT, TILE_HEIGHT_BIG, TILE_HEIGHT_SMALL, VecT, 
         to_const_vec_ptr, to_mut_vec_ptr, vec_add_16, vec_add_32, vec_store_si256, vec_sub_16, vec_sub_32, vec_zero}
 };
 
@@ -48,7 +46,10 @@ impl<const DIM: usize> Default for Accumulator<DIM> {
 #[derive(Clone, Default)]
 pub struct AccumulatorState {
     big: Accumulator<TRANSFORMED_FEATURE_DIM_BIG>,
-    // small: Accumulator<TRANSFORMED_FEATURE_DIM_SMALL>,
+    /// Only computed when the small network is asked for an evaluation
+    small: Accumulator<TRANSFORMED_FEATURE_DIM_SMALL>,
+    /// Only computed when a Stockfish 12 network is asked for an evaluation
+    sf12: Accumulator<TRANSFORMED_FEATURE_DIM_SF12>,
     // store your DirtyPiece equivalent here
     pub dirty_piece: DirtyPiece,
 }
@@ -57,7 +58,8 @@ impl AccumulatorState {
     pub fn reset(&mut self, dp: DirtyPiece) {
         self.dirty_piece = dp;
         self.big.computed = [false; COLORS];
-        // self.small.computed = [false; COLORS];
+        self.small.computed = [false; COLORS];
+        self.sf12.computed = [false; COLORS];
     }
 
     pub fn get_accumulator_mut<const DIM: usize>(&mut self) -> &mut Accumulator<DIM> {
@@ -66,16 +68,22 @@ impl AccumulatorState {
                 &mut *(&mut self.big as *mut Accumulator<TRANSFORMED_FEATURE_DIM_BIG>
                     as *mut Accumulator<DIM>)
             }
+        } else if DIM == TRANSFORMED_FEATURE_DIM_SMALL {
+            unsafe {
+                &mut *(&mut self.small as *mut Accumulator<TRANSFORMED_FEATURE_DIM_SMALL>
+                    as *mut Accumulator<DIM>)
+            }
+        } else if DIM == TRANSFORMED_FEATURE_DIM_SF12 {
+            unsafe {
+                &mut *(&mut self.sf12 as *mut Accumulator<TRANSFORMED_FEATURE_DIM_SF12>
+                    as *mut Accumulator<DIM>)
+            }
         } else {
             panic!("Unsupported dimension for accumulator retrieval");
         }
     }
     pub fn is_computed<const DIM: usize>(&self, perspective: Player) -> bool {
-        if DIM == TRANSFORMED_FEATURE_DIM_BIG {
-            self.big.computed[perspective as usize]
-        } else {
-            panic!("Unsupported dimension for accumulator retrieval");
-        }
+        self.get_accumulator::<DIM>().computed[perspective as usize]
     }
     pub fn get_accumulator<const DIM: usize>(&self) -> &Accumulator<DIM> {
         if DIM == TRANSFORMED_FEATURE_DIM_BIG {
@@ -83,6 +91,16 @@ impl AccumulatorState {
                 &*(&self.big as *const Accumulator<TRANSFORMED_FEATURE_DIM_BIG>
                     as *const Accumulator<DIM>)
             }
+        } else if DIM == TRANSFORMED_FEATURE_DIM_SMALL {
+            unsafe {
+                &*(&self.small as *const Accumulator<TRANSFORMED_FEATURE_DIM_SMALL>
+                    as *const Accumulator<DIM>)
+            }
+        } else if DIM == TRANSFORMED_FEATURE_DIM_SF12 {
+            unsafe {
+                &*(&self.sf12 as *const Accumulator<TRANSFORMED_FEATURE_DIM_SF12>
+                    as *const Accumulator<DIM>)
+            }
         } else {
             panic!("Unsupported dimension for accumulator retrieval");
         }
@@ -105,6 +123,9 @@ fn update_accumulator_refresh_cache<const DIM: usize>(
 
     for c in [Player::White, Player::Black] {
         for pt in PAWN_THROUGH_KING {
+            if !ft.arch.feature_set.is_feature(pt) {
+                continue;
+            }
             let piece = Piece::make_lossy(c, pt);
             let old_bb = cache_entry.by_color_bb[c as usize] & cache_entry.by_type_bb[pt as usize];
             let new_bb = board.piece_bb(c, pt);
@@ -114,7 +135,7 @@ fn update_accumulator_refresh_cache<const DIM: usize>(
 
             while to_remove.is_not_empty() {
                 let sq = to_remove.pop_lsb();
-                removed_features.push(feature_sets::make_index(
+                removed_features.push(ft.arch.feature_set.make_index(
                     perspective as usize,
                     sq.0,
                     piece as usize,
@@ -124,7 +145,7 @@ fn update_accumulator_refresh_cache<const DIM: usize>(
 
             while to_add.is_not_empty() {
                 let sq = to_add.pop_lsb();
-                added_features.push(feature_sets::make_index(
+                added_features.push(ft.arch.feature_set.make_index(
                     perspective as usize,
                     sq.0,
                     piece as usize,
@@ -135,249 +156,223 @@ fn update_accumulator_refresh_cache<const DIM: usize>(
     }
     accum.computed[perspective as usize] = true;
 
-    if USE_AVX2 {
-
-        let combine_last_3 = (removed_features.len() as i32 - added_features.len() as i32).abs() == 1 &&
-            (removed_features.len() + added_features.len()) > 2;
+    let combine_last_3 = (removed_features.len() as i32 - added_features.len() as i32).abs() == 1 &&
+        (removed_features.len() + added_features.len()) > 2;
 
-        let num_regs = NUM_REGS_BIG;
-        let tile_height = TILE_HEIGHT_BIG;
-
-        // Just going to use big dim automatically, no idea how to make it dynamic for consts
-        let mut acc: [VecT; NUM_REGS_BIG] = [vec_zero(); NUM_REGS_BIG]; 
-        let mut psqt: [PsqtVecT; NUM_PSQT_REGS] = [vec_zero(); NUM_PSQT_REGS];
+    let (num_regs, tile_height) = if DIM == TRANSFORMED_FEATURE_DIM_SMALL {
+        (NUM_REGS_SMALL, TILE_HEIGHT_SMALL)
+    } else {
+        (NUM_REGS_BIG, TILE_HEIGHT_BIG)
+    };
 
-        for j in 0 .. DIM / tile_height {
+    // Sized for the big net, the small one only uses the first NUM_REGS_SMALL
+    let mut acc: [VecT; NUM_REGS_BIG] = [vec_zero(); NUM_REGS_BIG]; 
+    let mut psqt: [PsqtVecT; NUM_PSQT_REGS] = [vec_zero(); NUM_PSQT_REGS];
+
+    for j in 0 .. DIM / tile_height {
+
+        let acc_tile: *mut VecT = unsafe {
+            accum
+                .accumulation[perspective as usize]
+                .as_mut_ptr()
+                .add(j * tile_height)
+                .cast()
+        };
+
+        // let entry_tile: *mut VecT = to_mut_vec_ptr(
+        //     &mut cache_entry.accumulation, 
+        //     j * tile_height
+        // );
+        let entry_tile: *mut VecT = unsafe {
+            cache_entry
+                .accumulation
+                .as_mut_ptr()
+                .add(j * tile_height)
+                .cast()
+        };
+
+        // Store off accumulation
+        for k in 0 .. num_regs {
+            acc[k] = unsafe { *entry_tile.add(k) };
+        }
 
-            let acc_tile: *mut VecT = unsafe {
-                accum
-                    .accumulation[perspective as usize]
-                    .as_mut_ptr()
-                    .add(j * tile_height)
+        let mut i: usize = 0;
+        while i < removed_features.len().min(added_features.len()) - combine_last_3 as usize {
+            
+            let index_r = removed_features[i];
+            let offset_r = DIM * index_r + j * tile_height;
+            let column_r: *const VecT = unsafe {
+                ft.weights
+                    .as_ptr()
+                    .add(offset_r)
                     .cast()
             };
-
-            // let entry_tile: *mut VecT = to_mut_vec_ptr(
-            //     &mut cache_entry.accumulation, 
-            //     j * tile_height
-            // );
-            let entry_tile: *mut VecT = unsafe {
-                cache_entry
-                    .accumulation
-                    .as_mut_ptr()
-                    .add(j * tile_height)
+            let index_a = added_features[i];
+            let offset_a = DIM * index_a + j * tile_height;
+            let column_a: *const VecT = unsafe {
+                ft.weights
+                    .as_ptr()
+                    .add(offset_a)
                     .cast()
             };
 
-            // Store off accumulation
             for k in 0 .. num_regs {
-                acc[k] = unsafe { *entry_tile.add(k) };
-            }
-
-            let mut i: usize = 0;
-            while i < removed_features.len().min(added_features.len()) - combine_last_3 as usize {
-                
-                let index_r = removed_features[i];
-                let offset_r = DIM * index_r + j * tile_height;
-                let column_r: *const VecT = unsafe {
-                    ft.weights
-                        .as_ptr()
-                        .add(offset_r)
-                        .cast()
-                };
-                let index_a = added_features[i];
-                let offset_a = DIM * index_a + j * tile_height;
-                let column_a: *const VecT = unsafe {
-                    ft.weights
-                        .as_ptr()
-                        .add(offset_a)
-                        .cast()
-                };
-
-                for k in 0 .. num_regs {
-                    acc[k] = vec_add_16(
-                        acc[k],
-                        unsafe {
-                            vec_sub_16(
-                                *column_a.add(k),
-                                *column_r.add(k)
-                            )
-                        }
-                    );
-                }
-                i += 1;
+                acc[k] = vec_add_16(
+                    acc[k],
+                    unsafe {
+                        vec_sub_16(
+                            *column_a.add(k),
+                            *column_r.add(k)
+                        )
+                    }
+                );
             }
+            i += 1;
+        }
 
-            if combine_last_3 {
-                let index_r = removed_features[i];
-                let offset_r = DIM * index_r + j * tile_height;
-                let column_r: *const VecT = to_const_vec_ptr(&ft.weights, offset_r);
-                // let column_r: *const VecT = unsafe {
+        if combine_last_3 {
+            let index_r = removed_features[i];
+            let offset_r = DIM * index_r + j * tile_height;
+            let column_r: *const VecT = to_const_vec_ptr(&ft.weights, offset_r);
+            // let column_r: *const VecT = unsafe {
+            //     ft.weights
+            //         .as_ptr()
+            //         .add(offset_r)
+            //         .cast()
+            // };
+            let index_a = added_features[i];
+            let offset_a = DIM * index_a + j * tile_height;
+            let column_a: *const VecT = to_const_vec_ptr(&ft.weights, offset_a);
+            // let column_a: *const VecT = unsafe {
+            //     ft.weights
+            //         .as_ptr()
+            //         .add(offset_a)
+            //         .cast()
+            // };
+
+            if removed_features.len() > added_features.len() {
+                let index_r2 = removed_features[i + 1];
+                let offset_r2 = DIM * index_r2 + j * tile_height;
+                let column_r2: *const VecT = to_const_vec_ptr(&ft.weights, offset_r2);
+                // let column_r2: *const VecT = unsafe {
                 //     ft.weights
                 //         .as_ptr()
-                //         .add(offset_r)
+                //         .add(offset_r2)
                 //         .cast()
                 // };
-                let index_a = added_features[i];
-                let offset_a = DIM * index_a + j * tile_height;
-                let column_a: *const VecT = to_const_vec_ptr(&ft.weights, offset_a);
-                // let column_a: *const VecT = unsafe {
+
+                for k in 0 .. num_regs {
+                    acc[k] = vec_sub_16(
+                        vec_add_16(acc[k], unsafe { *column_a.add(k) }), 
+                        vec_add_16(unsafe { *column_r.add(k) }, unsafe { *column_r2.add(k) })
+                    )
+                }
+            } else {
+                let index_a2 = added_features[i + 1];
+                let offset_a2 = DIM * index_a2 + j * tile_height;
+                let column_a2: *const VecT = to_const_vec_ptr(&ft.weights, offset_a2);
+                // let column_a2: *const VecT = unsafe {
                 //     ft.weights
                 //         .as_ptr()
-                //         .add(offset_a)
+                //         .add(offset_a2)
                 //         .cast()
                 // };
 
-                if removed_features.len() > added_features.len() {
-                    let index_r2 = removed_features[i + 1];
-                    let offset_r2 = DIM * index_r2 + j * tile_height;
-                    let column_r2: *const VecT = to_const_vec_ptr(&ft.weights, offset_r2);
-                    // let column_r2: *const VecT = unsafe {
-                    //     ft.weights
-                    //         .as_ptr()
-                    //         .add(offset_r2)
-                    //         .cast()
-                    // };
-
-                    for k in 0 .. num_regs {
-                        acc[k] = vec_sub_16(
-                            vec_add_16(acc[k], unsafe { *column_a.add(k) }), 
-                            vec_add_16(unsafe { *column_r.add(k) }, unsafe { *column_r2.add(k) })
-                        )
-                    }
-                } else {
-                    let index_a2 = added_features[i + 1];
-                    let offset_a2 = DIM * index_a2 + j * tile_height;
-                    let column_a2: *const VecT = to_const_vec_ptr(&ft.weights, offset_a2);
-                    // let column_a2: *const VecT = unsafe {
-                    //     ft.weights
-                    //         .as_ptr()
-                    //         .add(offset_a2)
-                    //         .cast()
-                    // };
-
-                    for k in 0 .. num_regs {
-                        acc[k] = vec_add_16(
-                            vec_sub_16(acc[k], unsafe { *column_r.add(k) }), 
-                            vec_add_16(unsafe { *column_a.add(k) }, unsafe { *column_a2.add(k) })
-                        )
-                    }
-                }
-            } else {
-                while i < removed_features.len() {
-                    let index = removed_features[i];
-                    let offset = DIM * index + j * tile_height;
-                    let column: *const VecT = to_const_vec_ptr(&ft.weights, offset);
-
-                    for k in 0 .. num_regs {
-                        acc[k] = vec_sub_16(
-                            acc[k],
-                            unsafe { *column.add(k) }
-                        );
-                    }
-                    i += 1;
-                }
-                while i < added_features.len() {
-                    let index = added_features[i];
-                    let offset = DIM * index + j * tile_height;
-                    let column: *const VecT = to_const_vec_ptr(&ft.weights, offset);
-
-                    for k in 0 .. num_regs {
-                        acc[k] = vec_add_16(
-                            acc[k],
-                            unsafe { *column.add(k) }
-                        );
-                    }
-                    i += 1;
+                for k in 0 .. num_regs {
+                    acc[k] = vec_add_16(
+                        vec_sub_16(acc[k], unsafe { *column_r.add(k) }), 
+                        vec_add_16(unsafe { *column_a.add(k) }, unsafe { *column_a2.add(k) })
+                    )
                 }
             }
-
-            // Write out values
-            for k in 0 .. num_regs {
-                vec_store_si256(unsafe { entry_tile.add(k) }, acc[k]);
-            }
-            for k in 0 .. num_regs {
-                vec_store_si256(unsafe { acc_tile.add(k) }, acc[k]);
-            }
-
-        }
-
-        // PSQT Update
-        for j in 0 .. PSQT_BUCKETS / PSQT_TILE_HEIGHT {
-            let acc_tile_psqt: *mut VecT = to_mut_vec_ptr(
-                &mut accum.psqt_accum[perspective as usize], 
-                j * PSQT_TILE_HEIGHT
-            );
-
-            let entry_tile_psqt: *mut VecT = to_mut_vec_ptr(
-                &mut cache_entry.psqt_accum, 
-                j * PSQT_TILE_HEIGHT
-            );
-
-            for k in 0 .. NUM_PSQT_REGS {
-                psqt[k] = unsafe { *entry_tile_psqt.add(k) };
-            }
-
-            for i in 0 .. removed_features.len() {
+        } else {
+            while i < removed_features.len() {
                 let index = removed_features[i];
-                let offset = PSQT_BUCKETS * index + j * PSQT_TILE_HEIGHT;
-                let column_psqt: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset);
+                let offset = DIM * index + j * tile_height;
+                let column: *const VecT = to_const_vec_ptr(&ft.weights, offset);
 
-                for k in 0 .. NUM_PSQT_REGS {
-                    psqt[k] = vec_sub_32(
-                        psqt[k],
-                        unsafe { *column_psqt.add(k) }
+                for k in 0 .. num_regs {
+                    acc[k] = vec_sub_16(
+                        acc[k],
+                        unsafe { *column.add(k) }
                     );
                 }
+                i += 1;
             }
-
-            for i in 0 .. added_features.len() {
+            while i < added_features.len() {
                 let index = added_features[i];
-                let offset = PSQT_BUCKETS * index + j * PSQT_TILE_HEIGHT;
-                let column_psqt: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset);
+                let offset = DIM * index + j * tile_height;
+                let column: *const VecT = to_const_vec_ptr(&ft.weights, offset);
 
-                for k in 0 .. NUM_PSQT_REGS {
-                    psqt[k] = vec_add_32(
-                        psqt[k],
-                        unsafe { *column_psqt.add(k) }
+                for k in 0 .. num_regs {
+                    acc[k] = vec_add_16(
+                        acc[k],
+                        unsafe { *column.add(k) }
                     );
                 }
+                i += 1;
             }
+        }
 
-            // Write out values
-            for k in 0 .. NUM_PSQT_REGS {
-                vec_store_si256(unsafe { entry_tile_psqt.add(k) }, psqt[k]);
-            }
-            for k in 0 .. NUM_PSQT_REGS {
-                vec_store_si256(unsafe { acc_tile_psqt.add(k) }, psqt[k]);
-            }
+        // Write out values
+        for k in 0 .. num_regs {
+            vec_store_si256(unsafe { entry_tile.add(k) }, acc[k]);
+        }
+        for k in 0 .. num_regs {
+            vec_store_si256(unsafe { acc_tile.add(k) }, acc[k]);
         }
 
-    } else {
+    }
 
-        // Normal
-        for index in &removed_features {
-            let offset = DIM * index;
-            for j in 0..DIM {
-                cache_entry.accumulation[j] -= ft.weights[offset + j];
-            }
-            for k in 0..PSQT_BUCKETS {
-                cache_entry.psqt_accum[k] -= ft.psqt_weights[index * PSQT_BUCKETS + k];
-            }
+    // PSQT Update
+    for j in 0 .. PSQT_BUCKETS / PSQT_TILE_HEIGHT {
+        let acc_tile_psqt: *mut VecT = to_mut_vec_ptr(
+            &mut accum.psqt_accum[perspective as usize], 
+            j * PSQT_TILE_HEIGHT
+        );
+
+        let entry_tile_psqt: *mut VecT = to_mut_vec_ptr(
+            &mut cache_entry.psqt_accum, 
+            j * PSQT_TILE_HEIGHT
+        );
+
+        for k in 0 .. NUM_PSQT_REGS {
+            psqt[k] = unsafe { *entry_tile_psqt.add(k) };
         }
-        for index in &added_features {
-            let offset = DIM * index;
-            for j in 0..DIM {
-                cache_entry.accumulation[j] += ft.weights[offset + j];
+
+        for i in 0 .. removed_features.len() {
+            let index = removed_features[i];
+            let offset = PSQT_BUCKETS * index + j * PSQT_TILE_HEIGHT;
+            let column_psqt: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset);
+
+            for k in 0 .. NUM_PSQT_REGS {
+                psqt[k] = vec_sub_32(
+                    psqt[k],
+                    unsafe { *column_psqt.add(k) }
+                );
             }
-            for k in 0..PSQT_BUCKETS {
-                cache_entry.psqt_accum[k] += ft.psqt_weights[index * PSQT_BUCKETS + k];
+        }
+
+        for i in 0 .. added_features.len() {
+            let index = added_features[i];
+            let offset = PSQT_BUCKETS * index + j * PSQT_TILE_HEIGHT;
+            let column_psqt: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset);
+
+            for k in 0 .. NUM_PSQT_REGS {
+                psqt[k] = vec_add_32(
+                    psqt[k],
+                    unsafe { *column_psqt.add(k) }
+                );
             }
         }
 
-        // Cache Entry Accum is updated, copy to Accumulator
-        accum.accumulation[perspective as usize].copy_from_slice(&cache_entry.accumulation);
-        accum.psqt_accum[perspective as usize].copy_from_slice(&cache_entry.psqt_accum);
+        // Write out values
+        for k in 0 .. NUM_PSQT_REGS {
+            vec_store_si256(unsafe { entry_tile_psqt.add(k) }, psqt[k]);
+        }
+        for k in 0 .. NUM_PSQT_REGS {
+            vec_store_si256(unsafe { acc_tile_psqt.add(k) }, psqt[k]);
+        }
     }
 
     // End vector ops block
@@ -422,7 +417,7 @@ fn update_accumulator_incremental<const DIM: usize>(
     let mut removed = IndexList::with_capacity(MAX_ACTIVE_DIMENSIONS);
     let mut added = IndexList::with_capacity(MAX_ACTIVE_DIMENSIONS);
     if direction == Direction::Forward {
-        append_changed_indices(
+        ft.arch.feature_set.append_changed_indices(
             perspective,
             ksq.0,
             &target_state.dirty_piece,
@@ -430,7 +425,7 @@ fn update_accumulator_incremental<const DIM: usize>(
             &mut added,
         );
     } else {
-        append_changed_indices(
+        ft.arch.feature_set.append_changed_indices(
             perspective,
             ksq.0,
             &current_state.dirty_piece,
@@ -453,6 +448,26 @@ fn update_accumulator_incremental<const DIM: usize>(
         target_accum.psqt_accum[perspective as usize]
             .copy_from_slice(&current_accum.psqt_accum[perspective as usize]);
         target_accum.computed[perspective as usize] = true;
+    } else if removed.is_empty() || added.is_empty() {
+        // Only feature sets without king features get here, a king capture removes one
+        // feature and adds none. Rare enough not to need the SIMD paths below.
+        let acc_out = &mut target_accum.accumulation[perspective as usize];
+        let psqt_out = &mut target_accum.psqt_accum[perspective as usize];
+        acc_out.copy_from_slice(&current_accum.accumulation[perspective as usize]);
+        psqt_out.copy_from_slice(&current_accum.psqt_accum[perspective as usize]);
+
+        for (indices, remove) in [(&removed, true), (&added, false)] {
+            for &index in indices.iter() {
+                let column = &ft.weights[DIM * index..DIM * (index + 1)];
+                for (a, &w) in acc_out.iter_mut().zip(column) {
+                    *a = if remove { a.wrapping_sub(w) } else { a.wrapping_add(w) };
+                }
+                let psqt_column = &ft.psqt_weights[PSQT_BUCKETS * index..PSQT_BUCKETS * (index + 1)];
+                for (p, &w) in psqt_out.iter_mut().zip(psqt_column) {
+                    *p = if remove { *p - w } else { *p + w };
+                }
+            }
+        }
     } else {
         assert!(added.len() == 1 || added.len() == 2);
         assert!(removed.len() == 1 || removed.len() == 2);
@@ -462,207 +477,173 @@ fn update_accumulator_incremental<const DIM: usize>(
             assert!(removed.len() <= added.len())
         }
 
-        if USE_AVX2 {
-
-            let acc_in: *const VecT = to_const_vec_ptr(
-                &current_accum.accumulation[perspective as usize], 
-                0
-            );
-            let acc_out: *mut VecT = to_mut_vec_ptr(
-                &mut target_accum.accumulation[perspective as usize], 
-                0
-            );
-
-            let offset_a0 = DIM * added[0];
-            let column_a0: *const VecT = to_const_vec_ptr(&ft.weights, offset_a0);
-            let offset_r0 = DIM * removed[0];
-            let column_r0: *const VecT = to_const_vec_ptr(&ft.weights, offset_r0);
-
-            if (direction == Direction::Forward && removed.len() == 1) ||
-                (direction == Direction::Backward && added.len() == 1) {
-                    assert!(added.len() == 1 && removed.len() == 1);
-                    for i in 0 .. DIM * size_of::<WeightType>() / size_of::<VecT>() {
-
-                        unsafe {
-                            acc_out.add(i).write(
-                                vec_add_16(
-                                    vec_sub_16(*acc_in.add(i), *column_r0.add(i)), 
-                                    *column_a0.add(i)
-                                )
-                            );
-                        }
-
-                    }
-            } else if direction == Direction::Forward && added.len() == 1 {
-                assert!(removed.len() == 2);
-                let offset_r1 = DIM * removed[1];
-                let column_r1: *const VecT = to_const_vec_ptr(&ft.weights, offset_r1);
-
-                for i in 0 .. DIM * size_of::<WeightType>() / size_of::<VecT>() {
-
-                    unsafe {
-                        acc_out.add(i).write(
-                            vec_sub_16(
-                                vec_add_16(*acc_in.add(i), *column_a0.add(i)), 
-                                vec_add_16(*column_r0.add(i), *column_r1.add(i))
-                            )
-                        );
-                    }
+        let acc_in: *const VecT = to_const_vec_ptr(
+            &current_accum.accumulation[perspective as usize], 
+            0
+        );
+        let acc_out: *mut VecT = to_mut_vec_ptr(
+            &mut target_accum.accumulation[perspective as usize], 
+            0
+        );
 
-                }
-            } else if direction == Direction::Backward && removed.len() == 1 {
-                assert!(added.len() == 2);
-                let offset_a1 = DIM * added[1];
-                let column_a1: *const VecT = to_const_vec_ptr(&ft.weights, offset_a1);
+        let offset_a0 = DIM * added[0];
+        let column_a0: *const VecT = to_const_vec_ptr(&ft.weights, offset_a0);
+        let offset_r0 = DIM * removed[0];
+        let column_r0: *const VecT = to_const_vec_ptr(&ft.weights, offset_r0);
 
+        if (direction == Direction::Forward && removed.len() == 1) ||
+            (direction == Direction::Backward && added.len() == 1) {
+                assert!(added.len() == 1 && removed.len() == 1);
                 for i in 0 .. DIM * size_of::<WeightType>() / size_of::<VecT>() {
 
                     unsafe {
                         acc_out.add(i).write(
                             vec_add_16(
-                                vec_add_16(*acc_in.add(i), *column_a0.add(i)), 
-                                vec_sub_16(*column_a1.add(i), *column_r0.add(i))
+                                vec_sub_16(*acc_in.add(i), *column_r0.add(i)), 
+                                *column_a0.add(i)
                             )
                         );
                     }
 
                 }
-            } else {
-                assert!(added.len() == 2 && removed.len() == 2);
-                let offset_a1 = DIM * added[1];
-                let column_a1: *const VecT = to_const_vec_ptr(&ft.weights, offset_a1);
-                let offset_r1 = DIM * removed[1];
-                let column_r1: *const VecT = to_const_vec_ptr(&ft.weights, offset_r1);
-
-                for i in 0 .. DIM * size_of::<WeightType>() / size_of::<VecT>() {
-
-                    unsafe {
-                        acc_out.add(i).write(
-                            vec_add_16(
-                                *acc_in.add(i), 
-                                vec_sub_16(
-                                    vec_add_16(*column_a0.add(i), *column_a1.add(i)), 
-                                    vec_add_16(*column_r0.add(i), *column_r1.add(i))
-                                )
-                            )
-                        );
-                    }
+        } else if direction == Direction::Forward && added.len() == 1 {
+            assert!(removed.len() == 2);
+            let offset_r1 = DIM * removed[1];
+            let column_r1: *const VecT = to_const_vec_ptr(&ft.weights, offset_r1);
+
+            for i in 0 .. DIM * size_of::<WeightType>() / size_of::<VecT>() {
+
+                unsafe {
+                    acc_out.add(i).write(
+                        vec_sub_16(
+                            vec_add_16(*acc_in.add(i), *column_a0.add(i)), 
+                            vec_add_16(*column_r0.add(i), *column_r1.add(i))
+                        )
+                    );
                 }
-            }
-
-            // PSQT Update
-            let acc_psqt_in: *const PsqtVecT = to_const_vec_ptr(
-                &current_accum.psqt_accum[perspective as usize], 
-                0
-            );
-            let acc_psqt_out: *mut PsqtVecT = to_mut_vec_ptr(
-                &mut target_accum.psqt_accum[perspective as usize], 
-                0
-            );
-
-            let offset_psqt_a0 = PSQT_BUCKETS * added[0];
-            let column_psqt_a0: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_a0);
-            let offset_psqt_r0 = PSQT_BUCKETS * removed[0];
-            let column_psqt_r0: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_r0);
-
-            if (direction == Direction::Forward && removed.len() == 1) ||
-                (direction == Direction::Backward && added.len() == 1) {
-                
-                for i in 0 .. PSQT_BUCKETS * size_of::<PsqtWeightType>() / size_of::<PsqtVecT>() {
-
-                    unsafe {
-                        acc_psqt_out.add(i).write(
-                            vec_add_32(
-                                vec_sub_32(*acc_psqt_in.add(i), *column_psqt_r0.add(i)), 
-                                *column_psqt_a0.add(i)
-                            )
-                        );
-                    }
 
+            }
+        } else if direction == Direction::Backward && removed.len() == 1 {
+            assert!(added.len() == 2);
+            let offset_a1 = DIM * added[1];
+            let column_a1: *const VecT = to_const_vec_ptr(&ft.weights, offset_a1);
+
+            for i in 0 .. DIM * size_of::<WeightType>() / size_of::<VecT>() {
+
+                unsafe {
+                    acc_out.add(i).write(
+                        vec_add_16(
+                            vec_add_16(*acc_in.add(i), *column_a0.add(i)), 
+                            vec_sub_16(*column_a1.add(i), *column_r0.add(i))
+                        )
+                    );
                 }
-            } else if direction == Direction::Forward && added.len() == 1 {
-                let offset_psqrt_r1 = PSQT_BUCKETS * removed[1];
-                let column_psqt_r1: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqrt_r1);
-                for i in 0 .. PSQT_BUCKETS * size_of::<PsqtWeightType>() / size_of::<PsqtVecT>() {
 
-                    unsafe {
-                        acc_psqt_out.add(i).write(
-                            vec_sub_32(
-                                vec_add_32(*acc_psqt_in.add(i), *column_psqt_a0.add(i)), 
-                                vec_add_32(*column_psqt_r0.add(i), *column_psqt_r1.add(i))
+            }
+        } else {
+            assert!(added.len() == 2 && removed.len() == 2);
+            let offset_a1 = DIM * added[1];
+            let column_a1: *const VecT = to_const_vec_ptr(&ft.weights, offset_a1);
+            let offset_r1 = DIM * removed[1];
+            let column_r1: *const VecT = to_const_vec_ptr(&ft.weights, offset_r1);
+
+            for i in 0 .. DIM * size_of::<WeightType>() / size_of::<VecT>() {
+
+                unsafe {
+                    acc_out.add(i).write(
+                        vec_add_16(
+                            *acc_in.add(i), 
+                            vec_sub_16(
+                                vec_add_16(*column_a0.add(i), *column_a1.add(i)), 
+                                vec_add_16(*column_r0.add(i), *column_r1.add(i))
                             )
-                        );
-                    }
-
+                        )
+                    );
                 }
+            }
+        }
 
-            } else if direction == Direction::Backward && removed.len() == 1 {
-                
-                let offset_psqt_a1 = PSQT_BUCKETS * added[1];
-                let column_psqt_a1: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_a1);
-                for i in 0 .. PSQT_BUCKETS * size_of::<PsqtWeightType>() / size_of::<PsqtVecT>() {
+        // PSQT Update
+        let acc_psqt_in: *const PsqtVecT = to_const_vec_ptr(
+            &current_accum.psqt_accum[perspective as usize], 
+            0
+        );
+        let acc_psqt_out: *mut PsqtVecT = to_mut_vec_ptr(
+            &mut target_accum.psqt_accum[perspective as usize], 
+            0
+        );
 
-                    unsafe {
-                        acc_psqt_out.add(i).write(
-                            vec_add_32(
-                                vec_add_32(*acc_psqt_in.add(i), *column_psqt_a0.add(i)), 
-                                vec_sub_32(*column_psqt_a1.add(i), *column_psqt_r0.add(i))
-                            )
-                        );
-                    }
+        let offset_psqt_a0 = PSQT_BUCKETS * added[0];
+        let column_psqt_a0: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_a0);
+        let offset_psqt_r0 = PSQT_BUCKETS * removed[0];
+        let column_psqt_r0: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_r0);
+
+        if (direction == Direction::Forward && removed.len() == 1) ||
+            (direction == Direction::Backward && added.len() == 1) {
+            
+            for i in 0 .. PSQT_BUCKETS * size_of::<PsqtWeightType>() / size_of::<PsqtVecT>() {
+
+                unsafe {
+                    acc_psqt_out.add(i).write(
+                        vec_add_32(
+                            vec_sub_32(*acc_psqt_in.add(i), *column_psqt_r0.add(i)), 
+                            *column_psqt_a0.add(i)
+                        )
+                    );
+                }
 
+            }
+        } else if direction == Direction::Forward && added.len() == 1 {
+            let offset_psqrt_r1 = PSQT_BUCKETS * removed[1];
+            let column_psqt_r1: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqrt_r1);
+            for i in 0 .. PSQT_BUCKETS * size_of::<PsqtWeightType>() / size_of::<PsqtVecT>() {
+
+                unsafe {
+                    acc_psqt_out.add(i).write(
+                        vec_sub_32(
+                            vec_add_32(*acc_psqt_in.add(i), *column_psqt_a0.add(i)), 
+                            vec_add_32(*column_psqt_r0.add(i), *column_psqt_r1.add(i))
+                        )
+                    );
                 }
-            } else {
-                let offset_psqt_a1 = PSQT_BUCKETS * added[1];
-                let column_psqt_a1: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_a1);
-                let offset_psqt_r1 = PSQT_BUCKETS * removed[1];
-                let column_psqt_r1: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_r1);
-                for i in 0 .. PSQT_BUCKETS * size_of::<PsqtWeightType>() / size_of::<PsqtVecT>() {
 
-                    unsafe {
-                        acc_psqt_out.add(i).write(
-                            vec_add_32(
-                                *acc_psqt_in.add(i), 
-                                vec_sub_32(
-                                    vec_add_32(*column_psqt_a0.add(i), *column_psqt_a1.add(i)), 
-                                    vec_add_32(*column_psqt_r0.add(i), *column_psqt_r1.add(i))
-                                )
-                            )
-                        );
-                    }
+            }
 
+        } else if direction == Direction::Backward && removed.len() == 1 {
+            
+            let offset_psqt_a1 = PSQT_BUCKETS * added[1];
+            let column_psqt_a1: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_a1);
+            for i in 0 .. PSQT_BUCKETS * size_of::<PsqtWeightType>() / size_of::<PsqtVecT>() {
+
+                unsafe {
+                    acc_psqt_out.add(i).write(
+                        vec_add_32(
+                            vec_add_32(*acc_psqt_in.add(i), *column_psqt_a0.add(i)), 
+                            vec_sub_32(*column_psqt_a1.add(i), *column_psqt_r0.add(i))
+                        )
+                    );
                 }
-            } 
 
+            }
         } else {
-            // Start from current accumulator
-            target_accum.accumulation[perspective as usize]
-                .copy_from_slice(&current_accum.accumulation[perspective as usize]);
-            target_accum.psqt_accum[perspective as usize]
-                .copy_from_slice(&current_accum.psqt_accum[perspective as usize]);
-
-            for index in removed {
-                let offset = DIM * index;
-                for i in 0..DIM {
-                    target_accum.accumulation[perspective as usize][i] -= 
-                        ft.weights[offset + i];
-                }
-                for i in 0..PSQT_BUCKETS {
-                    target_accum.psqt_accum[perspective as usize][i] -=
-                        ft.psqt_weights[index * PSQT_BUCKETS + i];
+            let offset_psqt_a1 = PSQT_BUCKETS * added[1];
+            let column_psqt_a1: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_a1);
+            let offset_psqt_r1 = PSQT_BUCKETS * removed[1];
+            let column_psqt_r1: *const PsqtVecT = to_const_vec_ptr(&ft.psqt_weights, offset_psqt_r1);
+            for i in 0 .. PSQT_BUCKETS * size_of::<PsqtWeightType>() / size_of::<PsqtVecT>() {
+
+                unsafe {
+                    acc_psqt_out.add(i).write(
+                        vec_add_32(
+                            *acc_psqt_in.add(i), 
+                            vec_sub_32(
+                                vec_add_32(*column_psqt_a0.add(i), *column_psqt_a1.add(i)), 
+                                vec_add_32(*column_psqt_r0.add(i), *column_psqt_r1.add(i))
+                            )
+                        )
+                    );
                 }
-            }
 
-            for index in added {
-                let offset = DIM * index;
-                for i in 0..DIM {
-                    target_accum.accumulation[perspective as usize][i] +=
-                        ft.weights[offset + i];
-                }
-                for i in 0..PSQT_BUCKETS {
-                    target_accum.psqt_accum[perspective as usize][i] +=
-                        ft.psqt_weights[index * PSQT_BUCKETS + i];
-                }
             }
         }
     }
@@ -670,20 +651,28 @@ fn update_accumulator_incremental<const DIM: usize>(
     target_accum.computed[perspective as usize] = true;
 }
 
+/// One accumulator per ply with the root at index 0. Room for `MAX_PLY` plies is reserved up
+/// front and states are only built the first time a ply is reached; deeper lines grow the stack.
 pub struct AccumulatorStack {
-    //TODO: Should this be cache aligned? Should I use box?
-    accumulators: Box<[AccumulatorState; 32]>, // MAX_PLY
+    accumulators: Vec<AccumulatorState>,
     current_index: usize,
 }
 
 impl AccumulatorStack {
     pub fn new() -> Self {
+        let mut accumulators = Vec::with_capacity(MAX_PLY + 1);
+        accumulators.push(AccumulatorState::default());
         Self {
-            accumulators: Box::new(from_fn(|_| AccumulatorState::default())),
+            accumulators,
             current_index: 0,
         }
     }
 
+    /// Plies pushed on top of the root
+    pub fn ply(&self) -> usize {
+        self.current_index.saturating_sub(1)
+    }
+
     pub fn reset(&mut self, board: &Board, nnue: &nnue::Nnue, caches: &mut AccumulatorCaches) {
         self.current_index = 1;
 
@@ -701,17 +690,31 @@ impl AccumulatorStack {
             self.accumulators[0].get_accumulator_mut::<TRANSFORMED_FEATURE_DIM_BIG>(),
             &mut caches.big,
         );
-        // Optionally, initialize the base accumulator from the board position here.
+        // The other widths are refreshed from their cache the first time they are used
+        self.accumulators[0].small.computed = [false; COLORS];
+        self.accumulators[0].sf12.computed = [false; COLORS];
     }
 
-    pub fn find_last_usable_accumulator(&self, perspective: Player) -> usize {
+    /// Start over with nothing computed, the next evaluation refreshes from the board through
+    /// whichever cache it is given. Unlike `reset` it needs no network.
+    pub fn clear(&mut self) {
+        self.current_index = 1;
+        self.accumulators[0].big.computed = [false; COLORS];
+        self.accumulators[0].small.computed = [false; COLORS];
+        self.accumulators[0].sf12.computed = [false; COLORS];
+    }
+
+    pub fn find_last_usable_accumulator<const DIM: usize>(
+        &self,
+        perspective: Player,
+        feature_set: FeatureSet,
+    ) -> usize {
         for i in (1..self.current_index).rev() {
-            //TODO: Check small, stockfish does this by passing around an accumulator pointer
-            if self.accumulators[i].big.computed[perspective as usize] {
+            if self.accumulators[i].is_computed::<DIM>(perspective) {
                 return i;
             }
 
-            if requires_refresh(&self.accumulators[i].dirty_piece, perspective) {
+            if feature_set.requires_refresh(&self.accumulators[i].dirty_piece, perspective) {
                 return i;
             }
         }
@@ -733,16 +736,18 @@ impl AccumulatorStack {
         ft: &FeatureTransformer<DIM>,
         cache: &mut AccumulatorCache<DIM>,
     ) {
-        let last_usable_accum = self.find_last_usable_accumulator(perspective);
+        let last_usable_accum = self.find_last_usable_accumulator::<DIM>(perspective, ft.arch.feature_set);
 
-        if self.accumulators[last_usable_accum].big.computed[perspective as usize] {
+        if self.accumulators[last_usable_accum].is_computed::<DIM>(perspective) {
             self.forward_update_incremental(perspective, board, ft, last_usable_accum);
         } else {
+            // Refresh the latest accumulator from the board, then fill in the ones between
+            // it and the last usable one backwards
             update_accumulator_refresh_cache::<DIM>(
                 perspective,
                 ft,
                 board,
-                self.accumulators[last_usable_accum].get_accumulator_mut::<DIM>(),
+                self.current_mut().get_accumulator_mut::<DIM>(),
                 cache,
             );
 
@@ -809,7 +814,9 @@ impl AccumulatorStack {
     }
 
     pub fn push(&mut self, dirty_piece: DirtyPiece) {
-        assert!(self.current_index < MAX_PLY);
+        if self.current_index == self.accumulators.len() {
+            self.accumulators.push(AccumulatorState::default());
+        }
         self.accumulators[self.current_index].reset(dirty_piece);
         self.current_index += 1;
     }
@@ -828,17 +835,34 @@ impl AccumulatorStack {
     }
 }
 
+/// The caches are most of an evaluator's memory, about 800 KB for the big network, so they live
+/// on the heap and an evaluator can be built on any thread's stack
 pub struct AccumulatorCaches {
-    pub big: AccumulatorCache<TRANSFORMED_FEATURE_DIM_BIG>,
-    // pub small: AccumulatorCache<TRANSFORMED_FEATURE_DIM_SMALL>,
+    pub big: Box<AccumulatorCache<TRANSFORMED_FEATURE_DIM_BIG>>,
+    pub small: Box<AccumulatorCache<TRANSFORMED_FEATURE_DIM_SMALL>>,
+    pub sf12: Box<AccumulatorCache<TRANSFORMED_FEATURE_DIM_SF12>>,
 }
 impl AccumulatorCaches {
-    pub fn new(biases: &AVec<i16, VectorAlignment>) -> Self {
-        let mut big = AccumulatorCache::new();
-        big.clear_with_biases(biases);
+    /// Caches for the big network, and for the small one when its biases are given
+    pub fn new(
+        biases: &AVec<i16, VectorAlignment>,
+        small_biases: Option<&AVec<i16, VectorAlignment>>,
+    ) -> Self {
+        let mut caches = Self::empty();
+        caches.big.clear_with_biases(biases);
+        if let Some(small_biases) = small_biases {
+            caches.small.clear_with_biases(small_biases);
+        }
+        caches
+    }
+
+    /// A cache of every width with nothing in it, `clear_with_biases` the ones a network uses.
+    /// The zeroed pages of the others are never touched.
+    pub fn empty() -> Self {
         Self {
-            big,
-            // small: AccumulatorCache::new(),
+            big: AccumulatorCache::new_boxed(),
+            small: AccumulatorCache::new_boxed(),
+            sf12: AccumulatorCache::new_boxed(),
         }
     }
 }
@@ -869,17 +893,11 @@ impl<const DIM: usize> CacheEntry<DIM> {
 }
 
 impl<const DIM: usize> AccumulatorCache<DIM> {
-    pub fn new() -> Self {
-        Self {
-            entries: from_fn(|_| {
-                from_fn(|_| CacheEntry {
-                    accumulation: [0; DIM],
-                    psqt_accum: [0; PSQT_BUCKETS],
-                    by_color_bb: [BitBoard(0); COLORS],
-                    by_type_bb: [BitBoard(0); PIECE_TYPE_NB],
-                })
-            }),
-        }
+    /// All zero, built in place on the heap since it is too big for a stack.
+    /// `clear_with_biases` before use.
+    pub fn new_boxed() -> Box<Self> {
+        // Every field is integers or bitboards, for which all zero bytes are valid
+        unsafe { Box::new_zeroed().assume_init() }
     }
 
     pub fn clear_with_biases(&mut self, biases: &AVec<i16, VectorAlignment>) {
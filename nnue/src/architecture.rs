@@ -0,0 +1,311 @@
+//! Description of a network architecture: input features, layer sizes, bucket counts and
+//! activations. The hashes in a `.nnue` file are computed from the same description, which is
+//! how the loader tells which architecture a file holds.
+
+use std::fmt;
+
+/// Input features of the feature transformer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureSet {
+    /// King bucket, piece and square with horizontal mirroring, 22,528 inputs
+    HalfKAv2Hm,
+    /// Own king square, piece and square for every non king piece, 41,024 inputs
+    HalfKP,
+}
+
+impl FeatureSet {
+    pub const fn hash(self) -> u32 {
+        match self {
+            FeatureSet::HalfKAv2Hm => 0x7F234CB8,
+            FeatureSet::HalfKP => 0x5D69D5B8,
+        }
+    }
+    pub const fn input_dims(self) -> usize {
+        match self {
+            FeatureSet::HalfKAv2Hm => 22_528,
+            FeatureSet::HalfKP => 41_024,
+        }
+    }
+    pub const fn name(self) -> &'static str {
+        match self {
+            FeatureSet::HalfKAv2Hm => "HalfKAv2_hm",
+            FeatureSet::HalfKP => "HalfKP",
+        }
+    }
+}
+
+/// How the feature transformer turns the two accumulators into the first layer's input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    /// Both accumulators clamped to 0..=127 side by side, twice `transformed_dims` outputs.
+    /// The parameters are stored uncompressed and there are no PSQT weights.
+    Clamp,
+    /// Each half of an accumulator clamped and multiplied with the other, `transformed_dims`
+    /// outputs. The parameters are LEB128 compressed and doubled on load.
+    PairwiseMul,
+}
+
+/// One step of a layer stack, in the order Stockfish hashes them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    /// Affine layer on the transformed features, skipping the zero ones
+    AffineSparse { outputs: usize },
+    Affine { outputs: usize },
+    ClippedReLU,
+    /// Squared clipped ReLU of the previous layer, concatenated with its clipped ReLU. It is
+    /// not part of Stockfish's hash.
+    SqrClippedReLUConcat,
+}
+
+impl Layer {
+    const fn hash(self, prev: u32) -> u32 {
+        match self {
+            Layer::AffineSparse { outputs } | Layer::Affine { outputs } => {
+                0xCC03DAE4u32.wrapping_add(outputs as u32) ^ (prev >> 1) ^ (prev << 31)
+            }
+            Layer::ClippedReLU => 0x538D24C7u32.wrapping_add(prev),
+            Layer::SqrClippedReLUConcat => prev,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Architecture {
+    pub name: &'static str,
+    pub version: u32,
+    pub feature_set: FeatureSet,
+    /// Width of each accumulator (L1)
+    pub transformed_dims: usize,
+    pub transform: Transform,
+    pub psqt_buckets: usize,
+    /// Number of layer stacks, one is picked by piece count
+    pub layer_stacks: usize,
+    pub layers: &'static [Layer],
+}
+
+impl Architecture {
+    pub const fn feature_transformer_hash(&self) -> u32 {
+        self.feature_set.hash() ^ (self.transformed_dims as u32 * 2)
+    }
+
+    /// Hash written in front of every layer stack
+    pub const fn layer_stack_hash(&self) -> u32 {
+        let mut hash = 0xEC42E90Du32 ^ (self.transformed_dims as u32 * 2);
+        let mut i = 0;
+        while i < self.layers.len() {
+            hash = self.layers[i].hash(hash);
+            i += 1;
+        }
+        hash
+    }
+
+    /// Hash in the file header
+    pub const fn hash(&self) -> u32 {
+        self.feature_transformer_hash() ^ self.layer_stack_hash()
+    }
+
+    /// Output width of the `n`th affine layer of a stack
+    pub const fn affine_outputs(&self, n: usize) -> usize {
+        let mut seen = 0;
+        let mut i = 0;
+        while i < self.layers.len() {
+            if let Layer::AffineSparse { outputs } | Layer::Affine { outputs } = self.layers[i] {
+                if seen == n {
+                    return outputs;
+                }
+                seen += 1;
+            }
+            i += 1;
+        }
+        panic!("architecture has fewer affine layers");
+    }
+
+    /// Inputs of the first affine layer, what the feature transformer outputs
+    pub const fn ft_outputs(&self) -> usize {
+        match self.transform {
+            Transform::Clamp => self.transformed_dims * 2,
+            Transform::PairwiseMul => self.transformed_dims,
+        }
+    }
+
+    /// Whether the first affine layer skips the zero inputs
+    pub const fn sparse_first_layer(&self) -> bool {
+        matches!(self.layers[0], Layer::AffineSparse { .. })
+    }
+
+    /// Whether the first affine layer goes through `SqrClippedReLUConcat`
+    pub const fn squares_first_layer(&self) -> bool {
+        let mut i = 0;
+        while i < self.layers.len() {
+            if let Layer::SqrClippedReLUConcat = self.layers[i] {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+
+    /// Whether the last output of the first affine layer skips the rest of the stack and is
+    /// added to its output. The Stockfish stacks that square the first layer have one.
+    pub const fn has_forward_term(&self) -> bool {
+        self.squares_first_layer()
+    }
+
+    /// Outputs of the first affine layer that go on to the second, without the forward term
+    pub const fn hidden_dims(&self) -> usize {
+        self.affine_outputs(0) - self.has_forward_term() as usize
+    }
+
+    /// Inputs of the second affine layer, twice the hidden ones when they are also squared
+    pub const fn fc1_inputs(&self) -> usize {
+        self.hidden_dims() * if self.squares_first_layer() { 2 } else { 1 }
+    }
+
+    /// The layer stack, and PSQT bucket, for a position with `pieces` pieces on the board
+    pub const fn bucket(&self, pieces: usize) -> usize {
+        (pieces - 1) * self.layer_stacks / 32
+    }
+
+    /// The architecture with header hash `hash`, among those this build can run
+    pub fn supported(hash: u32) -> Option<&'static Architecture> {
+        SUPPORTED_ARCHITECTURES.iter().copied().find(|arch| arch.hash() == hash)
+    }
+
+    /// The first supported architecture with `dims` transformed features, the Stockfish one for
+    /// the big and small widths. Fails to compile for other widths.
+    pub const fn with_transformed_dims(dims: usize) -> &'static Architecture {
+        let mut i = 0;
+        while i < SUPPORTED_ARCHITECTURES.len() {
+            if SUPPORTED_ARCHITECTURES[i].transformed_dims == dims {
+                return SUPPORTED_ARCHITECTURES[i];
+            }
+            i += 1;
+        }
+        panic!("no supported architecture with this many transformed features");
+    }
+}
+
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:#010x}): {}[{}] -> {}, {} PSQT buckets, {} layer stacks:",
+            self.name,
+            self.hash(),
+            self.feature_set.name(),
+            self.feature_set.input_dims(),
+            self.transformed_dims,
+            self.psqt_buckets,
+            self.layer_stacks
+        )?;
+        for layer in self.layers {
+            match layer {
+                Layer::AffineSparse { outputs } => write!(f, " AffineSparse({})", outputs)?,
+                Layer::Affine { outputs } => write!(f, " Affine({})", outputs)?,
+                Layer::ClippedReLU => write!(f, " ClippedReLU")?,
+                Layer::SqrClippedReLUConcat => write!(f, " SqrClippedReLU+Concat")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Layer stack shared by the Stockfish 16.1 to 17.1 networks, L2 = 15 and L3 = 32
+const SF_LAYERS: &[Layer] = &[
+    Layer::AffineSparse { outputs: 16 },
+    Layer::SqrClippedReLUConcat,
+    Layer::ClippedReLU,
+    Layer::Affine { outputs: 32 },
+    Layer::ClippedReLU,
+    Layer::Affine { outputs: 1 },
+];
+
+/// nn-1c0000000000.nnue and the other big Stockfish 16.1 to 17.1 networks
+pub const SF_BIG: Architecture = Architecture {
+    name: "Stockfish 17.1 big",
+    version: 0x7AF32F20,
+    feature_set: FeatureSet::HalfKAv2Hm,
+    transformed_dims: 3072,
+    transform: Transform::PairwiseMul,
+    psqt_buckets: 8,
+    layer_stacks: 8,
+    layers: SF_LAYERS,
+};
+
+/// nn-37f18f62d772.nnue and the other small Stockfish 16.1 to 17.1 networks
+pub const SF_SMALL: Architecture = Architecture {
+    name: "Stockfish 17.1 small",
+    transformed_dims: 128,
+    ..SF_BIG
+};
+
+/// halfkp_256x2-32-32, the networks Stockfish 12 shipped with, e.g. nn-82215d0fd0df.nnue
+pub const SF12: Architecture = Architecture {
+    name: "Stockfish 12",
+    version: 0x7AF32F16,
+    feature_set: FeatureSet::HalfKP,
+    transformed_dims: 256,
+    transform: Transform::Clamp,
+    psqt_buckets: 0,
+    layer_stacks: 1,
+    layers: &[
+        Layer::Affine { outputs: 32 },
+        Layer::ClippedReLU,
+        Layer::Affine { outputs: 32 },
+        Layer::ClippedReLU,
+        Layer::Affine { outputs: 1 },
+    ],
+};
+
+/// The big network layers on HalfKP features. No public network has this shape, it is for
+/// networks we train ourselves.
+pub const HALF_KP_BIG: Architecture = Architecture {
+    name: "HalfKP big",
+    feature_set: FeatureSet::HalfKP,
+    ..SF_BIG
+};
+
+/// The small network layers on HalfKP features, the shape we train ourselves. Like
+/// `HALF_KP_BIG` there is no public network of this shape.
+pub const HALF_KP_SMALL: Architecture = Architecture {
+    name: "HalfKP small",
+    feature_set: FeatureSet::HalfKP,
+    ..SF_SMALL
+};
+
+/// Architectures with an implementation in this crate. The Stockfish ones come first so they
+/// are the defaults for their widths.
+pub const SUPPORTED_ARCHITECTURES: &[&Architecture] =
+    &[&SF_BIG, &SF_SMALL, &SF12, &HALF_KP_BIG, &HALF_KP_SMALL];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stockfish_hashes() {
+        assert_eq!(SF_BIG.feature_transformer_hash(), 2133021880);
+        assert_eq!(SF_BIG.layer_stack_hash(), 1664316490);
+        assert_eq!(SF_BIG.hash(), 470819058);
+        assert_eq!(SF_SMALL.feature_transformer_hash(), 2133020088);
+        assert_eq!(SF_SMALL.layer_stack_hash(), 1664315690);
+        assert_eq!(SF_SMALL.hash(), 470826130);
+
+        assert_eq!(Architecture::supported(470826130), Some(&SF_SMALL));
+        assert_eq!(Architecture::supported(0), None);
+        assert_eq!(Architecture::with_transformed_dims(128), &SF_SMALL);
+
+        // halfkp_256x2-32-32 from Stockfish 12 hashes like the networks of that release
+        assert_eq!(SF12.hash(), 0x3E5AA6EE);
+        assert_eq!(Architecture::supported(0x3E5AA6EE), Some(&SF12));
+        assert_eq!(Architecture::with_transformed_dims(256), &SF12);
+        assert_eq!((SF12.ft_outputs(), SF12.hidden_dims(), SF12.fc1_inputs()), (512, 32, 32));
+        assert_eq!((SF12.bucket(2), SF12.bucket(32)), (0, 0));
+        assert!(!SF12.sparse_first_layer() && !SF12.has_forward_term());
+        assert_eq!(HALF_KP_SMALL.layer_stack_hash(), SF_SMALL.layer_stack_hash());
+        assert_eq!(Architecture::supported(HALF_KP_SMALL.hash()), Some(&HALF_KP_SMALL));
+        assert_eq!((SF_BIG.affine_outputs(0), SF_BIG.affine_outputs(1)), (16, 32));
+        assert_eq!((SF_BIG.hidden_dims(), SF_BIG.fc1_inputs()), (15, 30));
+        assert_eq!((SF_BIG.bucket(2), SF_BIG.bucket(5), SF_BIG.bucket(32)), (0, 1, 7));
+    }
+}
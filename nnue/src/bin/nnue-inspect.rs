@@ -0,0 +1,125 @@
+//! Looks inside a `.nnue` file: the header, the layers, statistics of the parameters and how
+//! often the activations saturate, and optionally how it differs from another network.
+//!
+//! `nnue-inspect <net> [--diff <other>] [--positions <path>] [--samples N] [--seed N]`
+//!
+//! Saturation and evaluation differences are measured on the FENs of `<path>`, one per line
+//! with anything after a `|` ignored, or on `N` positions of random games.
+
+use std::{fs::File, io::BufRead, io::BufReader};
+
+use clap::Parser;
+use nnue::inspect::{self, Header, Stats};
+use nnue::nnue::{load_any_nnue, AnyNnue};
+use nnue::reference::ReferenceNnue;
+use pleco::Board;
+
+#[derive(Parser)]
+#[command(about = "Look inside a .nnue file")]
+struct Cli {
+    /// Network to look inside
+    net: String,
+    /// Network to compare it with
+    #[arg(long = "diff")]
+    other: Option<String>,
+    /// FENs to measure on, one per line with anything after a `|` ignored
+    #[arg(long)]
+    positions: Option<String>,
+    /// Positions of random games to measure on without --positions
+    #[arg(long, default_value_t = 1000)]
+    samples: usize,
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+}
+
+fn read_positions(path: &str) -> Result<Vec<Board>, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+    let mut boards = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("failed to read line {}: {}", number + 1, e))?;
+        let fen = line.split('|').next().unwrap_or_default().trim();
+        if fen.is_empty() || fen.starts_with('#') {
+            continue;
+        }
+        match Board::from_fen(fen) {
+            Ok(board) => boards.push(board),
+            Err(e) => eprintln!("Skipping line {}, bad FEN {}: {:?}", number + 1, fen, e),
+        }
+    }
+    Ok(boards)
+}
+
+/// Prints the header and loads the network, explaining the hash when it can't be loaded
+fn load(path: &str) -> Result<AnyNnue, String> {
+    let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+    let header = Header::read(&mut BufReader::new(file))
+        .map_err(|e| format!("failed to read the header of {}: {}", path, e))?;
+    println!("{}", path);
+    println!("{}", header);
+    load_any_nnue(path).map_err(|e| format!("failed to load {}: {}\nThe hash says: {}", path, e, header.explain_hash()))
+}
+
+fn print_stats(name: &str, stats: &Stats) {
+    println!("{:<22} {}", name, stats);
+    for line in stats.histogram_lines() {
+        println!("    {}", line);
+    }
+}
+
+pub fn main() {
+    if let Err(e) = run(&Cli::parse()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), String> {
+    let net = load(&cli.net)?.to_quantized();
+
+    println!("\nLayers");
+    for line in inspect::describe_layers(net.arch) {
+        println!("  {}", line);
+    }
+
+    println!("\nParameters");
+    print_stats("ft.weights", &inspect::ft_weight_stats(&net));
+    for (name, stats) in inspect::parameter_stats(&net) {
+        print_stats(&name, &stats);
+    }
+    println!("\nParameters per stack");
+    for (name, stats) in inspect::stack_stats(&net) {
+        println!("  {:<20} {}", name, stats);
+    }
+
+    let boards = match &cli.positions {
+        Some(path) => read_positions(path)?,
+        None => inspect::sample_positions(cli.samples, cli.seed),
+    };
+    let other = match &cli.other {
+        Some(path) => {
+            println!();
+            let other = load(path)?.to_quantized();
+            println!("\nDifferences");
+            match inspect::diff(&net, &other) {
+                Ok(diffs) => {
+                    for diff in diffs {
+                        println!("  {}", diff);
+                    }
+                }
+                Err(e) => println!("  {}", e),
+            }
+            Some(ReferenceNnue::new(other))
+        }
+        None => None,
+    };
+
+    let reference = ReferenceNnue::new(net);
+    if let Some(other) = &other {
+        println!("  eval: {}", inspect::eval_diff(&reference, other, &boards));
+    }
+    println!("\nSaturation over {} positions", boards.len());
+    for saturation in inspect::saturation(&reference, &boards) {
+        println!("  {}", saturation);
+    }
+    Ok(())
+}
@@ -1,9 +1,13 @@
 use aligned_vec::ConstAlign;
 use pleco::{PieceType, Player};
 
+use crate::architecture::{SF12, SF_BIG, SF_SMALL};
+
 /// This is the NNUE file that is currently supported. All hashes/versions etc are based
 /// upon this file used in stockfish 17.1
 pub const NNUE_FILE: &str = "nn-1c0000000000.nnue";
+/// The small network shipped with the same Stockfish release
+pub const SMALL_NNUE_FILE: &str = "nn-37f18f62d772.nnue";
 
 pub const COLORS: usize = 2; // 0=White,1=Black
 pub const COLOR_OPS: [Player; COLORS] = [Player::White, Player::Black];
@@ -20,26 +24,23 @@ pub const PAWN_THROUGH_KING: [PieceType; 6] = [
 pub type WeightType = i16;
 pub type PsqtWeightType = i32;
 
-pub const VERSION: u32 = 0x7AF32F20;
-pub const BIG_HASH: u32 = 470819058;
-pub const LAYER_STACKS: usize = 8;
-pub const PSQT_BUCKETS: usize = 8;
+/// PSQT buckets an accumulator holds. Networks with fewer have their PSQT rows padded with
+/// zeros, the layer stacks and everything after them are sized by the architecture.
+pub const PSQT_BUCKETS: usize = SF_BIG.psqt_buckets;
 
-pub const MAX_PLY: usize = 64;
+/// Plies the accumulator stack holds without growing, the search's ceiling
+pub const MAX_PLY: usize = 128;
 
-pub const TRANSFORMED_FEATURE_DIM_BIG: usize = 3072;
-pub const TRANSFORMED_FEATURE_DIM_SMALL: usize = 128;
-pub const L1: usize = 3_072;
-pub const L1_SMALL: usize = 128;
-pub const L2: usize = 15;
-pub const L2_PLUS_1: usize = L2 + 1;
-pub const L3: usize = 32;
+// The accumulators are sized at compile time, one slot per supported transformer width
+pub const TRANSFORMED_FEATURE_DIM_BIG: usize = SF_BIG.transformed_dims;
+pub const TRANSFORMED_FEATURE_DIM_SMALL: usize = SF_SMALL.transformed_dims;
+pub const TRANSFORMED_FEATURE_DIM_SF12: usize = SF12.transformed_dims;
+pub const L1: usize = TRANSFORMED_FEATURE_DIM_BIG;
+pub const L1_SMALL: usize = TRANSFORMED_FEATURE_DIM_SMALL;
 
 
 pub const MAX_SIMD_WIDTH: usize = 32; // AVX2
 
-pub const USE_AVX2: bool = cfg!(target_feature = "avx2");
-pub const USE_SSSE3: bool = cfg!(target_feature = "ssse3");
 
 pub const SQUARES: usize = 64;
 
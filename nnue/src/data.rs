@@ -0,0 +1,278 @@
+//! Training samples and the file formats they are stored in, shared by the data generator and
+//! the trainer. Both formats are bullet's: the text one, `<fen> | <score> | <result>` with white
+//! relative score and result, and the 32 byte `ChessBoard` of the `bulletformat` crate, which
+//! bullet-utils can turn into Stockfish's `.binpack` among others.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use pleco::{Board, PieceType, Player};
+
+use crate::constants::PAWN_THROUGH_KING;
+
+/// Outcome of the game a sample was taken from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    BlackWin,
+    Draw,
+    WhiteWin,
+}
+
+impl GameResult {
+    /// 0 for a loss, 1 for a draw and 2 for a win of `player`
+    pub fn half_points(self, player: Player) -> u8 {
+        let white = match self {
+            GameResult::BlackWin => 0,
+            GameResult::Draw => 1,
+            GameResult::WhiteWin => 2,
+        };
+        if player == Player::White { white } else { 2 - white }
+    }
+}
+
+/// Storage format of a sample file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataFormat {
+    /// One `<fen> | <score> | <result>` line per sample
+    Text,
+    /// bulletformat's `ChessBoard`, 32 bytes per sample
+    Bullet,
+}
+
+impl FromStr for DataFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DataFormat::Text),
+            "bullet" => Ok(DataFormat::Bullet),
+            _ => Err(format!("unknown data format {}, expected text or bullet", s)),
+        }
+    }
+}
+
+pub const BULLET_SAMPLE_SIZE: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub fen: String,
+    /// Search score in centipawns, from white's point of view. Internal scores are turned into
+    /// centipawns with `nnue_utils::to_cp`, as Stockfish reports them.
+    pub score: i16,
+    pub result: GameResult,
+}
+
+impl Sample {
+    pub fn new(board: &Board, stm_score: i16, result: GameResult) -> Self {
+        let score = if board.turn() == Player::White { stm_score } else { -stm_score };
+        Sample { fen: board.fen(), score, result }
+    }
+
+    pub fn write(&self, w: &mut impl Write, format: DataFormat) -> io::Result<()> {
+        match format {
+            DataFormat::Text => writeln!(w, "{}", self),
+            DataFormat::Bullet => w.write_all(&self.to_bullet()),
+        }
+    }
+
+    /// bulletformat's `ChessBoard`: the board from the side to move's point of view, flipped
+    /// vertically with colours swapped when black is to move. `occ` is followed by one nibble
+    /// per occupied square, `colour << 3 | piece` with colour 0 for the side to move, then the
+    /// score and result relative to the side to move, both kings and three spare bytes.
+    pub fn to_bullet(&self) -> [u8; BULLET_SAMPLE_SIZE] {
+        let board = Board::from_fen(&self.fen).expect("sample has a valid FEN");
+        let stm = board.turn();
+        let orient = |bb: u64| if stm == Player::White { bb } else { bb.swap_bytes() };
+
+        let ours = orient(board.get_occupied_player(stm).0);
+        let theirs = orient(board.get_occupied_player(!stm).0);
+        let pieces = PAWN_THROUGH_KING.map(|pt| orient(board.piece_bb_both_players(pt).0));
+
+        let occ = ours | theirs;
+        let mut pcs = [0u8; 16];
+        let mut rest = occ;
+        let mut i = 0;
+        while rest != 0 {
+            let bit = rest & rest.wrapping_neg();
+            rest &= rest - 1;
+            let colour = u8::from(theirs & bit != 0) << 3;
+            let piece = pieces.iter().position(|bb| bb & bit != 0).unwrap() as u8;
+            pcs[i / 2] |= (colour | piece) << (4 * (i & 1));
+            i += 1;
+        }
+
+        let score = if stm == Player::White { self.score } else { -self.score };
+        let ksq = (ours & pieces[PieceType::K as usize - 1]).trailing_zeros() as u8;
+        let opp_ksq = (theirs & pieces[PieceType::K as usize - 1]).trailing_zeros() as u8 ^ 56;
+
+        let mut out = [0u8; BULLET_SAMPLE_SIZE];
+        out[..8].copy_from_slice(&occ.to_le_bytes());
+        out[8..24].copy_from_slice(&pcs);
+        out[24..26].copy_from_slice(&score.to_le_bytes());
+        out[26] = self.result.half_points(stm);
+        out[27] = ksq;
+        out[28] = opp_ksq;
+        out
+    }
+
+    /// Inverse of `to_bullet`. The format keeps neither the side to move nor castling rights, so
+    /// the board comes back from the side to move's point of view with white to move.
+    pub fn from_bullet(bytes: &[u8; BULLET_SAMPLE_SIZE]) -> Result<Self, String> {
+        let mut squares = [None; 64];
+        let mut rest = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let mut i = 0;
+        while rest != 0 {
+            if i == 32 {
+                return Err("bullet sample has more than 32 pieces".to_string());
+            }
+            let sq = rest.trailing_zeros() as usize;
+            rest &= rest - 1;
+            let nibble = (bytes[8 + i / 2] >> (4 * (i & 1))) & 0xF;
+            let piece = *b"PNBRQK"
+                .get(usize::from(nibble & 7))
+                .ok_or_else(|| format!("bad piece {} in bullet sample", nibble))?;
+            squares[sq] = Some(if nibble & 8 == 0 { piece } else { piece.to_ascii_lowercase() });
+            i += 1;
+        }
+
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match squares[rank * 8 + file] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece as char);
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            fen.push(if rank > 0 { '/' } else { ' ' });
+        }
+        fen.push_str("w - - 0 1");
+        Board::from_fen(&fen).map_err(|e| format!("bad bullet sample {}: {:?}", fen, e))?;
+
+        let result = match bytes[26] {
+            0 => GameResult::BlackWin,
+            1 => GameResult::Draw,
+            2 => GameResult::WhiteWin,
+            r => return Err(format!("bad result {} in bullet sample", r)),
+        };
+        Ok(Sample { fen, score: i16::from_le_bytes([bytes[24], bytes[25]]), result })
+    }
+}
+
+/// Every sample of a file in `format`
+pub fn read_samples(mut r: impl BufRead, format: DataFormat) -> io::Result<Vec<Sample>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut samples = Vec::new();
+    match format {
+        DataFormat::Text => {
+            for line in r.lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    samples.push(line.parse().map_err(invalid)?);
+                }
+            }
+        }
+        DataFormat::Bullet => {
+            let mut bytes = [0u8; BULLET_SAMPLE_SIZE];
+            while !r.fill_buf()?.is_empty() {
+                r.read_exact(&mut bytes)?;
+                samples.push(Sample::from_bullet(&bytes).map_err(invalid)?);
+            }
+        }
+    }
+    Ok(samples)
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = match self.result {
+            GameResult::BlackWin => "0.0",
+            GameResult::Draw => "0.5",
+            GameResult::WhiteWin => "1.0",
+        };
+        write!(f, "{} | {} | {}", self.fen, self.score, result)
+    }
+}
+
+impl FromStr for Sample {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut parts = line.split('|').map(str::trim);
+        let (Some(fen), Some(score), Some(result), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!("expected <fen> | <score> | <result>, got {}", line));
+        };
+        Board::from_fen(fen).map_err(|e| format!("bad FEN {}: {:?}", fen, e))?;
+        let score = score.parse().map_err(|_| format!("bad score {}", score))?;
+        let result = match result {
+            "0" | "0.0" => GameResult::BlackWin,
+            "0.5" => GameResult::Draw,
+            "1" | "1.0" => GameResult::WhiteWin,
+            _ => return Err(format!("bad result {}", result)),
+        };
+        Ok(Sample { fen: fen.to_string(), score, result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_formats() {
+        let start = Board::start_pos();
+        let sample = Sample::new(&start, 25, GameResult::WhiteWin);
+        let line = sample.to_string();
+        assert_eq!(line, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 | 25 | 1.0");
+        assert_eq!(line.parse::<Sample>(), Ok(sample.clone()));
+        assert!("8/8/8 w - - 0 1 | 1 | 1.0".parse::<Sample>().is_err());
+        assert!(line.replace("1.0", "2").parse::<Sample>().is_err());
+
+        let packed = sample.to_bullet();
+        assert_eq!(u64::from_le_bytes(packed[..8].try_into().unwrap()), 0xFFFF_0000_0000_FFFF);
+        // a1 rook and b1 knight of the side to move, then the black pawns on a7 and b7
+        assert_eq!(packed[8], 0x13);
+        assert_eq!(packed[16], 0x88);
+        assert_eq!(i16::from_le_bytes([packed[24], packed[25]]), 25);
+        assert_eq!(&packed[26..], &[2, 4, 4, 0, 0, 0]);
+
+        // With black to move everything is seen from black's side
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        let packed = Sample::new(&board, 30, GameResult::WhiteWin).to_bullet();
+        let occ = u64::from_le_bytes(packed[..8].try_into().unwrap());
+        assert_eq!(occ, 0xFFEF_0010_0000_FFFF);
+        assert_eq!(packed[8], 0x13);
+        assert_eq!(i16::from_le_bytes([packed[24], packed[25]]), 30);
+        assert_eq!(packed[26], 0);
+        assert_eq!((packed[27], packed[28]), (4, 4));
+
+        // Unpacking gives the same board seen from the side to move, white to move
+        let unpacked = Sample::from_bullet(&packed).unwrap();
+        assert_eq!(unpacked.fen, "rnbqkbnr/pppp1ppp/8/4p3/8/8/PPPPPPPP/RNBQKBNR w - - 0 1");
+        assert_eq!((unpacked.score, unpacked.result), (30, GameResult::BlackWin));
+        assert_eq!(unpacked.to_bullet(), packed);
+
+        let mut bytes = Vec::new();
+        for format in [DataFormat::Text, DataFormat::Bullet] {
+            bytes.clear();
+            sample.write(&mut bytes, format).unwrap();
+            sample.write(&mut bytes, format).unwrap();
+            let read = read_samples(bytes.as_slice(), format).unwrap();
+            assert_eq!(read.len(), 2);
+            assert_eq!(read[1].to_bullet(), sample.to_bullet());
+        }
+        assert!(read_samples(&bytes[..40], DataFormat::Bullet).is_err());
+    }
+}
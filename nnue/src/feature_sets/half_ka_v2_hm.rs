@@ -5,10 +5,13 @@ use core::panic;
 
 use pleco::{PieceType, Player, SQ};
 
+use crate::architecture::FeatureSet;
 use crate::nnue_misc::DirtyPiece;
 
+use super::IndexList;
+
 // The feature set, halfka_v2_hm, uses 22,528 input features.
-pub const INPUT_DIM: usize = 22_528;
+pub const INPUT_DIM: usize = FeatureSet::HalfKAv2Hm.input_dims();
 
 const SQ_A1: u8 = 0;
 const SQ_H1: u8 = 7;
@@ -90,12 +93,6 @@ pub fn make_index(perspective: usize, square: u8, piece: usize, king_sq: u8) ->
 }
 
 
-// Max number of simultaneously active features
-pub const MAX_ACTIVE_DIMENSIONS: usize = 32;
-// pub type IndexList = [usize; MAX_ACTIVE_DIMENSIONS];
-pub type IndexList = Vec<usize>;
-
-
 /// Check if a DirtyPiece requires a refresh of the accumulator cache
 /// This occurs when this perspective's king has moved
 pub fn requires_refresh(dp: &DirtyPiece, perspective: Player) -> bool {
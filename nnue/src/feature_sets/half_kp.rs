@@ -0,0 +1,93 @@
+use pleco::{PieceType, Player, SQ};
+
+use crate::architecture::FeatureSet;
+use crate::nnue_misc::DirtyPiece;
+
+use super::IndexList;
+
+// The feature set, halfkp, uses 41,024 input features: 64 king squares times 641 piece squares.
+pub const INPUT_DIM: usize = FeatureSet::HalfKP.input_dims();
+
+const COLOR_NB: usize = 2;
+const PIECE_NB: usize = 16;
+// Index 0 is unused, a leftover of the Shogi "BonaPiece" numbering
+const PS_NB: u32 = 10 * 64 + 1;
+
+// PieceSquareIndex[perspective][piece], own pieces first. Kings are not features.
+const PIECE_SQUARE_INDEX: [[u32; PIECE_NB]; COLOR_NB] = [
+    // perspective = white
+    [0, 1, 64 * 2 + 1, 64 * 4 + 1, 64 * 6 + 1, 64 * 8 + 1, 0, 0, 0, 64 + 1, 64 * 3 + 1, 64 * 5 + 1, 64 * 7 + 1, 64 * 9 + 1, 0, 0],
+    // perspective = black
+    [0, 64 + 1, 64 * 3 + 1, 64 * 5 + 1, 64 * 7 + 1, 64 * 9 + 1, 0, 0, 0, 1, 64 * 2 + 1, 64 * 4 + 1, 64 * 6 + 1, 64 * 8 + 1, 0, 0],
+];
+
+/// Black sees the board rotated by 180 degrees, there is no mirroring
+#[inline]
+fn orient(perspective: usize, square: u8) -> u32 {
+    u32::from(square) ^ (perspective as u32 * 63)
+}
+
+/// Every king square is its own bucket
+#[inline]
+pub fn make_index(perspective: usize, square: u8, piece: usize, king_sq: u8) -> usize {
+    (orient(perspective, square)
+        + PIECE_SQUARE_INDEX[perspective][piece]
+        + PS_NB * orient(perspective, king_sq)) as usize
+}
+
+/// Whether pieces of this type have features, kings only choose the bucket
+#[inline]
+pub fn is_feature(pt: PieceType) -> bool {
+    pt != PieceType::K
+}
+
+/// Same as HalfKAv2_hm, every feature of this perspective moves with its king
+pub fn requires_refresh(dp: &DirtyPiece, perspective: Player) -> bool {
+    dp.piece[0].player_piece()
+        .map(|(player, pt)| player == perspective && pt == PieceType::K)
+        .expect("DirtyPiece has no piece set")
+}
+
+/// A king move only changes the other pieces of the move, so a king capture removes a
+/// feature without adding one and a plain king move changes nothing.
+pub fn append_changed_indices(
+    perspective: Player,
+    king_sq: u8,
+    dp: &DirtyPiece,
+    removed: &mut IndexList,
+    added: &mut IndexList,
+) {
+    for i in 0..dp.dirty_num {
+        if dp.piece[i].type_of() == PieceType::K {
+            continue;
+        }
+        if dp.from[i] != SQ::NONE {
+            removed.push(make_index(perspective as usize, dp.from[i].0, dp.piece[i] as usize, king_sq));
+        }
+        if dp.to[i] != SQ::NONE {
+            added.push(make_index(perspective as usize, dp.to[i].0, dp.piece[i] as usize, king_sq));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pleco::Piece;
+
+    #[test]
+    fn test_half_kp_indices() {
+        // Values from Stockfish 12's HalfKP::MakeIndex
+        assert_eq!(make_index(0, SQ::E2.0, Piece::WhitePawn as usize, SQ::E1.0), 4 * 641 + 1 + 12);
+        assert_eq!(make_index(1, SQ::E2.0, Piece::WhitePawn as usize, SQ::E8.0), 3 * 641 + 65 + 51);
+        assert_eq!(make_index(1, SQ::H8.0, Piece::BlackQueen as usize, SQ::A1.0), 63 * 641 + 513);
+        assert_eq!(make_index(0, SQ::H8.0, Piece::BlackQueen as usize, SQ::H8.0), 63 * 641 + 577 + 63);
+
+        // Black's view is the board rotated, not flipped
+        assert_eq!(
+            make_index(0, SQ::C3.0, Piece::WhiteKnight as usize, SQ::G1.0),
+            make_index(1, SQ::F6.0, Piece::BlackKnight as usize, SQ::B8.0)
+        );
+        assert!(make_index(0, SQ::H8.0, Piece::BlackQueen as usize, SQ::H8.0) < INPUT_DIM);
+    }
+}
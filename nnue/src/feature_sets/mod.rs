@@ -1,3 +1,78 @@
 mod half_ka_v2_hm;
+mod half_kp;
 
-pub use half_ka_v2_hm::*;
\ No newline at end of file
+use pleco::{Board, Piece, PieceType, Player};
+
+use crate::architecture::FeatureSet;
+use crate::constants::PAWN_THROUGH_KING;
+use crate::nnue_misc::DirtyPiece;
+
+// Max number of simultaneously active features
+pub const MAX_ACTIVE_DIMENSIONS: usize = 32;
+// pub type IndexList = [usize; MAX_ACTIVE_DIMENSIONS];
+pub type IndexList = Vec<usize>;
+
+/// The feature set of a network is only known once its header is read, so the accumulator
+/// code goes through these instead of calling a feature set module directly
+impl FeatureSet {
+    #[inline]
+    pub fn make_index(self, perspective: usize, square: u8, piece: usize, king_sq: u8) -> usize {
+        match self {
+            FeatureSet::HalfKAv2Hm => half_ka_v2_hm::make_index(perspective, square, piece, king_sq),
+            FeatureSet::HalfKP => half_kp::make_index(perspective, square, piece, king_sq),
+        }
+    }
+
+    /// Every active feature of `board` seen from `perspective`, what a refresh adds up. Meant
+    /// for trainers and tools, the accumulators work on differences instead.
+    pub fn active_features(self, board: &Board, perspective: Player) -> IndexList {
+        let king_sq = board.king_sq(perspective);
+        let mut features = IndexList::with_capacity(MAX_ACTIVE_DIMENSIONS);
+        for c in [Player::White, Player::Black] {
+            for pt in PAWN_THROUGH_KING {
+                if !self.is_feature(pt) {
+                    continue;
+                }
+                let piece = Piece::make_lossy(c, pt);
+                let mut bb = board.piece_bb(c, pt);
+                while bb.is_not_empty() {
+                    let sq = bb.pop_lsb();
+                    features.push(self.make_index(perspective as usize, sq.0, piece as usize, king_sq.0));
+                }
+            }
+        }
+        features
+    }
+
+    /// Whether pieces of this type have features of their own
+    #[inline]
+    pub fn is_feature(self, pt: PieceType) -> bool {
+        match self {
+            FeatureSet::HalfKAv2Hm => true,
+            FeatureSet::HalfKP => half_kp::is_feature(pt),
+        }
+    }
+
+    pub fn requires_refresh(self, dp: &DirtyPiece, perspective: Player) -> bool {
+        match self {
+            FeatureSet::HalfKAv2Hm => half_ka_v2_hm::requires_refresh(dp, perspective),
+            FeatureSet::HalfKP => half_kp::requires_refresh(dp, perspective),
+        }
+    }
+
+    pub fn append_changed_indices(
+        self,
+        perspective: Player,
+        king_sq: u8,
+        dp: &DirtyPiece,
+        removed: &mut IndexList,
+        added: &mut IndexList,
+    ) {
+        match self {
+            FeatureSet::HalfKAv2Hm => {
+                half_ka_v2_hm::append_changed_indices(perspective, king_sq, dp, removed, added)
+            }
+            FeatureSet::HalfKP => half_kp::append_changed_indices(perspective, king_sq, dp, removed, added),
+        }
+    }
+}
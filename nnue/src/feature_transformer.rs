@@ -1,15 +1,15 @@
 use std::{
     fmt::Debug,
-    io::{self, Read},
+    io::{self, Read, Write},
 };
 
 use aligned_vec::AVec;
-use pleco::Board;
+use pleco::{Board, Player};
 
+use crate::architecture::{Architecture, Transform};
 use crate::{
-    accumulator::{AccumulatorCache, AccumulatorStack},
+    accumulator::{Accumulator, AccumulatorCache, AccumulatorStack},
     constants::*,
-    feature_sets::INPUT_DIM,
     nnue_utils::*,
     vectors::{
         MAX_CHUNK_SIZE, VecT, vec_max_16, vec_min_16, vec_mulhi_16, vec_packus_16, vec_set1_16,
@@ -28,13 +28,16 @@ type OutputType = u8;
 /// Some methods use unsafe code for pointer arithmetic and SIMD operations.
 /// # Description
 pub struct FeatureTransformer<const FEATURE_DIM: usize> {
+    pub arch: &'static Architecture,
     pub biases: AVec<i16, VectorAlignment>,       // FEATURE_DIMENSIONS
     pub weights: AVec<WeightType, VectorAlignment>,      // FEATURE_DIMENSIONS * INPUT_DIM
-    pub psqt_weights: AVec<PsqtWeightType, VectorAlignment>, // PSQT_BUCKETS * INPUT_DIM
+    /// PSQT_BUCKETS * INPUT_DIM, the buckets past the architecture's are zero
+    pub psqt_weights: AVec<PsqtWeightType, VectorAlignment>,
 }
 
 impl<const DIM: usize> Debug for FeatureTransformer<DIM> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("  Features {}\n", self.arch.feature_set.name()))?;
         f.write_fmt(format_args!("  Biases Len {}\n", self.biases.len()))?;
         f.write_str(&get_first_and_last(&self.biases))?;
         f.write_fmt(format_args!("  Weights Len {}\n", self.weights.len()))?;
@@ -61,46 +64,119 @@ const INV_PACK_ORDER: [usize; 8] = {
     inv
 };
 impl<const FEATURE_DIM: usize> FeatureTransformer<FEATURE_DIM> {
-    pub fn read_parameters(r: &mut impl Read) -> io::Result<Self> {
+    /// Hash of the input features combined with the output width
+    pub const fn hash(&self) -> u32 {
+        self.arch.feature_set.hash() ^ (FEATURE_DIM as u32 * 2)
+    }
+
+    pub fn read_parameters(r: &mut impl Read, arch: &'static Architecture) -> io::Result<Self> {
+        let input_dims = arch.feature_set.input_dims();
         // Feature transformer
-        const FT_HASH_HEADER: u32 = 2133021880;
         let ft_hash = read_u32(r)?; // Hash header
-        if ft_hash != FT_HASH_HEADER {
+        if ft_hash != arch.feature_set.hash() ^ (FEATURE_DIM as u32 * 2) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Feature Transformer hash header mismatch",
             ));
         }
-        let bias_vec = read_leb128_i16(r, FEATURE_DIM)?;
-        let weight_vec = read_leb128_i16(r, FEATURE_DIM * INPUT_DIM)?;
-        let psqt_weight_vec = read_leb128_i32(r, PSQT_BUCKETS * INPUT_DIM)?;
+        let (bias_vec, weight_vec, psqt_weight_vec) = match arch.transform {
+            Transform::Clamp => (read_i16_vec(r, FEATURE_DIM)?, read_i16_vec(r, FEATURE_DIM * input_dims)?, Vec::new()),
+            Transform::PairwiseMul => (
+                read_leb128_i16(r, FEATURE_DIM)?,
+                read_leb128_i16(r, FEATURE_DIM * input_dims)?,
+                read_leb128_i32(r, arch.psqt_buckets * input_dims)?,
+            ),
+        };
+
+        Ok(Self::from_parameters(arch, &bias_vec, &weight_vec, &psqt_weight_vec))
+    }
+    /// Build from parameters in file order, permuting and scaling them like a read does
+    pub fn from_parameters(
+        arch: &'static Architecture,
+        bias_vec: &[i16],
+        weight_vec: &[i16],
+        psqt_weight_vec: &[i32],
+    ) -> Self {
+        let input_dims = arch.feature_set.input_dims();
+        assert_eq!(arch.transformed_dims, FEATURE_DIM);
+        assert!(arch.psqt_buckets <= PSQT_BUCKETS);
+        assert!(arch.transform == Transform::PairwiseMul || arch.psqt_buckets == 0);
+        assert_eq!(bias_vec.len(), FEATURE_DIM);
+        assert_eq!(weight_vec.len(), FEATURE_DIM * input_dims);
+        assert_eq!(psqt_weight_vec.len(), arch.psqt_buckets * input_dims);
 
         let mut biases = AVec::with_capacity(CACHE_ALIGN, FEATURE_DIM);
-        biases.extend_from_slice(&bias_vec);
-        let mut weights = AVec::with_capacity(CACHE_ALIGN, FEATURE_DIM * INPUT_DIM);
-        weights.extend_from_slice(&weight_vec);
-        let mut psqt_weights = AVec::with_capacity(CACHE_ALIGN, PSQT_BUCKETS * INPUT_DIM);
-        psqt_weights.extend_from_slice(&psqt_weight_vec);
+        biases.extend_from_slice(bias_vec);
+        let mut weights = AVec::with_capacity(CACHE_ALIGN, FEATURE_DIM * input_dims);
+        weights.extend_from_slice(weight_vec);
+        let mut psqt_weights = AVec::with_capacity(CACHE_ALIGN, PSQT_BUCKETS * input_dims);
+        psqt_weights.resize(PSQT_BUCKETS * input_dims, 0);
+        if arch.psqt_buckets > 0 {
+            for (row, file_row) in psqt_weights.chunks_mut(PSQT_BUCKETS).zip(psqt_weight_vec.chunks(arch.psqt_buckets)) {
+                row[..arch.psqt_buckets].copy_from_slice(file_row);
+            }
+        }
 
         let mut ft = FeatureTransformer {
+            arch,
             biases,
             weights,
             psqt_weights,
         };
 
         ft.permute_weights();
-        ft.scale_weights(L1, INPUT_DIM, true);
+        if arch.transform == Transform::PairwiseMul {
+            ft.scale_weights(FEATURE_DIM, input_dims, true);
+        }
 
-        Ok(ft)
+        ft
+    }
+    /// Biases, weights and PSQT weights in file order, undoing the permutation and scaling
+    /// done on read
+    pub fn file_parameters(&self) -> (Vec<i16>, Vec<i16>, Vec<i32>) {
+        let mut ft = FeatureTransformer::<FEATURE_DIM> {
+            arch: self.arch,
+            biases: self.biases.clone(),
+            weights: self.weights.clone(),
+            psqt_weights: AVec::new(CACHE_ALIGN),
+        };
+        ft.unpermute_weights();
+        if self.arch.transform == Transform::PairwiseMul {
+            ft.scale_weights(FEATURE_DIM, self.input_dims(), false);
+        }
+        let psqt_weights = self
+            .psqt_weights
+            .chunks(PSQT_BUCKETS)
+            .flat_map(|row| &row[..self.arch.psqt_buckets])
+            .copied()
+            .collect();
+        (ft.biases.to_vec(), ft.weights.to_vec(), psqt_weights)
+    }
+    pub fn write_parameters(&self, w: &mut impl Write) -> io::Result<()> {
+        let (biases, weights, psqt_weights) = self.file_parameters();
+        write_u32(w, self.hash())?;
+        match self.arch.transform {
+            Transform::Clamp => {
+                write_i16_slice(w, &biases)?;
+                write_i16_slice(w, &weights)
+            }
+            Transform::PairwiseMul => {
+                write_leb128(w, &biases)?;
+                write_leb128(w, &weights)?;
+                write_leb128(w, &psqt_weights)
+            }
+        }
     }
     pub const fn input_dims(&self) -> usize {
-        INPUT_DIM
+        self.arch.feature_set.input_dims()
     }
     pub const fn output_dims(&self) -> usize {
         FEATURE_DIM
     }
-    pub const fn new_output_buffer(&self) -> CacheAligned<[OutputType; FEATURE_DIM]> {
-        CacheAligned([0; FEATURE_DIM])
+    pub fn new_output_buffer(&self) -> AVec<OutputType, VectorAlignment> {
+        let mut a = AVec::with_capacity(CACHE_ALIGN, self.arch.ft_outputs());
+        a.resize(self.arch.ft_outputs(), 0);
+        a
     }
     // Permute 16-byte blocks according to order (matches C++ PackusEpi16Order)
     fn permute_blocks(data: &mut [i16], order: &[usize]) {
@@ -128,7 +204,7 @@ impl<const FEATURE_DIM: usize> FeatureTransformer<FEATURE_DIM> {
         Self::permute_blocks(&mut self.weights, &INV_PACK_ORDER);
     }
 
-    // Scale ×2 on load (read=true), ÷2 on save (read=false); half_dims is L1, input_dims is INPUT_DIM
+    // Scale ×2 on load (read=true), ÷2 on save (read=false); half_dims is FEATURE_DIM, input_dims is INPUT_DIM
     pub fn scale_weights(&mut self, half_dims: usize, input_dims: usize, read: bool) {
         for j in 0..input_dims {
             let row = &mut self.weights[j * half_dims..(j + 1) * half_dims];
@@ -170,79 +246,84 @@ impl<const FEATURE_DIM: usize> FeatureTransformer<FEATURE_DIM> {
 
         // Layer computation
 
-        // build aligned output buffer for FT
-        let output_dim: usize = self.output_dims();
+        if self.arch.transform == Transform::Clamp {
+            self.clamp(accum, perspectives, output);
+            return psqt;
+        }
 
         for player in 0..COLORS {
             // Offset into buffer for this color
             // FT output is [White features | Black features], each is OUTPUT_DIM/2 entries
             let buff_offset = player * (self.output_dims() / 2);
 
-            if cfg!(target_feature = "avx2") {
-                const OUTPUT_CHUNK_SIZE: usize = MAX_CHUNK_SIZE;
-                assert!((self.output_dims() / 2) % OUTPUT_CHUNK_SIZE == 0);
-                let num_output_chunks = self.output_dims() / 2 / OUTPUT_CHUNK_SIZE;
+            const OUTPUT_CHUNK_SIZE: usize = MAX_CHUNK_SIZE;
+            assert!((self.output_dims() / 2) % OUTPUT_CHUNK_SIZE == 0);
+            let num_output_chunks = self.output_dims() / 2 / OUTPUT_CHUNK_SIZE;
 
-                let zero: VecT = vec_zero();
-                let one: VecT = vec_set1_16(127 * 2);
+            let zero: VecT = vec_zero();
+            let one: VecT = vec_set1_16(127 * 2);
 
-                let in0: *const VecT = accum.accumulation[perspectives[player] as usize]
+            let in0: *const VecT = accum.accumulation[perspectives[player] as usize]
+                .as_ptr()
+                .cast();
+            let in1: *const VecT = unsafe {
+                accum.accumulation[perspectives[player] as usize]
                     .as_ptr()
-                    .cast();
-                let in1: *const VecT = unsafe {
-                    accum.accumulation[perspectives[player] as usize]
-                        .as_ptr()
-                        .add(L1 / 2)
-                        .cast()
-                };
-                let out_ptr: *mut VecT = unsafe { output.add(buff_offset).cast() };
-
-                const SHIFT: i32 = 7; // predifined shift as long as SSSE2 is supported
-
-                // Loop runs over NumOutputChunks blocks inside nnue/nnue_feature_transformer.h (line 382).
-                // Each block represents MaxChunkSize transformed outputs (e.g. 32 or 64 values, depending on SIMD width).
-                // For each chunk it loads two SIMD vectors from the first accumulator half (in0) and two from the second half (in1),
-                //   clips them to [0, 254], left-shifts the first pair (preparing for later right shift), and then multiplies the pairs with vec_mulhi_16 so the product is effectively divided by 512.
-                // The resulting two vectors (pa, pb) are packed via vec_packus_16 into a single byte vector and written to out[j], producing the final transformed features for that chunk.
-                // Net effect: it computes output[offset + j] = clamp(sum0,0,254) * clamp(sum1,0,254) / 512 but does it MaxChunkSize elements at a time using SIMD,
-                //   which is why it iterates over NumOutputChunks rather than every index individually.
-                for j in 0..num_output_chunks {
-                    let sum0a: VecT = vec_slli_16::<SHIFT>(vec_max_16(
-                        vec_min_16(unsafe { *in0.add(j * 2 + 0) }, one),
-                        zero,
-                    ));
-                    let sum0b: VecT = vec_slli_16::<SHIFT>(vec_max_16(
-                        vec_min_16(unsafe { *in0.add(j * 2 + 1) }, one),
-                        zero,
-                    ));
-
-                    let sum1a = vec_min_16(unsafe { *in1.add(j * 2 + 0) }, one);
-                    let sum1b = vec_min_16(unsafe { *in1.add(j * 2 + 1) }, one);
-
-                    let pa = vec_mulhi_16(sum0a, sum1a);
-                    let pb = vec_mulhi_16(sum0b, sum1b);
-
-                    unsafe {
-                        out_ptr.add(j).write(vec_packus_16(pa, pb));
-                    }
-                }
-            } else {
-                for j in 0..self.output_dims() / 2 {
-                    let mut sum0: i16 =
-                        (accum.accumulation[perspectives[player] as usize][j] / 2) as i16;
-                    let mut sum1 = (accum.accumulation[perspectives[player] as usize]
-                        [j + output_dim / 2]
-                        / 2) as i16;
-                    sum0 = sum0.clamp(0, 254);
-                    sum1 = sum1.clamp(0, 254);
-
-                    unsafe {
-                        *output.add(buff_offset + j) = ((sum0 as i32 * sum1 as i32) / 512) as u8;
-                    }
+                    .add(FEATURE_DIM / 2)
+                    .cast()
+            };
+            let out_ptr: *mut VecT = unsafe { output.add(buff_offset).cast() };
+
+            const SHIFT: i32 = 7; // predifined shift as long as SSSE2 is supported
+
+            // Loop runs over NumOutputChunks blocks inside nnue/nnue_feature_transformer.h (line 382).
+            // Each block represents MaxChunkSize transformed outputs (e.g. 32 or 64 values, depending on SIMD width).
+            // For each chunk it loads two SIMD vectors from the first accumulator half (in0) and two from the second half (in1),
+            //   clips them to [0, 254], left-shifts the first pair (preparing for later right shift), and then multiplies the pairs with vec_mulhi_16 so the product is effectively divided by 512.
+            // The resulting two vectors (pa, pb) are packed via vec_packus_16 into a single byte vector and written to out[j], producing the final transformed features for that chunk.
+            // Net effect: it computes output[offset + j] = clamp(sum0,0,254) * clamp(sum1,0,254) / 512 but does it MaxChunkSize elements at a time using SIMD,
+            //   which is why it iterates over NumOutputChunks rather than every index individually.
+            for j in 0..num_output_chunks {
+                let sum0a: VecT = vec_slli_16::<SHIFT>(vec_max_16(
+                    vec_min_16(unsafe { *in0.add(j * 2 + 0) }, one),
+                    zero,
+                ));
+                let sum0b: VecT = vec_slli_16::<SHIFT>(vec_max_16(
+                    vec_min_16(unsafe { *in0.add(j * 2 + 1) }, one),
+                    zero,
+                ));
+
+                let sum1a = vec_min_16(unsafe { *in1.add(j * 2 + 0) }, one);
+                let sum1b = vec_min_16(unsafe { *in1.add(j * 2 + 1) }, one);
+
+                let pa = vec_mulhi_16(sum0a, sum1a);
+                let pb = vec_mulhi_16(sum0b, sum1b);
+
+                unsafe {
+                    out_ptr.add(j).write(vec_packus_16(pa, pb));
                 }
             }
         }
 
         psqt
     }
+
+    /// Both accumulators clamped to 0..=127, the side to move first. The weights are permuted
+    /// like for the pairwise product, so the packed bytes come out in order.
+    fn clamp(&self, accum: &Accumulator<FEATURE_DIM>, perspectives: [Player; COLORS], output: *mut OutputType) {
+        let num_output_chunks = FEATURE_DIM / MAX_CHUNK_SIZE;
+        let max: VecT = vec_set1_16(127);
+        for (player, perspective) in perspectives.into_iter().enumerate() {
+            let input: *const VecT = accum.accumulation[perspective as usize].as_ptr().cast();
+            let out_ptr: *mut VecT = unsafe { output.add(player * FEATURE_DIM).cast() };
+            for j in 0..num_output_chunks {
+                // packus saturates the negative sums to zero
+                let a = vec_min_16(unsafe { *input.add(j * 2) }, max);
+                let b = vec_min_16(unsafe { *input.add(j * 2 + 1) }, max);
+                unsafe {
+                    out_ptr.add(j).write(vec_packus_16(a, b));
+                }
+            }
+        }
+    }
 }
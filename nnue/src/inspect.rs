@@ -0,0 +1,536 @@
+//! What `nnue-inspect` reports about a network: the header, also of files that don't load,
+//! the layers, statistics of every parameter tensor, how often the activations saturate on
+//! sample positions and how two networks differ. Parameters are looked at in file order, as
+//! `Nnue::to_quantized` gives them, and activations come from the reference evaluator.
+
+use std::fmt;
+use std::io::{self, Read};
+
+use pleco::{Board, MoveList};
+
+use crate::architecture::{Architecture, FeatureSet, Layer, SF_BIG, SUPPORTED_ARCHITECTURES, Transform};
+use crate::constants::WEIGHT_SCALE_BITS;
+use crate::nnue::{QuantizedAffine, QuantizedNetwork};
+use crate::nnue_utils::read_u32;
+use crate::reference::ReferenceNnue;
+
+/// Descriptions longer than this are taken for a broken file
+const MAX_DESC_LEN: usize = 1 << 16;
+/// Widths tried when a hash matches no supported architecture
+const MAX_GUESSED_DIMS: usize = 8192;
+pub const HISTOGRAM_BINS: usize = 16;
+
+/// The start of a `.nnue` file, read without checking anything
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub hash: u32,
+    pub desc: String,
+}
+
+impl Header {
+    pub fn read(r: &mut impl Read) -> io::Result<Self> {
+        let version = read_u32(r)?;
+        let hash = read_u32(r)?;
+        let desc_len = read_u32(r)? as usize;
+        if desc_len > MAX_DESC_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("description of {} bytes, this is not a network", desc_len),
+            ));
+        }
+        let mut desc = vec![0u8; desc_len];
+        r.read_exact(&mut desc)?;
+        Ok(Header { version, hash, desc: String::from_utf8_lossy(&desc).to_string() })
+    }
+
+    pub fn architecture(&self) -> Option<&'static Architecture> {
+        Architecture::supported(self.hash)
+    }
+
+    /// What the hash says about the network: its architecture, or the closest thing to it
+    pub fn explain_hash(&self) -> String {
+        if let Some(arch) = self.architecture() {
+            return arch.to_string();
+        }
+        // Same features and layers as a supported network, but another width
+        for feature_set in [FeatureSet::HalfKAv2Hm, FeatureSet::HalfKP] {
+            for dims in (32..=MAX_GUESSED_DIMS).step_by(32) {
+                let arch = Architecture { feature_set, transformed_dims: dims, ..SF_BIG };
+                if arch.hash() == self.hash {
+                    let built: Vec<String> =
+                        SUPPORTED_ARCHITECTURES.iter().map(|arch| arch.transformed_dims.to_string()).collect();
+                    return format!(
+                        "{}[{}] -> {} with the Stockfish layer stacks, only widths {} are built",
+                        feature_set.name(),
+                        feature_set.input_dims(),
+                        dims,
+                        built.join(", ")
+                    );
+                }
+            }
+        }
+        "no known combination of features and layers".to_string()
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Version     {:#010x}", self.version)?;
+        // Checked against the architecture the hash names, or any if it names none
+        match self.architecture() {
+            Some(arch) if arch.version != self.version => writeln!(f, ", expected {:#010x}", arch.version)?,
+            None if SUPPORTED_ARCHITECTURES.iter().all(|arch| arch.version != self.version) => {
+                writeln!(f, ", expected {:#010x}", SF_BIG.version)?
+            }
+            _ => writeln!(f)?,
+        }
+        match self.architecture() {
+            // The architecture shows the hash itself
+            Some(arch) => writeln!(f, "Hash        {}", arch)?,
+            None => writeln!(f, "Hash        {:#010x}, {}", self.hash, self.explain_hash())?,
+        }
+        write!(f, "Description {}", self.desc)
+    }
+}
+
+/// One line per step of a layer stack with its input and output widths, after the feature
+/// transformer, and the number of parameters over all stacks
+pub fn describe_layers(arch: &Architecture) -> Vec<String> {
+    let input_dims = arch.feature_set.input_dims();
+    let mut lines = vec![format!(
+        "{:<22} {} -> {} + {} PSQT, {} parameters",
+        "Feature transformer",
+        input_dims,
+        arch.transformed_dims,
+        arch.psqt_buckets,
+        (input_dims + 1) * arch.transformed_dims + input_dims * arch.psqt_buckets
+    )];
+    let mut width = arch.ft_outputs();
+    let mut affine = 0;
+    for layer in arch.layers {
+        let line = match *layer {
+            Layer::AffineSparse { outputs } | Layer::Affine { outputs } => {
+                let kind = if matches!(layer, Layer::AffineSparse { .. }) { "sparse" } else { "dense" };
+                let line = format!(
+                    "{:<22} {} -> {}, {} parameters",
+                    format!("fc{} {}", affine, kind),
+                    width,
+                    outputs,
+                    (width + 1) * outputs * arch.layer_stacks
+                );
+                affine += 1;
+                width = outputs;
+                line
+            }
+            Layer::ClippedReLU => format!("{:<22} {}", "ClippedReLU", width),
+            Layer::SqrClippedReLUConcat => {
+                // The last output is the forward term, the rest go through both activations
+                let line = format!("{:<22} {} -> {}", "SqrClippedReLU+Concat", width - 1, 2 * (width - 1));
+                width = 2 * (width - 1);
+                line
+            }
+        };
+        lines.push(line);
+    }
+    lines
+}
+
+/// Summary of a set of parameters or activations
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub count: usize,
+    pub min: i64,
+    pub max: i64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub zeros: usize,
+    /// Counts in `HISTOGRAM_BINS` equally wide bins from `min` to `max`
+    pub histogram: Vec<usize>,
+}
+
+impl Stats {
+    pub fn of<T: Copy + Into<i64>>(values: impl Iterator<Item = T> + Clone) -> Self {
+        let (mut count, mut min, mut max, mut sum, mut zeros) = (0, i64::MAX, i64::MIN, 0f64, 0);
+        for v in values.clone() {
+            let v = v.into();
+            count += 1;
+            min = min.min(v);
+            max = max.max(v);
+            sum += v as f64;
+            zeros += usize::from(v == 0);
+        }
+        if count == 0 {
+            return Stats { count, min: 0, max: 0, mean: 0.0, std_dev: 0.0, zeros, histogram: vec![0; HISTOGRAM_BINS] };
+        }
+
+        let mean = sum / count as f64;
+        let width = Self::bin_width(min, max);
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        let mut squares = 0f64;
+        for v in values {
+            let v = v.into();
+            squares += (v as f64 - mean).powi(2);
+            histogram[((v - min) / width) as usize] += 1;
+        }
+        Stats { count, min, max, mean, std_dev: (squares / count as f64).sqrt(), zeros, histogram }
+    }
+
+    fn bin_width(min: i64, max: i64) -> i64 {
+        ((max - min) / HISTOGRAM_BINS as i64 + 1).max(1)
+    }
+
+    /// One line per bin with its range, count and a bar
+    pub fn histogram_lines(&self) -> Vec<String> {
+        const BAR: usize = 50;
+        let width = Self::bin_width(self.min, self.max);
+        let most = self.histogram.iter().copied().max().unwrap_or(0).max(1);
+        self.histogram
+            .iter()
+            .enumerate()
+            .filter(|&(bin, _)| self.min + bin as i64 * width <= self.max)
+            .map(|(bin, &n)| {
+                let lo = self.min + bin as i64 * width;
+                let hi = (lo + width - 1).min(self.max);
+                format!("{:>8} ..{:>8} {:>10} {}", lo, hi, n, "#".repeat(n.div_ceil(most / BAR + 1)))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>10} values, min {:>6}, max {:>6}, mean {:>8.2}, std {:>8.2}, {:>5.1}% zero",
+            self.count,
+            self.min,
+            self.max,
+            self.mean,
+            self.std_dev,
+            100.0 * self.zeros as f64 / self.count.max(1) as f64
+        )
+    }
+}
+
+/// The parameter tensors of a network by name except the transformer weights, with the affine
+/// layers over all stacks, or the affine layers of stack `stack` only
+fn tensors(net: &QuantizedNetwork, stack: Option<usize>) -> Vec<(String, Vec<i64>)> {
+    let mut tensors = Vec::new();
+    let stacks = match stack {
+        Some(i) => &net.layer_stacks[i..i + 1],
+        None => {
+            tensors.push(("ft.biases".to_string(), net.ft_biases.iter().map(|&v| i64::from(v)).collect()));
+            tensors.push(("psqt.weights".to_string(), net.psqt_weights.iter().map(|&v| i64::from(v)).collect()));
+            &net.layer_stacks[..]
+        }
+    };
+    for n in 0..3 {
+        let layers: Vec<&QuantizedAffine> = stacks.iter().map(|s| [&s.fc0, &s.fc1, &s.fc2][n]).collect();
+        let name = |kind| match stack {
+            Some(i) => format!("fc{}[{}].{}", n, i, kind),
+            None => format!("fc{}.{}", n, kind),
+        };
+        tensors.push((name("biases"), layers.iter().flat_map(|l| &l.biases).map(|&v| i64::from(v)).collect()));
+        tensors.push((name("weights"), layers.iter().flat_map(|l| &l.weights).map(|&v| i64::from(v)).collect()));
+    }
+    tensors
+}
+
+/// Statistics of every parameter tensor, the affine layers over all stacks. The transformer
+/// weights are left to `ft_weight_stats`, they are too many to copy.
+pub fn parameter_stats(net: &QuantizedNetwork) -> Vec<(String, Stats)> {
+    tensors(net, None).into_iter().map(|(name, values)| (name, Stats::of(values.into_iter()))).collect()
+}
+
+pub fn ft_weight_stats(net: &QuantizedNetwork) -> Stats {
+    Stats::of(net.ft_weights.iter().copied())
+}
+
+/// Statistics of the affine layers of each stack
+pub fn stack_stats(net: &QuantizedNetwork) -> Vec<(String, Stats)> {
+    (0..net.layer_stacks.len())
+        .flat_map(|i| tensors(net, Some(i)))
+        .map(|(name, values)| (name, Stats::of(values.into_iter())))
+        .collect()
+}
+
+/// How often the activation of a layer is clipped, over all its neurons and sample positions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Saturation {
+    pub name: &'static str,
+    pub values: u64,
+    /// Values clipped to 0
+    pub low: u64,
+    /// Values clipped to the maximum
+    pub high: u64,
+    pub neurons: usize,
+    /// Neurons at 0 on every sample
+    pub dead: usize,
+    /// Neurons at the maximum on every sample
+    pub stuck: usize,
+}
+
+impl fmt::Display for Saturation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let share = |n: u64| 100.0 * n as f64 / self.values.max(1) as f64;
+        write!(
+            f,
+            "{:<10} {:>5.1}% at 0, {:>5.1}% at max, {} of {} neurons always 0, {} always at max",
+            self.name,
+            share(self.low),
+            share(self.high),
+            self.dead,
+            self.neurons,
+            self.stuck
+        )
+    }
+}
+
+/// Per neuron counts of clipped values
+struct Clipping {
+    name: &'static str,
+    samples: u64,
+    low: Vec<u64>,
+    high: Vec<u64>,
+}
+
+impl Clipping {
+    fn new(name: &'static str, neurons: usize) -> Self {
+        Clipping { name, samples: 0, low: vec![0; neurons], high: vec![0; neurons] }
+    }
+
+    /// Count one sample of every neuron, `clip` tells whether a value is clipped low or high
+    fn add(&mut self, values: &[i64], clip: impl Fn(i64) -> (bool, bool)) {
+        self.samples += 1;
+        for (i, &v) in values.iter().enumerate() {
+            let (low, high) = clip(v);
+            self.low[i] += u64::from(low);
+            self.high[i] += u64::from(high);
+        }
+    }
+
+    fn finish(&self) -> Saturation {
+        Saturation {
+            name: self.name,
+            values: self.samples * self.low.len() as u64,
+            low: self.low.iter().sum(),
+            high: self.high.iter().sum(),
+            neurons: self.low.len(),
+            dead: self.low.iter().filter(|&&n| self.samples > 0 && n == self.samples).count(),
+            stuck: self.high.iter().filter(|&&n| self.samples > 0 && n == self.samples).count(),
+        }
+    }
+}
+
+/// Clipping of every activation on `boards`: the transformer's accumulators at 0 and 254, or 127
+/// when they are only clamped, the squared (if the stack has it) and the plain clipped ReLU of fc0 and the clipped ReLU of fc1
+/// at 0 and 127
+pub fn saturation(net: &ReferenceNnue, boards: &[Board]) -> Vec<Saturation> {
+    let arch = net.network().arch;
+    let hidden = arch.hidden_dims();
+    let mut ft = Clipping::new("ft", arch.transformed_dims);
+    let mut fc0_sqr = arch.squares_first_layer().then(|| Clipping::new("fc0 sqr", hidden));
+    let mut fc0 = Clipping::new("fc0", hidden);
+    let mut fc1 = Clipping::new("fc1", arch.affine_outputs(1));
+    let ft_max = match arch.transform {
+        Transform::Clamp => 127,
+        Transform::PairwiseMul => 254,
+    };
+
+    let crelu = |x: i64| {
+        let y = x >> WEIGHT_SCALE_BITS;
+        (y <= 0, y >= 127)
+    };
+    for board in boards {
+        let a = net.activations(board);
+        for accumulator in &a.accumulators {
+            ft.add(accumulator, |x| (x <= 0, x >= ft_max));
+        }
+        if let Some(fc0_sqr) = &mut fc0_sqr {
+            fc0_sqr.add(&a.fc0[..hidden], |x| {
+                let y = (x * x) >> (2 * WEIGHT_SCALE_BITS + 7);
+                (y == 0, y >= 127)
+            });
+        }
+        fc0.add(&a.fc0[..hidden], crelu);
+        fc1.add(&a.fc1, crelu);
+    }
+    [Some(ft), fc0_sqr, Some(fc0), Some(fc1)].iter().flatten().map(Clipping::finish).collect()
+}
+
+/// How one parameter tensor changed between two networks
+#[derive(Clone, Debug, PartialEq)]
+pub struct TensorDiff {
+    pub name: String,
+    pub values: usize,
+    pub changed: usize,
+    pub max_abs: i64,
+    pub mean_abs: f64,
+}
+
+impl fmt::Display for TensorDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<14} {:>10} of {:>10} changed, max |diff| {:>6}, mean |diff| {:.3}",
+            self.name, self.changed, self.values, self.max_abs, self.mean_abs
+        )
+    }
+}
+
+fn tensor_diff<T: Copy + Into<i64>>(name: String, a: impl Iterator<Item = T>, b: impl Iterator<Item = T>) -> TensorDiff {
+    let (mut values, mut changed, mut max_abs, mut sum) = (0, 0, 0, 0f64);
+    for (x, y) in a.zip(b) {
+        let d = (x.into() - y.into()).abs();
+        values += 1;
+        changed += usize::from(d != 0);
+        max_abs = max_abs.max(d);
+        sum += d as f64;
+    }
+    TensorDiff { name, values, changed, max_abs, mean_abs: sum / values.max(1) as f64 }
+}
+
+/// Parameter by parameter differences, only networks of the same architecture line up
+pub fn diff(a: &QuantizedNetwork, b: &QuantizedNetwork) -> Result<Vec<TensorDiff>, String> {
+    if a.arch != b.arch {
+        return Err(format!("{} and {} have different parameters", a.arch.name, b.arch.name));
+    }
+    let mut diffs = vec![tensor_diff("ft.weights".to_string(), a.ft_weights.iter().copied(), b.ft_weights.iter().copied())];
+    for ((name, x), (_, y)) in tensors(a, None).into_iter().zip(tensors(b, None)) {
+        diffs.push(tensor_diff(name, x.into_iter(), y.into_iter()));
+    }
+    Ok(diffs)
+}
+
+/// How far apart the evaluations of two networks are, in internal units
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalDiff {
+    pub positions: usize,
+    pub mean_abs: f64,
+    pub max_abs: i32,
+    /// Position with the largest difference
+    pub worst_fen: Option<String>,
+}
+
+impl fmt::Display for EvalDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} positions, mean |diff| {:.1}, max |diff| {}", self.positions, self.mean_abs, self.max_abs)?;
+        if let Some(fen) = &self.worst_fen {
+            write!(f, " at {}", fen)?;
+        }
+        Ok(())
+    }
+}
+
+/// Differences of the scaled totals, works across architectures
+pub fn eval_diff(a: &ReferenceNnue, b: &ReferenceNnue, boards: &[Board]) -> EvalDiff {
+    let mut result = EvalDiff { positions: boards.len(), mean_abs: 0.0, max_abs: 0, worst_fen: None };
+    for board in boards {
+        let d = (a.evaluate(board).scaled_total() - b.evaluate(board).scaled_total()).abs();
+        result.mean_abs += f64::from(d) / boards.len() as f64;
+        if d > result.max_abs || result.worst_fen.is_none() {
+            result.max_abs = d;
+            result.worst_fen = Some(board.fen());
+        }
+    }
+    result
+}
+
+/// `count` positions from random games, the same for the same seed
+pub fn sample_positions(count: usize, seed: u64) -> Vec<Board> {
+    // xorshift, 0 would stay 0
+    let mut state = seed | 1;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let mut boards = Vec::with_capacity(count);
+    let mut board = Board::start_pos();
+    while boards.len() < count {
+        let moves: MoveList = board.generate_moves();
+        if moves.is_empty() || board.fifty_move_rule() || board.moves_played() > 200 {
+            board = Board::start_pos();
+            continue;
+        }
+        board.apply_move(moves[next() as usize % moves.len()]);
+        // Skip most of the opening, and take every few plies after it
+        if board.moves_played() > 6 && next() % 4 == 0 {
+            boards.push(board.shallow_clone());
+        }
+    }
+    boards
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::of([-3i8, 0, 0, 5, 12].into_iter());
+        assert_eq!((stats.count, stats.min, stats.max, stats.zeros), (5, -3, 12, 2));
+        assert!((stats.mean - 2.8).abs() < 1e-9);
+        assert_eq!(stats.histogram.iter().sum::<usize>(), 5);
+        assert_eq!(stats.histogram[0], 1);
+        assert_eq!(stats.histogram[3], 2);
+        assert_eq!(stats.histogram_lines().len(), HISTOGRAM_BINS);
+        assert_eq!(Stats::of([7i16; 4].into_iter()).histogram_lines().len(), 1);
+        assert_eq!(Stats::of(std::iter::empty::<i32>()).count, 0);
+    }
+
+    #[test]
+    fn test_header() {
+        for arch in SUPPORTED_ARCHITECTURES {
+            let header = Header { version: arch.version, hash: arch.hash(), desc: String::new() };
+            assert_eq!(header.explain_hash(), arch.to_string());
+        }
+        // Stockfish 16's width
+        let sf16 = Architecture { transformed_dims: 2560, ..SF_BIG };
+        let header = Header { version: SF_BIG.version, hash: sf16.hash(), desc: "sf16".to_string() };
+        assert!(header.architecture().is_none());
+        assert!(header.explain_hash().starts_with("HalfKAv2_hm[22528] -> 2560"), "{}", header.explain_hash());
+        assert!(Header { hash: 1, ..header.clone() }.explain_hash().starts_with("no known"));
+
+        let mut bytes = Vec::new();
+        for v in [SF_BIG.version ^ 1, sf16.hash(), 4] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"sf16");
+        let read = Header::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, Header { version: SF_BIG.version ^ 1, ..header });
+        assert!(read.to_string().contains("expected"));
+
+        bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Header::read(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_describe_layers() {
+        assert_eq!(
+            describe_layers(&crate::architecture::SF_SMALL),
+            [
+                "Feature transformer    22528 -> 128 + 8 PSQT, 3063936 parameters",
+                "fc0 sparse             128 -> 16, 16512 parameters",
+                "SqrClippedReLU+Concat  15 -> 30",
+                "ClippedReLU            30",
+                "fc1 dense              30 -> 32, 7936 parameters",
+                "ClippedReLU            32",
+                "fc2 dense              32 -> 1, 264 parameters",
+            ]
+        );
+        assert_eq!(
+            describe_layers(&crate::architecture::SF12),
+            [
+                "Feature transformer    41024 -> 256 + 0 PSQT, 10502400 parameters",
+                "fc0 dense              512 -> 32, 16416 parameters",
+                "ClippedReLU            32",
+                "fc1 dense              32 -> 32, 1056 parameters",
+                "ClippedReLU            32",
+                "fc2 dense              32 -> 1, 33 parameters",
+            ]
+        );
+    }
+}
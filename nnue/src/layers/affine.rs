@@ -1,8 +1,8 @@
-use std::{fmt::Debug, io::{self, Read}};
+use std::{fmt::Debug, io::{self, Read, Write}};
 
 use aligned_vec::AVec;
 
-use crate::{constants::{CACHE_ALIGN, USE_AVX2, USE_SSSE3, VectorAlignment}, nnue_utils::{ceil_to_multiple, read_i8, read_i32_vec}, vectors::{VecT, m256_hadd, vec_add_dpbusd_epi32, vec_set1_32, vec_zero}};
+use crate::{constants::{CACHE_ALIGN, VectorAlignment}, nnue_utils::{ceil_to_multiple, read_i8_vec, read_i32_vec, write_i8_slice, write_i32_slice}, vectors::{VecT, m256_hadd, vec_add_dpbusd_epi32, vec_set1_32, vec_zero}};
 
 pub type InputType = u8;
 pub type OutputType = i32;
@@ -29,14 +29,11 @@ impl Debug for AffineTransform {
     }
 }
 
+/// Weights are stored in groups of 4 inputs per output, the layout `vec_add_dpbusd_epi32` expects
 #[inline(always)]
 fn get_weight_index(i: usize, padded_input_dim : usize, output_dims: usize) -> usize {
-    if USE_SSSE3 {
-        (i / 4) % (padded_input_dim / 4) * output_dims * 4
-            + i / padded_input_dim * 4 + i % 4
-    } else {
-        i
-    }
+    (i / 4) % (padded_input_dim / 4) * output_dims * 4
+        + i / padded_input_dim * 4 + i % 4
 }
 impl AffineTransform {
     pub fn new(input_dims: usize, output_dims: usize) -> Self {
@@ -59,18 +56,44 @@ impl AffineTransform {
         output_dims: usize,
     ) -> io::Result<Self> {
     
+        let padded_input_dims = ceil_to_multiple(input_dims, 32);
+        let bias_vec = read_i32_vec(r, output_dims)?;
+        let weight_vec = read_i8_vec(r, output_dims * padded_input_dims)?;
+
+        Ok(Self::from_parameters(input_dims, output_dims, &bias_vec, &weight_vec))
+    }
+    /// Build from parameters in file order, `weights` is row major over the padded inputs
+    pub fn from_parameters(
+        input_dims: usize,
+        output_dims: usize,
+        biases: &[BiasType],
+        weights: &[WeightType],
+    ) -> Self {
         let mut at = Self::new(input_dims, output_dims);
+        assert_eq!(biases.len(), output_dims);
+        assert_eq!(weights.len(), output_dims * at.padded_input_dims);
 
-        let bias_vec = read_i32_vec(r, output_dims)?;
-        at.biases.extend_from_slice(&bias_vec);
+        at.biases.extend_from_slice(biases);
 
-        let mut weights = vec![WeightType::default(); output_dims * at.padded_input_dims];
-        for i in 0 .. output_dims * at.padded_input_dims {
-            weights[get_weight_index(i, at.padded_input_dims, output_dims)] = read_i8(r)?;
+        let mut scrambled = vec![WeightType::default(); output_dims * at.padded_input_dims];
+        for (i, &w) in weights.iter().enumerate() {
+            scrambled[get_weight_index(i, at.padded_input_dims, output_dims)] = w;
         }
-        at.weights.extend_from_slice(&weights);
+        at.weights.extend_from_slice(&scrambled);
+
+        at
+    }
 
-        Ok(at)
+    /// Weights in file order, row major over the padded inputs
+    pub fn file_weights(&self) -> Vec<WeightType> {
+        (0..self.output_dims * self.padded_input_dims)
+            .map(|i| self.weights[get_weight_index(i, self.padded_input_dims, self.output_dims)])
+            .collect()
+    }
+
+    pub fn write_parameters(&self, w: &mut impl Write) -> io::Result<()> {
+        write_i32_slice(w, &self.biases)?;
+        write_i8_slice(w, &self.file_weights())
     }
 
     pub fn new_input_buffer(&self) -> AVec<InputType, VectorAlignment> {
@@ -84,7 +107,7 @@ impl AffineTransform {
         a
     }
 
-    fn propagate_avx2(&self, input: *const InputType, output: *mut OutputType) {
+    pub fn propagate(&self, input: *const InputType, output: *mut OutputType) {
         
         if self.output_dims > 1 {
 
@@ -147,14 +170,4 @@ impl AffineTransform {
             }
         }
     }
-
-
-
-    pub fn propagate(&self, input: *const InputType, output: *mut OutputType) {
-        if USE_SSSE3 && USE_AVX2 {
-            self.propagate_avx2(input, output);
-        } else {
-            // self.propagate_fallback(input, output);
-        }
-    }
 }
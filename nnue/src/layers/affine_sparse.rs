@@ -1,11 +1,13 @@
 use std::{
     fmt::Debug,
-    io::{self, Read},
+    io::{self, Read, Write},
 };
 
+use aligned_vec::AVec;
+
 use crate::nnue_utils::*;
 use crate::{
-    constants::{L1, L2, MAX_SIMD_WIDTH, USE_AVX2, USE_SSSE3},
+    constants::{CACHE_ALIGN, L1, MAX_SIMD_WIDTH, VectorAlignment},
     nnue_utils::read_i32_vec,
     vectors::{
         MAX_CHUNK_SIZE, VecT, Vec128T, mm_add_epi16, mm_load_si128, mm_set1_epi16,
@@ -13,10 +15,8 @@ use crate::{
     },
 };
 
-pub const INPUT_DIMENSIONS: usize = L1;
-pub const PADDED_INPUT_DIMENSIONS: usize = ceil_to_multiple(INPUT_DIMENSIONS, MAX_SIMD_WIDTH);
-pub const OUTPUT_DIMENSIONS: usize = L2 + 1;
-pub const PADDED_OUTPUT_DIMENSIONS: usize = ceil_to_multiple(OUTPUT_DIMENSIONS, MAX_SIMD_WIDTH);
+/// Widest input, the big network's feature transformer output
+const MAX_INPUT_DIMENSIONS: usize = L1;
 
 pub type InputType = u8;
 pub type OutputType = i32;
@@ -24,32 +24,26 @@ pub type OutputType = i32;
 type WeightType = i8;
 type BiasType = OutputType;
 
-#[cfg(target_feature = "avx2")]
 pub const CHUNK_SIZE: usize = 4;
 
-#[cfg(target_feature = "ssse3")]
 #[repr(align(64))]
 pub struct OffsetIndices {
     pub offset_indices: [[u16; 8]; 256],
 }
 
-#[cfg(target_feature = "ssse3")]
 const DEBRUIJN64: u64 = 0x03F7_9D71_B4CB_0A89;
-#[cfg(target_feature = "ssse3")]
 const LSB_INDEX64: [i32; 64] = [
     0, 47, 1, 56, 48, 27, 2, 60, 57, 49, 41, 37, 28, 16, 3, 61, 54, 58, 35, 52, 50, 42, 21, 44, 38,
     32, 29, 23, 17, 11, 4, 62, 46, 55, 26, 59, 40, 36, 15, 53, 34, 51, 20, 43, 31, 22, 10, 45, 25,
     39, 14, 33, 19, 30, 9, 24, 13, 18, 8, 12, 7, 6, 5, 63,
 ];
 
-#[cfg(target_feature = "ssse3")]
 const fn constexpr_lsb(bb: u64) -> i32 {
     debug_assert!(bb != 0);
     let idx = ((bb ^ (bb - 1)).wrapping_mul(DEBRUIJN64)) >> 58;
     LSB_INDEX64[idx as usize]
 }
 
-#[cfg(target_feature = "ssse3")]
 pub const fn build_offset_indices() -> OffsetIndices {
     let mut table = [[0u16; 8]; 256];
     let mut i = 0;
@@ -72,24 +66,13 @@ pub const fn build_offset_indices() -> OffsetIndices {
     }
 }
 
-#[cfg(target_feature = "ssse3")]
 pub static LOOKUP: OffsetIndices = build_offset_indices();
 
 #[inline(always)]
-fn get_weight_index(i: usize) -> usize {
-    // if cfg!(target_feature = "avx2") {
-    //     // compiled with avx2 enabled
-    // }
-    // if std::is_x86_feature_detected!("ssse3") {
-    //     // CPU supports SSSE3 at runtime
-    // }
-    if USE_SSSE3 {
-        (i / CHUNK_SIZE) % (PADDED_INPUT_DIMENSIONS / CHUNK_SIZE) * OUTPUT_DIMENSIONS * CHUNK_SIZE
-            + i / PADDED_INPUT_DIMENSIONS * CHUNK_SIZE
-            + i % CHUNK_SIZE
-    } else {
-        i
-    }
+fn get_weight_index(i: usize, padded_input_dims: usize, output_dims: usize) -> usize {
+    (i / CHUNK_SIZE) % (padded_input_dims / CHUNK_SIZE) * output_dims * CHUNK_SIZE
+        + i / padded_input_dims * CHUNK_SIZE
+        + i % CHUNK_SIZE
 }
 /// Affine Transformation Sparse Input
 /// Sparse affine layer (input-major weights):
@@ -98,13 +81,16 @@ fn get_weight_index(i: usize) -> usize {
 //   its row is scaled by the feature value (u8) and added to the outputs.
 // - padded_output is output_dims rounded up for SIMD-friendly stride (AVX2/SSSE3).
 // This mirrors Stockfish’s AffineTransformSparseInput: biases + sparse weighted adds.
+// INPUT_DIMENSIONS is the feature transformer width of the big or the small network, the
+// output width comes from the architecture and has to fill whole registers.
 
-pub struct AffineTransformSparse {
-    pub biases: CacheAligned<[BiasType; OUTPUT_DIMENSIONS]>, // len = output_dims
-    pub weights: CacheAligned<[WeightType; OUTPUT_DIMENSIONS * PADDED_INPUT_DIMENSIONS]>, // len = input_dims * padded_output
+pub struct AffineTransformSparse<const INPUT_DIMENSIONS: usize = L1> {
+    pub output_dims: usize,
+    pub biases: AVec<BiasType, VectorAlignment>, // len = output_dims
+    pub weights: AVec<WeightType, VectorAlignment>, // len = padded_input_dims * output_dims
 }
 
-impl Debug for AffineTransformSparse {
+impl<const INPUT_DIMENSIONS: usize> Debug for AffineTransformSparse<INPUT_DIMENSIONS> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!(
             "  Sparse Affine Biases Len {}\n",
@@ -120,15 +106,16 @@ impl Debug for AffineTransformSparse {
     }
 }
 
-fn find_nnz<const NNZ_IN_DIMS: usize>(
+fn find_nnz(
     input: *const i32,
-    output: &mut [u16; NNZ_IN_DIMS],
+    nnz_in_dims: usize,
+    output: &mut [u16],
     count_out: &mut u32,
 ) {
     const INPUT_SIMD_WIDTH: usize =
         std::mem::size_of::<VecT>() / std::mem::size_of::<OutputType>();
     const CHUNK_SIZE: usize = 8;
-    let num_chunks: usize = NNZ_IN_DIMS / CHUNK_SIZE;
+    let num_chunks: usize = nnz_in_dims / CHUNK_SIZE;
     const INPUTS_PER_CHUNK: usize = CHUNK_SIZE / INPUT_SIMD_WIDTH;
     const OUTPUTS_PER_CHUNK: usize = CHUNK_SIZE / 8;
 
@@ -165,29 +152,61 @@ fn find_nnz<const NNZ_IN_DIMS: usize>(
     *count_out = count as u32;
 }
 
-impl AffineTransformSparse {
-    pub fn new() -> Self {
+impl<const INPUT_DIMENSIONS: usize> AffineTransformSparse<INPUT_DIMENSIONS> {
+    pub const PADDED_INPUT_DIMENSIONS: usize = ceil_to_multiple(INPUT_DIMENSIONS, MAX_SIMD_WIDTH);
+
+    pub fn new(output_dims: usize) -> Self {
+        const OUTPUT_SIMD_WIDTH: usize = MAX_CHUNK_SIZE / std::mem::size_of::<OutputType>();
+        assert!(output_dims.is_multiple_of(OUTPUT_SIMD_WIDTH), "{} outputs don't fill whole registers", output_dims);
+
+        let mut biases = AVec::with_capacity(CACHE_ALIGN, output_dims);
+        biases.resize(output_dims, 0);
+        let mut weights = AVec::with_capacity(CACHE_ALIGN, output_dims * Self::PADDED_INPUT_DIMENSIONS);
+        weights.resize(output_dims * Self::PADDED_INPUT_DIMENSIONS, 0);
         Self {
-            biases: CacheAligned([0; OUTPUT_DIMENSIONS]),
-            weights: CacheAligned([0; OUTPUT_DIMENSIONS * PADDED_INPUT_DIMENSIONS]),
+            output_dims,
+            biases,
+            weights,
         }
     }
 
-    pub fn read_parameters(r: &mut impl Read) -> io::Result<Self> {
-        let mut at = Self::new();
+    pub fn read_parameters(r: &mut impl Read, output_dims: usize) -> io::Result<Self> {
+        let bias_vec = read_i32_vec(r, output_dims)?;
+        let weight_vec = read_i8_vec(r, output_dims * Self::PADDED_INPUT_DIMENSIONS)?;
 
-        let bias_vec = read_i32_vec(r, OUTPUT_DIMENSIONS)?;
-        at.biases.0.copy_from_slice(&bias_vec);
+        Ok(Self::from_parameters(&bias_vec, &weight_vec))
+    }
+
+    /// Build from parameters in file order, `weights` is row major over the padded inputs
+    pub fn from_parameters(biases: &[BiasType], weights: &[WeightType]) -> Self {
+        let mut at = Self::new(biases.len());
+        at.biases.copy_from_slice(biases);
 
-        for i in 0..OUTPUT_DIMENSIONS * PADDED_INPUT_DIMENSIONS {
-            at.weights[get_weight_index(i)] = read_i8(r)?;
+        assert_eq!(weights.len(), at.output_dims * Self::PADDED_INPUT_DIMENSIONS);
+        for (i, &w) in weights.iter().enumerate() {
+            at.weights[get_weight_index(i, Self::PADDED_INPUT_DIMENSIONS, at.output_dims)] = w;
         }
 
-        Ok(at)
+        at
+    }
+
+    /// Weights in file order, row major over the padded inputs
+    pub fn file_weights(&self) -> Vec<WeightType> {
+        (0..self.output_dims * Self::PADDED_INPUT_DIMENSIONS)
+            .map(|i| self.weights[get_weight_index(i, Self::PADDED_INPUT_DIMENSIONS, self.output_dims)])
+            .collect()
     }
 
-    pub const fn new_output_buffer(&self) -> CacheAligned<[OutputType; PADDED_OUTPUT_DIMENSIONS]> {
-        CacheAligned([0i32; PADDED_OUTPUT_DIMENSIONS])
+    pub fn write_parameters(&self, w: &mut impl Write) -> io::Result<()> {
+        write_i32_slice(w, &self.biases)?;
+        write_i8_slice(w, &self.file_weights())
+    }
+
+    pub fn new_output_buffer(&self) -> AVec<OutputType, VectorAlignment> {
+        let padded = ceil_to_multiple(self.output_dims, MAX_SIMD_WIDTH);
+        let mut a = AVec::with_capacity(CACHE_ALIGN, padded);
+        a.resize(padded, 0);
+        a
     }
 
     pub fn propagate(
@@ -197,48 +216,46 @@ impl AffineTransformSparse {
         input: *const InputType,
         output: *mut OutputType,
     ) {
-        if USE_AVX2 && USE_SSSE3 {
-            const OUTPUT_SIMD_WIDTH: usize = MAX_CHUNK_SIZE / std::mem::size_of::<OutputType>();
+        const OUTPUT_SIMD_WIDTH: usize = MAX_CHUNK_SIZE / std::mem::size_of::<OutputType>();
 
-            const NUM_CHUNKS: usize = ceil_to_multiple(INPUT_DIMENSIONS, 8) / CHUNK_SIZE;
-            const NUM_REGS: usize = OUTPUT_DIMENSIONS / OUTPUT_SIMD_WIDTH;
-            let mut nnz = [0u16; NUM_CHUNKS];
+        const MAX_NUM_CHUNKS: usize = ceil_to_multiple(MAX_INPUT_DIMENSIONS, 8) / CHUNK_SIZE;
+        let num_regs = self.output_dims / OUTPUT_SIMD_WIDTH;
+        let num_chunks = ceil_to_multiple(INPUT_DIMENSIONS, 8) / CHUNK_SIZE;
+        // Sized for the widest input, only the first num_chunks entries are used
+        let mut nnz = [0u16; MAX_NUM_CHUNKS];
 
-            let mut count = 0;
+        let mut count = 0;
 
-            let input32: *const i32 = input as *const i32;
-            // Find indices of nonzero 32-bit blocks
-            find_nnz(input32, &mut nnz, &mut count);
+        let input32: *const i32 = input as *const i32;
+        // Find indices of nonzero 32-bit blocks
+        find_nnz(input32, num_chunks, &mut nnz, &mut count);
 
-            let bias_vector: *const VecT = self.biases.as_ptr() as *const VecT;
+        let bias_vector: *const VecT = self.biases.as_ptr() as *const VecT;
 
-            let mut acc = [vec_zero(); NUM_REGS];
+        let mut acc = vec![vec_zero(); num_regs];
 
-            for k in 0..NUM_REGS {
-                acc[k] = unsafe { *bias_vector.add(k) };
-            }
+        for k in 0..num_regs {
+            acc[k] = unsafe { *bias_vector.add(k) };
+        }
 
-            for j in 0..count as usize {
-                let i = nnz[j];
-                let in_vec = vec_set1_32(unsafe { *input32.add(i as usize) });
-                let col: *const VecT = unsafe {
-                    self.weights.as_ptr().add(i as usize * OUTPUT_DIMENSIONS * CHUNK_SIZE)
-                        as *const VecT
-                };
-
-                for k in 0..NUM_REGS {
-                    vec_add_dpbusd_epi32(&mut acc[k], in_vec, unsafe { *col.add(k) });
-                }
-            }
+        for j in 0..count as usize {
+            let i = nnz[j];
+            let in_vec = vec_set1_32(unsafe { *input32.add(i as usize) });
+            let col: *const VecT = unsafe {
+                self.weights.as_ptr().add(i as usize * self.output_dims * CHUNK_SIZE)
+                    as *const VecT
+            };
 
-            let outptr: *mut VecT = output as *mut VecT;
-            for k in 0..NUM_REGS {
-                unsafe {
-                    *outptr.add(k) = acc[k];
-                }
+            for k in 0..num_regs {
+                vec_add_dpbusd_epi32(&mut acc[k], in_vec, unsafe { *col.add(k) });
             }
-        } else {
+        }
 
+        let outptr: *mut VecT = output as *mut VecT;
+        for k in 0..num_regs {
+            unsafe {
+                *outptr.add(k) = acc[k];
+            }
         }
     }
 
@@ -1,31 +1,83 @@
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 
-use crate::{constants::{OUTPUT_SCALE, WEIGHT_SCALE_BITS}, nnue_utils::{ceil_to_multiple, read_u32}};
+use aligned_vec::AVec;
+
+use crate::{architecture::Architecture, constants::{OUTPUT_SCALE, VectorAlignment, WEIGHT_SCALE_BITS}, nnue::{QuantizedAffine, QuantizedLayerStack}, nnue_utils::{read_u32, write_u32}};
 
 mod affine_sparse;
 mod affine;
 mod sq_clipped_relu;
 mod clipped_relu;
 
-// Predefined SqClippedReLU for use in BucketNet to use L2 from constants
-type SqClippedReLU = sq_clipped_relu::SqClippedReLU<
-{crate::constants::L2 + 1}, {ceil_to_multiple(crate::constants::L2 + 1, 32)}
->;
+pub use affine::AffineTransform;
+pub use affine_sparse::AffineTransformSparse;
+
+use clipped_relu::ClippedReLU;
+use sq_clipped_relu::SqClippedReLU;
+
+/// The first affine layer of a stack, on the feature transformer output
+pub enum FirstLayer<const L1: usize> {
+    /// Skips the zero inputs, the Stockfish 16.1 to 17.1 stacks
+    Sparse(AffineTransformSparse<L1>),
+    /// Plain dense layer, the Stockfish 12 stacks
+    Dense(AffineTransform),
+}
+
+impl<const L1: usize> FirstLayer<L1> {
+    fn output_dims(&self) -> usize {
+        match self {
+            FirstLayer::Sparse(fc) => fc.output_dims,
+            FirstLayer::Dense(fc) => fc.output_dims,
+        }
+    }
+
+    fn to_quantized(&self) -> QuantizedAffine {
+        match self {
+            FirstLayer::Sparse(fc) => QuantizedAffine::from_padded(fc.biases.to_vec(), &fc.file_weights(), L1),
+            FirstLayer::Dense(fc) => QuantizedAffine::from_padded(fc.biases.to_vec(), &fc.file_weights(), fc.input_dims),
+        }
+    }
+
+    fn write_parameters(&self, w: &mut impl Write) -> io::Result<()> {
+        match self {
+            FirstLayer::Sparse(fc) => fc.write_parameters(w),
+            FirstLayer::Dense(fc) => fc.write_parameters(w),
+        }
+    }
+
+    fn new_output_buffer(&self) -> AVec<i32, VectorAlignment> {
+        match self {
+            FirstLayer::Sparse(fc) => fc.new_output_buffer(),
+            FirstLayer::Dense(fc) => fc.new_output_buffer(),
+        }
+    }
+
+    fn propagate(&self, input: *const u8, output: *mut i32) {
+        match self {
+            FirstLayer::Sparse(fc) => fc.propagate(input, output),
+            FirstLayer::Dense(fc) => fc.propagate(input, output),
+        }
+    }
+}
 
-type ClippedReLU0 = clipped_relu::ClippedReLU<
-{crate::constants::L2 + 1}, {ceil_to_multiple(crate::constants::L2 + 1, 32)}
->;
-type ClippedReLU1 = clipped_relu::ClippedReLU<
-{crate::constants::L3}, {ceil_to_multiple(crate::constants::L3, 32)}
->;
+impl<const L1: usize> std::fmt::Debug for FirstLayer<L1> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FirstLayer::Sparse(fc) => write!(f, "  FC0: Sparse Affine Layer\n{:?}", fc),
+            FirstLayer::Dense(fc) => write!(f, "  FC0: Dense Affine Layer\n{:?}", fc),
+        }
+    }
+}
 
 /// One of the buckets, containing 3 Fully Connected layers
 /// And their transformations
 /// Weights are i8, biases are i32
 ///
 /// # Architecture
+/// The layer sizes come from the `Architecture`. Stockfish 12 has a single bucket of plain
+/// dense layers with clipped ReLUs, 512 -> 32 -> 32 -> 1. For Stockfish 17.1 (L2 = 15, L3 = 32)
 /// 8 buckets (by material count):
-///   FC0 (sparse) L1 -> L2           // sparse matmul on active features; extra 16th used as forward term
+///   FC0 (sparse) L1 -> L2 + 1         // sparse matmul on active features; extra 16th used as forward term
 ///   SqrClippedReLU on first L2        // square and clamp hidden activations
 ///   ClippedReLU on same L2            // linear clamp of same activations
 ///   Concat L2_sq + L2_lin -> L2 * 2       // build mixed feature vector
@@ -33,20 +85,18 @@ type ClippedReLU1 = clipped_relu::ClippedReLU<
 ///   ClippedReLU L3                    // clamp hidden layer
 ///   FC2 (dense) L3 -> 1               // final linear output
 ///  + scaled forward term from FC0[15] // add king-safety-style bonus to output
-pub struct BucketNet
-<const L1: usize, const L2: usize, const L3: usize>
-{
-    fc0: affine_sparse::AffineTransformSparse,
-    ac_sqr_0: SqClippedReLU,
-    ac_0: ClippedReLU0,
-    fc1: affine::AffineTransform,
-    ac1: ClippedReLU1,
-    fc2: affine::AffineTransform,
+pub struct BucketNet<const L1: usize> {
+    arch: &'static Architecture,
+    fc0: FirstLayer<L1>,
+    ac_sqr_0: Option<SqClippedReLU>,
+    ac_0: ClippedReLU,
+    fc1: AffineTransform,
+    ac1: ClippedReLU,
+    fc2: AffineTransform,
 }
 
-impl<const L1: usize, const L2: usize, const L3: usize> std::fmt::Debug for BucketNet<L1, L2, L3> {
+impl<const L1: usize> std::fmt::Debug for BucketNet<L1> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("  FC0: Sparse Affine Layer\n")?;
         f.write_fmt(format_args!("{:?}", self.fc0))?;
 
         f.write_str("  FC1: Dense Affine Layer\n")?;
@@ -57,59 +107,82 @@ impl<const L1: usize, const L2: usize, const L3: usize> std::fmt::Debug for Buck
     }
 }
 
-impl<const L1: usize, const L2: usize, const L3: usize> BucketNet<L1, L2, L3> {
-    const TRANSFORMED_FEATURE_DIMENSIONS: usize = L1;
-    const FC_0_OUTPUT_DIMENSIONS: usize = L2; // +1 for forward term
-    const FC_1_OUTPUT_DIMENSIONS: usize = L3;
-
+impl<const L1: usize> BucketNet<L1> {
+    /// Panics when the layers don't have the sizes `arch` gives them
     pub fn new(
-        fc0: affine_sparse::AffineTransformSparse,
-        fc1: affine::AffineTransform,
-        fc2: affine::AffineTransform,
+        arch: &'static Architecture,
+        fc0: FirstLayer<L1>,
+        fc1: AffineTransform,
+        fc2: AffineTransform,
     ) -> Self {
+        assert_eq!(fc0.output_dims(), arch.affine_outputs(0));
+        assert_eq!(matches!(fc0, FirstLayer::Sparse(_)), arch.sparse_first_layer());
+        assert_eq!((fc1.input_dims, fc1.output_dims), (arch.fc1_inputs(), arch.affine_outputs(1)));
+        assert_eq!((fc2.input_dims, fc2.output_dims), (arch.affine_outputs(1), 1));
         Self {
+            arch,
+            ac_sqr_0: arch.squares_first_layer().then(|| SqClippedReLU::new(fc0.output_dims())),
+            ac_0: ClippedReLU::new(fc0.output_dims()),
             fc0,
-            ac_sqr_0: sq_clipped_relu::SqClippedReLU::new(),
-            ac_0: clipped_relu::ClippedReLU::new(),
+            ac1: ClippedReLU::new(fc1.output_dims),
             fc1,
-            ac1: clipped_relu::ClippedReLU::new(),
-            fc2 
+            fc2,
         }
     }
 
-    pub fn read_parameters(r: &mut impl Read) -> io::Result<Self> {
-        const LAYER_HASH_HEADER: u32 = 1664316490;
-
+    pub fn read_parameters(r: &mut impl Read, arch: &'static Architecture) -> io::Result<Self> {
         let layer_hash = read_u32(r)?; // Hash header
-        if layer_hash != LAYER_HASH_HEADER {
+        if layer_hash != arch.layer_stack_hash() {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Layer hash header mismatch"));
         }
-        let fc0 = affine_sparse::AffineTransformSparse::read_parameters(r)?;
-        let fc1 = affine::AffineTransform::read_parameters(
-            r, Self::FC_0_OUTPUT_DIMENSIONS * 2, Self::FC_1_OUTPUT_DIMENSIONS
+        let fc0 = if arch.sparse_first_layer() {
+            FirstLayer::Sparse(AffineTransformSparse::read_parameters(r, arch.affine_outputs(0))?)
+        } else {
+            FirstLayer::Dense(AffineTransform::read_parameters(r, arch.ft_outputs(), arch.affine_outputs(0))?)
+        };
+        let fc1 = AffineTransform::read_parameters(
+            r, arch.fc1_inputs(), arch.affine_outputs(1)
         )?;
-        let fc2 = affine::AffineTransform::read_parameters(
-            r, Self::FC_1_OUTPUT_DIMENSIONS, 1
+        let fc2 = AffineTransform::read_parameters(
+            r, arch.affine_outputs(1), 1
         )?;
-        Ok(BucketNet::new(fc0, fc1, fc2))
+        Ok(BucketNet::new(arch, fc0, fc1, fc2))
+    }
+
+    /// Parameters in file order without the input padding, see `Nnue::to_quantized`
+    pub fn to_quantized(&self) -> QuantizedLayerStack {
+        QuantizedLayerStack {
+            fc0: self.fc0.to_quantized(),
+            fc1: QuantizedAffine::from_padded(self.fc1.biases.to_vec(), &self.fc1.file_weights(), self.fc1.input_dims),
+            fc2: QuantizedAffine::from_padded(self.fc2.biases.to_vec(), &self.fc2.file_weights(), self.fc2.input_dims),
+        }
+    }
+
+    pub fn write_parameters(&self, w: &mut impl Write) -> io::Result<()> {
+        write_u32(w, self.arch.layer_stack_hash())?;
+        self.fc0.write_parameters(w)?;
+        self.fc1.write_parameters(w)?;
+        self.fc2.write_parameters(w)
     }
 
     pub fn propagate(&self, input: *const u8) -> i32 {
         //TODO: Cache Align all of them?
         let mut fc0_out = self.fc0.new_output_buffer();
-        let mut ac_sqr_out = self.ac_sqr_0.new_output_buffer();
         let mut ac0_out = self.ac_0.new_output_buffer();
 
         self.fc0.propagate(input, fc0_out.as_mut_ptr());
-
-
-        self.ac_sqr_0.propagate(fc0_out.as_ptr(), ac_sqr_out.as_mut_ptr());
         self.ac_0.propagate(fc0_out.as_ptr(), ac0_out.as_mut_ptr());
 
+        let hidden = self.arch.hidden_dims();
         let mut fc1_in = self.fc1.new_input_buffer();
-        for i in 0 .. Self::FC_0_OUTPUT_DIMENSIONS {
-            fc1_in[i] = ac_sqr_out.0[i];
-            fc1_in[i + Self::FC_0_OUTPUT_DIMENSIONS] = ac0_out.0[i];
+        match &self.ac_sqr_0 {
+            Some(ac_sqr_0) => {
+                let mut ac_sqr_out = ac_sqr_0.new_output_buffer();
+                ac_sqr_0.propagate(fc0_out.as_ptr(), ac_sqr_out.as_mut_ptr());
+                fc1_in[..hidden].copy_from_slice(&ac_sqr_out[..hidden]);
+                fc1_in[hidden..hidden * 2].copy_from_slice(&ac0_out[..hidden]);
+            }
+            None => fc1_in[..hidden].copy_from_slice(&ac0_out[..hidden]),
         }
 
         let mut fc1_out = self.fc1.new_output_buffer();
@@ -121,12 +194,14 @@ impl<const L1: usize, const L2: usize, const L3: usize> BucketNet<L1, L2, L3> {
         let mut fc2_out = self.fc2.new_output_buffer();
         self.fc2.propagate(ac1_out.as_ptr(), fc2_out.as_mut_ptr());
 
-        let fwd_out = 
-            (fc0_out[Self::FC_0_OUTPUT_DIMENSIONS]) * (600 * OUTPUT_SCALE) / (127 * (1 << WEIGHT_SCALE_BITS));
+        let fwd_out = if self.arch.has_forward_term() {
+            fc0_out[hidden] * (600 * OUTPUT_SCALE) / (127 * (1 << WEIGHT_SCALE_BITS))
+        } else {
+            0
+        };
 
         let output_value = (fc2_out[0] as i32) + fwd_out;
         
         output_value
     }
 }
-
@@ -5,8 +5,12 @@ mod layers;
 mod vectors;
 mod feature_sets;
 
+pub mod architecture;
+pub mod data;
+pub mod inspect;
 pub mod nnue;
+pub mod reference;
 mod constants;
 mod nnue_misc;
-mod nnue_utils;
+pub mod nnue_utils;
 mod feature_transformer;
\ No newline at end of file
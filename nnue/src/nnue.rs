@@ -1,47 +1,277 @@
 use std::env;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::fs::File;
-use std::io::{self, BufReader, Read};
-use std::path::Path;
-use std::sync::LazyLock;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 
-use pleco::{Board, Piece, Player};
+use pleco::board::FenBuildError;
+use pleco::{Board, Piece, PieceType, Player, SQ};
 
+use crate::architecture::{Architecture, FeatureSet, SUPPORTED_ARCHITECTURES};
 use crate::accumulator::{Accumulator, AccumulatorCache, AccumulatorCaches, AccumulatorStack};
 use crate::constants::*;
 use crate::feature_transformer::FeatureTransformer;
-use crate::feature_sets::make_index;
-use crate::layers::BucketNet;
-use crate::nnue_misc::{DirtyPiece, EvalTrace};
+use crate::layers::{AffineTransform, AffineTransformSparse, BucketNet, FirstLayer};
+use crate::nnue_misc::{DirtyPiece, EvalTrace, PieceValues};
 use crate::nnue_utils::*;
 
-static NNUE_BIG: LazyLock<Nnue> = LazyLock::new(|| {
-    load_big_nnue(&env::var("BIG_NNUE").unwrap_or("/home/deploy/nn-1c0000000000.nnue".to_string()))
-        .expect("Failed to load NNUE")
-});
+/// Used when `BIG_NNUE` is not set and no network is embedded
+pub const DEFAULT_BIG_NNUE_PATH: &str = "/home/deploy/nn-1c0000000000.nnue";
+/// Used when `SMALL_NNUE` is not set
+pub const DEFAULT_SMALL_NNUE_PATH: &str = "/home/deploy/nn-37f18f62d772.nnue";
+
+/// The network file next to nnue's Cargo.toml, built in with the `embedded-nnue` feature
+#[cfg(feature = "embedded-nnue")]
+static EMBEDDED_BIG_NNUE: &[u8] =
+    include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/nn-1c0000000000.nnue"));
+
+static NNUE_BIG: OnceLock<Arc<Nnue>> = OnceLock::new();
+static NNUE_BIG_LOADING: Mutex<()> = Mutex::new(());
+/// Failures are kept too, the small network is optional and asked for by every evaluator
+static NNUE_SMALL: OnceLock<Result<Arc<SmallNnue>, Arc<NnueError>>> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum NnueError {
+    /// The network could not be read, or one of its layers is malformed
+    Io { path: Option<PathBuf>, source: io::Error },
+    /// The header names a supported architecture, but not the version its files have
+    VersionMismatch { found: u32, expected: u32 },
+    /// The network is supported, but not where it was loaded, e.g. a small one as the big one
+    ArchMismatch { found: &'static Architecture, expected: &'static Architecture },
+    /// No architecture in `SUPPORTED_ARCHITECTURES` has this hash
+    UnsupportedArchitecture { found: u32 },
+    TrailingData,
+}
+
+impl fmt::Display for NnueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NnueError::Io { path: Some(path), source } => {
+                write!(f, "failed to read NNUE {}: {}", path.display(), source)
+            }
+            NnueError::Io { path: None, source } => write!(f, "failed to read NNUE: {}", source),
+            NnueError::VersionMismatch { found, expected } => {
+                write!(f, "NNUE version {:#x} does not match {:#x}", found, expected)
+            }
+            NnueError::ArchMismatch { found, expected } => write!(
+                f,
+                "NNUE is {} ({:#x}, {} wide) where {} ({:#x}, {} wide) was expected, load_any_nnue takes either",
+                found.name,
+                found.hash(),
+                found.transformed_dims,
+                expected.name,
+                expected.hash(),
+                expected.transformed_dims
+            ),
+            NnueError::UnsupportedArchitecture { found } => {
+                write!(f, "NNUE architecture hash {:#x} is not supported, expected one of:", found)?;
+                for arch in SUPPORTED_ARCHITECTURES {
+                    write!(f, " {} ({:#x})", arch.name, arch.hash())?;
+                }
+                Ok(())
+            }
+            NnueError::TrailingData => write!(f, "NNUE has data after the last layer"),
+        }
+    }
+}
+
+impl std::error::Error for NnueError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NnueError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NnueError {
+    fn from(source: io::Error) -> Self {
+        NnueError::Io { path: None, source }
+    }
+}
+
+/// Load the shared big network if it isn't loaded yet. The `BIG_NNUE` path wins, then the
+/// embedded network, then `DEFAULT_BIG_NNUE_PATH`. Call this at startup to surface a missing
+/// or broken network before the first evaluation.
+pub fn init_big_nnue() -> Result<Arc<Nnue>, NnueError> {
+    if let Some(nnue) = NNUE_BIG.get() {
+        return Ok(nnue.clone());
+    }
+    // Only one thread reads the file, the others wait for its result
+    let _loading = NNUE_BIG_LOADING.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(nnue) = NNUE_BIG.get() {
+        return Ok(nnue.clone());
+    }
 
+    let nnue = match env::var("BIG_NNUE") {
+        Ok(path) => load_big_nnue(path)?,
+        #[cfg(feature = "embedded-nnue")]
+        Err(_) => load_big_nnue_from_bytes(EMBEDDED_BIG_NNUE)?,
+        #[cfg(not(feature = "embedded-nnue"))]
+        Err(_) => load_big_nnue(DEFAULT_BIG_NNUE_PATH)?,
+    };
+    Ok(NNUE_BIG.get_or_init(|| Arc::new(nnue)).clone())
+}
+
+/// The shared big network, if `init_big_nnue` succeeded
+pub fn big_nnue() -> Option<Arc<Nnue>> {
+    NNUE_BIG.get().cloned()
+}
+
+/// Load the shared small network from `SMALL_NNUE` or `DEFAULT_SMALL_NNUE_PATH` on the first
+/// call. It is optional, without it every position goes through the big network. The file is
+/// only read once, later calls return the same network or the same error.
+pub fn init_small_nnue() -> Result<Arc<SmallNnue>, Arc<NnueError>> {
+    NNUE_SMALL
+        .get_or_init(|| {
+            let path = env::var("SMALL_NNUE").unwrap_or_else(|_| DEFAULT_SMALL_NNUE_PATH.to_string());
+            load_small_nnue(path).map(Arc::new).map_err(Arc::new)
+        })
+        .clone()
+}
+
+/// The shared small network, if `init_small_nnue` succeeded
+pub fn small_nnue() -> Option<Arc<SmallNnue>> {
+    NNUE_SMALL.get().and_then(|small| small.as_ref().ok()).cloned()
+}
+
+/// The network at `path` for tools that take one, or the shared networks without it. A network
+/// of our own is used alone, the small Stockfish one would not match it.
+pub fn load_networks(path: Option<&str>) -> Result<(AnyNnue, Option<Arc<SmallNnue>>), NnueError> {
+    match path {
+        Some(path) => Ok((load_any_nnue(path)?, None)),
+        None => Ok((init_big_nnue()?.into(), init_small_nnue().ok())),
+    }
+}
+
+/// Stockfish piece values, only used to pick the network
+const SELECTION_PIECE_VALUES: [(PieceType, i32); 5] = [
+    (PieceType::P, 208),
+    (PieceType::N, 781),
+    (PieceType::B, 825),
+    (PieceType::R, 1276),
+    (PieceType::Q, 2538),
+];
+/// Material imbalance above which the small network evaluates the position
+const SMALL_NET_THRESHOLD: i32 = 962;
+/// Small network evaluations closer to zero than this are redone with the big network
+const SMALL_NET_RETRY_THRESHOLD: i32 = 236;
+
+/// Stockfish's `use_smallnet`: the position is lopsided enough that the small network's
+/// accuracy will do
+pub fn use_small_net(board: &Board) -> bool {
+    let material: i32 = SELECTION_PIECE_VALUES
+        .iter()
+        .map(|&(pt, value)| {
+            let diff = board.count_piece(Player::White, pt) as i32
+                - board.count_piece(Player::Black, pt) as i32;
+            diff * value
+        })
+        .sum();
+    material.abs() > SMALL_NET_THRESHOLD
+}
+
+/// Evaluates positions with one network of any supported architecture, or a big and small
+/// pair. Evaluators are per search thread, the networks behind them are shared, so several
+/// evaluators (and several networks) can live side by side.
 pub struct NnueEvaluator {
+    nnue: AnyNnue,
+    small: Option<Arc<SmallNnue>>,
     accum_stack: AccumulatorStack,
     accum_cache: AccumulatorCaches,
 }
 
 impl NnueEvaluator {
+    /// Panics if the shared network can't be loaded, see `try_new`
     pub fn new() -> Self {
-        let accum_cache = AccumulatorCaches::new(&NNUE_BIG.ft.biases);
+        match Self::try_new() {
+            Ok(evaluator) => evaluator,
+            Err(e) => panic!("Failed to load NNUE: {}", e),
+        }
+    }
+    /// Evaluator for the shared networks, see `init_big_nnue` and `init_small_nnue`. The small
+    /// network is used when it loads.
+    pub fn try_new() -> Result<Self, NnueError> {
+        Ok(Self::with_networks(init_big_nnue()?, init_small_nnue().ok()))
+    }
+    /// Evaluator for a network loaded by the caller, e.g. one side of an A/B match or one
+    /// we trained
+    pub fn with_network(nnue: impl Into<AnyNnue>) -> Self {
+        Self::with_networks(nnue, None)
+    }
+    /// Evaluator that uses `small` for lopsided positions, see `evaluate`. Panics when `nnue`
+    /// isn't a big network, the accumulators only have room for one network of each width.
+    pub fn with_networks(nnue: impl Into<AnyNnue>, small: Option<Arc<SmallNnue>>) -> Self {
+        let nnue = nnue.into();
+        assert!(
+            small.is_none() || matches!(nnue, AnyNnue::Big(_)),
+            "the small network only goes with a big one, not {}",
+            nnue.architecture().name
+        );
+        let mut accum_cache = AccumulatorCaches::empty();
+        match &nnue {
+            AnyNnue::Big(nnue) => accum_cache.big.clear_with_biases(&nnue.ft.biases),
+            AnyNnue::Small(nnue) => accum_cache.small.clear_with_biases(&nnue.ft.biases),
+            AnyNnue::Sf12(nnue) => accum_cache.sf12.clear_with_biases(&nnue.ft.biases),
+        }
+        if let Some(small) = &small {
+            accum_cache.small.clear_with_biases(&small.ft.biases);
+        }
         Self {
+            nnue,
+            small,
             accum_stack: AccumulatorStack::new(),
             accum_cache,
         }
     }
+    pub fn network(&self) -> &AnyNnue {
+        &self.nnue
+    }
+    pub fn small_network(&self) -> Option<&Arc<SmallNnue>> {
+        self.small.as_ref()
+    }
+    /// Like Stockfish 17.1, lopsided positions go through the small network and are only
+    /// re-evaluated with the big one when the result comes out close
     pub fn evaluate(&mut self, board: &Board) -> EvalResult {
-        NNUE_BIG.evaluate(board, &mut self.accum_stack, &mut self.accum_cache.big)
+        if let Some(small) = &self.small
+            && use_small_net(board)
+        {
+            let eval = small.evaluate(board, &mut self.accum_stack, &mut self.accum_cache.small);
+            if eval.scaled_total().abs() >= SMALL_NET_RETRY_THRESHOLD {
+                return eval;
+            }
+        }
+        let (stack, caches) = (&mut self.accum_stack, &mut self.accum_cache);
+        match &self.nnue {
+            AnyNnue::Big(nnue) => nnue.evaluate(board, stack, &mut caches.big),
+            AnyNnue::Small(nnue) => nnue.evaluate(board, stack, &mut caches.small),
+            AnyNnue::Sf12(nnue) => nnue.evaluate(board, stack, &mut caches.sf12),
+        }
     }
     pub fn trace_eval(&mut self, board: &Board) -> EvalTrace {
-        NNUE_BIG.trace_eval(board, &mut self.accum_stack, &mut self.accum_cache.big)
+        let (stack, caches) = (&mut self.accum_stack, &mut self.accum_cache);
+        match &self.nnue {
+            AnyNnue::Big(nnue) => nnue.trace_eval(board, stack, &mut caches.big),
+            AnyNnue::Small(nnue) => nnue.trace_eval(board, stack, &mut caches.small),
+            AnyNnue::Sf12(nnue) => nnue.trace_eval(board, stack, &mut caches.sf12),
+        }
+    }
+    /// Per piece values from the main network, `board` must be the position the evaluator is at
+    pub fn piece_values(&mut self, board: &Board) -> PieceValues {
+        let (stack, caches) = (&mut self.accum_stack, &mut self.accum_cache);
+        match &self.nnue {
+            AnyNnue::Big(nnue) => nnue.piece_values(board, stack, &mut caches.big),
+            AnyNnue::Small(nnue) => nnue.piece_values(board, stack, &mut caches.small),
+            AnyNnue::Sf12(nnue) => nnue.piece_values(board, stack, &mut caches.sf12),
+        }
     }
     pub fn reset(&mut self, board: &Board) {
-        self.accum_stack
-            .reset(board, &NNUE_BIG, &mut self.accum_cache);
+        match &self.nnue {
+            AnyNnue::Big(nnue) => self.accum_stack.reset(board, nnue, &mut self.accum_cache),
+            // Refreshed through their cache by the first evaluation
+            AnyNnue::Small(_) | AnyNnue::Sf12(_) => self.accum_stack.clear(),
+        }
     }
 
     pub fn do_move(&mut self, board: &Board, mv: pleco::BitMove) {
@@ -54,6 +284,7 @@ impl NnueEvaluator {
 }
 
 fn build_accum(
+    feature_set: FeatureSet,
     board: &Board,
     biases: &[i16],
     weights: &[i16],
@@ -70,11 +301,11 @@ fn build_accum(
     let ksq = [board.king_sq(Player::White), board.king_sq(Player::Black)];
 
     for (sq, pc) in board.get_piece_locations() {
-        if pc == Piece::None {
+        if pc == Piece::None || !feature_set.is_feature(pc.type_of()) {
             continue;
         }
         for c in [Player::White, Player::Black] {
-            let idx = make_index(c as usize, sq.0, pc as usize, ksq[c as usize].0);
+            let idx = feature_set.make_index(c as usize, sq.0, pc as usize, ksq[c as usize].0);
             // add weights for this feature to accumulator
             // weights are input-major: weights[idx * L1 + feature]
             let row = &weights[idx * L1..(idx + 1) * L1];
@@ -92,15 +323,24 @@ fn build_accum(
     accum
 }
 
-pub struct Nnue {
+/// A network file, the big one (3072 transformed features) unless `DIM` says otherwise
+pub struct Nnue<const DIM: usize = TRANSFORMED_FEATURE_DIM_BIG> {
+    arch: &'static Architecture,
     desc: String,
-    pub ft: FeatureTransformer<TRANSFORMED_FEATURE_DIM_BIG>,
-    buckets: Vec<BucketNet<TRANSFORMED_FEATURE_DIM_BIG, L2, L3>>,
+    pub ft: FeatureTransformer<DIM>,
+    buckets: Vec<BucketNet<DIM>>,
 }
 
-impl Debug for Nnue {
+/// The 128 feature network for lopsided positions
+pub type SmallNnue = Nnue<TRANSFORMED_FEATURE_DIM_SMALL>;
+
+/// A Stockfish 12 network, 256 HalfKP features per side
+pub type Sf12Nnue = Nnue<TRANSFORMED_FEATURE_DIM_SF12>;
+
+impl<const DIM: usize> Debug for Nnue<DIM> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("NNUE\n")?;
+        f.write_fmt(format_args!("Arch {}\n", self.arch))?;
         f.write_fmt(format_args!("Desc {}\n", self.desc))?;
         f.write_fmt(format_args!("Feature Transformer\n{:?}\n", self.ft))?;
         for (i, bucket) in self.buckets.iter().enumerate() {
@@ -110,9 +350,12 @@ impl Debug for Nnue {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct EvalResult {
     pub psqt: i32,
     pub positional: i32,
+    /// Whether the small network produced this evaluation
+    pub small_net: bool,
 }
 
 impl EvalResult {
@@ -137,16 +380,180 @@ impl EvalResult {
     pub fn scaled_total(&self) -> i32 {
         (125 * self.psqt + 131 * self.positional) / 128
     }
+    /// Scaled total in centipawns for the side to move
+    pub fn to_cp(&self, board: &Board) -> f32 {
+        to_cp(self.scaled_total(), board)
+    }
+    /// Win, draw and loss chances of the side to move for the scaled total
+    pub fn wdl(&self, board: &Board) -> Wdl {
+        let win = win_rate_model(self.scaled_total(), board);
+        let loss = win_rate_model(-self.scaled_total(), board);
+        Wdl { win, draw: 1000u32.saturating_sub(win + loss), loss }
+    }
+}
+
+/// Per mille chances like Stockfish's `wdl` output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Wdl {
+    pub win: u32,
+    pub draw: u32,
+    pub loss: u32,
+}
+
+/// Evaluates every board on its own from the shared networks, see `evaluate_many_with`.
+/// Panics if the big network can't be loaded, like `NnueEvaluator::new`.
+pub fn evaluate_many(boards: &[Board]) -> Vec<EvalResult> {
+    let nnue = init_big_nnue().unwrap_or_else(|e| panic!("Failed to load NNUE: {}", e));
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    evaluate_many_with(&nnue.into(), init_small_nnue().ok().as_ref(), boards, threads)
 }
 
-impl Nnue {
+/// Evaluations of `boards` in order, split into one contiguous chunk per thread. Each thread has
+/// its own `NnueEvaluator`, whose accumulator cache carries over between its boards.
+pub fn evaluate_many_with(
+    nnue: &AnyNnue,
+    small: Option<&Arc<SmallNnue>>,
+    boards: &[Board],
+    threads: usize,
+) -> Vec<EvalResult> {
+    if boards.is_empty() {
+        return Vec::new();
+    }
+    let chunk = boards.len().div_ceil(threads.max(1));
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = boards
+            .chunks(chunk)
+            .map(|boards| {
+                let (nnue, small) = (nnue.clone(), small.cloned());
+                scope.spawn(move || {
+                    let mut evaluator = NnueEvaluator::with_networks(nnue, small);
+                    boards
+                        .iter()
+                        .map(|board| {
+                            evaluator.reset(board);
+                            evaluator.evaluate(board)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    })
+}
+
+impl<const DIM: usize> Nnue<DIM> {
+    /// The Stockfish architecture of this width
+    pub const ARCHITECTURE: &'static Architecture = Architecture::with_transformed_dims(DIM);
+    /// Header hash of the Stockfish network of this width
+    pub const HASH: u32 = Self::ARCHITECTURE.hash();
+
+    /// What the header of this network said, which may use other features than Stockfish's
+    pub fn architecture(&self) -> &'static Architecture {
+        self.arch
+    }
+
+    /// Description string stored in the network file
+    pub fn description(&self) -> &str {
+        &self.desc
+    }
+
+    /// Serialize in the `.nnue` format, byte for byte what `load_nnue_from_reader` read
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        write_u32(w, self.arch.version)?;
+        write_u32(w, self.arch.hash())?;
+        write_u32(w, self.desc.len() as u32)?;
+        w.write_all(self.desc.as_bytes())?;
+
+        self.ft.write_parameters(w)?;
+        for bucket in &self.buckets {
+            bucket.write_parameters(w)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), NnueError> {
+        let path = path.as_ref();
+        let with_path = |source| NnueError::Io { path: Some(path.to_path_buf()), source };
+
+        let mut w = BufWriter::new(File::create(path).map_err(with_path)?);
+        self.write(&mut w).and_then(|_| w.flush()).map_err(with_path)
+    }
+
+    /// Build a network from trained parameters. Panics when they don't fit the architecture.
+    pub fn from_quantized(net: &QuantizedNetwork) -> Self {
+        let arch = net.arch;
+        assert_eq!(arch.transformed_dims, DIM, "{} is not {} wide", arch.name, DIM);
+        assert_eq!(net.layer_stacks.len(), arch.layer_stacks);
+
+        let ft = FeatureTransformer::from_parameters(
+            arch,
+            &net.ft_biases,
+            &net.ft_weights,
+            &net.psqt_weights,
+        );
+        let (fc1_inputs, l3) = (arch.fc1_inputs(), arch.affine_outputs(1));
+        let buckets = net
+            .layer_stacks
+            .iter()
+            .map(|stack| {
+                let fc0_weights = stack.fc0.padded_weights(arch.ft_outputs());
+                let fc0 = if arch.sparse_first_layer() {
+                    FirstLayer::Sparse(AffineTransformSparse::from_parameters(&stack.fc0.biases, &fc0_weights))
+                } else {
+                    FirstLayer::Dense(AffineTransform::from_parameters(
+                        arch.ft_outputs(),
+                        stack.fc0.biases.len(),
+                        &stack.fc0.biases,
+                        &fc0_weights,
+                    ))
+                };
+                BucketNet::new(
+                    arch,
+                    fc0,
+                    AffineTransform::from_parameters(
+                        fc1_inputs,
+                        l3,
+                        &stack.fc1.biases,
+                        &stack.fc1.padded_weights(fc1_inputs),
+                    ),
+                    AffineTransform::from_parameters(l3, 1, &stack.fc2.biases, &stack.fc2.padded_weights(l3)),
+                )
+            })
+            .collect();
+
+        Nnue { arch, desc: net.desc.clone(), ft, buckets }
+    }
+
+    /// The parameters in file order, what `from_quantized` builds this network from
+    pub fn to_quantized(&self) -> QuantizedNetwork {
+        let (ft_biases, ft_weights, psqt_weights) = self.ft.file_parameters();
+        QuantizedNetwork {
+            arch: self.arch,
+            desc: self.desc.clone(),
+            ft_biases,
+            ft_weights,
+            psqt_weights,
+            layer_stacks: self.buckets.iter().map(BucketNet::to_quantized).collect(),
+        }
+    }
+
+    /// Evaluate from scratch with a stack and cache of its own. Slow, meant for tools and tests
+    /// that have no `NnueEvaluator`, e.g. for a small network on its own.
+    pub fn evaluate_fresh(&self, board: &Board) -> EvalResult {
+        let mut accum_stack = AccumulatorStack::new();
+        accum_stack.clear();
+        let mut accum_cache = AccumulatorCache::<DIM>::new_boxed();
+        accum_cache.clear_with_biases(&self.ft.biases);
+        self.evaluate(board, &mut accum_stack, &mut accum_cache)
+    }
+
     pub fn evaluate(
         &self,
         board: &Board,
         accum_stack: &mut AccumulatorStack,
-        accum_cache: &mut AccumulatorCache<TRANSFORMED_FEATURE_DIM_BIG>,
+        accum_cache: &mut AccumulatorCache<DIM>,
     ) -> EvalResult {
-        let bucket: usize = (board.count_all_pieces() as usize - 1) / 4;
+        let bucket = self.arch.bucket(board.count_all_pieces() as usize);
 
         // TRANSFORM BLOCK
         let mut buf = self.ft.new_output_buffer();
@@ -161,6 +568,7 @@ impl Nnue {
         EvalResult {
             psqt: psqt / OUTPUT_SCALE,
             positional: positional / OUTPUT_SCALE,
+            small_net: DIM == TRANSFORMED_FEATURE_DIM_SMALL,
         }
     }
 
@@ -168,13 +576,13 @@ impl Nnue {
         &self,
         board: &Board,
         accum_stack: &mut AccumulatorStack,
-        accum_cache: &mut AccumulatorCache<TRANSFORMED_FEATURE_DIM_BIG>,
+        accum_cache: &mut AccumulatorCache<DIM>,
     ) -> EvalTrace {
-        let mut trace = EvalTrace::new();
-        trace.selected_bucket = (board.count_all_pieces() as usize - 1) / 4;
+        let mut trace = EvalTrace::new(self.arch.layer_stacks);
+        trace.selected_bucket = self.arch.bucket(board.count_all_pieces() as usize);
         trace.side_to_move = board.turn();
 
-        for bucket in 0..PSQT_BUCKETS {
+        for bucket in 0..self.arch.layer_stacks {
             // TRANSFORM BLOCK
 
             let mut buf = self.ft.new_output_buffer();
@@ -193,6 +601,37 @@ impl Nnue {
 
         trace
     }
+
+    /// How much the evaluation drops when each piece but the kings is taken off the board, like
+    /// Stockfish's trace. Every removal is an incremental update on top of the accumulator of
+    /// `board`, which has to be the position `accum_stack` is at. A piece whose removal leaves a
+    /// position pleco rejects, like a blocker uncovering a second check, gets no value.
+    pub fn piece_values(
+        &self,
+        board: &Board,
+        accum_stack: &mut AccumulatorStack,
+        accum_cache: &mut AccumulatorCache<DIM>,
+    ) -> PieceValues {
+        let white_side = |eval: EvalResult| {
+            let total = eval.psqt + eval.positional;
+            if board.turn() == Player::White { total } else { -total }
+        };
+        let eval = white_side(self.evaluate(board, accum_stack, accum_cache));
+
+        let mut values = [None; SQUARES];
+        for (sq, piece) in board.get_piece_locations() {
+            if piece.type_of() == PieceType::K {
+                continue;
+            }
+            let Ok(removed) = without_piece(board, sq) else {
+                continue;
+            };
+            accum_stack.push(DirtyPiece::removal(piece, sq));
+            values[sq.0 as usize] = Some(eval - white_side(self.evaluate(&removed, accum_stack, accum_cache)));
+            accum_stack.pop();
+        }
+        PieceValues { eval, values }
+    }
 }
 
 // Bucket selection: bucket = (piece_count - 1) / 4 (0..7).
@@ -211,36 +650,252 @@ impl Nnue {
 // Add forward term: take FC0[15] scaled to match Stockfish’s 600 * OutputScale / (127 * 2^WeightScaleBits) and add to FC2 output.
 // Scale output: divide by OutputScale (16) to get eval units; combine psqt+positional as Stockfish does ((125*psqt + 131*pos)/128, with small-net retry logic if you implement both).
 
+/// `board` with the piece on `sq` taken off. The en passant square is dropped, since taking off
+/// the pawn that just double pushed makes it invalid.
+fn without_piece(board: &Board, sq: SQ) -> Result<Board, FenBuildError> {
+    let mut placement = String::new();
+    for rank in (0..8u8).rev() {
+        let mut empty = 0u8;
+        for file in 0..8u8 {
+            let square = SQ(rank * 8 + file);
+            let piece = board.piece_at_sq(square);
+            if square == sq || piece == Piece::None {
+                empty += 1;
+                continue;
+            }
+            if empty > 0 {
+                placement.push(char::from(b'0' + empty));
+                empty = 0;
+            }
+            placement.push(piece.character_lossy());
+        }
+        if empty > 0 {
+            placement.push(char::from(b'0' + empty));
+        }
+        if rank > 0 {
+            placement.push('/');
+        }
+    }
+    let fen = board.fen();
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    Board::from_fen(&format!("{} {} {} - {} {}", placement, fields[1], fields[2], fields[4], fields[5]))
+}
+
 // --- Loader ---
-pub fn load_big_nnue(path: impl AsRef<Path>) -> io::Result<Nnue> {
-    let f = File::open(path)?;
-    let mut r = BufReader::new(f);
-
-    let version = read_u32(&mut r)?;
-    let hash = read_u32(&mut r)?;
-    if version != VERSION {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "version mismatch",
-        ));
+pub fn load_big_nnue(path: impl AsRef<Path>) -> Result<Nnue, NnueError> {
+    load_nnue(path)
+}
+
+pub fn load_big_nnue_from_bytes(bytes: &[u8]) -> Result<Nnue, NnueError> {
+    load_nnue_from_reader(bytes)
+}
+
+pub fn load_big_nnue_from_reader(r: impl Read) -> Result<Nnue, NnueError> {
+    load_nnue_from_reader(r)
+}
+
+pub fn load_small_nnue(path: impl AsRef<Path>) -> Result<SmallNnue, NnueError> {
+    load_nnue(path)
+}
+
+pub fn load_nnue<const DIM: usize>(path: impl AsRef<Path>) -> Result<Nnue<DIM>, NnueError> {
+    load_file(path, load_nnue_from_reader)
+}
+
+pub fn load_nnue_from_reader<const DIM: usize>(mut r: impl Read) -> Result<Nnue<DIM>, NnueError> {
+    let arch = read_header(&mut r)?;
+    if arch.transformed_dims != DIM {
+        return Err(NnueError::ArchMismatch { found: arch, expected: Nnue::<DIM>::ARCHITECTURE });
     }
-    if hash != BIG_HASH {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "arch hash mismatch",
-        ));
+    read_network(&mut r, arch)
+}
+
+/// A shared network of any supported architecture, for callers that don't know which one a
+/// file holds. Cloning it shares the network.
+#[derive(Clone, Debug)]
+pub enum AnyNnue {
+    Big(Arc<Nnue>),
+    Small(Arc<SmallNnue>),
+    Sf12(Arc<Sf12Nnue>),
+}
+
+impl From<Arc<Nnue>> for AnyNnue {
+    fn from(nnue: Arc<Nnue>) -> Self {
+        AnyNnue::Big(nnue)
+    }
+}
+
+impl From<Arc<SmallNnue>> for AnyNnue {
+    fn from(nnue: Arc<SmallNnue>) -> Self {
+        AnyNnue::Small(nnue)
+    }
+}
+
+impl From<Arc<Sf12Nnue>> for AnyNnue {
+    fn from(nnue: Arc<Sf12Nnue>) -> Self {
+        AnyNnue::Sf12(nnue)
+    }
+}
+
+impl AnyNnue {
+    pub fn architecture(&self) -> &'static Architecture {
+        match self {
+            AnyNnue::Big(nnue) => nnue.architecture(),
+            AnyNnue::Small(nnue) => nnue.architecture(),
+            AnyNnue::Sf12(nnue) => nnue.architecture(),
+        }
+    }
+
+    /// Build whichever width `net.arch` has, see `Nnue::from_quantized`
+    pub fn from_quantized(net: &QuantizedNetwork) -> Self {
+        match net.arch.transformed_dims {
+            TRANSFORMED_FEATURE_DIM_BIG => AnyNnue::Big(Arc::new(Nnue::from_quantized(net))),
+            TRANSFORMED_FEATURE_DIM_SMALL => AnyNnue::Small(Arc::new(Nnue::from_quantized(net))),
+            TRANSFORMED_FEATURE_DIM_SF12 => AnyNnue::Sf12(Arc::new(Nnue::from_quantized(net))),
+            _ => unreachable!("{} is supported but not instantiated", net.arch.name),
+        }
+    }
+
+    pub fn to_quantized(&self) -> QuantizedNetwork {
+        match self {
+            AnyNnue::Big(nnue) => nnue.to_quantized(),
+            AnyNnue::Small(nnue) => nnue.to_quantized(),
+            AnyNnue::Sf12(nnue) => nnue.to_quantized(),
+        }
+    }
+
+    pub fn evaluate_fresh(&self, board: &Board) -> EvalResult {
+        match self {
+            AnyNnue::Big(nnue) => nnue.evaluate_fresh(board),
+            AnyNnue::Small(nnue) => nnue.evaluate_fresh(board),
+            AnyNnue::Sf12(nnue) => nnue.evaluate_fresh(board),
+        }
+    }
+
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        match self {
+            AnyNnue::Big(nnue) => nnue.write(w),
+            AnyNnue::Small(nnue) => nnue.write(w),
+            AnyNnue::Sf12(nnue) => nnue.write(w),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), NnueError> {
+        match self {
+            AnyNnue::Big(nnue) => nnue.save(path),
+            AnyNnue::Small(nnue) => nnue.save(path),
+            AnyNnue::Sf12(nnue) => nnue.save(path),
+        }
     }
-    let desc_len = read_u32(&mut r)? as usize;
+}
+
+/// Integer parameters of a network as a trainer produces them, in file order and without the
+/// padding of the layer inputs. The scales are Stockfish's: 127 is one for the transformer
+/// weights and biases, 64 for affine weights and 64 * 127 for affine biases. The last layer's
+/// weights and biases and the PSQT weights are scaled to `600 * OUTPUT_SCALE` per unit of output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuantizedNetwork {
+    pub arch: &'static Architecture,
+    pub desc: String,
+    pub ft_biases: Vec<i16>,
+    /// One row of `transformed_dims` weights per input feature
+    pub ft_weights: Vec<i16>,
+    /// One row of `psqt_buckets` weights per input feature
+    pub psqt_weights: Vec<i32>,
+    pub layer_stacks: Vec<QuantizedLayerStack>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuantizedLayerStack {
+    pub fc0: QuantizedAffine,
+    pub fc1: QuantizedAffine,
+    pub fc2: QuantizedAffine,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuantizedAffine {
+    pub biases: Vec<i32>,
+    /// One row of inputs per output
+    pub weights: Vec<i8>,
+}
+
+impl QuantizedAffine {
+    /// The weights with every row padded to a multiple of 32 inputs, as the layers read them
+    fn padded_weights(&self, inputs: usize) -> Vec<i8> {
+        let padded = ceil_to_multiple(inputs, 32);
+        assert_eq!(self.weights.len(), self.biases.len() * inputs);
+        let mut weights = vec![0; self.biases.len() * padded];
+        for (row, padded_row) in self.weights.chunks(inputs).zip(weights.chunks_mut(padded)) {
+            padded_row[..inputs].copy_from_slice(row);
+        }
+        weights
+    }
+
+    /// Undo `padded_weights`
+    pub(crate) fn from_padded(biases: Vec<i32>, padded: &[i8], inputs: usize) -> Self {
+        let padded_inputs = ceil_to_multiple(inputs, 32);
+        assert_eq!(padded.len(), biases.len() * padded_inputs);
+        let weights = padded.chunks(padded_inputs).flat_map(|row| &row[..inputs]).copied().collect();
+        QuantizedAffine { biases, weights }
+    }
+}
+
+pub fn load_any_nnue(path: impl AsRef<Path>) -> Result<AnyNnue, NnueError> {
+    load_file(path, load_any_nnue_from_reader)
+}
+
+/// Load whichever supported architecture the header names
+pub fn load_any_nnue_from_reader(mut r: impl Read) -> Result<AnyNnue, NnueError> {
+    let arch = read_header(&mut r)?;
+    match arch.transformed_dims {
+        TRANSFORMED_FEATURE_DIM_BIG => Ok(AnyNnue::Big(Arc::new(read_network(&mut r, arch)?))),
+        TRANSFORMED_FEATURE_DIM_SMALL => Ok(AnyNnue::Small(Arc::new(read_network(&mut r, arch)?))),
+        TRANSFORMED_FEATURE_DIM_SF12 => Ok(AnyNnue::Sf12(Arc::new(read_network(&mut r, arch)?))),
+        _ => unreachable!("{} is supported but not instantiated", arch.name),
+    }
+}
+
+fn load_file<T>(
+    path: impl AsRef<Path>,
+    load: impl FnOnce(BufReader<File>) -> Result<T, NnueError>,
+) -> Result<T, NnueError> {
+    let path = path.as_ref();
+    let with_path = |source| NnueError::Io { path: Some(path.to_path_buf()), source };
+
+    let f = File::open(path).map_err(with_path)?;
+    load(BufReader::new(f)).map_err(|e| match e {
+        NnueError::Io { path: None, source } => with_path(source),
+        e => e,
+    })
+}
+
+/// Look the architecture hash up and check the version against it
+fn read_header(r: &mut impl Read) -> Result<&'static Architecture, NnueError> {
+    let version = read_u32(r)?;
+    let hash = read_u32(r)?;
+    let arch = Architecture::supported(hash).ok_or(NnueError::UnsupportedArchitecture { found: hash })?;
+    if version != arch.version {
+        return Err(NnueError::VersionMismatch { found: version, expected: arch.version });
+    }
+    Ok(arch)
+}
+
+/// Everything after the header
+fn read_network<const DIM: usize>(
+    r: &mut impl Read,
+    arch: &'static Architecture,
+) -> Result<Nnue<DIM>, NnueError> {
+    let desc_len = read_u32(r)? as usize;
     let mut desc_bytes = vec![0u8; desc_len];
     r.read_exact(&mut desc_bytes)?;
     let desc = String::from_utf8_lossy(&desc_bytes).to_string();
 
     // Feature transformer
-    let ft = FeatureTransformer::read_parameters(&mut r)?;
+    let ft = FeatureTransformer::read_parameters(r, arch)?;
 
-    let mut buckets = Vec::with_capacity(LAYER_STACKS);
-    for _ in 0..LAYER_STACKS {
-        let net = BucketNet::read_parameters(&mut r)?;
+    let mut buckets = Vec::with_capacity(arch.layer_stacks);
+    for _ in 0..arch.layer_stacks {
+        let net = BucketNet::read_parameters(r, arch)?;
         buckets.push(net);
     }
 
@@ -248,10 +903,10 @@ pub fn load_big_nnue(path: impl AsRef<Path>) -> io::Result<Nnue> {
     let mut tail = Vec::new();
     r.read_to_end(&mut tail)?;
     if !tail.is_empty() {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "trailing data"));
+        return Err(NnueError::TrailingData);
     }
 
-    Ok(Nnue { desc, ft, buckets })
+    Ok(Nnue { arch, desc, ft, buckets })
 }
 
 #[cfg(test)]
@@ -262,7 +917,7 @@ mod tests {
 
     use pleco::{BitMove, SQ};
 
-    use crate::feature_sets::INPUT_DIM;
+    use crate::architecture::{HALF_KP_SMALL, SF12, SF_BIG, SF_SMALL};
 
     use super::*;
 
@@ -272,9 +927,44 @@ mod tests {
             load_big_nnue("/home/bmellin/chess/chessBackendWebFinal/nn-1c0000000000.nnue").unwrap();
         println!("{:#?}", nnue);
         assert_eq!(nnue.ft.biases.len(), L1);
-        assert_eq!(nnue.ft.weights.len(), L1 * INPUT_DIM);
-        assert_eq!(nnue.ft.psqt_weights.len(), PSQT_BUCKETS * INPUT_DIM);
-        assert_eq!(nnue.buckets.len(), LAYER_STACKS);
+        assert_eq!(nnue.ft.weights.len(), L1 * nnue.ft.input_dims());
+        assert_eq!(nnue.ft.psqt_weights.len(), PSQT_BUCKETS * nnue.ft.input_dims());
+        assert_eq!(nnue.buckets.len(), SF_BIG.layer_stacks);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        match load_big_nnue("/nonexistent/net.nnue") {
+            Err(NnueError::Io { path: Some(p), .. }) => assert_eq!(p, Path::new("/nonexistent/net.nnue")),
+            other => panic!("expected io error with path, got {:?}", other.err()),
+        }
+
+        assert!(matches!(load_big_nnue_from_bytes(&[]), Err(NnueError::Io { path: None, .. })));
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(SF_BIG.version ^ 1).to_le_bytes());
+        header.extend_from_slice(&SF_BIG.hash().to_le_bytes());
+        assert!(matches!(
+            load_big_nnue_from_bytes(&header),
+            Err(NnueError::VersionMismatch { expected, .. }) if expected == SF_BIG.version
+        ));
+
+        header[..4].copy_from_slice(&SF_BIG.version.to_le_bytes());
+        header[4..8].copy_from_slice(&(SF_BIG.hash() ^ 1).to_le_bytes());
+        assert!(matches!(
+            load_big_nnue_from_bytes(&header),
+            Err(NnueError::UnsupportedArchitecture { found }) if found == SF_BIG.hash() ^ 1
+        ));
+        assert!(matches!(
+            load_any_nnue_from_reader(&header[..]),
+            Err(NnueError::UnsupportedArchitecture { .. })
+        ));
+
+        // Valid header followed by garbage parameters
+        header[4..8].copy_from_slice(&SF_BIG.hash().to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&[0u8; 64]);
+        assert!(matches!(load_big_nnue_from_bytes(&header), Err(NnueError::Io { .. })));
     }
 
     #[test]
@@ -430,6 +1120,544 @@ mod tests {
         }
     }
 
+    /// Small xorshift so the synthetic networks are the same on every run
+    struct Rng(u64);
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn range(&mut self, lo: i32, hi: i32) -> i32 {
+            lo + (self.next() % (hi - lo + 1) as u64) as i32
+        }
+        fn vec<T>(&mut self, n: usize, lo: i32, hi: i32, f: impl Fn(i32) -> T) -> Vec<T> {
+            (0..n).map(|_| f(self.range(lo, hi))).collect()
+        }
+    }
+
+    /// Random network with the real architecture, big or small. Ranges are picked so the accumulator
+    /// clamps and the sparse input path get exercised, while the forward term of fc0
+    /// stays small enough not to overflow like it would with real weights.
+    fn synthetic_nnue<const DIM: usize>(seed: u64) -> Nnue<DIM> {
+        synthetic_nnue_with(seed, Nnue::<DIM>::ARCHITECTURE)
+    }
+
+    fn synthetic_nnue_with<const DIM: usize>(seed: u64, arch: &'static Architecture) -> Nnue<DIM> {
+        let mut rng = Rng(seed);
+        let input_dims = arch.feature_set.input_dims();
+        let ft = FeatureTransformer::from_parameters(
+            arch,
+            &rng.vec(DIM, -40, 100, |x| x as i16),
+            &rng.vec(DIM * input_dims, -30, 30, |x| x as i16),
+            &rng.vec(arch.psqt_buckets * input_dims, -3000, 3000, |x| x),
+        );
+
+        let fc0_padded = ceil_to_multiple(arch.ft_outputs(), 32);
+        let (l2, fc1_in, l3) = (arch.affine_outputs(0), arch.fc1_inputs(), arch.affine_outputs(1));
+        let buckets = (0..arch.layer_stacks)
+            .map(|_| {
+                let fc0_biases = rng.vec(l2, -3000, 3000, |x| x);
+                let fc0_weights = rng.vec(l2 * fc0_padded, -8, 8, |x| x as i8);
+                let fc0 = if arch.sparse_first_layer() {
+                    FirstLayer::Sparse(AffineTransformSparse::from_parameters(&fc0_biases, &fc0_weights))
+                } else {
+                    FirstLayer::Dense(AffineTransform::from_parameters(arch.ft_outputs(), l2, &fc0_biases, &fc0_weights))
+                };
+                let fc1 = AffineTransform::from_parameters(
+                    fc1_in,
+                    l3,
+                    &rng.vec(l3, -3000, 3000, |x| x),
+                    &rng.vec(l3 * ceil_to_multiple(fc1_in, 32), -128, 127, |x| x as i8),
+                );
+                let fc2 = AffineTransform::from_parameters(
+                    l3,
+                    1,
+                    &rng.vec(1, -3000, 3000, |x| x),
+                    &rng.vec(ceil_to_multiple(l3, 32), -128, 127, |x| x as i8),
+                );
+                BucketNet::new(arch, fc0, fc1, fc2)
+            })
+            .collect();
+
+        Nnue { arch, desc: "synthetic".to_string(), ft, buckets }
+    }
+
+    /// Evaluations of the synthetic network. Every SIMD backend must reproduce them exactly,
+    /// check with e.g. `RUSTFLAGS="-C target-cpu=x86-64"` for the scalar one.
+    #[test]
+    fn test_synthetic_golden_evals() {
+        const POSITIONS: [(&str, i32, i32); 6] = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 0, 1574),
+            ("rnbqkbnr/pppppppp/8/8/2P5/8/PP1PPPPP/RNBQKBNR b KQkq - 0 1", 96, 1578),
+            ("rq2kb1r/pppb1ppp/3ppn2/8/4PP2/2P5/PP1P2PP/RNB1KBNR w KQkq - 0 1", -37, 401),
+            ("r1bqkb1r/ppp2ppp/2n1p3/3pPn2/3P4/2P2P2/PP1N2PP/R1BQKBNR b KQkq - 2 6", 100, 719),
+            ("8/4P3/2bk1K2/8/1PB5/8/8/8 w - - 1 60", -131, 2472),
+            ("6k1/5ppp/8/8/8/8/5PPP/3R2K1 b - - 0 1", -10, -541),
+        ];
+
+        let nnue: Nnue = synthetic_nnue(0x2545_F491_4F6C_DD1D);
+        let mut caches = AccumulatorCaches::new(&nnue.ft.biases, None);
+        let mut stack = AccumulatorStack::new();
+
+        let mut actual = Vec::new();
+        for (fen, _, _) in POSITIONS {
+            let board = Board::from_fen(fen).unwrap();
+            stack.reset(&board, &nnue, &mut caches);
+            let eval = nnue.evaluate(&board, &mut stack, &mut caches.big);
+            actual.push((fen, eval.psqt, eval.positional));
+        }
+        assert_eq!(actual, POSITIONS);
+
+        // Incremental updates land on the same values as a refresh
+        let mut board = Board::start_pos();
+        stack.reset(&board, &nnue, &mut caches);
+        for uci in ["e2e4", "d7d5", "e4d5", "g8f6", "f1b5", "c7c6", "g1f3", "c6b5", "e1g1"] {
+            let mv = board.generate_moves().iter().copied().find(|m| m.to_string() == uci).unwrap();
+            let dirty_piece = DirtyPiece::from_move(&board, mv);
+            stack.push(dirty_piece);
+            board.apply_move(mv);
+            let incremental = nnue.evaluate(&board, &mut stack, &mut caches.big);
+
+            let mut fresh_stack = AccumulatorStack::new();
+            let mut fresh_caches = AccumulatorCaches::new(&nnue.ft.biases, None);
+            fresh_stack.reset(&board, &nnue, &mut fresh_caches);
+            let fresh = nnue.evaluate(&board, &mut fresh_stack, &mut fresh_caches.big);
+            assert_eq!((incremental.psqt, incremental.positional), (fresh.psqt, fresh.positional), "{}", uci);
+        }
+    }
+
+    /// Lines longer than `MAX_PLY` grow the stack instead of running out of it
+    #[test]
+    fn test_deep_stack() {
+        let nnue: Nnue = synthetic_nnue(6);
+        let mut caches = AccumulatorCaches::new(&nnue.ft.biases, None);
+        let mut stack = AccumulatorStack::new();
+        let mut board = Board::start_pos();
+        stack.reset(&board, &nnue, &mut caches);
+        let root = nnue.evaluate(&board, &mut stack, &mut caches.big);
+
+        // Knights out and back, then the kings, which forces refreshes along the way
+        let cycle = ["g1f3", "g8f6", "f3g1", "f6g8", "e1e2", "e8e7", "e2e1", "e7e8"];
+        let plies = 3 * MAX_PLY;
+        for ply in 0..plies {
+            let uci = match ply {
+                0 => "e2e3",
+                1 => "e7e6",
+                _ => cycle[(ply - 2) % cycle.len()],
+            };
+            let mv = board.generate_moves().iter().copied().find(|m| m.to_string() == uci).unwrap();
+            stack.push(DirtyPiece::from_move(&board, mv));
+            board.apply_move(mv);
+            if ply % 50 == 0 || ply == plies - 1 {
+                let incremental = nnue.evaluate(&board, &mut stack, &mut caches.big);
+                let mut fresh_stack = AccumulatorStack::new();
+                let mut fresh_caches = AccumulatorCaches::new(&nnue.ft.biases, None);
+                fresh_stack.reset(&board, &nnue, &mut fresh_caches);
+                let fresh = nnue.evaluate(&board, &mut fresh_stack, &mut fresh_caches.big);
+                assert_eq!((incremental.psqt, incremental.positional), (fresh.psqt, fresh.positional), "ply {}", ply);
+            }
+        }
+        assert_eq!(stack.ply(), plies);
+
+        for _ in 0..plies {
+            stack.pop();
+            board.undo_move();
+        }
+        assert_eq!(stack.ply(), 0);
+        let back = nnue.evaluate(&board, &mut stack, &mut caches.big);
+        assert_eq!((back.psqt, back.positional), (root.psqt, root.positional));
+    }
+
+    #[test]
+    fn test_piece_values() {
+        let nnue: Nnue = synthetic_nnue(5);
+        let mut caches = AccumulatorCaches::new(&nnue.ft.biases, None);
+        let mut stack = AccumulatorStack::new();
+        let white_side = |board: &Board, eval: EvalResult| {
+            let total = eval.psqt + eval.positional;
+            if board.turn() == Player::White { total } else { -total }
+        };
+
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bqkb1r/ppp2ppp/2n1p3/3pPn2/3P4/2P2P2/PP1N2PP/R1BQKBNR b KQkq - 2 6",
+            // Taking off the pawn that just double pushed invalidates the e.p. square
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            stack.reset(&board, &nnue, &mut caches);
+            let values = nnue.piece_values(&board, &mut stack, &mut caches.big);
+            assert_eq!(values.eval, white_side(&board, nnue.evaluate(&board, &mut stack, &mut caches.big)));
+
+            for sq in 0..SQUARES as u8 {
+                let piece = board.piece_at_sq(SQ(sq));
+                if piece == Piece::None || piece.type_of() == PieceType::K {
+                    assert_eq!(values.values[sq as usize], None, "{} {}", fen, SQ(sq));
+                    continue;
+                }
+                // Same as evaluating the position without the piece from scratch
+                let removed = without_piece(&board, SQ(sq)).unwrap();
+                let mut fresh_stack = AccumulatorStack::new();
+                let mut fresh_caches = AccumulatorCaches::new(&nnue.ft.biases, None);
+                fresh_stack.reset(&removed, &nnue, &mut fresh_caches);
+                let fresh = white_side(&removed, nnue.evaluate(&removed, &mut fresh_stack, &mut fresh_caches.big));
+                assert_eq!(values.values[sq as usize], Some(values.eval - fresh), "{} {}", fen, SQ(sq));
+            }
+            assert_eq!(values.pawns(&board).count(), board.count_all_pieces() as usize - 2);
+            assert_eq!(values.grid(&board).lines().count(), 8 * 3 + 1);
+        }
+
+        // Without the pinned bishop white would be in check from the pawn and the bishop at
+        // once, which pleco rejects. Only that piece goes without a value.
+        let board = Board::from_fen("4k3/8/8/8/1b6/8/3B1p2/4K3 w - - 0 1").unwrap();
+        assert!(without_piece(&board, SQ::D2).is_err());
+        stack.reset(&board, &nnue, &mut caches);
+        let values = nnue.piece_values(&board, &mut stack, &mut caches.big);
+        assert_eq!(values.values[SQ::D2.0 as usize], None);
+        assert!(values.values[SQ::B4.0 as usize].is_some() && values.values[SQ::F2.0 as usize].is_some());
+    }
+
+    #[test]
+    fn test_networks_side_by_side() {
+        let nets: [Arc<Nnue>; 2] = [Arc::new(synthetic_nnue(1)), Arc::new(synthetic_nnue(2))];
+        const FEN: &str = "r1bqkb1r/ppp2ppp/2n1p3/3pPn2/3P4/2P2P2/PP1N2PP/R1BQKBNR b KQkq - 2 6";
+
+        // Two evaluators per network, each on its own thread
+        let handles: Vec<_> = nets
+            .iter()
+            .flat_map(|net| [net.clone(), net.clone()])
+            .map(|net| {
+                std::thread::spawn(move || {
+                    let board = Board::from_fen(FEN).unwrap();
+                    let mut evaluator = NnueEvaluator::with_network(net);
+                    evaluator.reset(&board);
+                    let eval = evaluator.evaluate(&board);
+                    (eval.psqt, eval.positional)
+                })
+            })
+            .collect();
+        let evals: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert_eq!(evals[0], evals[1]);
+        assert_eq!(evals[2], evals[3]);
+        assert_ne!(evals[0], evals[2]);
+        assert_eq!(Arc::strong_count(&nets[0]), 1);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let path = "/home/bmellin/chess/chessBackendWebFinal/nn-1c0000000000.nnue";
+        let bytes = std::fs::read(path).unwrap();
+        let nnue = load_big_nnue_from_bytes(&bytes).unwrap();
+
+        let mut written = Vec::with_capacity(bytes.len());
+        nnue.write(&mut written).unwrap();
+        assert!(written == bytes, "written network differs from {}", path);
+    }
+
+    #[test]
+    fn test_synthetic_write_round_trip() {
+        let big: Nnue = synthetic_nnue(5);
+        let small: SmallNnue = synthetic_nnue(6);
+
+        let mut big_bytes = Vec::new();
+        big.write(&mut big_bytes).unwrap();
+        let mut small_bytes = Vec::new();
+        small.write(&mut small_bytes).unwrap();
+
+        let big_read: Nnue = load_nnue_from_reader(big_bytes.as_slice()).unwrap();
+        let small_read: SmallNnue = load_nnue_from_reader(small_bytes.as_slice()).unwrap();
+        assert_eq!(big_read.description(), "synthetic");
+
+        let mut rewritten = Vec::new();
+        big_read.write(&mut rewritten).unwrap();
+        assert!(rewritten == big_bytes);
+        rewritten.clear();
+        small_read.write(&mut rewritten).unwrap();
+        assert!(rewritten == small_bytes);
+
+        // The parameters in file order build the same network again, up to the input
+        // padding, which the synthetic layers fill with unused weights
+        let (big_quantized, small_quantized) = (big_read.to_quantized(), small_read.to_quantized());
+        assert_eq!(big_quantized.desc, "synthetic");
+        let big_rebuilt: Nnue = Nnue::from_quantized(&big_quantized);
+        let small_rebuilt: SmallNnue = Nnue::from_quantized(&small_quantized);
+        assert!(big_rebuilt.to_quantized() == big_quantized);
+        assert!(small_rebuilt.to_quantized() == small_quantized);
+
+        // A small network is not a big one
+        assert!(matches!(
+            load_big_nnue_from_bytes(&small_bytes),
+            Err(NnueError::ArchMismatch { found, expected }) if found == &SF_SMALL && expected == &SF_BIG
+        ));
+        // but loads as whatever the header says
+        assert!(matches!(load_any_nnue_from_reader(&big_bytes[..]), Ok(AnyNnue::Big(_))));
+        match load_any_nnue_from_reader(&small_bytes[..]) {
+            Ok(any @ AnyNnue::Small(_)) => assert_eq!(any.architecture(), &SF_SMALL),
+            other => panic!("expected the small network, got {:?}", other.err()),
+        }
+
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkb1r/ppp2ppp/2n1p3/3pPn2/3P4/2P2P2/PP1N2PP/R1BQKBNR b KQkq - 2 6",
+            "8/4P3/2bk1K2/8/1PB5/8/8/8 w - - 1 60",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            assert_eq!(fresh_evals(&big, &small, &board), fresh_evals(&big_read, &small_read, &board), "{}", fen);
+            assert_eq!(fresh_evals(&big, &small, &board), fresh_evals(&big_rebuilt, &small_rebuilt, &board), "{}", fen);
+        }
+    }
+
+    /// Whether the small network is there or not, it is only looked for once
+    #[test]
+    fn test_init_small_nnue_once() {
+        match (init_small_nnue(), init_small_nnue()) {
+            (Ok(first), Ok(second)) => {
+                assert!(Arc::ptr_eq(&first, &second));
+                assert!(small_nnue().is_some_and(|small| Arc::ptr_eq(&small, &first)));
+            }
+            (Err(first), Err(second)) => {
+                assert!(Arc::ptr_eq(&first, &second));
+                assert!(small_nnue().is_none());
+            }
+            _ => panic!("the second call loaded the small network again"),
+        }
+    }
+
+    #[test]
+    fn test_network_hashes() {
+        assert_eq!(Nnue::<L1>::HASH, SF_BIG.hash());
+        assert_eq!(SmallNnue::HASH, SF_SMALL.hash());
+
+        // Every supported architecture has an accumulator of its width and a layer stack the
+        // layer code can build, and writes the hashes it is looked up by
+        for (seed, arch) in SUPPORTED_ARCHITECTURES.iter().enumerate() {
+            assert!(arch.psqt_buckets <= PSQT_BUCKETS);
+            let mut bytes = Vec::new();
+            match arch.transformed_dims {
+                L1 => synthetic_nnue_with::<L1>(seed as u64, arch).write(&mut bytes).unwrap(),
+                L1_SMALL => synthetic_nnue_with::<L1_SMALL>(seed as u64, arch).write(&mut bytes).unwrap(),
+                TRANSFORMED_FEATURE_DIM_SF12 => synthetic_nnue_with::<TRANSFORMED_FEATURE_DIM_SF12>(seed as u64, arch).write(&mut bytes).unwrap(),
+                dims => panic!("{} has no accumulator of width {}", arch.name, dims),
+            }
+            assert_eq!(bytes[..4], arch.version.to_le_bytes());
+            assert_eq!(bytes[4..8], arch.hash().to_le_bytes());
+            let desc_len = "synthetic".len();
+            assert_eq!(bytes[12 + desc_len..16 + desc_len], arch.feature_transformer_hash().to_le_bytes());
+        }
+        let ft = &synthetic_nnue_with::<L1_SMALL>(1, &HALF_KP_SMALL).ft;
+        assert_eq!(ft.hash(), HALF_KP_SMALL.feature_transformer_hash());
+        assert_eq!(ft.input_dims(), 41_024);
+    }
+
+    #[test]
+    fn test_half_kp() {
+        let big: Nnue = synthetic_nnue(3);
+        let small: SmallNnue = synthetic_nnue_with(7, &HALF_KP_SMALL);
+
+        let mut bytes = Vec::new();
+        small.write(&mut bytes).unwrap();
+        let small_read: SmallNnue = load_nnue_from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(small_read.architecture(), &HALF_KP_SMALL);
+        assert_eq!(small_read.ft.arch.feature_set, FeatureSet::HalfKP);
+        let mut rewritten = Vec::new();
+        small_read.write(&mut rewritten).unwrap();
+        assert!(rewritten == bytes);
+        match load_any_nnue_from_reader(bytes.as_slice()) {
+            Ok(any @ AnyNnue::Small(_)) => assert_eq!(any.architecture(), &HALF_KP_SMALL),
+            other => panic!("expected the small network, got {:?}", other.err()),
+        }
+
+        // Incremental updates agree with a refresh, through castling, which only moves the rook,
+        // and king captures, which remove a feature without adding one
+        let line = ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6", "e1g1", "f6e4", "d2d3", "e4f2", "g1f2", "d7d5"];
+        for every in [1, 3] {
+            let mut caches = AccumulatorCaches::new(&big.ft.biases, Some(&small.ft.biases));
+            let mut stack = AccumulatorStack::new();
+            let mut board = Board::start_pos();
+            stack.reset(&board, &big, &mut caches);
+            for (ply, uci) in line.iter().enumerate() {
+                let mv = board.generate_moves().iter().copied().find(|m| m.to_string() == *uci).unwrap();
+                stack.push(DirtyPiece::from_move(&board, mv));
+                board.apply_move(mv);
+                if ply % every == every - 1 || ply == line.len() - 1 {
+                    let eval = small.evaluate(&board, &mut stack, &mut caches.small);
+                    assert_eq!((eval.psqt, eval.positional), fresh_evals(&big, &small, &board).1, "{}", uci);
+                }
+            }
+        }
+    }
+
+    /// A Stockfish 12 network, with the description those networks carry, is laid out like the
+    /// released files and loads as whatever its header says
+    #[test]
+    fn test_sf12() {
+        let mut sf12: Sf12Nnue = synthetic_nnue_with(9, &SF12);
+        sf12.desc = "Features=HalfKP(Friend)[41024->256x2],Network=AffineTransform[1<-32](ClippedReLU[32](\
+            AffineTransform[32<-32](ClippedReLU[32](AffineTransform[32<-512](InputSlice[512(0:512)])))))"
+            .to_string();
+        let mut bytes = Vec::new();
+        sf12.write(&mut bytes).unwrap();
+        // The size of nn-82215d0fd0df.nnue and the other Stockfish 12 networks
+        assert_eq!(bytes.len(), 21_022_697);
+
+        let any = load_any_nnue_from_reader(bytes.as_slice()).unwrap();
+        assert!(matches!(any, AnyNnue::Sf12(_)));
+        assert_eq!(any.architecture(), &SF12);
+        let mut rewritten = Vec::new();
+        any.write(&mut rewritten).unwrap();
+        assert!(rewritten == bytes);
+        let quantized = any.to_quantized();
+        assert!(quantized.psqt_weights.is_empty() && quantized.layer_stacks.len() == 1);
+        assert!(AnyNnue::from_quantized(&quantized).to_quantized() == quantized);
+
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkb1r/ppp2ppp/2n1p3/3pPn2/3P4/2P2P2/PP1N2PP/R1BQKBNR b KQkq - 2 6",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            let eval = any.evaluate_fresh(&board);
+            assert_eq!((eval.psqt, eval.small_net), (0, false));
+            assert_eq!(eval.positional, sf12.evaluate_fresh(&board).positional);
+        }
+        assert!(matches!(
+            load_big_nnue_from_bytes(&bytes),
+            Err(NnueError::ArchMismatch { found, .. }) if found == &SF12
+        ));
+    }
+
+    /// (big, small) evaluations from new accumulators and caches
+    fn fresh_evals(big: &Nnue, small: &SmallNnue, board: &Board) -> ((i32, i32), (i32, i32)) {
+        let mut stack = AccumulatorStack::new();
+        let mut caches = AccumulatorCaches::new(&big.ft.biases, Some(&small.ft.biases));
+        stack.reset(board, big, &mut caches);
+        let big_eval = big.evaluate(board, &mut stack, &mut caches.big);
+        let small_eval = small.evaluate(board, &mut stack, &mut caches.small);
+        ((big_eval.psqt, big_eval.positional), (small_eval.psqt, small_eval.positional))
+    }
+
+    /// An evaluator runs a network of any supported architecture on its own, with the same
+    /// incremental updates as the big one
+    #[test]
+    fn test_evaluator_any_network() {
+        let networks: [AnyNnue; 3] = [
+            Arc::new(synthetic_nnue::<L1>(11)).into(),
+            Arc::new(synthetic_nnue_with::<L1_SMALL>(12, &HALF_KP_SMALL)).into(),
+            Arc::new(synthetic_nnue::<TRANSFORMED_FEATURE_DIM_SF12>(13)).into(),
+        ];
+        let line = ["e2e4", "d7d5", "e4d5", "g8f6", "f1b5", "c7c6", "d5c6", "d8d2", "b1d2", "b7c6", "g1f3", "c8g4", "e1g1"];
+        for network in networks {
+            let mut evaluator = NnueEvaluator::with_network(network.clone());
+            let mut board = Board::start_pos();
+            evaluator.reset(&board);
+            for uci in line {
+                let mv = board.generate_moves().iter().copied().find(|m| m.to_string() == uci).unwrap();
+                evaluator.do_move(&board, mv);
+                board.apply_move(mv);
+                let (eval, fresh) = (evaluator.evaluate(&board), network.evaluate_fresh(&board));
+                assert_eq!((eval.psqt, eval.positional), (fresh.psqt, fresh.positional), "{} {}", network.architecture().name, uci);
+            }
+            evaluator.undo_move();
+            board.undo_move();
+            let (eval, fresh) = (evaluator.evaluate(&board), network.evaluate_fresh(&board));
+            assert_eq!((eval.psqt, eval.positional), (fresh.psqt, fresh.positional));
+            assert_eq!(evaluator.trace_eval(&board).psqt.len(), network.architecture().layer_stacks);
+            assert!(evaluator.piece_values(&board).values.iter().any(Option::is_some));
+        }
+
+        // Only the big network has room for a small one next to it
+        let small: Arc<SmallNnue> = Arc::new(synthetic_nnue(14));
+        let half_kp = Arc::new(synthetic_nnue_with::<L1_SMALL>(12, &HALF_KP_SMALL));
+        assert!(std::panic::catch_unwind(|| NnueEvaluator::with_networks(half_kp, Some(small))).is_err());
+    }
+
+    #[test]
+    fn test_small_net() {
+        let big: Arc<Nnue> = Arc::new(synthetic_nnue(3));
+        let small: Arc<SmallNnue> = Arc::new(synthetic_nnue(4));
+        let mut caches = AccumulatorCaches::new(&big.ft.biases, Some(&small.ft.biases));
+        let mut stack = AccumulatorStack::new();
+
+        // The big net evaluates every ply, the small one only now and then, so its
+        // accumulators are filled in lazily over several plies, including a king move
+        let mut board = Board::start_pos();
+        stack.reset(&board, &big, &mut caches);
+        let line = ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6", "e1g1", "f6e4", "d2d3", "e4f2"];
+        for (ply, uci) in line.iter().enumerate() {
+            let mv = board.generate_moves().iter().copied().find(|m| m.to_string() == *uci).unwrap();
+            stack.push(DirtyPiece::from_move(&board, mv));
+            board.apply_move(mv);
+            big.evaluate(&board, &mut stack, &mut caches.big);
+
+            if ply % 4 == 3 || ply == line.len() - 1 {
+                let eval = small.evaluate(&board, &mut stack, &mut caches.small);
+                assert!(eval.small_net);
+                assert_eq!((eval.psqt, eval.positional), fresh_evals(&big, &small, &board).1, "{}", uci);
+            }
+        }
+
+        // Lopsided positions go to the small net unless its evaluation is close
+        let mut evaluator = NnueEvaluator::with_networks(big.clone(), Some(small.clone()));
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "4k3/8/8/8/8/8/PPPP4/RR2K3 b - - 0 1",
+            "4k3/pppp4/8/8/8/8/8/R3K3 w - - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            evaluator.reset(&board);
+            let eval = evaluator.evaluate(&board);
+
+            let (big_eval, small_eval) = fresh_evals(&big, &small, &board);
+            let close = (125 * small_eval.0 + 131 * small_eval.1).abs() / 128 < 236;
+            let expected = if use_small_net(&board) && !close { small_eval } else { big_eval };
+            assert_eq!(eval.small_net, use_small_net(&board) && !close, "{}", fen);
+            assert_eq!((eval.psqt, eval.positional), expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_many() {
+        let big: Arc<Nnue> = Arc::new(synthetic_nnue(7));
+        let small: Arc<SmallNnue> = Arc::new(synthetic_nnue(8));
+
+        // Positions along random games, which get lopsided enough for the small net
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        let mut boards = Vec::new();
+        let mut board = Board::start_pos();
+        for _ in 0..200 {
+            let moves = board.generate_moves();
+            if moves.is_empty() || board.fifty_move_rule() {
+                board = Board::start_pos();
+                continue;
+            }
+            board.apply_move(moves[rng.next() as usize % moves.len()]);
+            boards.push(Board::from_fen(&board.fen()).unwrap());
+        }
+
+        let expected: Vec<EvalResult> = boards
+            .iter()
+            .map(|board| {
+                let mut evaluator = NnueEvaluator::with_networks(big.clone(), Some(small.clone()));
+                evaluator.reset(board);
+                evaluator.evaluate(board)
+            })
+            .collect();
+        assert!(expected.iter().any(|eval| eval.small_net));
+        for threads in [1, 3, 500] {
+            assert_eq!(evaluate_many_with(&big.clone().into(), Some(&small), &boards, threads), expected, "{} threads", threads);
+        }
+        assert!(evaluate_many_with(&big.clone().into(), None, &[], 4).is_empty());
+
+        for (board, eval) in boards.iter().zip(&expected) {
+            let wdl = eval.wdl(board);
+            assert_eq!(wdl.win + wdl.draw + wdl.loss, 1000);
+            assert_eq!(wdl.win >= wdl.loss, eval.scaled_total() >= 0);
+        }
+    }
+
     #[test]
     fn instantiate_test() {
         let start = Instant::now();
@@ -1,21 +1,22 @@
 use pleco::{Board, Piece, PieceType, Player, SQ};
 
-use crate::{constants::LAYER_STACKS, nnue_utils::{format_cp_aligned_dot, to_cp}};
+use crate::{constants::SQUARES, nnue_utils::{format_cp_aligned_dot, to_cp}};
 
 #[derive(Clone)]
 pub struct EvalTrace {
     pub selected_bucket: usize,
     pub side_to_move: Player,
-    pub psqt: [i32; LAYER_STACKS],
-    pub positional: [i32; LAYER_STACKS],
+    /// One entry per layer stack
+    pub psqt: Vec<i32>,
+    pub positional: Vec<i32>,
 }
 impl EvalTrace {
-    pub fn new() -> Self {
+    pub fn new(layer_stacks: usize) -> Self {
         Self {
             selected_bucket: 0,
             side_to_move: Player::White,
-            psqt: [0; LAYER_STACKS],
-            positional: [0; LAYER_STACKS],
+            psqt: vec![0; layer_stacks],
+            positional: vec![0; layer_stacks],
         }
     }
     pub fn print(&self, board: &Board) {
@@ -38,7 +39,7 @@ impl EvalTrace {
         );
         println!("+{}+{}+{}+{}+", linspace, linspace, linspace, linspace);
 
-        for bucket in 0..LAYER_STACKS {
+        for bucket in 0..self.psqt.len() {
             let total = self.psqt[bucket] + self.positional[bucket];
             println!(
                 "|{:^spacing$}|{:^spacing$}|{:^spacing$}|{:^spacing$}|{}",
@@ -64,6 +65,59 @@ impl EvalTrace {
 }
 
 
+/// Stockfish's NNUE derived piece values: how much the evaluation drops when a piece is taken
+/// off the board, both from white's point of view. Kings have no value.
+#[derive(Clone)]
+pub struct PieceValues {
+    /// Evaluation of the whole position
+    pub eval: i32,
+    /// Value of the piece on each square, by square index. `None` for empty squares, kings and
+    /// pieces the position can't do without.
+    pub values: [Option<i32>; SQUARES],
+}
+
+impl PieceValues {
+    /// The board with every piece and its value in pawns underneath, rank 8 at the top
+    pub fn grid(&self, board: &Board) -> String {
+        let separator = format!("{}+\n", "+-------".repeat(8));
+        let mut grid = separator.clone();
+        for rank in (0..8u8).rev() {
+            let mut pieces = String::new();
+            let mut values = String::new();
+            for file in 0..8u8 {
+                let sq = SQ(rank * 8 + file);
+                let piece = board.piece_at_sq(sq).character().unwrap_or(' ');
+                let value = self.values[sq.0 as usize]
+                    .map_or(String::new(), |v| format!("{:+.2}", 0.01 * to_cp(v, board)));
+                pieces.push_str(&format!("|{:^7}", piece));
+                values.push_str(&format!("|{:^7}", value));
+            }
+            grid.push_str(&format!("{}|\n{}|\n{}", pieces, values, separator));
+        }
+        grid
+    }
+
+    /// Evaluation of the whole position in pawns
+    pub fn eval_pawns(&self, board: &Board) -> f64 {
+        0.01 * f64::from(to_cp(self.eval, board))
+    }
+
+    /// Every valued piece with its square and value in pawns, from a1 to h8
+    pub fn pawns<'a>(&'a self, board: &'a Board) -> impl Iterator<Item = (SQ, Piece, f64)> + 'a {
+        (0..SQUARES as u8).filter_map(move |sq| {
+            let v = self.values[sq as usize]?;
+            Some((SQ(sq), board.piece_at_sq(SQ(sq)), 0.01 * f64::from(to_cp(v, board))))
+        })
+    }
+
+    pub fn print(&self, board: &Board) {
+        println!("NNUE derived piece values:");
+        print!("{}", self.grid(board));
+        println!("NNUE Evaluation            {} (white side)", 0.01 * to_cp(self.eval, board));
+    }
+}
+
+
 /// DirtyPiece is the “what changed” record passed to the NNUE updater. It captures up to three piece changes from a move:
 /// dirty_num: how many entries are valid.
 /// piece[3]: which piece was involved (one per change).
@@ -93,6 +147,14 @@ impl Default for DirtyPiece {
 }
 
 impl DirtyPiece {
+    /// `piece` taken off `sq`, which no move does
+    pub fn removal(piece: Piece, sq: SQ) -> Self {
+        let mut dp = DirtyPiece { dirty_num: 1, ..Default::default() };
+        dp.piece[0] = piece;
+        dp.from[0] = sq;
+        dp
+    }
+
     pub fn from_move(board: &Board, mv: pleco::BitMove) -> Self {
         let mut dp = DirtyPiece::default();
 // DirtyPiece (types.h (line 276)) tracks up to three board changes per move: dirty_num says how many entries are valid,
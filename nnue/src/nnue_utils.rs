@@ -1,6 +1,6 @@
 use std::{
     fmt::Display,
-    io::{self, Read},
+    io::{self, Read, Write},
     ops::{Deref, DerefMut},
 };
 
@@ -21,11 +21,17 @@ impl<T> DerefMut for CacheAligned<T> {
     }
 }
 
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
 // --- LEB128 signed ---
 pub fn read_leb128_i16(r: &mut impl Read, n: usize) -> io::Result<Vec<i16>> {
     let mut magic = [0u8; 17];
     r.read_exact(&mut magic)?;
-    assert!(&magic == b"COMPRESSED_LEB128");
+    if &magic != b"COMPRESSED_LEB128" {
+        return Err(invalid_data("missing LEB128 magic"));
+    }
     let mut len_buf = [0u8; 4];
     r.read_exact(&mut len_buf)?;
     let mut remaining = u32::from_le_bytes(len_buf);
@@ -43,8 +49,11 @@ pub fn read_leb128_i16(r: &mut impl Read, n: usize) -> io::Result<Vec<i16>> {
         let mut shift = 0;
         let mut val: i32 = 0;
         loop {
-            let byte = buf[i] as i32;
+            let byte = *buf.get(i).ok_or_else(|| invalid_data("LEB128 block too short"))? as i32;
             i += 1;
+            if shift >= 32 {
+                return Err(invalid_data("LEB128 value too long"));
+            }
             val |= (byte & 0x7f) << shift;
             shift += 7;
             if (byte & 0x80) == 0 {
@@ -62,7 +71,9 @@ pub fn read_leb128_i16(r: &mut impl Read, n: usize) -> io::Result<Vec<i16>> {
 pub fn read_leb128_i32(r: &mut impl Read, n: usize) -> io::Result<Vec<i32>> {
     let mut magic = [0u8; 17];
     r.read_exact(&mut magic)?;
-    assert!(&magic == b"COMPRESSED_LEB128");
+    if &magic != b"COMPRESSED_LEB128" {
+        return Err(invalid_data("missing LEB128 magic"));
+    }
     let mut len_buf = [0u8; 4];
     r.read_exact(&mut len_buf)?;
     let mut remaining = u32::from_le_bytes(len_buf);
@@ -80,8 +91,11 @@ pub fn read_leb128_i32(r: &mut impl Read, n: usize) -> io::Result<Vec<i32>> {
         let mut shift = 0;
         let mut val: i32 = 0;
         loop {
-            let byte = buf[i] as i32;
+            let byte = *buf.get(i).ok_or_else(|| invalid_data("LEB128 block too short"))? as i32;
             i += 1;
+            if shift >= 32 {
+                return Err(invalid_data("LEB128 value too long"));
+            }
             val |= (byte & 0x7f) << shift;
             shift += 7;
             if (byte & 0x80) == 0 {
@@ -96,7 +110,41 @@ pub fn read_leb128_i32(r: &mut impl Read, n: usize) -> io::Result<Vec<i32>> {
     Ok(out)
 }
 
+/// Writes a `COMPRESSED_LEB128` block the way Stockfish does: magic, byte count, then each
+/// value in its shortest signed LEB128 form, so `read_leb128_*` gets the values back
+pub fn write_leb128<T: Copy + Into<i32>>(w: &mut impl Write, values: &[T]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(values.len() * 2);
+    for &value in values {
+        let mut val: i32 = value.into();
+        loop {
+            let byte = (val & 0x7f) as u8;
+            val >>= 7;
+            let done = if byte & 0x40 == 0 { val == 0 } else { val == -1 };
+            if done {
+                buf.push(byte);
+                break;
+            }
+            buf.push(byte | 0x80);
+        }
+    }
+    w.write_all(b"COMPRESSED_LEB128")?;
+    write_u32(w, buf.len() as u32)?;
+    w.write_all(&buf)
+}
+
 // --- Little endian helpers ---
+pub fn write_u32(w: &mut impl Write, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+pub fn write_i32_slice(w: &mut impl Write, v: &[i32]) -> io::Result<()> {
+    w.write_all(bytemuck::cast_slice(v))
+}
+pub fn write_i16_slice(w: &mut impl Write, v: &[i16]) -> io::Result<()> {
+    w.write_all(bytemuck::cast_slice(v))
+}
+pub fn write_i8_slice(w: &mut impl Write, v: &[i8]) -> io::Result<()> {
+    w.write_all(bytemuck::cast_slice(v))
+}
 pub fn read_u32(r: &mut impl Read) -> io::Result<u32> {
     let mut b = [0u8; 4];
     r.read_exact(&mut b)?;
@@ -172,8 +220,56 @@ pub fn to_cp(v: i32, board: &Board) -> f32 {
     (100f32 * v as f32 / a).round()
 }
 
+/// Internal evaluation of `cp` centipawns, the inverse of `to_cp` up to its rounding
+pub fn from_cp(cp: f32, board: &Board) -> f32 {
+    let (a, _b) = win_rate_params(board);
+    cp * a / 100f32
+}
+
+/// Per mille chance of winning with internal evaluation `v`, Stockfish's win rate model
+pub fn win_rate_model(v: i32, board: &Board) -> u32 {
+    let (a, b) = win_rate_params(board);
+    (0.5 + 1000.0 / (1.0 + ((a - v as f32) / b).exp())) as u32
+}
+
 pub fn format_cp_aligned_dot(v: i32, board: &Board) -> String {
     let pawns = (0.01 * to_cp(v, board)).abs();
 
     format!("{} {:.2}", if v < 0 { "-" } else { "+" }, pawns)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leb128_round_trip() {
+        let values: [i16; 9] = [0, 1, -1, 63, 64, -64, -65, i16::MAX, i16::MIN];
+        let mut bytes = Vec::new();
+        write_leb128(&mut bytes, &values).unwrap();
+
+        // Shortest encodings: one byte for -64..=63, two up to 13 bits, three for the rest
+        assert_eq!(&bytes[..17], b"COMPRESSED_LEB128");
+        assert_eq!(u32::from_le_bytes(bytes[17..21].try_into().unwrap()), 1 + 1 + 1 + 1 + 2 + 1 + 2 + 3 + 3);
+        assert_eq!(&bytes[21..27], &[0x00, 0x01, 0x7f, 0x3f, 0xc0, 0x00]);
+        assert_eq!(read_leb128_i16(&mut bytes.as_slice(), values.len()).unwrap(), values);
+
+        let values = [0, 1_000_000, -1_000_000, i32::MAX, i32::MIN];
+        let mut bytes = Vec::new();
+        write_leb128(&mut bytes, &values).unwrap();
+        assert_eq!(read_leb128_i32(&mut bytes.as_slice(), values.len()).unwrap(), values);
+    }
+
+    #[test]
+    fn test_win_rate_model() {
+        let board = Board::start_pos();
+        let (a, _) = win_rate_params(&board);
+        // An evaluation of a, 100 centipawns, wins half the time
+        assert_eq!(to_cp(a.round() as i32, &board), 100.0);
+        assert!((from_cp(to_cp(555, &board), &board) - 555.0).abs() < a / 200.0);
+        assert!((499..=501).contains(&win_rate_model(a.round() as i32, &board)));
+        assert!(win_rate_model(0, &board) < 100);
+        assert_eq!(win_rate_model(10_000, &board), 1000);
+        assert_eq!(win_rate_model(-10_000, &board), 0);
+    }
+}
@@ -0,0 +1,432 @@
+//! Slow evaluation straight from the network parameters, to check the optimized code against.
+//! Every layer is recomputed for every position with plain loops over the parameters in file
+//! order, nothing permuted, padded, cached or updated incrementally, so it reads line by line
+//! like Stockfish's scalar code. `evaluate` does the same integer math as the optimized path
+//! and has to match it exactly, `evaluate_float` computes what the quantized network stands for.
+
+use pleco::{Board, Player};
+
+use crate::architecture::Transform;
+use crate::constants::{OUTPUT_SCALE, TRANSFORMED_FEATURE_DIM_SMALL, WEIGHT_SCALE_BITS};
+use crate::nnue::{EvalResult, QuantizedAffine, QuantizedNetwork};
+
+/// An evaluation in the units of `EvalResult`, without any rounding
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FloatEval {
+    pub psqt: f64,
+    pub positional: f64,
+}
+
+/// Values of every layer for one position, before their activation. Accumulators are scaled like
+/// the optimized path's, where 254 is one for the pairwise product and 127 for the clamp.
+#[derive(Clone, Debug)]
+pub struct Activations {
+    /// Layer stack and PSQT bucket
+    pub bucket: usize,
+    pub psqt: i64,
+    /// Accumulators of the side to move and of the other side
+    pub accumulators: [Vec<i64>; 2],
+    /// Output of the feature transformer, 127 is one
+    pub transformed: Vec<i64>,
+    /// Hidden layer outputs followed by the forward term
+    pub fc0: Vec<i64>,
+    pub fc1: Vec<i64>,
+    pub fc2: i64,
+}
+
+pub struct ReferenceNnue {
+    net: QuantizedNetwork,
+}
+
+impl ReferenceNnue {
+    /// Panics when the parameters don't fit the architecture
+    pub fn new(net: QuantizedNetwork) -> Self {
+        let arch = net.arch;
+        let input_dims = arch.feature_set.input_dims();
+        assert_eq!(net.ft_biases.len(), arch.transformed_dims);
+        assert_eq!(net.ft_weights.len(), input_dims * arch.transformed_dims);
+        assert_eq!(net.psqt_weights.len(), input_dims * arch.psqt_buckets);
+        assert_eq!(net.layer_stacks.len(), arch.layer_stacks);
+
+        let shapes = [
+            (arch.ft_outputs(), arch.affine_outputs(0)),
+            (arch.fc1_inputs(), arch.affine_outputs(1)),
+            (arch.affine_outputs(1), arch.affine_outputs(2)),
+        ];
+        for stack in &net.layer_stacks {
+            for (layer, (inputs, outputs)) in [&stack.fc0, &stack.fc1, &stack.fc2].into_iter().zip(shapes) {
+                assert_eq!(layer.biases.len(), outputs);
+                assert_eq!(layer.weights.len(), inputs * outputs);
+            }
+        }
+        Self { net }
+    }
+
+    pub fn network(&self) -> &QuantizedNetwork {
+        &self.net
+    }
+
+    /// Transformer biases plus the weights of every active feature of `perspective`, and the
+    /// PSQT weights of the same features summed per bucket
+    fn accumulate(&self, board: &Board, perspective: Player) -> (Vec<i64>, Vec<i64>) {
+        let dims = self.net.arch.transformed_dims;
+        let buckets = self.net.arch.psqt_buckets;
+        let mut accumulation: Vec<i64> = self.net.ft_biases.iter().map(|&b| i64::from(b)).collect();
+        let mut psqt = vec![0; buckets];
+        for feature in self.net.arch.feature_set.active_features(board, perspective) {
+            let weights = &self.net.ft_weights[feature * dims..(feature + 1) * dims];
+            for (a, &w) in accumulation.iter_mut().zip(weights) {
+                *a += i64::from(w);
+            }
+            let psqt_weights = &self.net.psqt_weights[feature * buckets..(feature + 1) * buckets];
+            for (p, &w) in psqt.iter_mut().zip(psqt_weights) {
+                *p += i64::from(w);
+            }
+        }
+        (accumulation, psqt)
+    }
+
+    /// Every layer's values for `board`, with the integer arithmetic of the optimized path
+    pub fn activations(&self, board: &Board) -> Activations {
+        let arch = self.net.arch;
+        let bucket = arch.bucket(board.count_all_pieces() as usize);
+        let (us, psqt_us) = self.accumulate(board, board.turn());
+        let (them, psqt_them) = self.accumulate(board, !board.turn());
+        let psqt = if arch.psqt_buckets > 0 { (psqt_us[bucket] - psqt_them[bucket]) / 2 } else { 0 };
+
+        let (accumulators, transformed) = match arch.transform {
+            // Both accumulators clamped side by side
+            Transform::Clamp => {
+                let transformed = us.iter().chain(&them).map(|&x| x.clamp(0, 127)).collect();
+                ([us, them], transformed)
+            }
+            // The loader doubles the transformer parameters, so 254 is one here. Each half of an
+            // accumulator is clamped and multiplied with the other half.
+            Transform::PairwiseMul => {
+                let accumulators = [us, them].map(|accumulation| accumulation.into_iter().map(|x| 2 * x).collect::<Vec<_>>());
+                let half = arch.transformed_dims / 2;
+                let clamp = |x: i64| x.clamp(0, 254);
+                let mut transformed = Vec::with_capacity(half * 2);
+                for accumulation in &accumulators {
+                    for j in 0..half {
+                        transformed.push(clamp(accumulation[j]) * clamp(accumulation[j + half]) / 512);
+                    }
+                }
+                (accumulators, transformed)
+            }
+        };
+
+        let stack = &self.net.layer_stacks[bucket];
+        let fc0 = affine(&stack.fc0, &transformed);
+        let hidden = arch.hidden_dims();
+        let mut fc1_input = Vec::with_capacity(arch.fc1_inputs());
+        if arch.squares_first_layer() {
+            fc1_input.extend(fc0[..hidden].iter().map(|&x| ((x * x) >> (2 * WEIGHT_SCALE_BITS + 7)).min(127)));
+        }
+        fc1_input.extend(fc0[..hidden].iter().map(|&x| (x >> WEIGHT_SCALE_BITS).clamp(0, 127)));
+
+        let fc1 = affine(&stack.fc1, &fc1_input);
+        let fc2_input: Vec<i64> = fc1.iter().map(|&x| (x >> WEIGHT_SCALE_BITS).clamp(0, 127)).collect();
+        let fc2 = affine(&stack.fc2, &fc2_input)[0];
+
+        Activations { bucket, psqt, accumulators, transformed, fc0, fc1, fc2 }
+    }
+
+    /// Evaluate with the integer arithmetic of the optimized path, bit for bit
+    pub fn evaluate(&self, board: &Board) -> EvalResult {
+        let a = self.activations(board);
+        let forward = if self.net.arch.has_forward_term() {
+            a.fc0[self.net.arch.hidden_dims()] * i64::from(600 * OUTPUT_SCALE) / (127 << WEIGHT_SCALE_BITS)
+        } else {
+            0
+        };
+
+        EvalResult {
+            psqt: (a.psqt / i64::from(OUTPUT_SCALE)) as i32,
+            positional: ((a.fc2 + forward) / i64::from(OUTPUT_SCALE)) as i32,
+            small_net: self.net.arch.transformed_dims == TRANSFORMED_FEATURE_DIM_SMALL,
+        }
+    }
+
+    /// Evaluate the network the parameters quantize, as a trainer sees it: 127 is one in the
+    /// transformer, 64 in affine weights and 64 * 127 in affine biases, and the network output
+    /// is scaled by 600 into evaluation units.
+    pub fn evaluate_float(&self, board: &Board) -> FloatEval {
+        let arch = self.net.arch;
+        let bucket = arch.bucket(board.count_all_pieces() as usize);
+        let (us, psqt_us) = self.accumulate(board, board.turn());
+        let (them, psqt_them) = self.accumulate(board, !board.turn());
+        let psqt = if arch.psqt_buckets > 0 { (psqt_us[bucket] - psqt_them[bucket]) as f64 / 2.0 } else { 0.0 };
+
+        let clamp = |x: i64| (x as f64 / 127.0).clamp(0.0, 1.0);
+        let transformed = match arch.transform {
+            Transform::Clamp => us.iter().chain(&them).map(|&x| clamp(x)).collect(),
+            Transform::PairwiseMul => {
+                let half = arch.transformed_dims / 2;
+                let mut transformed = Vec::with_capacity(half * 2);
+                for accumulation in [&us, &them] {
+                    for j in 0..half {
+                        transformed.push(clamp(accumulation[j]) * clamp(accumulation[j + half]) * 127.0 / 128.0);
+                    }
+                }
+                transformed
+            }
+        };
+
+        let stack = &self.net.layer_stacks[bucket];
+        let fc0 = affine_float(&stack.fc0, &transformed, 64.0, 64.0 * 127.0);
+        let hidden = arch.hidden_dims();
+        let mut fc1_input = Vec::with_capacity(arch.fc1_inputs());
+        if arch.squares_first_layer() {
+            fc1_input.extend(fc0[..hidden].iter().map(|&x| (x * x * 127.0 / 128.0).min(1.0)));
+        }
+        fc1_input.extend(fc0[..hidden].iter().map(|&x| x.clamp(0.0, 1.0)));
+
+        let fc2_input: Vec<f64> = affine_float(&stack.fc1, &fc1_input, 64.0, 64.0 * 127.0)
+            .into_iter()
+            .map(|x| x.clamp(0.0, 1.0))
+            .collect();
+        let output_scale = f64::from(600 * OUTPUT_SCALE);
+        let fc2 = affine_float(&stack.fc2, &fc2_input, output_scale / 127.0, output_scale)[0];
+
+        FloatEval {
+            psqt: psqt / f64::from(OUTPUT_SCALE),
+            positional: (fc2 + if arch.has_forward_term() { fc0[hidden] } else { 0.0 }) * 600.0,
+        }
+    }
+}
+
+fn affine(layer: &QuantizedAffine, input: &[i64]) -> Vec<i64> {
+    layer
+        .biases
+        .iter()
+        .zip(layer.weights.chunks(input.len()))
+        .map(|(&bias, row)| i64::from(bias) + row.iter().zip(input).map(|(&w, &x)| i64::from(w) * x).sum::<i64>())
+        .collect()
+}
+
+fn affine_float(layer: &QuantizedAffine, input: &[f64], weight_scale: f64, bias_scale: f64) -> Vec<f64> {
+    layer
+        .biases
+        .iter()
+        .zip(layer.weights.chunks(input.len()))
+        .map(|(&bias, row)| {
+            f64::from(bias) / bias_scale + row.iter().zip(input).map(|(&w, &x)| f64::from(w) / weight_scale * x).sum::<f64>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use pleco::{BitMove, MoveList};
+
+    use crate::accumulator::{AccumulatorCache, AccumulatorStack};
+    use crate::architecture::{Architecture, HALF_KP_SMALL, SF12, SF_BIG, SF_SMALL};
+    use crate::nnue::{Nnue, QuantizedLayerStack};
+    use crate::nnue_misc::DirtyPiece;
+
+    use super::*;
+
+    /// Small xorshift so every run sees the same networks and games
+    struct Rng(u64);
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn range(&mut self, lo: i32, hi: i32) -> i32 {
+            lo + (self.next() % (hi - lo + 1) as u64) as i32
+        }
+        fn vec<T>(&mut self, n: usize, lo: i32, hi: i32, f: impl Fn(i32) -> T) -> Vec<T> {
+            (0..n).map(|_| f(self.range(lo, hi))).collect()
+        }
+        fn affine(&mut self, inputs: usize, outputs: usize, weights: i32) -> QuantizedAffine {
+            QuantizedAffine {
+                biases: self.vec(outputs, -3000, 3000, |x| x),
+                weights: self.vec(inputs * outputs, -weights, weights, |x| x as i8),
+            }
+        }
+    }
+
+    /// Parameters in the ranges of the synthetic networks of the `nnue` tests, which hit the
+    /// clamps of every layer and leave plenty of zero inputs for the sparse layer
+    fn random_network(arch: &'static Architecture, rng: &mut Rng) -> QuantizedNetwork {
+        let input_dims = arch.feature_set.input_dims();
+        let dims = arch.transformed_dims;
+        let (l2, l3) = (arch.affine_outputs(0), arch.affine_outputs(1));
+        QuantizedNetwork {
+            arch,
+            desc: "random".to_string(),
+            ft_biases: rng.vec(dims, -40, 100, |x| x as i16),
+            ft_weights: rng.vec(dims * input_dims, -30, 30, |x| x as i16),
+            psqt_weights: rng.vec(arch.psqt_buckets * input_dims, -3000, 3000, |x| x),
+            layer_stacks: (0..arch.layer_stacks)
+                .map(|_| QuantizedLayerStack {
+                    fc0: rng.affine(arch.ft_outputs(), l2, 8),
+                    fc1: rng.affine(arch.fc1_inputs(), l3, 127),
+                    fc2: rng.affine(l3, 1, 127),
+                })
+                .collect(),
+        }
+    }
+
+    /// Starting points rich in castling, en passant and promotions
+    const STARTS: [&str; 4] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+    ];
+
+    /// The moves that change more than one feature per side: castling, en passant and promotions
+    fn special_kind(mv: BitMove) -> Option<usize> {
+        if mv.is_castle() {
+            Some(0)
+        } else if mv.is_en_passant() {
+            Some(1)
+        } else if mv.is_promo() {
+            Some(2)
+        } else {
+            None
+        }
+    }
+
+    /// A random legal move, half the time one of the special ones if there are any
+    fn random_move(board: &Board, rng: &mut Rng) -> Option<BitMove> {
+        let moves: MoveList = board.generate_moves();
+        if moves.is_empty() {
+            return None;
+        }
+        let special: Vec<BitMove> = moves.iter().copied().filter(|&mv| special_kind(mv).is_some()).collect();
+        if !special.is_empty() && rng.next().is_multiple_of(2) {
+            return Some(special[rng.next() as usize % special.len()]);
+        }
+        Some(moves[rng.next() as usize % moves.len()])
+    }
+
+    fn values(eval: EvalResult) -> (i32, i32, bool) {
+        (eval.psqt, eval.positional, eval.small_net)
+    }
+
+    /// Compare the optimized network with the reference on `positions` random positions, then on
+    /// random walks of `steps` moves and take backs from every start. Returns how many
+    /// castling, en passant and promotion moves the walks made.
+    fn check_against_reference<const DIM: usize>(
+        arch: &'static Architecture,
+        seed: u64,
+        positions: usize,
+        steps: usize,
+    ) -> [usize; 3] {
+        let mut rng = Rng(seed);
+        let net = random_network(arch, &mut rng);
+        let nnue = Nnue::<DIM>::from_quantized(&net);
+        let reference = ReferenceNnue::new(net);
+
+        let mut stack = AccumulatorStack::new();
+        let mut cache = AccumulatorCache::<DIM>::new_boxed();
+        cache.clear_with_biases(&nnue.ft.biases);
+
+        // A refresh through a cache that still holds the earlier positions
+        for i in 0..positions {
+            let mut board = Board::from_fen(STARTS[i % STARTS.len()]).unwrap();
+            for _ in 0..rng.range(0, 60) {
+                match random_move(&board, &mut rng) {
+                    Some(mv) => board.apply_move(mv),
+                    None => break,
+                }
+            }
+            stack.clear();
+            let expected = values(reference.evaluate(&board));
+            assert_eq!(values(nnue.evaluate(&board, &mut stack, &mut cache)), expected, "{}", board.fen());
+            if i % 100 == 0 {
+                assert_eq!(values(nnue.evaluate_fresh(&board)), expected, "{}", board.fen());
+            }
+        }
+
+        // Incremental updates, evaluating every other ply or so, so that some evaluations catch
+        // up over several moves. The stack only holds so many plies, so the walk takes moves back
+        // as it goes.
+        const MAX_DEPTH: usize = 24;
+        let mut special = [0; 3];
+        for fen in STARTS {
+            let mut board = Board::from_fen(fen).unwrap();
+            stack.clear();
+            assert_eq!(values(nnue.evaluate(&board, &mut stack, &mut cache)), values(reference.evaluate(&board)));
+
+            let mut depth = 0;
+            for _ in 0..steps {
+                let mv = if depth < MAX_DEPTH && (depth == 0 || !rng.next().is_multiple_of(4)) {
+                    random_move(&board, &mut rng)
+                } else {
+                    None
+                };
+                match mv {
+                    Some(mv) => {
+                        if let Some(kind) = special_kind(mv) {
+                            special[kind] += 1;
+                        }
+                        stack.push(DirtyPiece::from_move(&board, mv));
+                        board.apply_move(mv);
+                        depth += 1;
+                    }
+                    None if depth > 0 => {
+                        stack.pop();
+                        board.undo_move();
+                        depth -= 1;
+                    }
+                    None => break,
+                }
+                if rng.next().is_multiple_of(2) {
+                    let expected = values(reference.evaluate(&board));
+                    assert_eq!(values(nnue.evaluate(&board, &mut stack, &mut cache)), expected, "{}", board.fen());
+                }
+            }
+        }
+        special
+    }
+
+    #[test]
+    fn test_reference() {
+        let mut special = [0; 3];
+        for counts in [
+            check_against_reference::<{ SF_BIG.transformed_dims }>(&SF_BIG, 1, 1000, 400),
+            check_against_reference::<{ SF_SMALL.transformed_dims }>(&SF_SMALL, 2, 2000, 800),
+            check_against_reference::<{ HALF_KP_SMALL.transformed_dims }>(&HALF_KP_SMALL, 3, 2000, 800),
+            check_against_reference::<{ SF12.transformed_dims }>(&SF12, 5, 2000, 800),
+        ] {
+            for (total, count) in special.iter_mut().zip(counts) {
+                *total += count;
+            }
+        }
+        assert!(special.iter().all(|&count| count >= 10), "castling, en passant and promotions: {:?}", special);
+    }
+
+    /// The integer evaluation only differs from the float one by rounding. Random layers amplify
+    /// it more than trained ones would, but a wrong scale anywhere is off by far more.
+    #[test]
+    fn test_float_reference() {
+        let mut rng = Rng(4);
+        for arch in [&HALF_KP_SMALL, &SF12] {
+            let reference = ReferenceNnue::new(random_network(arch, &mut rng));
+            let mut total_error = 0.0;
+            let mut total = 0.0;
+            for i in 0..500 {
+                let mut board = Board::from_fen(STARTS[i % STARTS.len()]).unwrap();
+                for _ in 0..rng.range(0, 60) {
+                    match random_move(&board, &mut rng) {
+                        Some(mv) => board.apply_move(mv),
+                        None => break,
+                    }
+                }
+                let eval = reference.evaluate(&board);
+                let float = reference.evaluate_float(&board);
+                assert!((f64::from(eval.psqt) - float.psqt).abs() < 1.0, "{}", board.fen());
+                total_error += (f64::from(eval.positional) - float.positional).abs();
+                total += float.positional.abs();
+            }
+            assert!(total_error < total * 0.04, "{}: mean error {} of {}", arch.name, total_error / 500.0, total / 500.0);
+        }
+    }
+}
@@ -1,5 +1,214 @@
+//! SIMD abstraction used by the accumulator, feature transformer and layers.
+//!
+//! Every backend exposes the same 256-bit `VecT` API with AVX2 semantics, including the
+//! per 128-bit lane behaviour of the pack and multiply-add instructions. The callers (and the
+//! weight permutation done on load) therefore run the same code whatever the backend, and
+//! every backend produces bit-identical evaluations. The backend is picked at compile time:
+//! AVX2, then SSE4.1, then a portable scalar fallback.
+
+use crate::constants::{PSQT_BUCKETS, PsqtWeightType};
 
 #[cfg(target_feature = "avx2")]
 mod vec_ops_avx2;
 #[cfg(target_feature = "avx2")]
 pub use crate::vectors::vec_ops_avx2::*;
+
+#[cfg(all(target_feature = "sse4.1", any(test, not(target_feature = "avx2"))))]
+mod vec_ops_sse41;
+#[cfg(all(target_feature = "sse4.1", not(target_feature = "avx2")))]
+pub use crate::vectors::vec_ops_sse41::*;
+
+// Always built for tests, it is the reference the other backends are checked against
+#[cfg(any(test, not(any(target_feature = "avx2", target_feature = "sse4.1"))))]
+mod vec_ops_scalar;
+#[cfg(not(any(target_feature = "avx2", target_feature = "sse4.1")))]
+pub use crate::vectors::vec_ops_scalar::*;
+
+/// Number of bytes processed per chunk. Each register holds 16 `i16` lanes (32 bytes).
+pub const MAX_CHUNK_SIZE: usize = 32;
+pub const SIMD_WIDTH: usize = MAX_CHUNK_SIZE;
+
+pub const NUM_REGISTERS_SIMD: usize = 16;
+
+const fn best_register_count() -> usize {
+
+    const REGISTER_SIZE: usize = std::mem::size_of::<VecT>();
+    // const LANE_SIZE: usize = std::mem::size_of::<WeightType>(); // For Normal Regs
+    const LANE_SIZE: usize = std::mem::size_of::<PsqtWeightType>(); // For PSQT Regs
+
+
+    const NUM_LANES: usize = PSQT_BUCKETS; // PSQT
+    // const NUM_LANES: usize = TRANSFORMED_FEATURE_DIM_BIG; // Big net
+    // const NUM_LANES: usize = TRANSFORMED_FEATURE_DIM_BIG; //Small for small net
+
+    const IDEAL: usize = (NUM_LANES * LANE_SIZE) / REGISTER_SIZE;
+
+    if IDEAL <= NUM_REGISTERS_SIMD {
+        return IDEAL;
+    }
+
+    let mut divisor = NUM_REGISTERS_SIMD;
+    while divisor > 1 {
+        if IDEAL % divisor == 0 {
+            return divisor;
+        }
+        divisor -= 1;
+    }
+
+    1
+}
+
+pub enum RegsAndTileHeight {
+    SmallNet,
+    BigNet,
+    Psqt,
+}
+
+
+/// Number of registers to use for big net accumulator tiling
+pub const NUM_REGS_SMALL: usize = 8; // Derived from plugging numbers into best_register_count()
+pub const TILE_HEIGHT_SMALL: usize = NUM_REGS_SMALL * std::mem::size_of::<VecT>() / 2;
+
+/// Number of registers to use for big net accumulator tiling
+pub const NUM_REGS_BIG: usize = 16; // Derived from plugging numbers into best_register_count()
+pub const TILE_HEIGHT_BIG: usize = NUM_REGS_BIG * std::mem::size_of::<VecT>() / 2;
+
+pub const NUM_PSQT_REGS: usize = 1; // Derived from plugging numbers into best_register_count()
+pub const PSQT_TILE_HEIGHT: usize = NUM_PSQT_REGS * std::mem::size_of::<PsqtVecT>() / 4;
+
+// pub const NUM_REGS: usize = best_register_count::<Vec_T, WeightType ();
+
+/// Packed 256-bit lane used for PSQT-specific SIMD operations. Currently the same as [`VecT`],
+/// but kept separate in case we want to specialize PSQT arithmetic later.
+pub type PsqtVecT = VecT;
+
+pub fn to_mut_vec_ptr<T>(v: &mut [T], offset: usize) -> *mut VecT {
+    unsafe {
+        v.as_mut_ptr().add(offset).cast::<VecT>()
+    }
+}
+pub fn to_const_vec_ptr<T>(v: &[T], offset: usize) -> *const VecT {
+    unsafe {
+        v.as_ptr().add(offset).cast::<VecT>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::vec_ops_scalar as scalar;
+
+    /// Small xorshift so the inputs are the same on every run
+    struct Rng(u64);
+    impl Rng {
+        fn bytes<const N: usize>(&mut self) -> [u8; N] {
+            std::array::from_fn(|_| {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                (self.0 >> 24) as u8
+            })
+        }
+    }
+
+    fn to_bytes<T: Copy, const N: usize>(v: T) -> [u8; N] {
+        assert_eq!(std::mem::size_of::<T>(), N);
+        unsafe { std::mem::transmute_copy(&v) }
+    }
+    fn from_bytes<T: Copy, const N: usize>(b: [u8; N]) -> T {
+        assert_eq!(std::mem::size_of::<T>(), N);
+        unsafe { std::mem::transmute_copy(&b) }
+    }
+
+    /// Compares a two argument op of backend `$b` with the scalar reference, byte for byte
+    macro_rules! same {
+        ($b:ident, $op:ident, $x:expr, $y:expr) => {
+            assert_eq!(
+                to_bytes::<_, 32>($b::$op(from_bytes($x), from_bytes($y))),
+                to_bytes::<_, 32>(scalar::$op(from_bytes($x), from_bytes($y))),
+                stringify!($op)
+            )
+        };
+    }
+    macro_rules! same_128 {
+        ($b:ident, $op:ident, $x:expr, $y:expr) => {
+            assert_eq!(
+                to_bytes::<_, 16>($b::$op(from_bytes($x), from_bytes($y))),
+                to_bytes::<_, 16>(scalar::$op(from_bytes($x), from_bytes($y))),
+                stringify!($op)
+            )
+        };
+    }
+
+    /// Runs every op of backend `$b` on random inputs and compares the raw bytes with the
+    /// scalar reference
+    macro_rules! check_backend {
+        ($b:path) => {{
+            use $b as b;
+            let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+            for _ in 0..1000 {
+                let (x, y, z): ([u8; 32], [u8; 32], [u8; 32]) = (rng.bytes(), rng.bytes(), rng.bytes());
+                same!(b, vec_max_16, x, y);
+                same!(b, vec_min_16, x, y);
+                same!(b, vec_add_16, x, y);
+                same!(b, vec_add_32, x, y);
+                same!(b, vec_sub_16, x, y);
+                same!(b, vec_sub_32, x, y);
+                same!(b, vec_madd_16, x, y);
+                same!(b, vec_mulhi_16, x, y);
+                same!(b, vec_packus_16, x, y);
+                same!(b, vec_packus_32, x, y);
+                same!(b, vec_packs_epi16, x, y);
+                same!(b, vec_permutevar8x32_epi32, x, y);
+
+                let (shifted, expected) = (b::vec_srli_epi16::<6>(from_bytes(x)), scalar::vec_srli_epi16::<6>(from_bytes(x)));
+                assert_eq!(to_bytes::<_, 32>(shifted), to_bytes::<_, 32>(expected));
+                let (shifted, expected) = (b::vec_slli_16::<7>(from_bytes(x)), scalar::vec_slli_16::<7>(from_bytes(x)));
+                assert_eq!(to_bytes::<_, 32>(shifted), to_bytes::<_, 32>(expected));
+
+                let (mut acc, mut expected) = (from_bytes::<b::VecT, 32>(z), from_bytes::<scalar::VecT, 32>(z));
+                b::vec_add_dpbusd_epi32(&mut acc, from_bytes(x), from_bytes(y));
+                scalar::vec_add_dpbusd_epi32(&mut expected, from_bytes(x), from_bytes(y));
+                assert_eq!(to_bytes::<_, 32>(acc), to_bytes::<_, 32>(expected), "vec_add_dpbusd_epi32");
+
+                assert_eq!(b::vec_nnz(from_bytes(x)), scalar::vec_nnz(from_bytes(x)));
+                assert_eq!(b::m256_hadd(from_bytes(x), 7), scalar::m256_hadd(from_bytes(x), 7));
+
+                let (x, y): ([u8; 16], [u8; 16]) = (rng.bytes(), rng.bytes());
+                same_128!(b, mm_add_epi16, x, y);
+                same_128!(b, mm_mulhi_epi16, x, y);
+                same_128!(b, mm_packs_epi16, x, y);
+                same_128!(b, mm_packs_epi32, x, y);
+                same_128!(b, mm_packus_epi32, x, y);
+                let (shifted, expected) = (b::mm_srli_epi16::<3>(from_bytes(x)), scalar::mm_srli_epi16::<3>(from_bytes(x)));
+                assert_eq!(to_bytes::<_, 16>(shifted), to_bytes::<_, 16>(expected));
+            }
+
+            let v = b::vec_set_32(7, 3, 6, 2, 5, 1, 4, 0);
+            assert_eq!(to_bytes::<_, 32>(v), to_bytes::<_, 32>(scalar::vec_set_32(7, 3, 6, 2, 5, 1, 4, 0)));
+            assert_eq!(to_bytes::<_, 32>(b::vec_set1_16(-3)), to_bytes::<_, 32>(scalar::vec_set1_16(-3)));
+            assert_eq!(to_bytes::<_, 32>(b::vec_set1_32(-3)), to_bytes::<_, 32>(scalar::vec_set1_32(-3)));
+        }};
+    }
+
+    #[test]
+    fn test_scalar_matches_avx2_lane_order() {
+        // Packs work per 128-bit lane: a's low lane, b's low lane, a's high lane, b's high lane
+        let a: [i16; 16] = std::array::from_fn(|i| i as i16);
+        let b: [i16; 16] = std::array::from_fn(|i| 100 + i as i16);
+        let packed: [u8; 32] = to_bytes(scalar::vec_packus_16(from_bytes(to_bytes::<_, 32>(a)), from_bytes(to_bytes::<_, 32>(b))));
+        assert_eq!(&packed[0..8], &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(&packed[8..16], &[100, 101, 102, 103, 104, 105, 106, 107]);
+        assert_eq!(&packed[16..24], &[8, 9, 10, 11, 12, 13, 14, 15]);
+    }
+
+    #[test]
+    fn test_compiled_backend_matches_scalar() {
+        check_backend!(super);
+    }
+
+    #[cfg(target_feature = "sse4.1")]
+    #[test]
+    fn test_sse41_matches_scalar() {
+        check_backend!(super::vec_ops_sse41);
+    }
+}
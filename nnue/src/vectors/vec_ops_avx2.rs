@@ -1,80 +1,10 @@
 use std::arch::x86_64::{__m256i, _mm_add_epi32, _mm_cvtsi128_si32, _mm_shuffle_epi32, _mm256_castsi256_si128, _mm256_extracti128_si256};
 
-use crate::constants::{PSQT_BUCKETS, PsqtWeightType};
+/// Name of the compiled SIMD backend
+pub const BACKEND: &str = "avx2";
 
 /// Packed 256-bit lane used for the main NNUE accumulator math (identical to Stockfish's `vec_t`).
 pub type VecT = __m256i;
-/// Packed 256-bit lane used for PSQT-specific SIMD operations. Currently the same as [`VecT`],
-/// but kept separate in case we want to specialize PSQT arithmetic later.
-pub type PsqtVecT = __m256i;
-
-/// Number of bytes processed per AVX2 chunk. Each register holds 16 `i16` lanes (32 bytes).
-pub const MAX_CHUNK_SIZE: usize = 32; // AVX2
-pub const SIMD_WIDTH: usize = MAX_CHUNK_SIZE;
-
-pub const NUM_REGISTERS_SIMD: usize = 16;
-
-
-const fn best_register_count() -> usize {
-
-    const REGISTER_SIZE: usize = std::mem::size_of::<VecT>();
-    // const LANE_SIZE: usize = std::mem::size_of::<WeightType>(); // For Normal Regs
-    const LANE_SIZE: usize = std::mem::size_of::<PsqtWeightType>(); // For PSQT Regs
-
-
-    const NUM_LANES: usize = PSQT_BUCKETS; // PSQT
-    // const NUM_LANES: usize = TRANSFORMED_FEATURE_DIM_BIG; // Big net
-    // const NUM_LANES: usize = TRANSFORMED_FEATURE_DIM_BIG; //Small for small net
-
-    const IDEAL: usize = (NUM_LANES * LANE_SIZE) / REGISTER_SIZE;
-
-    if IDEAL <= NUM_REGISTERS_SIMD {
-        return IDEAL;
-    }
-
-    let mut divisor = NUM_REGISTERS_SIMD;
-    while divisor > 1 {
-        if IDEAL % divisor == 0 {
-            return divisor;
-        }
-        divisor -= 1;
-    }
-
-    1
-}
-
-pub enum RegsAndTileHeight {
-    SmallNet,
-    BigNet,
-    Psqt,
-}
-
-
-/// Number of registers to use for big net accumulator tiling
-pub const NUM_REGS_SMALL: usize = 8; // Derived from plugging numbers into best_register_count()
-pub const TILE_HEIGHT_SMALL: usize = NUM_REGS_SMALL * std::mem::size_of::<VecT>() / 2;
-
-/// Number of registers to use for big net accumulator tiling
-pub const NUM_REGS_BIG: usize = 16; // Derived from plugging numbers into best_register_count()
-pub const TILE_HEIGHT_BIG: usize = NUM_REGS_BIG * std::mem::size_of::<VecT>() / 2;
-
-pub const NUM_PSQT_REGS: usize = 1; // Derived from plugging numbers into best_register_count()
-pub const PSQT_TILE_HEIGHT: usize = NUM_PSQT_REGS * std::mem::size_of::<PsqtVecT>() / 4;
-
-// pub const NUM_REGS: usize = best_register_count::<Vec_T, WeightType ();
-
-
-pub fn to_mut_vec_ptr<T>(v: &mut [T], offset: usize) -> *mut VecT {
-    unsafe {
-        v.as_mut_ptr().add(offset).cast::<VecT>()
-    }
-}
-pub fn to_const_vec_ptr<T>(v: &[T], offset: usize) -> *const VecT {
-    unsafe {
-        v.as_ptr().add(offset).cast::<VecT>()
-    }
-}
-
 
 /// Returns a vector with all lanes set to zero. This mirrors `vec_zero()` in NNUE reference code.
 pub fn vec_zero() -> VecT {
@@ -93,6 +23,7 @@ pub fn vec_set1_16(x: i16) -> VecT {
 pub fn vec_set1_32(x: i32) -> VecT {
     unsafe { std::arch::x86_64::_mm256_set1_epi32(x) }
 }
+#[allow(clippy::too_many_arguments)]
 pub fn vec_set_32(e0: i32, e1: i32, e2: i32, e3: i32, e4: i32, e5: i32, e6: i32, e7: i32) -> VecT {
     unsafe { std::arch::x86_64::_mm256_set_epi32(e0, e1, e2, e3, e4, e5, e6, e7) }
 }
@@ -0,0 +1,255 @@
+//! Portable fallback with the same lane semantics as the AVX2 backend, including the per
+//! 128-bit lane ordering of the pack instructions, so the rest of the crate is unchanged.
+
+/// Name of the compiled SIMD backend
+pub const BACKEND: &str = "scalar";
+
+/// 256 bits viewed as 32 bytes, 16 `i16` or 8 `i32` lanes depending on the op.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C, align(32))]
+pub struct VecT([u8; 32]);
+
+impl VecT {
+    #[inline(always)]
+    fn u8s(self) -> [u8; 32] {
+        self.0
+    }
+    #[inline(always)]
+    fn i16s(self) -> [i16; 16] {
+        bytemuck::cast(self.0)
+    }
+    #[inline(always)]
+    fn i32s(self) -> [i32; 8] {
+        bytemuck::cast(self.0)
+    }
+    #[inline(always)]
+    fn from_u8s(v: [u8; 32]) -> Self {
+        Self(v)
+    }
+    #[inline(always)]
+    fn from_i16s(v: [i16; 16]) -> Self {
+        Self(bytemuck::cast(v))
+    }
+    #[inline(always)]
+    fn from_i32s(v: [i32; 8]) -> Self {
+        Self(bytemuck::cast(v))
+    }
+}
+
+#[inline(always)]
+fn map_16(a: VecT, b: VecT, f: impl Fn(i16, i16) -> i16) -> VecT {
+    let (a, b) = (a.i16s(), b.i16s());
+    VecT::from_i16s(std::array::from_fn(|i| f(a[i], b[i])))
+}
+
+#[inline(always)]
+fn map_32(a: VecT, b: VecT, f: impl Fn(i32, i32) -> i32) -> VecT {
+    let (a, b) = (a.i32s(), b.i32s());
+    VecT::from_i32s(std::array::from_fn(|i| f(a[i], b[i])))
+}
+
+/// Returns a vector with all lanes set to zero. This mirrors `vec_zero()` in NNUE reference code.
+pub fn vec_zero() -> VecT {
+    VecT([0; 32])
+}
+
+pub fn vec_srli_epi16<const IMM8: i32>(a: VecT) -> VecT {
+    let a = a.i16s();
+    VecT::from_i16s(std::array::from_fn(|i| {
+        if IMM8 > 15 { 0 } else { ((a[i] as u16) >> IMM8) as i16 }
+    }))
+}
+
+/// Broadcasts the provided 16-bit value into every lane.
+pub fn vec_set1_16(x: i16) -> VecT {
+    VecT::from_i16s([x; 16])
+}
+/// Broadcasts the provided 32-bit value into every lane.
+pub fn vec_set1_32(x: i32) -> VecT {
+    VecT::from_i32s([x; 8])
+}
+/// Arguments are given highest lane first, like `_mm256_set_epi32`.
+#[allow(clippy::too_many_arguments)]
+pub fn vec_set_32(e0: i32, e1: i32, e2: i32, e3: i32, e4: i32, e5: i32, e6: i32, e7: i32) -> VecT {
+    VecT::from_i32s([e7, e6, e5, e4, e3, e2, e1, e0])
+}
+
+pub fn m256_hadd(sum: VecT, bias: i32) -> i32 {
+    sum.i32s().iter().fold(0i32, |acc, &x| acc.wrapping_add(x)) + bias
+}
+
+/// Takes the per-lane maximum of two signed 16-bit vectors.
+pub fn vec_max_16(a: VecT, b: VecT) -> VecT {
+    map_16(a, b, i16::max)
+}
+
+/// Takes the per-lane minimum of two signed 16-bit vectors.
+pub fn vec_min_16(a: VecT, b: VecT) -> VecT {
+    map_16(a, b, i16::min)
+}
+
+pub fn vec_add_32(a: VecT, b: VecT) -> VecT {
+    map_32(a, b, i32::wrapping_add)
+}
+pub fn vec_add_16(a: VecT, b: VecT) -> VecT {
+    map_16(a, b, i16::wrapping_add)
+}
+pub fn vec_sub_16(a: VecT, b: VecT) -> VecT {
+    map_16(a, b, i16::wrapping_sub)
+}
+pub fn vec_sub_32(a: VecT, b: VecT) -> VecT {
+    map_32(a, b, i32::wrapping_sub)
+}
+pub fn vec_madd_16(a: VecT, b: VecT) -> VecT {
+    let (a, b) = (a.i16s(), b.i16s());
+    VecT::from_i32s(std::array::from_fn(|i| {
+        let lo = a[2 * i] as i32 * b[2 * i] as i32;
+        let hi = a[2 * i + 1] as i32 * b[2 * i + 1] as i32;
+        lo.wrapping_add(hi)
+    }))
+}
+
+/// Multiplies signed 16-bit lanes and keeps the high 16 bits of each 32-bit product. This matches
+/// the arithmetic Stockfish uses during the feature transform clamping/multiplication step.
+pub fn vec_mulhi_16(a: VecT, b: VecT) -> VecT {
+    map_16(a, b, |x, y| ((x as i32 * y as i32) >> 16) as i16)
+}
+
+/// Packs two signed 16-bit vectors into a single unsigned-saturated 8-bit vector. Used to convert
+/// the accumulator halves into the final feature buffer.
+pub fn vec_packus_16(a: VecT, b: VecT) -> VecT {
+    let (a, b) = (a.i16s(), b.i16s());
+    VecT::from_u8s(std::array::from_fn(|i| {
+        let (lane, j) = (i / 16, i % 16);
+        let src = if j < 8 { a } else { b };
+        src[lane * 8 + j % 8].clamp(0, u8::MAX as i16) as u8
+    }))
+}
+pub fn vec_packus_32(a: VecT, b: VecT) -> VecT {
+    let (a, b) = (a.i32s(), b.i32s());
+    VecT::from_i16s(std::array::from_fn(|i| {
+        let (lane, j) = (i / 8, i % 8);
+        let src = if j < 4 { a } else { b };
+        src[lane * 4 + j % 4].clamp(0, u16::MAX as i32) as u16 as i16
+    }))
+}
+pub fn vec_load_si256(ptr: *const VecT) -> VecT {
+    unsafe { ptr.read() }
+}
+pub fn vec_store_si256(ptr: *mut VecT, a: VecT) {
+    unsafe { ptr.write(a) }
+}
+
+pub fn vec_packs_epi16(a: VecT, b: VecT) -> VecT {
+    let (a, b) = (a.i16s(), b.i16s());
+    VecT::from_u8s(std::array::from_fn(|i| {
+        let (lane, j) = (i / 16, i % 16);
+        let src = if j < 8 { a } else { b };
+        src[lane * 8 + j % 8].clamp(i8::MIN as i16, i8::MAX as i16) as i8 as u8
+    }))
+}
+
+pub fn vec_permutevar8x32_epi32(a: VecT, idx: VecT) -> VecT {
+    let (a, idx) = (a.i32s(), idx.i32s());
+    VecT::from_i32s(std::array::from_fn(|i| a[(idx[i] & 7) as usize]))
+}
+
+/// Shifts every 16-bit lane left by the provided immediate value.
+pub fn vec_slli_16<const IMM8: i32>(a: VecT) -> VecT {
+    let a = a.i16s();
+    VecT::from_i16s(std::array::from_fn(|i| {
+        if IMM8 > 15 { 0 } else { ((a[i] as u16) << IMM8) as i16 }
+    }))
+}
+
+/// `maddubs` (u8 * i8 pairs summed with i16 saturation) followed by a pairwise add into `acc`.
+pub fn vec_add_dpbusd_epi32(acc: &mut VecT, a: VecT, b: VecT) {
+    let (a, b) = (a.u8s(), b.u8s());
+    let product0: [i16; 16] = std::array::from_fn(|i| {
+        let lo = a[2 * i] as i32 * b[2 * i] as i8 as i32;
+        let hi = a[2 * i + 1] as i32 * b[2 * i + 1] as i8 as i32;
+        (lo + hi).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    });
+    let product0 = vec_madd_16(VecT::from_i16s(product0), vec_set1_16(1));
+    *acc = vec_add_32(*acc, product0);
+}
+
+pub fn vec_nnz(a: VecT) -> i32 {
+    a.i32s()
+        .iter()
+        .enumerate()
+        .fold(0, |mask, (i, &x)| mask | (((x > 0) as i32) << i))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C, align(16))]
+pub struct Vec128T([u8; 16]);
+
+impl Vec128T {
+    #[inline(always)]
+    fn i16s(self) -> [i16; 8] {
+        bytemuck::cast(self.0)
+    }
+    #[inline(always)]
+    fn i32s(self) -> [i32; 4] {
+        bytemuck::cast(self.0)
+    }
+    #[inline(always)]
+    fn from_i16s(v: [i16; 8]) -> Self {
+        Self(bytemuck::cast(v))
+    }
+}
+
+pub fn mm_setzero_si128() -> Vec128T {
+    Vec128T([0; 16])
+}
+pub fn mm_set1_epi16(x: i16) -> Vec128T {
+    Vec128T::from_i16s([x; 8])
+}
+pub fn mm_load_si128(ptr: *const Vec128T) -> Vec128T {
+    unsafe { ptr.read() }
+}
+pub fn mm_storeu_si128(ptr: *mut Vec128T, a: Vec128T) {
+    unsafe { ptr.write_unaligned(a) }
+}
+pub fn mm_store_si128(ptr: *mut Vec128T, a: Vec128T) {
+    unsafe { ptr.write(a) }
+}
+pub fn mm_add_epi16(a: Vec128T, b: Vec128T) -> Vec128T {
+    let (a, b) = (a.i16s(), b.i16s());
+    Vec128T::from_i16s(std::array::from_fn(|i| a[i].wrapping_add(b[i])))
+}
+pub fn mm_mulhi_epi16(a: Vec128T, b: Vec128T) -> Vec128T {
+    let (a, b) = (a.i16s(), b.i16s());
+    Vec128T::from_i16s(std::array::from_fn(|i| ((a[i] as i32 * b[i] as i32) >> 16) as i16))
+}
+
+pub fn mm_packs_epi16(a: Vec128T, b: Vec128T) -> Vec128T {
+    let (a, b) = (a.i16s(), b.i16s());
+    Vec128T(std::array::from_fn(|i| {
+        let src = if i < 8 { a } else { b };
+        src[i % 8].clamp(i8::MIN as i16, i8::MAX as i16) as i8 as u8
+    }))
+}
+pub fn mm_packs_epi32(a: Vec128T, b: Vec128T) -> Vec128T {
+    let (a, b) = (a.i32s(), b.i32s());
+    Vec128T::from_i16s(std::array::from_fn(|i| {
+        let src = if i < 4 { a } else { b };
+        src[i % 4].clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }))
+}
+
+pub fn mm_packus_epi32(a: Vec128T, b: Vec128T) -> Vec128T {
+    let (a, b) = (a.i32s(), b.i32s());
+    Vec128T::from_i16s(std::array::from_fn(|i| {
+        let src = if i < 4 { a } else { b };
+        src[i % 4].clamp(0, u16::MAX as i32) as u16 as i16
+    }))
+}
+
+pub fn mm_srli_epi16<const IMM8: i32>(a: Vec128T) -> Vec128T {
+    let a = a.i16s();
+    Vec128T::from_i16s(std::array::from_fn(|i| {
+        if IMM8 > 15 { 0 } else { ((a[i] as u16) >> IMM8) as i16 }
+    }))
+}
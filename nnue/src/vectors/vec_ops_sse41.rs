@@ -0,0 +1,171 @@
+//! SSE4.1 backend. A 256-bit `VecT` is a pair of 128-bit registers; the AVX2 pack and
+//! multiply-add instructions work per 128-bit lane, so running them on each half gives
+//! exactly the AVX2 results and the weight layout stays the same.
+
+use std::arch::x86_64::*;
+
+/// Name of the compiled SIMD backend
+pub const BACKEND: &str = "sse4.1";
+
+/// Low and high 128-bit halves of a 256-bit vector.
+#[derive(Clone, Copy)]
+#[repr(C, align(32))]
+pub struct VecT(__m128i, __m128i);
+
+#[inline(always)]
+fn halves(a: VecT, b: VecT, f: impl Fn(__m128i, __m128i) -> __m128i) -> VecT {
+    VecT(f(a.0, b.0), f(a.1, b.1))
+}
+
+/// Returns a vector with all lanes set to zero. This mirrors `vec_zero()` in NNUE reference code.
+pub fn vec_zero() -> VecT {
+    unsafe { VecT(_mm_setzero_si128(), _mm_setzero_si128()) }
+}
+
+pub fn vec_srli_epi16<const IMM8: i32>(a: VecT) -> VecT {
+    unsafe { VecT(_mm_srli_epi16::<IMM8>(a.0), _mm_srli_epi16::<IMM8>(a.1)) }
+}
+
+/// Broadcasts the provided 16-bit value into every lane.
+pub fn vec_set1_16(x: i16) -> VecT {
+    unsafe { VecT(_mm_set1_epi16(x), _mm_set1_epi16(x)) }
+}
+/// Broadcasts the provided 32-bit value into every lane.
+pub fn vec_set1_32(x: i32) -> VecT {
+    unsafe { VecT(_mm_set1_epi32(x), _mm_set1_epi32(x)) }
+}
+/// Arguments are given highest lane first, like `_mm256_set_epi32`.
+#[allow(clippy::too_many_arguments)]
+pub fn vec_set_32(e0: i32, e1: i32, e2: i32, e3: i32, e4: i32, e5: i32, e6: i32, e7: i32) -> VecT {
+    unsafe { VecT(_mm_set_epi32(e4, e5, e6, e7), _mm_set_epi32(e0, e1, e2, e3)) }
+}
+
+const _MM_PERM_BADC: i32 = 0x4E;
+const _MM_PERM_CDAB: i32 = 0xB1;
+
+pub fn m256_hadd(sum: VecT, bias: i32) -> i32 {
+    unsafe {
+        let mut sum128 = _mm_add_epi32(sum.0, sum.1);
+        sum128 = _mm_add_epi32(sum128, _mm_shuffle_epi32(sum128, _MM_PERM_BADC));
+        sum128 = _mm_add_epi32(sum128, _mm_shuffle_epi32(sum128, _MM_PERM_CDAB));
+        _mm_cvtsi128_si32(sum128) + bias
+    }
+}
+
+/// Takes the per-lane maximum of two signed 16-bit vectors.
+pub fn vec_max_16(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_max_epi16(x, y) })
+}
+
+/// Takes the per-lane minimum of two signed 16-bit vectors.
+pub fn vec_min_16(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_min_epi16(x, y) })
+}
+
+pub fn vec_add_32(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_add_epi32(x, y) })
+}
+pub fn vec_add_16(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_add_epi16(x, y) })
+}
+pub fn vec_sub_16(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_sub_epi16(x, y) })
+}
+pub fn vec_sub_32(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_sub_epi32(x, y) })
+}
+pub fn vec_madd_16(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_madd_epi16(x, y) })
+}
+
+/// Multiplies signed 16-bit lanes and keeps the high 16 bits of each 32-bit product. This matches
+/// the arithmetic Stockfish uses during the feature transform clamping/multiplication step.
+pub fn vec_mulhi_16(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_mulhi_epi16(x, y) })
+}
+
+/// Packs two signed 16-bit vectors into a single unsigned-saturated 8-bit vector. Used to convert
+/// the accumulator halves into the final feature buffer.
+pub fn vec_packus_16(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_packus_epi16(x, y) })
+}
+pub fn vec_packus_32(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_packus_epi32(x, y) })
+}
+pub fn vec_load_si256(ptr: *const VecT) -> VecT {
+    unsafe { ptr.read() }
+}
+pub fn vec_store_si256(ptr: *mut VecT, a: VecT) {
+    unsafe { ptr.write(a) }
+}
+
+pub fn vec_packs_epi16(a: VecT, b: VecT) -> VecT {
+    halves(a, b, |x, y| unsafe { _mm_packs_epi16(x, y) })
+}
+
+/// Lanes cross the 128-bit halves here, so it goes through memory.
+pub fn vec_permutevar8x32_epi32(a: VecT, idx: VecT) -> VecT {
+    let a: [i32; 8] = unsafe { std::mem::transmute(a) };
+    let idx: [i32; 8] = unsafe { std::mem::transmute(idx) };
+    let out: [i32; 8] = std::array::from_fn(|i| a[(idx[i] & 7) as usize]);
+    unsafe { std::mem::transmute(out) }
+}
+
+/// Shifts every 16-bit lane left by the provided immediate value.
+pub fn vec_slli_16<const IMM8: i32>(a: VecT) -> VecT {
+    unsafe { VecT(_mm_slli_epi16::<IMM8>(a.0), _mm_slli_epi16::<IMM8>(a.1)) }
+}
+
+pub fn vec_add_dpbusd_epi32(acc: &mut VecT, a: VecT, b: VecT) {
+    let mut product0: VecT = halves(a, b, |x, y| unsafe { _mm_maddubs_epi16(x, y) });
+    product0 = vec_madd_16(product0, vec_set1_16(1));
+    *acc = vec_add_32(*acc, product0);
+}
+
+pub fn vec_nnz(a: VecT) -> i32 {
+    unsafe {
+        let zero = _mm_setzero_si128();
+        let lo = _mm_movemask_ps(_mm_castsi128_ps(_mm_cmpgt_epi32(a.0, zero)));
+        let hi = _mm_movemask_ps(_mm_castsi128_ps(_mm_cmpgt_epi32(a.1, zero)));
+        lo | (hi << 4)
+    }
+}
+
+pub type Vec128T = __m128i;
+
+pub fn mm_setzero_si128() -> Vec128T {
+    unsafe { _mm_setzero_si128() }
+}
+pub fn mm_set1_epi16(x: i16) -> Vec128T {
+    unsafe { _mm_set1_epi16(x) }
+}
+pub fn mm_load_si128(ptr: *const Vec128T) -> Vec128T {
+    unsafe { _mm_load_si128(ptr) }
+}
+pub fn mm_storeu_si128(ptr: *mut Vec128T, a: Vec128T) {
+    unsafe { _mm_storeu_si128(ptr, a) }
+}
+pub fn mm_store_si128(ptr: *mut Vec128T, a: Vec128T) {
+    unsafe { _mm_store_si128(ptr, a) }
+}
+pub fn mm_add_epi16(a: Vec128T, b: Vec128T) -> Vec128T {
+    unsafe { _mm_add_epi16(a, b) }
+}
+pub fn mm_mulhi_epi16(a: Vec128T, b: Vec128T) -> Vec128T {
+    unsafe { _mm_mulhi_epi16(a, b) }
+}
+
+pub fn mm_packs_epi16(a: Vec128T, b: Vec128T) -> Vec128T {
+    unsafe { _mm_packs_epi16(a, b) }
+}
+pub fn mm_packs_epi32(a: Vec128T, b: Vec128T) -> Vec128T {
+    unsafe { _mm_packs_epi32(a, b) }
+}
+
+pub fn mm_packus_epi32(a: Vec128T, b: Vec128T) -> Vec128T {
+    unsafe { _mm_packus_epi32(a, b) }
+}
+
+pub fn mm_srli_epi16<const IMM8: i32>(a: Vec128T) -> Vec128T {
+    unsafe { _mm_srli_epi16::<IMM8>(a) }
+}
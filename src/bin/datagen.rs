@@ -0,0 +1,330 @@
+//! Self-play training data for our own networks.
+//!
+//! `datagen --out <path> [--format text|bullet] [--games 1000] [--threads N] [--seed 0]
+//!          [--nodes 5000 | --depth D] [--random-plies 8] [--net <path>]`
+//!
+//! Every game starts from a few random moves and is then played by `MySearcher` on both sides.
+//! Quiet positions are kept with the search score in centipawns and the game result. A game
+//! only depends on the seed and its number, so running the same command again continues where
+//! it stopped: `<out>.progress` records the games written and how far `<out>` got after each of
+//! them.
+
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use clap::Parser;
+use engine::{
+    debug::{NoTrace, Tracing},
+    search::MySearcher,
+};
+use nnue::{
+    data::{DataFormat, GameResult, Sample},
+    nnue::{load_networks, NnueEvaluator},
+    nnue_utils,
+};
+use pleco::{Board, MoveList, Player};
+use rand::{rngs::StdRng, seq::IndexedRandom, SeedableRng};
+
+/// Games still going after this many plies are drawn
+const MAX_GAME_PLIES: usize = 400;
+/// A side this far ahead wins
+const ADJUDICATE_SCORE: i16 = 2500;
+/// Positions scored beyond this are decided already and teach little
+const MAX_SAMPLE_SCORE: i16 = 2000;
+
+#[derive(Parser)]
+#[command(about = "Self-play training data for our own networks")]
+struct Cli {
+    /// Where to write the samples, `<out>.progress` records how far it got
+    #[arg(long)]
+    out: PathBuf,
+    /// text or bullet
+    #[arg(long, default_value = "text")]
+    format: DataFormat,
+    #[arg(long, default_value_t = 1000)]
+    games: u64,
+    /// Worker threads, defaults to the number of cores
+    #[arg(long)]
+    threads: Option<usize>,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Nodes searched per move
+    #[arg(long, default_value_t = 5000, conflicts_with = "depth")]
+    nodes: u64,
+    /// Search every move to this depth instead of a node count
+    #[arg(long)]
+    depth: Option<u8>,
+    /// Random moves at the start of every game
+    #[arg(long, default_value_t = 8)]
+    random_plies: usize,
+    /// Network to play with instead of the shared ones
+    #[arg(long)]
+    net: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Limit {
+    Nodes(u64),
+    Depth(u8),
+}
+
+struct Config {
+    out: PathBuf,
+    format: DataFormat,
+    games: u64,
+    threads: usize,
+    seed: u64,
+    limit: Limit,
+    random_plies: usize,
+    net: Option<String>,
+}
+
+impl Config {
+    /// Everything the samples depend on, checked when resuming
+    fn header(&self) -> String {
+        let limit = match self.limit {
+            Limit::Nodes(nodes) => format!("nodes {}", nodes),
+            Limit::Depth(depth) => format!("depth {}", depth),
+        };
+        format!(
+            "seed {}, {}, random plies {}, format {:?}, net {}",
+            self.seed,
+            limit,
+            self.random_plies,
+            self.format,
+            self.net.as_deref().unwrap_or("default")
+        )
+    }
+}
+
+fn parse_args() -> Config {
+    let cli = Cli::parse();
+    let limit = match cli.depth {
+        Some(depth) => Limit::Depth(depth),
+        None => Limit::Nodes(cli.nodes),
+    };
+    Config {
+        out: cli.out,
+        format: cli.format,
+        games: cli.games,
+        threads: cli.threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())).max(1),
+        seed: cli.seed,
+        limit,
+        random_plies: cli.random_plies,
+        net: cli.net,
+    }
+}
+
+/// The data file and its progress log, written one whole game at a time
+struct Output {
+    data: File,
+    progress: File,
+    len: u64,
+}
+
+fn progress_path(out: &Path) -> PathBuf {
+    let mut path = out.as_os_str().to_owned();
+    path.push(".progress");
+    PathBuf::from(path)
+}
+
+/// Open the output, resuming a previous run with the same settings. Returns the games it
+/// already wrote. Data after the last recorded game, from a run that was killed mid write,
+/// is cut off.
+fn open_output(config: &Config) -> io::Result<(Output, HashSet<u64>)> {
+    let progress_path = progress_path(&config.out);
+    let mut done = HashSet::new();
+    let mut len = 0;
+
+    if progress_path.exists() {
+        let mut lines = BufReader::new(File::open(&progress_path)?).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        if header != config.header() {
+            return Err(io::Error::other(format!(
+                "{} was generated with \"{}\", not \"{}\"",
+                config.out.display(),
+                header,
+                config.header()
+            )));
+        }
+        for line in lines {
+            let line = line?;
+            let parsed = line.split_once(' ').and_then(|(g, l)| Some((g.parse().ok()?, l.parse().ok()?)));
+            let Some((game, end)) = parsed else {
+                return Err(io::Error::other(format!("bad line in {}: {}", progress_path.display(), line)));
+            };
+            done.insert(game);
+            len = end;
+        }
+    } else if config.out.exists() {
+        return Err(io::Error::other(format!("{} exists but has no progress file", config.out.display())));
+    } else {
+        let mut progress = File::create(&progress_path)?;
+        writeln!(progress, "{}", config.header())?;
+    }
+
+    let mut data = OpenOptions::new().create(true).write(true).truncate(false).open(&config.out)?;
+    data.set_len(len)?;
+    data.seek(SeekFrom::End(0))?;
+    let progress = OpenOptions::new().append(true).open(&progress_path)?;
+
+    Ok((Output { data, progress, len }, done))
+}
+
+impl Output {
+    fn write_game(&mut self, game: u64, bytes: &[u8]) -> io::Result<()> {
+        self.data.write_all(bytes)?;
+        self.data.flush()?;
+        self.len += bytes.len() as u64;
+        writeln!(self.progress, "{} {}", game, self.len)?;
+        self.progress.flush()
+    }
+}
+
+/// A position `plies` random moves into the game that is not over yet
+fn random_opening(rng: &mut StdRng, plies: usize) -> Board {
+    'retry: loop {
+        let mut board = Board::start_pos();
+        for _ in 0..plies {
+            let moves: MoveList = board.generate_moves();
+            match moves.choose(rng) {
+                Some(&mv) => board.apply_move(mv),
+                None => continue 'retry,
+            }
+        }
+        if !board.generate_moves().is_empty() {
+            return board;
+        }
+    }
+}
+
+fn play_game(config: &Config, game: u64, nnue_eval: &mut NnueEvaluator) -> (GameResult, Vec<Sample>) {
+    let mut rng = StdRng::seed_from_u64(config.seed.wrapping_add(game.wrapping_mul(0x9E37_79B9_7F4A_7C15)));
+    let mut board = random_opening(&mut rng, config.random_plies);
+
+    let mut searcher = MySearcher::new(nnue_eval, NoTrace::new(), None);
+    let depth = match config.limit {
+        Limit::Nodes(nodes) => {
+            searcher = searcher.with_node_limit(nodes);
+            engine::search::MAX_PLY as u8
+        }
+        Limit::Depth(depth) => depth,
+    };
+
+    let mut samples = Vec::new();
+    let result = loop {
+        if board.generate_moves().is_empty() {
+            break match (board.in_check(), board.turn()) {
+                (false, _) => GameResult::Draw,
+                (true, Player::White) => GameResult::BlackWin,
+                (true, Player::Black) => GameResult::WhiteWin,
+            };
+        }
+        if board.fifty_move_rule() || board.threefold_repetition() || board.ply() as usize >= MAX_GAME_PLIES {
+            break GameResult::Draw;
+        }
+
+        let best = searcher.perform_search(&mut board, depth);
+        if best.bit_move.is_null() {
+            break GameResult::Draw;
+        }
+        if best.score.abs() >= ADJUDICATE_SCORE {
+            let white_ahead = (best.score > 0) == (board.turn() == Player::White);
+            break if white_ahead { GameResult::WhiteWin } else { GameResult::BlackWin };
+        }
+
+        // Leave out positions the evaluation can't judge by itself
+        let quiet = !board.in_check() && !board.is_capture_or_promotion(best.bit_move);
+        if quiet && best.score.abs() < MAX_SAMPLE_SCORE {
+            let cp = nnue_utils::to_cp(i32::from(best.score), &board) as i16;
+            samples.push(Sample::new(&board, cp, GameResult::Draw));
+        }
+        board.apply_move(best.bit_move);
+    };
+
+    for sample in &mut samples {
+        sample.result = result;
+    }
+    (result, samples)
+}
+
+pub fn main() {
+    if let Err(e) = run(&parse_args()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(config: &Config) -> Result<(), String> {
+    let (nnue, small) = load_networks(config.net.as_deref()).map_err(|e| e.to_string())?;
+    let (output, done) = open_output(config).map_err(|e| e.to_string())?;
+    println!(
+        "Generating {} games ({} done) into {} with {} threads: {}",
+        config.games,
+        done.len(),
+        config.out.display(),
+        config.threads,
+        config.header()
+    );
+
+    let output = Mutex::new(output);
+    let next_game = AtomicU64::new(0);
+    let total_samples = AtomicU64::new(0);
+    let start = Instant::now();
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..config.threads)
+            .map(|_| {
+                let (output, next_game, total_samples, done) = (&output, &next_game, &total_samples, &done);
+                let mut nnue_eval = NnueEvaluator::with_networks(nnue.clone(), small.clone());
+                scope.spawn(move || loop {
+                    let game = next_game.fetch_add(1, Ordering::Relaxed);
+                    if game >= config.games {
+                        return Ok(());
+                    }
+                    if done.contains(&game) {
+                        continue;
+                    }
+
+                    let (result, samples) = play_game(config, game, &mut nnue_eval);
+                    let mut bytes = Vec::new();
+                    for sample in &samples {
+                        sample.write(&mut bytes, config.format).unwrap();
+                    }
+                    if let Err(e) = output.lock().unwrap().write_game(game, &bytes) {
+                        // The other workers finish their games and stop
+                        next_game.store(config.games, Ordering::Relaxed);
+                        return Err(format!("failed to write game {}: {}", game, e));
+                    }
+
+                    let total = total_samples.fetch_add(samples.len() as u64, Ordering::Relaxed) + samples.len() as u64;
+                    println!(
+                        "Game {}: {:?}, {} samples, {} total, {:.0} samples/s",
+                        game + 1,
+                        result,
+                        samples.len(),
+                        total,
+                        total as f64 / start.elapsed().as_secs_f64()
+                    );
+                })
+            })
+            .collect();
+        workers.into_iter().map(|worker| worker.join().unwrap()).collect::<Result<(), String>>()
+    })?;
+
+    println!(
+        "Wrote {} samples in {} seconds",
+        total_samples.load(Ordering::Relaxed),
+        start.elapsed().as_secs()
+    );
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Static NNUE evaluations of many positions, e.g. to label data or score an EPD suite.
+//!
+//! `evaluate [--in <path>] [--threads N] [--net <path>]`
+//!
+//! Reads one position per line from `<path>`, or stdin without it. A line is a FEN, an EPD
+//! record whose move counters default to `0 1`, or a `<fen> | <score> | <result>` sample of
+//! `datagen`. Empty lines and lines starting with `#` are skipped. Writes
+//! `<fen> | <centipawns> | <win> <draw> <loss>` for every position, from the side to move with
+//! the chances in per mille.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    time::Instant,
+};
+
+use clap::Parser;
+use nnue::nnue::{evaluate_many_with, load_networks};
+use pleco::Board;
+
+#[derive(Parser)]
+#[command(about = "Static NNUE evaluations of many positions")]
+struct Cli {
+    /// Positions to evaluate, stdin without it or with `-`
+    #[arg(long = "in")]
+    input: Option<String>,
+    /// Worker threads, defaults to the number of cores
+    #[arg(short, long)]
+    threads: Option<usize>,
+    /// Network to use instead of the shared ones
+    #[arg(long)]
+    net: Option<String>,
+}
+
+/// The FEN of an input line, `None` for lines without a position
+fn line_fen(line: &str) -> Option<String> {
+    let line = line.split('|').next().unwrap_or_default().trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let counters = fields.len() >= 6 && fields[4..6].iter().all(|f| f.parse::<u32>().is_ok());
+    Some(if counters || fields.len() < 4 {
+        fields[..fields.len().min(6)].join(" ")
+    } else {
+        format!("{} 0 1", fields[..4].join(" "))
+    })
+}
+
+pub fn main() {
+    if let Err(e) = run(&Cli::parse()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), String> {
+    let input: Box<dyn BufRead> = match cli.input.as_deref().filter(|path| *path != "-") {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?,
+        )),
+        None => Box::new(io::stdin().lock()),
+    };
+
+    let mut boards = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| format!("failed to read line {}: {}", number + 1, e))?;
+        let Some(fen) = line_fen(&line) else {
+            continue;
+        };
+        match Board::from_fen(&fen) {
+            Ok(board) => boards.push(board),
+            Err(e) => eprintln!("Skipping line {}, bad FEN {}: {:?}", number + 1, fen, e),
+        }
+    }
+
+    let (nnue, small) = load_networks(cli.net.as_deref()).map_err(|e| e.to_string())?;
+    let threads = cli.threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())).max(1);
+    let start = Instant::now();
+    let evals = evaluate_many_with(&nnue, small.as_ref(), &boards, threads);
+    let elapsed = start.elapsed();
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (board, eval) in boards.iter().zip(&evals) {
+        let wdl = eval.wdl(board);
+        writeln!(out, "{} | {} | {} {} {}", board.fen(), eval.to_cp(board), wdl.win, wdl.draw, wdl.loss)
+            .map_err(|e| e.to_string())?;
+    }
+    out.flush().map_err(|e| e.to_string())?;
+
+    eprintln!(
+        "Evaluated {} positions in {:.2} seconds with {} threads, {:.0} positions/s",
+        evals.len(),
+        elapsed.as_secs_f64(),
+        threads,
+        evals.len() as f64 / elapsed.as_secs_f64().max(1e-9)
+    );
+    Ok(())
+}
@@ -1,4 +1,12 @@
-use std::time::Instant;
+use std::{env, fs::File, io::Write, time::Instant};
+
+use book::pgn::PgnGame;
+use engine::{
+    debug::{NoTrace, Tracing},
+    search::Extensions,
+};
+use nnue::nnue::NnueEvaluator;
+use pleco::BitMove;
 
 #[allow(unused)]
 pub const TRAINING_FENS: [&str; 1000] = [
@@ -1160,6 +1168,186 @@ pub const EARLY_COMMON_FENS: &[&str] = &[
     "rnbqkbnr/ppp1pp1p/3p2p1/8/3PP3/5N2/PPP2PPP/RNBQKB1R b KQkq - 1 3",
 ];
 
+/// Searches a position, returning the move and the number of nodes searched
+type SearchFn =
+    Box<dyn Fn(&mut NnueEvaluator, &mut pleco::Board, u8, Option<u128>) -> (BitMove, u64)>;
+
+fn search(
+    nnue_eval: &mut NnueEvaluator,
+    board: &mut pleco::Board,
+    ply: u8,
+    time: Option<u128>,
+) -> (BitMove, u64) {
+    let mut searcher = engine::search::MySearcher::new(nnue_eval, NoTrace::new(), time);
+    (searcher.find_best_move(board, ply), searcher.nodes())
+}
+
+/// The current search with some of its heuristics turned off
+fn search_with(
+    history_tables: bool,
+    extensions: Extensions,
+) -> impl Fn(&mut NnueEvaluator, &mut pleco::Board, u8, Option<u128>) -> (BitMove, u64) {
+    move |nnue_eval: &mut NnueEvaluator, board: &mut pleco::Board, ply: u8, time: Option<u128>| {
+        let mut searcher = engine::search::MySearcher::new(nnue_eval, NoTrace::new(), time)
+            .with_history_tables(history_tables)
+            .with_extensions(extensions);
+        (searcher.find_best_move(board, ply), searcher.nodes())
+    }
+}
+
+fn search_wip(
+    nnue_eval: &mut NnueEvaluator,
+    board: &mut pleco::Board,
+    ply: u8,
+    time: Option<u128>,
+) -> (BitMove, u64) {
+    let mut searcher = engine::search_wip::MySearcher::new(nnue_eval, NoTrace::new(), time);
+    (searcher.find_best_move(board, ply), searcher.nodes())
+}
+
+/// One side of the match
+struct Contestant {
+    name: String,
+    nnue_eval: NnueEvaluator,
+    search: SearchFn,
+    moves: u64,
+    nodes: u64,
+    search_ms: u128,
+}
+
+impl Contestant {
+    fn new(
+        name: impl Into<String>,
+        nnue_eval: NnueEvaluator,
+        search: impl Fn(&mut NnueEvaluator, &mut pleco::Board, u8, Option<u128>) -> (BitMove, u64)
+            + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            nnue_eval,
+            search: Box::new(search),
+            moves: 0,
+            nodes: 0,
+            search_ms: 0,
+        }
+    }
+
+    fn print_search_stats(&self) {
+        let moves = self.moves.max(1);
+        let nps = self.nodes as u128 * 1000 / self.search_ms.max(1);
+        println!(
+            "{}: {} moves, {} nodes per move, {} ms per move, {} nps",
+            self.name,
+            self.moves,
+            self.nodes / moves,
+            self.search_ms / moves as u128,
+            nps
+        );
+    }
+}
+
+fn load_network(path: &str) -> nnue::nnue::AnyNnue {
+    match nnue::nnue::load_any_nnue(path) {
+        Ok(network) => network,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// With `--net-a <path> --net-b <path>` both sides run the current search and only the
+/// network differs, B playing as "new". With `--no-history-tables` the current search plays
+/// itself without the counter-move, continuation and capture histories as "old", and with
+/// `--without-extension <check|singular|recapture|passed-pawn-push>` without that extension.
+/// Otherwise search_wip plays search on the shared network.
+fn contestants() -> (Contestant, Contestant) {
+    let args: Vec<String> = env::args().collect();
+    let arg = |flag: &str| {
+        args.iter()
+            .position(|a| a == flag)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+
+    match (arg("--net-a"), arg("--net-b")) {
+        (Some(net_a), Some(net_b)) => {
+            let old = Contestant::new(
+                format!("A ({})", net_a),
+                NnueEvaluator::with_network(load_network(&net_a)),
+                search,
+            );
+            let new = Contestant::new(
+                format!("B ({})", net_b),
+                NnueEvaluator::with_network(load_network(&net_b)),
+                search,
+            );
+            (new, old)
+        }
+        (None, None) => {
+            let network = match nnue::nnue::init_big_nnue() {
+                Ok(network) => network,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            let small = nnue::nnue::init_small_nnue().ok();
+            if args.iter().any(|a| a == "--no-history-tables") {
+                (
+                    Contestant::new(
+                        "History",
+                        NnueEvaluator::with_networks(network.clone(), small.clone()),
+                        search,
+                    ),
+                    Contestant::new(
+                        "No History",
+                        NnueEvaluator::with_networks(network, small),
+                        search_with(false, Extensions::ALL),
+                    ),
+                )
+            } else if let Some(name) = arg("--without-extension") {
+                let mut extensions = Extensions::ALL;
+                match name.as_str() {
+                    "check" => extensions.check = false,
+                    "singular" => extensions.singular = false,
+                    "recapture" => extensions.recapture = false,
+                    "passed-pawn-push" => extensions.passed_pawn_push = false,
+                    _ => {
+                        eprintln!("unknown extension {}, expected check, singular, recapture or passed-pawn-push", name);
+                        std::process::exit(1);
+                    }
+                }
+                (
+                    Contestant::new(
+                        "Extensions",
+                        NnueEvaluator::with_networks(network.clone(), small.clone()),
+                        search,
+                    ),
+                    Contestant::new(
+                        format!("No {} extension", name),
+                        NnueEvaluator::with_networks(network, small),
+                        search_with(true, extensions),
+                    ),
+                )
+            } else {
+                (
+                    Contestant::new(
+                        "New",
+                        NnueEvaluator::with_networks(network.clone(), small.clone()),
+                        search_wip,
+                    ),
+                    Contestant::new("Old", NnueEvaluator::with_networks(network, small), search),
+                )
+            }
+        }
+        _ => {
+            eprintln!("--net-a and --net-b must be given together");
+            std::process::exit(1);
+        }
+    }
+}
+
 #[allow(unused)]
 pub fn main() {
     const GAMES: usize = 30;
@@ -1179,7 +1367,11 @@ pub fn main() {
     const SEARCH_DEPTH: u8 = 255;
 
 
-    let mut nnue_eval = nnue::nnue::NnueEvaluator::new();
+    const PGN_OUT: &str = "match_games.pgn";
+    let mut pgn_out = File::create(PGN_OUT).expect("Failed to create PGN output");
+
+    let (mut new, mut old) = contestants();
+    println!("New = {}, Old = {}", new.name, old.name);
 
     for game in 10..GAMES {
         let mut board = pleco::Board::default();
@@ -1189,6 +1381,12 @@ pub fn main() {
         let mut elapsed_ms: f64 = 0f64;
         let mut turn_count = 0;
 
+        let mut pgn = PgnGame::new(Some(&board.fen()));
+        pgn.tag("Event", "Engine Match");
+        pgn.tag("Round", &(game + 1).to_string());
+        pgn.tag("White", if new_is_white { &new.name } else { &old.name });
+        pgn.tag("Black", if new_is_white { &old.name } else { &new.name });
+
         println!(
             "Game {}, New Playing as {}: Start FEN = {}",
             game + 1,
@@ -1206,19 +1404,19 @@ pub fn main() {
             let start = Instant::now();
             let white_to_move = board.turn() == pleco::Player::White;
             turn_count += 1;
-            let mv = if (white_to_move && new_is_white) || (!white_to_move && !new_is_white) {
-                // New engine to move
-                engine::search_wip::search_to_depth_and_time(&mut nnue_eval, &mut board, SEARCH_DEPTH, SEARCH_TIME)
-            } else {
-                // Old engine to move
-                // engine::search::search_to_depth_and_time(&mut nnue_eval, &mut board, SEARCH_DEPTH, SEARCH_TIME)
-                engine::search::search_to_depth_and_time(&mut nnue_eval, &mut board, SEARCH_DEPTH, SEARCH_TIME)
-            };
-            elapsed_ms += start.elapsed().as_millis() as f64;
+            let side = if white_to_move == new_is_white { &mut new } else { &mut old };
+            let (mv, nodes) =
+                (side.search)(&mut side.nnue_eval, &mut board, SEARCH_DEPTH, SEARCH_TIME);
+            let move_ms = start.elapsed().as_millis();
+            elapsed_ms += move_ms as f64;
+            side.moves += 1;
+            side.nodes += nodes;
+            side.search_ms += move_ms;
             if mv.is_null() {
                 break 'gameloop;
             }
             board.apply_move(mv);
+            pgn.push(mv);
         }
 
         total_avg_move_times += elapsed_ms / turn_count as f64;
@@ -1233,21 +1431,26 @@ pub fn main() {
             let str;
             // If new player is to move
             if white_to_move == new_is_white {
-                str = format!("Game {}: Old engine wins", game + 1);
+                str = format!("Game {}: {} wins", game + 1, old.name);
                 old_wins += 1.0;
             } else {
-                str = format!("Game {}: New engine wins", game + 1);
+                str = format!("Game {}: {} wins", game + 1, new.name);
                 new_wins += 1.0;
             }
             println!("{}", str);
 
             important.push(str);
+            pgn.set_result(if white_to_move { "0-1" } else { "1-0" });
         } else {
             important.push(format!("Game {}: Draw by stalemate", game + 1));
             println!("Game {}: Draw by stalemate", game + 1);
             total_draws += 1;
+            pgn.set_result("1/2-1/2");
         }
         important.push(format!("Game {}: End FEN = {}", game + 1, board.fen()));
+        pgn_out
+            .write_all(pgn.to_pgn().as_bytes())
+            .expect("Failed to write PGN");
 
         new_is_white = !new_is_white;
     }
@@ -1256,11 +1459,15 @@ pub fn main() {
     for line in important {
         println!("{}", line);
     }
+    println!("Games written to {}", PGN_OUT);
     println!("========= Final Scores =========");
     println!("Total Games: {}", GAMES);
-    println!("Old Wins: {}", old_wins);
-    println!("New Wins: {}", new_wins);
+    println!("{} Wins: {}", old.name, old_wins);
+    println!("{} Wins: {}", new.name, new_wins);
     println!("Draws: {}", total_draws);
+    println!("========= Search =========");
+    old.print_search_stats();
+    new.print_search_stats();
     let duration = start.elapsed();
     println!("Total Time: {} seconds", duration.as_secs());
     println!(
@@ -1,4 +1,4 @@
-use book::Book;
+use book::{explorer::ExplorerMove, Book};
 use pleco::BitMove;
 use serde::{Deserialize, Serialize};
 
@@ -7,23 +7,41 @@ use serde::{Deserialize, Serialize};
 pub enum ClientMessage {
     GetBestMove { fen: String, move_history: Vec<String> },
     GetBoardEval { fen: String },
+    ExploreOpening { fen: String },
+    GetPieceValues { fen: String },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum ServerMessage {
-    BestMove { best_move: String},
-    BoardEval { score: f64 },
+    BestMove { best_move: String, eco: Option<String>, opening: Option<String> },
+    BoardEval { score: f64, eco: Option<String>, opening: Option<String> },
+    OpeningExplorer {
+        fen: String,
+        moves: Vec<ExplorerMove>,
+        eco: Option<String>,
+        opening: Option<String>,
+    },
+    PieceValues { fen: String, eval: f64, pieces: Vec<PieceValue> },
     Error { message: String },
 }
+
+/// What the NNUE thinks a piece is worth, in pawns from white's point of view
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PieceValue {
+    pub square: String,
+    pub piece: String,
+    pub value: f64,
+}
 use axum::{
     extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    http::StatusCode,
     response::IntoResponse,
     routing::get,
-    Router,
+    Json, Router,
 };
 use futures::StreamExt;
-use std::{env, net::SocketAddr};
+use std::{env, net::SocketAddr, sync::OnceLock};
 use tower_http::cors::{Any, CorsLayer};
 
 
@@ -45,7 +63,10 @@ async fn backend() {
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let app = Router::new().route("/ws", get(ws_handler)).layer(cors);
+    let app = Router::new()
+        .route("/ws", get(ws_handler))
+        .route("/health", get(health_handler))
+        .layer(cors);
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
@@ -53,7 +74,34 @@ async fn backend() {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// Why the network failed to load, when the server was started without one
+static NNUE_ERROR: OnceLock<String> = OnceLock::new();
+
+/// Load the evaluation network before serving. A server that can't evaluate refuses to
+/// start, unless ALLOW_MISSING_NNUE is set, in which case it reports unhealthy instead.
+fn init_nnue() {
+    match nnue::nnue::init_big_nnue() {
+        Ok(network) => {
+            println!("Loaded NNUE: {}", network.description());
+            match nnue::nnue::init_small_nnue() {
+                Ok(small) => println!("Loaded small NNUE: {}", small.description()),
+                Err(e) => println!("Evaluating with the big NNUE only: {}", e),
+            }
+        }
+        Err(e) if env::var("ALLOW_MISSING_NNUE").is_ok() => {
+            eprintln!("Starting without NNUE: {}", e);
+            let _ = NNUE_ERROR.set(e.to_string());
+        }
+        Err(e) => {
+            eprintln!("Failed to load NNUE: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
+    init_nnue();
+
     let rt = tokio::runtime::Builder::new_multi_thread()
         .worker_threads(4) // 4 worker threads
         .thread_stack_size(3 * 1024 * 1024) // Set stack size to 3 MiB
@@ -67,6 +115,33 @@ fn main() {
     });
 }
 
+async fn health_handler() -> impl IntoResponse {
+    match nnue::nnue::big_nnue() {
+        Some(network) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "status": "ok",
+                "nnue": network.description(),
+                "small_nnue": nnue::nnue::small_nnue().map(|small| small.description().to_string()),
+            })),
+        ),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "status": "unhealthy",
+                "error": NNUE_ERROR.get().map_or("NNUE not loaded", |e| e.as_str()),
+            })),
+        ),
+    }
+}
+
+/// Error reply for requests that need the engine while no network is loaded
+fn nnue_unavailable() -> Option<ServerMessage> {
+    nnue::nnue::big_nnue().is_none().then(|| ServerMessage::Error {
+        message: "Evaluation network is not loaded".to_string(),
+    })
+}
+
 async fn ws_handler(ws: WebSocketUpgrade) -> impl IntoResponse {
     ws.on_upgrade(handle_socket)
 }
@@ -86,6 +161,14 @@ fn check_fens(fen1: &str, fen2: &str) -> bool {
     true
 }
 
+/// ECO code and opening name for the game on `board`, if it is a known opening
+fn classify_opening(board: &pleco::Board) -> (Option<String>, Option<String>) {
+    match book::eco::classify(board) {
+        Some((eco, name)) => (Some(eco.to_string()), Some(name.to_string())),
+        None => (None, None),
+    }
+}
+
 fn try_book_move(board: &mut pleco::Board, fen: &str) -> Option<BitMove> {
     let mut book_opt: Option<BitMove> = None;
     if board.moves_played() <= 10 {
@@ -105,7 +188,38 @@ fn try_book_move(board: &mut pleco::Board, fen: &str) -> Option<BitMove> {
     book_opt
 }
 
+/// Piece values of `fen`. The evaluator is made on the connection's first request.
+fn piece_values(evaluator: &mut Option<nnue::nnue::NnueEvaluator>, fen: String) -> ServerMessage {
+    let board = match pleco::Board::from_fen(&fen) {
+        Ok(board) => board,
+        Err(e) => return ServerMessage::Error { message: format!("Invalid FEN {}: {:?}", fen, e) },
+    };
+    if evaluator.is_none() {
+        match nnue::nnue::NnueEvaluator::try_new() {
+            Ok(new) => *evaluator = Some(new),
+            Err(e) => return ServerMessage::Error { message: format!("Evaluation network is not loaded: {}", e) },
+        }
+    }
+    let evaluator = evaluator.as_mut().unwrap();
+    evaluator.reset(&board);
+    let values = evaluator.piece_values(&board);
+    let pieces = values
+        .pawns(&board)
+        .map(|(sq, piece, value)| PieceValue {
+            square: sq.to_string(),
+            piece: piece.character_lossy().to_string(),
+            value,
+        })
+        .collect();
+    ServerMessage::PieceValues { eval: values.eval_pawns(&board), pieces, fen }
+}
+
+async fn send(socket: &mut WebSocket, msg: &ServerMessage) -> Result<(), axum::Error> {
+    socket.send(Message::Text(serde_json::to_string(msg).unwrap().into())).await
+}
+
 async fn handle_socket(mut socket: WebSocket) {
+    let mut evaluator = None;
     while let Some(Ok(msg)) = socket.next().await {
         if let Message::Text(text) = msg {
             // Parse message
@@ -130,8 +244,7 @@ async fn handle_socket(mut socket: WebSocket) {
                         let err = ServerMessage::Error {
                             message: format!("FEN and Move History do not match"),
                         };
-                        let err_text = serde_json::to_string(&err).unwrap();
-                        if socket.send(Message::Text(err_text.into())).await.is_err() {
+                        if send(&mut socket, &err).await.is_err() {
                             break;
                         }
                         continue;
@@ -141,25 +254,68 @@ async fn handle_socket(mut socket: WebSocket) {
 
                     let mv = match book_opt {
                         Some(bm) => bm,
-                        None => engine::search::start_search(&mut board),
+                        None => {
+                            if let Some(err) = nnue_unavailable() {
+                                if send(&mut socket, &err).await.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            engine::search::start_search(&mut board)
+                        }
                     };
 
+                    // Book moves are already applied to the board
+                    if book_opt.is_none() && !mv.is_null() {
+                        board.apply_move(mv);
+                    }
+                    let (eco, opening) = classify_opening(&board);
                     let best_move = ServerMessage::BestMove {
                         best_move: mv.to_string(),
+                        eco,
+                        opening,
                     };
 
-                    let resp_text = serde_json::to_string(&best_move).unwrap();
-                    if socket.send(Message::Text(resp_text.into())).await.is_err() {
+                    if send(&mut socket, &best_move).await.is_err() {
                         break;
                     }
                 }
                 Ok(ClientMessage::GetBoardEval { fen }) => {
                     println!("Received FEN for eval: {}", fen);
+                    if let Some(err) = nnue_unavailable() {
+                        if send(&mut socket, &err).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
                     let mut board = pleco::Board::from_fen(&fen).expect("Board Fen Create Failed");
                     let score = engine::search::eval_search(&mut board);
-                    let eval = ServerMessage::BoardEval { score };
+                    let (eco, opening) = classify_opening(&board);
+                    let eval = ServerMessage::BoardEval { score, eco, opening };
+
+                    if send(&mut socket, &eval).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(ClientMessage::ExploreOpening { fen }) => {
+                    let (eco, opening) = match pleco::Board::from_fen(&fen) {
+                        Ok(board) => classify_opening(&board),
+                        Err(_) => (None, None),
+                    };
+                    let explorer = ServerMessage::OpeningExplorer {
+                        moves: book::explorer::explore(&BOOK, &fen),
+                        fen,
+                        eco,
+                        opening,
+                    };
 
-                    if socket.send(Message::Text(serde_json::to_string(&eval).unwrap().into())).await.is_err() {
+                    if send(&mut socket, &explorer).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(ClientMessage::GetPieceValues { fen }) => {
+                    let resp = piece_values(&mut evaluator, fen);
+                    if send(&mut socket, &resp).await.is_err() {
                         break;
                     }
                 }
@@ -167,8 +323,7 @@ async fn handle_socket(mut socket: WebSocket) {
                     let err = ServerMessage::Error {
                         message: format!("Invalid message: {}", e),
                     };
-                    let err_text = serde_json::to_string(&err).unwrap();
-                    if socket.send(Message::Text(err_text.into())).await.is_err() {
+                    if send(&mut socket, &err).await.is_err() {
                         break;
                     }
                 }
@@ -0,0 +1,102 @@
+//! Training positions with their features worked out once up front
+
+use nnue::architecture::FeatureSet;
+use nnue::data::Sample;
+use nnue::nnue_utils;
+use pleco::{Board, Player};
+use rayon::prelude::*;
+
+use crate::network::Features;
+
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    /// Start of the side to move's features, the other side's follow them
+    start: usize,
+    stm_len: u8,
+    other_len: u8,
+    bucket: u8,
+    /// Search score in internal units from the side to move's point of view
+    score: f32,
+    /// 1 for a win of the side to move, 0.5 for a draw and 0 for a loss
+    result: f32,
+}
+
+#[derive(Default)]
+pub struct Dataset {
+    features: Vec<u16>,
+    positions: Vec<Position>,
+}
+
+impl Dataset {
+    /// Parse every sample and collect its features, in parallel
+    pub fn from_samples(samples: &[Sample], feature_set: FeatureSet) -> Result<Self, String> {
+        let parts = samples
+            .par_chunks(4096)
+            .map(|chunk| {
+                let mut part = Dataset::default();
+                for sample in chunk {
+                    part.push(sample, feature_set)?;
+                }
+                Ok(part)
+            })
+            .collect::<Result<Vec<Dataset>, String>>()?;
+
+        let mut dataset = Dataset::default();
+        for part in parts {
+            let offset = dataset.features.len();
+            dataset.features.extend_from_slice(&part.features);
+            dataset.positions.extend(part.positions.iter().map(|p| Position { start: p.start + offset, ..*p }));
+        }
+        Ok(dataset)
+    }
+
+    fn push(&mut self, sample: &Sample, feature_set: FeatureSet) -> Result<(), String> {
+        let board = Board::from_fen(&sample.fen).map_err(|e| format!("bad FEN {}: {:?}", sample.fen, e))?;
+        let stm = board.turn();
+        let stm_features = feature_set.active_features(&board, stm);
+        let other_features = feature_set.active_features(&board, !stm);
+
+        let start = self.features.len();
+        self.features.extend(stm_features.iter().chain(&other_features).map(|&f| f as u16));
+        let score = if stm == Player::White { sample.score } else { -sample.score };
+        self.positions.push(Position {
+            start,
+            stm_len: stm_features.len() as u8,
+            other_len: other_features.len() as u8,
+            bucket: (board.count_all_pieces() - 1) / 4,
+            score: nnue_utils::from_cp(f32::from(score), &board),
+            result: f32::from(sample.result.half_points(stm)) / 2.0,
+        });
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn features(&self, i: usize) -> Features<'_> {
+        let p = &self.positions[i];
+        let stm_end = p.start + usize::from(p.stm_len);
+        Features {
+            perspectives: [
+                &self.features[p.start..stm_end],
+                &self.features[stm_end..stm_end + usize::from(p.other_len)],
+            ],
+            bucket: usize::from(p.bucket),
+        }
+    }
+
+    /// Search score of position `i` in internal units, from the side to move's point of view
+    pub fn score(&self, i: usize) -> f32 {
+        self.positions[i].score
+    }
+
+    /// Game result of position `i` for the side to move, 0 to 1
+    pub fn result(&self, i: usize) -> f32 {
+        self.positions[i].result
+    }
+}
@@ -0,0 +1,6 @@
+//! Trains networks of the architectures `nnue` runs on the CPU, from the samples written by
+//! datagen, and quantizes them into `.nnue` files.
+
+pub mod dataset;
+pub mod network;
+pub mod train;
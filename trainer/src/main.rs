@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::time::Instant;
+
+use clap::{Parser, ValueEnum};
+use nnue::architecture::{FeatureSet, SUPPORTED_ARCHITECTURES};
+use nnue::data::{DataFormat, read_samples};
+use nnue::nnue::AnyNnue;
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use trainer::dataset::Dataset;
+use trainer::network::{Adam, Network};
+use trainer::train::{Loss, mean_loss, train_epoch};
+
+#[derive(Parser)]
+#[command(about = "Train a network on datagen samples and write it as .nnue")]
+struct Cli {
+    /// Sample files written by datagen
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    #[arg(short, long, value_enum, default_value_t = FormatArg::Text)]
+    format: FormatArg,
+    /// Where to write the network, after every epoch
+    #[arg(short, long, default_value = "trained.nnue")]
+    out: String,
+    #[arg(long, value_enum, default_value_t = FeaturesArg::HalfKp)]
+    features: FeaturesArg,
+    /// Train the 3072 wide transformer instead of the 128 wide one, needs a few GB of memory
+    #[arg(long)]
+    big: bool,
+    #[arg(short, long, default_value_t = 20)]
+    epochs: usize,
+    #[arg(short, long, default_value_t = 16384)]
+    batch_size: usize,
+    /// Adam learning rate
+    #[arg(long, default_value_t = 1e-3)]
+    lr: f32,
+    /// The learning rate is multiplied by this after every epoch
+    #[arg(long, default_value_t = 0.95)]
+    lr_gamma: f32,
+    /// Weight of the search score in the target, the game result gets the rest
+    #[arg(long, default_value_t = 0.75)]
+    lambda: f32,
+    /// Internal evaluation units, 208 to a pawn, for which the win probability is sigmoid(1).
+    /// The centipawn scores of the samples are converted to them.
+    #[arg(long, default_value_t = 400.0)]
+    scale: f32,
+    /// Share of the samples held out for the validation loss
+    #[arg(long, default_value_t = 0.05)]
+    validation: f64,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Description stored in the network file
+    #[arg(long, default_value = "trained with chessBackend's trainer")]
+    desc: String,
+    /// Worker threads, defaults to the number of cores
+    #[arg(short, long)]
+    threads: Option<usize>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FormatArg {
+    Text,
+    Bullet,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FeaturesArg {
+    #[value(name = "halfkp")]
+    HalfKp,
+    #[value(name = "halfka")]
+    HalfKa,
+}
+
+fn main() {
+    if let Err(e) = run(&Cli::parse()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), String> {
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Failed to configure thread pool");
+    }
+
+    let feature_set = match cli.features {
+        FeaturesArg::HalfKp => FeatureSet::HalfKP,
+        FeaturesArg::HalfKa => FeatureSet::HalfKAv2Hm,
+    };
+    let dims = if cli.big { 3072 } else { 128 };
+    let arch = SUPPORTED_ARCHITECTURES
+        .iter()
+        .copied()
+        .find(|arch| arch.feature_set == feature_set && arch.transformed_dims == dims)
+        .ok_or_else(|| format!("no supported {} architecture {} wide", feature_set.name(), dims))?;
+    let format = match cli.format {
+        FormatArg::Text => DataFormat::Text,
+        FormatArg::Bullet => DataFormat::Bullet,
+    };
+
+    let start = Instant::now();
+    let mut samples = Vec::new();
+    for input in &cli.inputs {
+        let file = File::open(input).map_err(|e| format!("failed to open {}: {}", input, e))?;
+        let read = read_samples(BufReader::new(file), format).map_err(|e| format!("failed to read {}: {}", input, e))?;
+        samples.extend(read);
+    }
+    let data = Dataset::from_samples(&samples, feature_set)?;
+    drop(samples);
+    if data.is_empty() {
+        return Err("no samples to train on".to_string());
+    }
+
+    let mut rng = StdRng::seed_from_u64(cli.seed);
+    let mut indices: Vec<usize> = (0..data.len()).collect();
+    indices.shuffle(&mut rng);
+    let validation_len = (data.len() as f64 * cli.validation) as usize;
+    let (validation, training) = indices.split_at_mut(validation_len);
+    println!(
+        "Loaded {} samples in {:.1}s, training on {} and validating on {}",
+        data.len(),
+        start.elapsed().as_secs_f64(),
+        training.len(),
+        validation.len()
+    );
+    println!("{}", arch);
+
+    let loss = Loss { scale: cli.scale, lambda: cli.lambda };
+    let mut net = Network::new(arch, &mut rng);
+    let mut adam = Adam::new(arch, cli.lr);
+    if !validation.is_empty() {
+        println!("Epoch 0: validation loss {:.6}", mean_loss(&net, &data, validation, loss));
+    }
+
+    for epoch in 1..=cli.epochs {
+        let epoch_start = Instant::now();
+        let train_loss = train_epoch(&mut net, &mut adam, &data, training, cli.batch_size, loss, &mut rng);
+        let validation_loss = if validation.is_empty() {
+            "n/a".to_string()
+        } else {
+            format!("{:.6}", mean_loss(&net, &data, validation, loss))
+        };
+        let seconds = epoch_start.elapsed().as_secs_f64();
+        println!(
+            "Epoch {}: train loss {:.6}, validation loss {}, lr {:.2e}, {:.1}s, {:.0} positions/s",
+            epoch,
+            train_loss,
+            validation_loss,
+            adam.lr,
+            seconds,
+            training.len() as f64 / seconds
+        );
+
+        AnyNnue::from_quantized(&net.quantize(&cli.desc)).save(&cli.out).map_err(|e| e.to_string())?;
+        adam.lr *= cli.lr_gamma;
+    }
+    println!("Wrote {} after {:.0}s", cli.out, start.elapsed().as_secs_f64());
+    Ok(())
+}
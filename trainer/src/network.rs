@@ -0,0 +1,631 @@
+//! The float network the trainer works on. It computes what the integer layers in `nnue` do,
+//! with the same clamps and with the scales of the quantized values folded in, so quantizing it
+//! changes the evaluation by rounding only.
+
+use std::collections::HashMap;
+
+use nnue::architecture::{Architecture, SF_BIG};
+use nnue::nnue::{QuantizedAffine, QuantizedLayerStack, QuantizedNetwork};
+use pleco::{Piece, PieceType, Player};
+use rand::{Rng, rngs::StdRng};
+
+/// One, in the transformed features and the hidden activations
+const QUANTIZED_ONE: f32 = 127.0;
+/// One, in the weights of the hidden layers (1 << WEIGHT_SCALE_BITS)
+const WEIGHT_SCALE: f32 = 64.0;
+/// Evaluation units per unit of network output, Stockfish's `nnue2score`
+pub const NNUE_TO_SCORE: f32 = 600.0;
+/// The integer output is divided by this to get evaluation units
+const OUTPUT_SCALE: f32 = 16.0;
+/// The transformer multiplies pairs of 0..=254 and divides by 512, one times one is 127/128
+const PAIRWISE_SCALE: f32 = 127.0 / 128.0;
+/// Squares are shifted right by 19, one squared is 126/127, close enough to 127/128
+const SQUARE_SCALE: f32 = 127.0 / 128.0;
+/// Largest hidden layer weight that fits an i8
+const MAX_HIDDEN_WEIGHT: f32 = 127.0 / WEIGHT_SCALE;
+/// Largest output layer weight that fits an i8
+const MAX_OUTPUT_WEIGHT: f32 = 127.0 * QUANTIZED_ONE / (NNUE_TO_SCORE * OUTPUT_SCALE);
+
+/// Stockfish's piece values, the starting point of the PSQT weights
+const PIECE_VALUES: [(PieceType, f32); 5] = [
+    (PieceType::P, 208.0),
+    (PieceType::N, 781.0),
+    (PieceType::B, 825.0),
+    (PieceType::R, 1276.0),
+    (PieceType::Q, 2538.0),
+];
+
+#[derive(Clone, Debug)]
+pub struct Affine {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// One row of inputs per output
+    pub weights: Vec<f32>,
+    pub biases: Vec<f32>,
+}
+
+impl Affine {
+    fn zeros(inputs: usize, outputs: usize) -> Self {
+        Affine { inputs, outputs, weights: vec![0.0; inputs * outputs], biases: vec![0.0; outputs] }
+    }
+
+    /// PyTorch's default initialization, uniform in +-1/sqrt(inputs)
+    fn random(inputs: usize, outputs: usize, rng: &mut StdRng) -> Self {
+        let bound = 1.0 / (inputs as f32).sqrt();
+        let mut layer = Self::zeros(inputs, outputs);
+        for w in &mut layer.weights {
+            *w = rng.random_range(-bound..bound);
+        }
+        layer
+    }
+
+    fn forward(&self, input: &[f32], output: &mut [f32]) {
+        for (o, out) in output.iter_mut().enumerate() {
+            let row = &self.weights[o * self.inputs..(o + 1) * self.inputs];
+            *out = self.biases[o] + row.iter().zip(input).map(|(w, x)| w * x).sum::<f32>();
+        }
+    }
+
+    /// Add the gradients of this layer for `d_output` to `grad` and write the gradient of the input
+    fn backward(&self, input: &[f32], d_output: &[f32], grad: &mut Affine, d_input: &mut [f32]) {
+        d_input.fill(0.0);
+        for (o, &d) in d_output.iter().enumerate() {
+            if d == 0.0 {
+                continue;
+            }
+            grad.biases[o] += d;
+            let row = o * self.inputs..(o + 1) * self.inputs;
+            for ((g, &x), (di, &w)) in grad.weights[row.clone()]
+                .iter_mut()
+                .zip(input)
+                .zip(d_input.iter_mut().zip(&self.weights[row]))
+            {
+                *g += d * x;
+                *di += d * w;
+            }
+        }
+    }
+
+    fn clip_weights(&mut self, max: f32) {
+        for w in &mut self.weights {
+            *w = w.clamp(-max, max);
+        }
+    }
+
+    fn quantize(&self, weight_scale: f32, bias_scale: f32) -> QuantizedAffine {
+        QuantizedAffine {
+            biases: self.biases.iter().map(|b| (b * bias_scale).round() as i32).collect(),
+            weights: self.weights.iter().map(|w| (w * weight_scale).round().clamp(-128.0, 127.0) as i8).collect(),
+        }
+    }
+}
+
+/// The three affine layers picked by piece count
+#[derive(Clone, Debug)]
+pub struct LayerStack {
+    pub fc0: Affine,
+    pub fc1: Affine,
+    pub fc2: Affine,
+}
+
+impl LayerStack {
+    fn affines_mut(&mut self) -> [&mut Affine; 3] {
+        [&mut self.fc0, &mut self.fc1, &mut self.fc2]
+    }
+}
+
+/// Inputs of one position: the active features of the side to move and of the other side,
+/// and the bucket of its piece count
+#[derive(Clone, Copy, Debug)]
+pub struct Features<'a> {
+    pub perspectives: [&'a [u16]; 2],
+    pub bucket: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Network {
+    pub arch: &'static Architecture,
+    /// One row of `transformed_dims` weights per input feature
+    pub ft_weights: Vec<f32>,
+    pub ft_biases: Vec<f32>,
+    /// One row of `psqt_buckets` weights per input feature
+    pub psqt_weights: Vec<f32>,
+    pub layer_stacks: Vec<LayerStack>,
+}
+
+impl Network {
+    /// A network of zeros, e.g. for the moments of an optimizer
+    pub fn zeros(arch: &'static Architecture) -> Self {
+        assert!(
+            arch.layers == SF_BIG.layers,
+            "{} has layers the trainer doesn't know",
+            arch.name
+        );
+        assert!(arch.feature_set.input_dims() <= usize::from(u16::MAX) + 1);
+        let input_dims = arch.feature_set.input_dims();
+        Network {
+            arch,
+            ft_weights: vec![0.0; input_dims * arch.transformed_dims],
+            ft_biases: vec![0.0; arch.transformed_dims],
+            psqt_weights: vec![0.0; input_dims * arch.psqt_buckets],
+            layer_stacks: zero_layer_stacks(arch),
+        }
+    }
+
+    /// Random weights that keep most transformer outputs off their clamps, and PSQT weights
+    /// that count material
+    pub fn new(arch: &'static Architecture, rng: &mut StdRng) -> Self {
+        let mut net = Self::zeros(arch);
+        for w in &mut net.ft_weights {
+            *w = rng.random_range(-0.1..0.1);
+        }
+        net.ft_biases.fill(0.5);
+        for stack in &mut net.layer_stacks {
+            for layer in stack.affines_mut() {
+                *layer = Affine::random(layer.inputs, layer.outputs, rng);
+            }
+        }
+
+        // Every feature is some piece on some square, seen from white with the king on any
+        // square. Own pieces count for the side to move, the others against it.
+        let buckets = arch.psqt_buckets;
+        for king_sq in 0..64u8 {
+            for sq in 0..64u8 {
+                for player in [Player::White, Player::Black] {
+                    for &(pt, value) in &PIECE_VALUES {
+                        let piece = Piece::make_lossy(player, pt) as usize;
+                        let feature = arch.feature_set.make_index(0, sq, piece, king_sq);
+                        let sign = if player == Player::White { 1.0 } else { -1.0 };
+                        net.psqt_weights[feature * buckets..(feature + 1) * buckets]
+                            .fill(sign * value / NNUE_TO_SCORE);
+                    }
+                }
+            }
+        }
+        net
+    }
+
+    /// Output for one position, in units of `NNUE_TO_SCORE` evaluation units. `trace` keeps what
+    /// `backward` needs.
+    pub fn forward(&self, features: Features, trace: &mut Trace) -> f32 {
+        let l1 = self.arch.transformed_dims;
+        let half = l1 / 2;
+        let buckets = self.arch.psqt_buckets;
+
+        let mut psqt = [0.0; 2];
+        for (p, active) in features.perspectives.iter().enumerate() {
+            let acc = &mut trace.accumulators[p];
+            acc.copy_from_slice(&self.ft_biases);
+            for &f in *active {
+                let f = usize::from(f);
+                for (a, w) in acc.iter_mut().zip(&self.ft_weights[f * l1..(f + 1) * l1]) {
+                    *a += w;
+                }
+                psqt[p] += self.psqt_weights[f * buckets + features.bucket];
+            }
+            for j in 0..half {
+                trace.transformed[p * half + j] = acc[j].clamp(0.0, 1.0) * acc[j + half].clamp(0.0, 1.0) * PAIRWISE_SCALE;
+            }
+        }
+
+        let stack = &self.layer_stacks[features.bucket];
+        stack.fc0.forward(&trace.transformed, &mut trace.fc0_out);
+        let l2 = stack.fc0.outputs - 1;
+        for i in 0..l2 {
+            let z = trace.fc0_out[i];
+            trace.fc1_in[i] = (z * z * SQUARE_SCALE).min(1.0);
+            trace.fc1_in[i + l2] = z.clamp(0.0, 1.0);
+        }
+        stack.fc1.forward(&trace.fc1_in, &mut trace.fc1_out);
+        for (h, u) in trace.fc2_in.iter_mut().zip(&trace.fc1_out) {
+            *h = u.clamp(0.0, 1.0);
+        }
+        let mut positional = [0.0];
+        stack.fc2.forward(&trace.fc2_in, &mut positional);
+
+        // fc0's last output skips the rest of the stack
+        positional[0] + trace.fc0_out[l2] + (psqt[0] - psqt[1]) / 2.0
+    }
+
+    /// Add the gradients for `d_output`, the derivative of the loss by the output of the last
+    /// `forward`, to `grad`
+    pub fn backward(&self, features: Features, trace: &mut Trace, d_output: f32, grad: &mut Gradients) {
+        let l1 = self.arch.transformed_dims;
+        let half = l1 / 2;
+        let buckets = self.arch.psqt_buckets;
+        let stack = &self.layer_stacks[features.bucket];
+        let stack_grad = &mut grad.layer_stacks[features.bucket];
+
+        stack.fc2.backward(&trace.fc2_in, &[d_output], &mut stack_grad.fc2, &mut trace.d_fc2_in);
+        for (d, &u) in trace.d_fc2_in.iter_mut().zip(&trace.fc1_out) {
+            if u <= 0.0 || u >= 1.0 {
+                *d = 0.0;
+            }
+        }
+        stack.fc1.backward(&trace.fc1_in, &trace.d_fc2_in, &mut stack_grad.fc1, &mut trace.d_fc1_in);
+
+        let l2 = stack.fc0.outputs - 1;
+        for i in 0..l2 {
+            let z = trace.fc0_out[i];
+            let d_square = if z * z * SQUARE_SCALE < 1.0 { 2.0 * z * SQUARE_SCALE } else { 0.0 };
+            let d_clamp = if z > 0.0 && z < 1.0 { 1.0 } else { 0.0 };
+            trace.d_fc0_out[i] = trace.d_fc1_in[i] * d_square + trace.d_fc1_in[i + l2] * d_clamp;
+        }
+        trace.d_fc0_out[l2] = d_output;
+        stack.fc0.backward(&trace.transformed, &trace.d_fc0_out, &mut stack_grad.fc0, &mut trace.d_transformed);
+
+        for (p, active) in features.perspectives.iter().enumerate() {
+            let acc = &trace.accumulators[p];
+            let d_acc = &mut trace.d_accumulator;
+            for j in 0..half {
+                let d = trace.d_transformed[p * half + j] * PAIRWISE_SCALE;
+                let (x0, x1) = (acc[j], acc[j + half]);
+                d_acc[j] = if x0 > 0.0 && x0 < 1.0 { d * x1.clamp(0.0, 1.0) } else { 0.0 };
+                d_acc[j + half] = if x1 > 0.0 && x1 < 1.0 { d * x0.clamp(0.0, 1.0) } else { 0.0 };
+            }
+            for (g, d) in grad.ft_biases.iter_mut().zip(d_acc.iter()) {
+                *g += d;
+            }
+
+            // The PSQT term is half the difference between the perspectives
+            let d_psqt = if p == 0 { d_output / 2.0 } else { -d_output / 2.0 };
+            for &f in *active {
+                let row = grad.row(f);
+                for (g, d) in grad.ft_rows[row * l1..(row + 1) * l1].iter_mut().zip(d_acc.iter()) {
+                    *g += d;
+                }
+                grad.psqt_rows[row * buckets + features.bucket] += d_psqt;
+            }
+        }
+    }
+
+    /// Keep the layer weights within what their integer types hold
+    pub fn clip_weights(&mut self) {
+        for stack in &mut self.layer_stacks {
+            stack.fc0.clip_weights(MAX_HIDDEN_WEIGHT);
+            stack.fc1.clip_weights(MAX_HIDDEN_WEIGHT);
+            stack.fc2.clip_weights(MAX_OUTPUT_WEIGHT);
+        }
+    }
+
+    /// The integer parameters `nnue` loads, see `QuantizedNetwork` for the scales
+    pub fn quantize(&self, desc: &str) -> QuantizedNetwork {
+        // The loader doubles the transformer parameters, they have to fit an i16 after that
+        let max_ft = f32::from(i16::MAX / 2);
+        let ft = |x: &f32| (x * QUANTIZED_ONE).round().clamp(-max_ft, max_ft) as i16;
+        let output_scale = NNUE_TO_SCORE * OUTPUT_SCALE;
+
+        QuantizedNetwork {
+            arch: self.arch,
+            desc: desc.to_string(),
+            ft_biases: self.ft_biases.iter().map(ft).collect(),
+            ft_weights: self.ft_weights.iter().map(ft).collect(),
+            psqt_weights: self.psqt_weights.iter().map(|w| (w * output_scale).round() as i32).collect(),
+            layer_stacks: self
+                .layer_stacks
+                .iter()
+                .map(|stack| QuantizedLayerStack {
+                    fc0: stack.fc0.quantize(WEIGHT_SCALE, WEIGHT_SCALE * QUANTIZED_ONE),
+                    fc1: stack.fc1.quantize(WEIGHT_SCALE, WEIGHT_SCALE * QUANTIZED_ONE),
+                    fc2: stack.fc2.quantize(output_scale / QUANTIZED_ONE, output_scale),
+                })
+                .collect(),
+        }
+    }
+
+    /// The parameters trained densely, everything but the rows of the feature weights
+    fn dense_mut(&mut self) -> impl Iterator<Item = &mut Vec<f32>> {
+        std::iter::once(&mut self.ft_biases).chain(
+            self.layer_stacks
+                .iter_mut()
+                .flat_map(|stack| stack.affines_mut())
+                .flat_map(|layer| [&mut layer.weights, &mut layer.biases]),
+        )
+    }
+}
+
+fn zero_layer_stacks(arch: &Architecture) -> Vec<LayerStack> {
+    let (l1, l2, l3) = (arch.transformed_dims, arch.affine_outputs(0), arch.affine_outputs(1));
+    let stack = LayerStack {
+        fc0: Affine::zeros(l1, l2),
+        fc1: Affine::zeros((l2 - 1) * 2, l3),
+        fc2: Affine::zeros(l3, 1),
+    };
+    vec![stack; arch.layer_stacks]
+}
+
+/// Activations of one forward pass and scratch space for the backward pass
+pub struct Trace {
+    accumulators: [Vec<f32>; 2],
+    transformed: Vec<f32>,
+    fc0_out: Vec<f32>,
+    fc1_in: Vec<f32>,
+    fc1_out: Vec<f32>,
+    fc2_in: Vec<f32>,
+    d_accumulator: Vec<f32>,
+    d_transformed: Vec<f32>,
+    d_fc0_out: Vec<f32>,
+    d_fc1_in: Vec<f32>,
+    d_fc2_in: Vec<f32>,
+}
+
+impl Trace {
+    pub fn new(arch: &Architecture) -> Self {
+        let (l1, l2, l3) = (arch.transformed_dims, arch.affine_outputs(0), arch.affine_outputs(1));
+        Trace {
+            accumulators: [vec![0.0; l1], vec![0.0; l1]],
+            transformed: vec![0.0; l1],
+            fc0_out: vec![0.0; l2],
+            fc1_in: vec![0.0; (l2 - 1) * 2],
+            fc1_out: vec![0.0; l3],
+            fc2_in: vec![0.0; l3],
+            d_accumulator: vec![0.0; l1],
+            d_transformed: vec![0.0; l1],
+            d_fc0_out: vec![0.0; l2],
+            d_fc1_in: vec![0.0; (l2 - 1) * 2],
+            d_fc2_in: vec![0.0; l3],
+        }
+    }
+}
+
+/// Gradients of a batch. Only the feature weight rows of features in the batch are kept, in
+/// the order they were first seen.
+pub struct Gradients {
+    ft_biases: Vec<f32>,
+    layer_stacks: Vec<LayerStack>,
+    rows: HashMap<u16, usize>,
+    features: Vec<u16>,
+    ft_rows: Vec<f32>,
+    psqt_rows: Vec<f32>,
+    arch: &'static Architecture,
+}
+
+impl Gradients {
+    pub fn new(arch: &'static Architecture) -> Self {
+        Gradients {
+            ft_biases: vec![0.0; arch.transformed_dims],
+            layer_stacks: zero_layer_stacks(arch),
+            rows: HashMap::new(),
+            features: Vec::new(),
+            ft_rows: Vec::new(),
+            psqt_rows: Vec::new(),
+            arch,
+        }
+    }
+
+    /// Row of `feature` in `ft_rows` and `psqt_rows`, added as zeros the first time
+    fn row(&mut self, feature: u16) -> usize {
+        let next = self.features.len();
+        let row = *self.rows.entry(feature).or_insert(next);
+        if row == next {
+            self.features.push(feature);
+            self.ft_rows.resize(self.ft_rows.len() + self.arch.transformed_dims, 0.0);
+            self.psqt_rows.resize(self.psqt_rows.len() + self.arch.psqt_buckets, 0.0);
+        }
+        row
+    }
+
+    /// Same order as `Network::dense_mut`
+    fn dense(&self) -> impl Iterator<Item = &Vec<f32>> {
+        std::iter::once(&self.ft_biases).chain(
+            self.layer_stacks
+                .iter()
+                .flat_map(|stack| [&stack.fc0, &stack.fc1, &stack.fc2])
+                .flat_map(|layer| [&layer.weights, &layer.biases]),
+        )
+    }
+
+    /// Add the gradients of another part of the batch
+    pub fn merge(&mut self, other: &Gradients) {
+        let (l1, buckets) = (self.arch.transformed_dims, self.arch.psqt_buckets);
+        add(&mut self.ft_biases, &other.ft_biases);
+        for (stack, other_stack) in self.layer_stacks.iter_mut().zip(&other.layer_stacks) {
+            let others = [&other_stack.fc0, &other_stack.fc1, &other_stack.fc2];
+            for (layer, other_layer) in stack.affines_mut().into_iter().zip(others) {
+                add(&mut layer.weights, &other_layer.weights);
+                add(&mut layer.biases, &other_layer.biases);
+            }
+        }
+        for (other_row, &feature) in other.features.iter().enumerate() {
+            let row = self.row(feature);
+            add(&mut self.ft_rows[row * l1..(row + 1) * l1], &other.ft_rows[other_row * l1..(other_row + 1) * l1]);
+            add(
+                &mut self.psqt_rows[row * buckets..(row + 1) * buckets],
+                &other.psqt_rows[other_row * buckets..(other_row + 1) * buckets],
+            );
+        }
+    }
+}
+
+fn add(to: &mut [f32], from: &[f32]) {
+    for (t, f) in to.iter_mut().zip(from) {
+        *t += f;
+    }
+}
+
+/// Adam, updating the rows of feature weights only for features in the batch
+pub struct Adam {
+    pub lr: f32,
+    beta1: f32,
+    beta2: f32,
+    epsilon: f32,
+    steps: i32,
+    m: Network,
+    v: Network,
+}
+
+impl Adam {
+    pub fn new(arch: &'static Architecture, lr: f32) -> Self {
+        Adam { lr, beta1: 0.9, beta2: 0.999, epsilon: 1e-8, steps: 0, m: Network::zeros(arch), v: Network::zeros(arch) }
+    }
+
+    /// One step along the gradients, which are scaled by `scale` first, e.g. one over the
+    /// batch size for the gradients of the mean loss
+    pub fn step(&mut self, net: &mut Network, grad: &Gradients, scale: f32) {
+        self.steps += 1;
+        let lr = self.lr * (1.0 - self.beta2.powi(self.steps)).sqrt() / (1.0 - self.beta1.powi(self.steps));
+        let (beta1, beta2, epsilon) = (self.beta1, self.beta2, self.epsilon);
+        let update = |params: &mut [f32], grads: &[f32], m: &mut [f32], v: &mut [f32]| {
+            for (((p, g), m), v) in params.iter_mut().zip(grads).zip(m).zip(v) {
+                let g = g * scale;
+                *m = beta1 * *m + (1.0 - beta1) * g;
+                *v = beta2 * *v + (1.0 - beta2) * g * g;
+                *p -= lr * *m / (v.sqrt() + epsilon);
+            }
+        };
+
+        for (((p, g), m), v) in net.dense_mut().zip(grad.dense()).zip(self.m.dense_mut()).zip(self.v.dense_mut()) {
+            update(p, g, m, v);
+        }
+
+        let (l1, buckets) = (net.arch.transformed_dims, net.arch.psqt_buckets);
+        for (row, &feature) in grad.features.iter().enumerate() {
+            let f = usize::from(feature);
+            let (rows, cols) = (f * l1..(f + 1) * l1, row * l1..(row + 1) * l1);
+            update(
+                &mut net.ft_weights[rows.clone()],
+                &grad.ft_rows[cols],
+                &mut self.m.ft_weights[rows.clone()],
+                &mut self.v.ft_weights[rows],
+            );
+            let (rows, cols) = (f * buckets..(f + 1) * buckets, row * buckets..(row + 1) * buckets);
+            update(
+                &mut net.psqt_weights[rows.clone()],
+                &grad.psqt_rows[cols],
+                &mut self.m.psqt_weights[rows.clone()],
+                &mut self.v.psqt_weights[rows],
+            );
+        }
+        net.clip_weights();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nnue::architecture::HALF_KP_SMALL;
+    use nnue::nnue::{AnyNnue, load_any_nnue_from_reader};
+    use pleco::Board;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    /// Derivative of the output by one parameter, by central differences
+    fn numeric(net: &mut Network, features: Features, param: impl Fn(&mut Network) -> &mut f32) -> f32 {
+        let mut trace = Trace::new(net.arch);
+        let eps = 1e-4;
+        let saved = *param(net);
+        *param(net) = saved + eps;
+        let plus = net.forward(features, &mut trace);
+        *param(net) = saved - eps;
+        let minus = net.forward(features, &mut trace);
+        *param(net) = saved;
+        (plus - minus) / (2.0 * eps)
+    }
+
+    #[test]
+    fn test_gradients() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut net = Network::new(&HALF_KP_SMALL, &mut rng);
+        let board = Board::from_fen("r1bqkb1r/ppp2ppp/2n1p3/3pPn2/3P4/2P2P2/PP1N2PP/R1BQKBNR b KQkq - 2 6").unwrap();
+        let active = |perspective| -> Vec<u16> {
+            HALF_KP_SMALL.feature_set.active_features(&board, perspective).iter().map(|&f| f as u16).collect()
+        };
+        let (stm, other) = (active(board.turn()), active(!board.turn()));
+        let features = Features { perspectives: [&stm, &other], bucket: 7 };
+
+        let mut trace = Trace::new(net.arch);
+        let mut grad = Gradients::new(net.arch);
+        net.forward(features, &mut trace);
+        net.backward(features, &mut trace, 1.0, &mut grad);
+
+        // Away from the kinks of the clamps the differences agree closely
+        let mut checked = 0;
+        let mut check = |name: &str, analytic: f32, numeric: f32| {
+            assert!((numeric - analytic).abs() < 2e-3 + 0.02 * analytic.abs(), "{}: {} vs {}", name, numeric, analytic);
+            checked += usize::from(analytic != 0.0);
+        };
+        let stack = &grad.layer_stacks[7];
+        check("fc2 bias", stack.fc2.biases[0], numeric(&mut net, features, |n| &mut n.layer_stacks[7].fc2.biases[0]));
+        for i in 0..32 {
+            check("fc2 weight", stack.fc2.weights[i], numeric(&mut net, features, |n| &mut n.layer_stacks[7].fc2.weights[i]));
+            check("fc1 bias", stack.fc1.biases[i], numeric(&mut net, features, |n| &mut n.layer_stacks[7].fc1.biases[i]));
+        }
+        for o in 0..16 {
+            check("fc0 bias", stack.fc0.biases[o], numeric(&mut net, features, |n| &mut n.layer_stacks[7].fc0.biases[o]));
+            let w = o * 128 + 17;
+            check("fc0 weight", stack.fc0.weights[w], numeric(&mut net, features, |n| &mut n.layer_stacks[7].fc0.weights[w]));
+        }
+
+        let l1 = HALF_KP_SMALL.transformed_dims;
+        let f = usize::from(stm[3]);
+        let row = grad.rows[&stm[3]];
+        for j in (0..l1).step_by(7) {
+            check("ft bias", grad.ft_biases[j], numeric(&mut net, features, |n| &mut n.ft_biases[j]));
+            check("ft weight", grad.ft_rows[row * l1 + j], numeric(&mut net, features, |n| &mut n.ft_weights[f * l1 + j]));
+        }
+        assert!(checked > 60, "only {} gradients are not zero", checked);
+
+        // A feature of the side to move adds half its PSQT weight, one of the other side takes half off
+        assert_eq!(grad.psqt_rows[row * HALF_KP_SMALL.psqt_buckets + 7], 0.5);
+        assert_eq!(grad.psqt_rows[grad.rows[&other[0]] * HALF_KP_SMALL.psqt_buckets + 7], -0.5);
+    }
+
+    /// A random network with parameters the integer types hold exactly evaluates the same after
+    /// quantization, up to the rounding of the integer activations
+    #[test]
+    fn test_quantize() {
+        let snap = |values: &mut [f32], scale: f32| {
+            for v in values {
+                *v = (*v * scale).round() / scale;
+            }
+        };
+        let output_scale = NNUE_TO_SCORE * OUTPUT_SCALE;
+
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut net = Network::new(&HALF_KP_SMALL, &mut rng);
+        for b in &mut net.ft_biases {
+            *b = rng.random_range(0.2..0.8);
+        }
+        snap(&mut net.ft_biases, QUANTIZED_ONE);
+        snap(&mut net.ft_weights, QUANTIZED_ONE);
+        snap(&mut net.psqt_weights, output_scale);
+        for stack in &mut net.layer_stacks {
+            for layer in stack.affines_mut() {
+                for b in &mut layer.biases {
+                    *b = rng.random_range(-0.5..0.5);
+                }
+            }
+            for layer in [&mut stack.fc0, &mut stack.fc1] {
+                snap(&mut layer.weights, WEIGHT_SCALE);
+                snap(&mut layer.biases, WEIGHT_SCALE * QUANTIZED_ONE);
+            }
+            snap(&mut stack.fc2.weights, output_scale / QUANTIZED_ONE);
+            snap(&mut stack.fc2.biases, output_scale);
+        }
+
+        let mut bytes = Vec::new();
+        AnyNnue::from_quantized(&net.quantize("test")).write(&mut bytes).unwrap();
+        let loaded = load_any_nnue_from_reader(bytes.as_slice()).unwrap();
+
+        let mut trace = Trace::new(net.arch);
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkb1r/ppp2ppp/2n1p3/3pPn2/3P4/2P2P2/PP1N2PP/R1BQKBNR b KQkq - 2 6",
+            "8/4P3/2bk1K2/8/1PB5/8/8/8 w - - 1 60",
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 b - - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            let active = |perspective| -> Vec<u16> {
+                net.arch.feature_set.active_features(&board, perspective).iter().map(|&f| f as u16).collect()
+            };
+            let (stm, other) = (active(board.turn()), active(!board.turn()));
+            let bucket = (board.count_all_pieces() as usize - 1) / 4;
+            let features = Features { perspectives: [&stm, &other], bucket };
+
+            let float = net.forward(features, &mut trace) * NNUE_TO_SCORE;
+            let eval = loaded.evaluate_fresh(&board);
+            let quantized = (eval.psqt + eval.positional) as f32;
+            assert!((quantized - float).abs() < 3.0 + 0.002 * float.abs(), "{}: {} vs {}", fen, quantized, float);
+        }
+    }
+}
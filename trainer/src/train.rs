@@ -0,0 +1,178 @@
+//! Minibatch training with the gradients of a batch computed in parallel
+
+use rand::{rngs::StdRng, seq::SliceRandom};
+use rayon::prelude::*;
+
+use crate::dataset::Dataset;
+use crate::network::{Adam, Gradients, NNUE_TO_SCORE, Network, Trace};
+
+/// Squared error between win probabilities, Stockfish's trainer without the power tweaks
+#[derive(Clone, Copy, Debug)]
+pub struct Loss {
+    /// Internal evaluation units for which the win probability is sigmoid(1)
+    pub scale: f32,
+    /// Weight of the search score in the target, the game result gets the rest
+    pub lambda: f32,
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+impl Loss {
+    /// Loss of network output `output` on position `i` and its derivative by the output
+    pub fn eval(&self, output: f32, data: &Dataset, i: usize) -> (f32, f32) {
+        let target = self.lambda * sigmoid(data.score(i) / self.scale) + (1.0 - self.lambda) * data.result(i);
+        let predicted = sigmoid(output * NNUE_TO_SCORE / self.scale);
+        let error = predicted - target;
+        let d_output = 2.0 * error * predicted * (1.0 - predicted) * NNUE_TO_SCORE / self.scale;
+        (error * error, d_output)
+    }
+}
+
+/// Summed gradients and loss over the positions `indices`, split across the thread pool. The
+/// split only depends on the pool size, so a run is repeatable with the same seed and threads.
+pub fn gradients(net: &Network, data: &Dataset, indices: &[usize], loss: Loss) -> (Gradients, f64) {
+    let chunk = indices.len().div_ceil(rayon::current_num_threads()).max(1);
+    let parts: Vec<(Gradients, f64)> = indices
+        .par_chunks(chunk)
+        .map(|chunk| {
+            let mut trace = Trace::new(net.arch);
+            let mut grad = Gradients::new(net.arch);
+            let mut total = 0.0;
+            for &i in chunk {
+                let features = data.features(i);
+                let output = net.forward(features, &mut trace);
+                let (l, d_output) = loss.eval(output, data, i);
+                total += f64::from(l);
+                net.backward(features, &mut trace, d_output, &mut grad);
+            }
+            (grad, total)
+        })
+        .collect();
+
+    let mut parts = parts.into_iter();
+    let (mut grad, mut total) = parts.next().unwrap_or_else(|| (Gradients::new(net.arch), 0.0));
+    for (part, part_total) in parts {
+        grad.merge(&part);
+        total += part_total;
+    }
+    (grad, total)
+}
+
+/// Mean loss over the positions `indices`
+pub fn mean_loss(net: &Network, data: &Dataset, indices: &[usize], loss: Loss) -> f64 {
+    let total: f64 = indices
+        .par_chunks(4096)
+        .map(|chunk| {
+            let mut trace = Trace::new(net.arch);
+            chunk
+                .iter()
+                .map(|&i| f64::from(loss.eval(net.forward(data.features(i), &mut trace), data, i).0))
+                .sum::<f64>()
+        })
+        .sum();
+    total / indices.len().max(1) as f64
+}
+
+/// One pass over the training positions in a new random order, returning their mean loss
+pub fn train_epoch(
+    net: &mut Network,
+    adam: &mut Adam,
+    data: &Dataset,
+    indices: &mut [usize],
+    batch_size: usize,
+    loss: Loss,
+    rng: &mut StdRng,
+) -> f64 {
+    indices.shuffle(rng);
+    let mut total = 0.0;
+    for batch in indices.chunks(batch_size) {
+        let (grad, batch_total) = gradients(net, data, batch, loss);
+        adam.step(net, &grad, 1.0 / batch.len() as f32);
+        total += batch_total;
+    }
+    total / indices.len().max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use nnue::architecture::HALF_KP_SMALL;
+    use nnue::data::{GameResult, Sample};
+    use engine::debug::{NoTrace, Tracing};
+    use engine::search::MySearcher;
+    use nnue::nnue::{AnyNnue, NnueEvaluator, load_any_nnue_from_reader};
+    use nnue::nnue_utils;
+    use pleco::{Board, MoveList, PieceType};
+    use rand::{Rng, SeedableRng, seq::IndexedRandom};
+
+    use super::*;
+
+    /// Positions a few random moves in, scored by material and by how many more queenside
+    /// pawns the side to move has, which the PSQT weights don't start with
+    fn queenside_samples(count: usize, rng: &mut StdRng) -> Vec<Sample> {
+        const VALUES: [(PieceType, i16); 5] =
+            [(PieceType::P, 208), (PieceType::N, 781), (PieceType::B, 825), (PieceType::R, 1276), (PieceType::Q, 2538)];
+        let mut samples = Vec::new();
+        while samples.len() < count {
+            let mut board = Board::start_pos();
+            for _ in 0..rng.random_range(4..40) {
+                let moves: MoveList = board.generate_moves();
+                match moves.choose(rng) {
+                    Some(&mv) => board.apply_move(mv),
+                    None => break,
+                }
+            }
+            let queenside = |player| (board.piece_bb(player, PieceType::P).0 & 0x0F0F_0F0F_0F0F_0F0F).count_ones() as i16;
+            let material: i16 = VALUES
+                .iter()
+                .map(|&(pt, value)| value * (board.count_piece(board.turn(), pt) as i16 - board.count_piece(!board.turn(), pt) as i16))
+                .sum();
+            let score = material + 150 * (queenside(board.turn()) - queenside(!board.turn()));
+            let cp = nnue_utils::to_cp(i32::from(score), &board) as i16;
+            samples.push(Sample::new(&board, cp, GameResult::Draw));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_training() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let samples = queenside_samples(5000, &mut rng);
+        let data = Dataset::from_samples(&samples, HALF_KP_SMALL.feature_set).unwrap();
+        let (mut training, validation): (Vec<usize>, Vec<usize>) = (0..data.len()).partition(|i| i % 10 != 0);
+
+        let loss = Loss { scale: 400.0, lambda: 1.0 };
+        let mut net = Network::new(&HALF_KP_SMALL, &mut rng);
+        let mut adam = Adam::new(&HALF_KP_SMALL, 1e-3);
+        let before = mean_loss(&net, &data, &validation, loss);
+        for _ in 0..10 {
+            train_epoch(&mut net, &mut adam, &data, &mut training, 64, loss, &mut rng);
+        }
+        let after = mean_loss(&net, &data, &validation, loss);
+        assert!(after < before * 0.6, "validation loss went from {} to {}", before, after);
+
+        // The quantized network loads and evaluates like the float one, up to rounding
+        let mut bytes = Vec::new();
+        AnyNnue::from_quantized(&net.quantize("test")).write(&mut bytes).unwrap();
+        let loaded = load_any_nnue_from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.architecture(), &HALF_KP_SMALL);
+
+        let mut trace = Trace::new(&HALF_KP_SMALL);
+        let mut total_error = 0.0;
+        for &i in validation.iter().take(100) {
+            let float = net.forward(data.features(i), &mut trace) * NNUE_TO_SCORE;
+            let eval = loaded.evaluate_fresh(&Board::from_fen(&samples[i].fen).unwrap());
+            let error = ((eval.psqt + eval.positional) as f32 - float).abs();
+            assert!(error < 30.0, "{}: {} vs {}", samples[i].fen, eval.psqt + eval.positional, float);
+            total_error += error;
+        }
+        assert!(total_error / 100.0 < 10.0, "mean quantization error {}", total_error / 100.0);
+
+        // and plays: the search on it takes the queen the knight attacks
+        let mut nnue_eval = NnueEvaluator::with_network(loaded);
+        let mut searcher = MySearcher::new(&mut nnue_eval, NoTrace::new(), None);
+        let mut board = Board::from_fen("rnb1kbnr/pppp1ppp/8/4p3/3qP3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 3").unwrap();
+        assert_eq!(searcher.perform_search(&mut board, 3).bit_move.stringify(), "f3d4");
+    }
+}